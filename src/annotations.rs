@@ -0,0 +1,189 @@
+use crate::core::memory::Memory;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// the highest address of the 4 KB memory
+const MAX_ADDRESS: u32 = 0xFFF;
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    // the line number, starting from 1, and its contents
+    Parse(usize, String),
+    AddressOutOfRange(usize, u32),
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Io(err) => write!(f, "{}", err),
+            SymbolError::Parse(line, text) => {
+                write!(
+                    f,
+                    "line {}: expected 'label = address', got '{}'",
+                    line, text
+                )
+            }
+            SymbolError::AddressOutOfRange(line, address) => {
+                write!(f, "line {}: address {:#X} is out of memory", line, address)
+            }
+        }
+    }
+}
+
+impl Error for SymbolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SymbolError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SymbolError {
+    fn from(err: io::Error) -> Self {
+        SymbolError::Io(err)
+    }
+}
+
+// the annotations are stored as a json object of labels keyed by hex address, such as
+// {"0x0300": "sprite_data"}
+pub fn load_annotations(path: &Path) -> io::Result<BTreeMap<u16, String>> {
+    let labels: BTreeMap<String, String> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    labels
+        .into_iter()
+        .map(|(address, label)| {
+            u16::from_str_radix(address.trim_start_matches("0x"), 16)
+                .map(|address| (address, label))
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid address '{}'", address),
+                    )
+                })
+        })
+        .collect()
+}
+
+pub fn save_annotations(path: &Path, annotations: &BTreeMap<u16, String>) -> io::Result<()> {
+    let labels: BTreeMap<String, &String> = annotations
+        .iter()
+        .map(|(address, label)| (format!("0x{:04X}", address), label))
+        .collect();
+    fs::write(path, serde_json::to_string_pretty(&labels)?)
+}
+
+// reads the symbols written by assemblers, one `label = address` per line with the address
+// in hex (0x0300) or decimal. empty lines and lines starting with # are skipped
+pub fn parse_symbols(contents: &str) -> Result<BTreeMap<u16, String>, SymbolError> {
+    let mut symbols = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parse_error = || SymbolError::Parse(i + 1, line.to_string());
+        let mut parts = line.splitn(2, '=');
+        let label = parts
+            .next()
+            .map(str::trim)
+            .filter(|label| !label.is_empty());
+        let address = parts.next().map(str::trim).and_then(|address| {
+            match address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix("0X"))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => address.parse().ok(),
+            }
+        });
+        let (label, address) = label.zip(address).ok_or_else(parse_error)?;
+        if address > MAX_ADDRESS {
+            return Err(SymbolError::AddressOutOfRange(i + 1, address));
+        }
+        symbols.insert(address as u16, label.to_string());
+    }
+    Ok(symbols)
+}
+
+pub fn load_symbols(path: &Path, memory: &mut Memory) -> Result<(), SymbolError> {
+    for (address, label) in parse_symbols(&fs::read_to_string(path)?)? {
+        memory.annotate(address, &label);
+    }
+    Ok(())
+}
+
+// writes the annotations of the memory in the format read by `load_symbols`
+pub fn save_symbols(path: &Path, memory: &Memory) -> io::Result<()> {
+    let mut contents = String::new();
+    for (address, label) in memory.annotations() {
+        let _ = writeln!(contents, "{} = 0x{:04X}", label, address);
+    }
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("chipotto_annotations_{}.json", std::process::id()));
+        let mut annotations = BTreeMap::new();
+        annotations.insert(0x300, "sprite_data".to_string());
+        annotations.insert(0x20A, "main_loop".to_string());
+        save_annotations(&path, &annotations).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\"0x020A\": \"main_loop\""));
+        assert_eq!(annotations, load_annotations(&path).unwrap());
+
+        fs::write(&path, "{\"main\": \"main_loop\"}").unwrap();
+        let result = load_annotations(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_parse_symbols() {
+        let symbols = parse_symbols("# sprites\nsprite_data = 0x0300\n\nmain=512\n").unwrap();
+        assert_eq!(Some(&"sprite_data".to_string()), symbols.get(&0x300));
+        assert_eq!(Some(&"main".to_string()), symbols.get(&0x200));
+        assert!(matches!(
+            parse_symbols("main = 0x200\nloop 0x210"),
+            Err(SymbolError::Parse(2, _))
+        ));
+        assert!(matches!(
+            parse_symbols("= 0x200"),
+            Err(SymbolError::Parse(1, _))
+        ));
+        assert!(matches!(
+            parse_symbols("end = 0x1000"),
+            Err(SymbolError::AddressOutOfRange(1, 0x1000))
+        ));
+    }
+
+    #[test]
+    fn test_save_and_load_symbols() {
+        let path =
+            std::env::temp_dir().join(format!("chipotto_symbols_{}.sym", std::process::id()));
+        let mut memory = Memory::new();
+        memory.annotate(0x300, "sprite_data");
+        memory.annotate(0x200, "main");
+        save_symbols(&path, &memory).unwrap();
+        assert_eq!(
+            "main = 0x0200\nsprite_data = 0x0300\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        let mut loaded = Memory::new();
+        let result = load_symbols(&path, &mut loaded);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(memory.annotations(), loaded.annotations());
+    }
+}
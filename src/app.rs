@@ -1,131 +1,1136 @@
-use crate::core::chip8::Chip8;
-use crate::Config;
-use minifb::{Key, Scale, ScaleMode, Window, WindowOptions};
-use std::error::Error;
+use crate::annotations::{load_annotations, load_symbols, save_annotations};
+use crate::config::hotkeys::{key_of, Action, HotkeyMap};
+use crate::config::profiles::ProfileConfig;
+use crate::config::{Config, ConfigError, LockstepRole, PlaylistMode};
+use crate::core::audio::{PatternBuffer, SoundOutput};
+use crate::core::chip8::{Chip8, RomFormat, RomInfo, TIMERS_HZ};
+use crate::core::compat::{uses_superchip_instructions, CompatibilityMode, QuirkDatabase};
+use crate::core::framebuffer::{FrameBuffer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use crate::core::instr::Instr;
+use crate::core::keypad::Keypad;
+use crate::core::step_log::{StepChange, StepLog};
+use crate::disasm::Annotator;
+use crate::display::{DisplayBackend, HostKey, MemoryWindow, MenuAction, GRID_PIXEL};
+use crate::emulator_state::{EmulatorState, TransitionError};
+use crate::error::ChipottoError;
+use crate::exit_state::ExitState;
+use crate::input_log::{InputRecorder, InputReplay};
+use crate::memory_view::MemoryView;
+use crate::net::sync::{state_checksum, LockstepSession, SessionInfo};
+use crate::net::{P2PKeypad, StateSync};
+use crate::opcode_log::{format_opcode, OpcodeLog};
+use crate::profiler::CallGraph;
+use crate::recent::RecentRoms;
+use crate::rom_library::RomLibrary;
+use crate::timing::{Clock, FrameHistogram, SystemClock, TimingStats};
+use std::cell::RefCell;
 use std::fs;
-use std::time::{Duration, Instant};
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const WINDOW_WIDTH: usize = 64;
-const WINDOW_HEIGHT: usize = 32;
-
-const TIMERS_INTERVAL_MICROS: u64 = 1_000_000 / 60;
 const REFRESH_INTERVAL_MICROS: u64 = 1_000_000 / 60;
+// the memory window is redrawn every 6 frames, at 10 Hz
+const MEMORY_WINDOW_FRAMES: u64 = 6;
+
+// placeholders: {rom}, {clock}, {fps}, {cycles}, {pc} and {mode}
+const DEFAULT_TITLE_TEMPLATE: &str = "Chipotto [{mode}]";
 
-const KEYS: [Key; 16] = [
-    Key::X,
-    Key::Key1,
-    Key::Key2,
-    Key::Key3,
-    Key::Q,
-    Key::W,
-    Key::E,
-    Key::A,
-    Key::S,
-    Key::D,
-    Key::Z,
-    Key::C,
-    Key::Key4,
-    Key::R,
-    Key::F,
-    Key::V,
-];
-
-pub struct Chip8App {
+pub struct Chip8App<D: DisplayBackend> {
     config: Config,
+    display: D,
     chip8: Chip8,
+    recent_roms: RecentRoms,
+    title_template: String,
+    // the title is only pushed to the window when it changes
+    title: String,
+    rom_name: String,
+    timing_stats: Option<TimingStats>,
+    // the frame times captured by `capture_framerate_histogram`, and for how long
+    frame_histogram: Option<(FrameHistogram, Duration)>,
+    call_graph: Option<Rc<RefCell<CallGraph>>>,
+    opcode_log: Option<OpcodeLog>,
+    // whether the turbo key is held, checked once per frame
+    turbo: bool,
+    // whether the screen was above the dense screen threshold after the last draw
+    dense_screen: bool,
+    // index in the playlist of the rom being run
+    playlist_index: usize,
+    // set once the last rom of a playlist played only once is over
+    playlist_ended: bool,
+    step_log: Option<StepLog>,
+    // cycles run since the last screen refresh
+    frame_cycles: u32,
+    // starts at the configured clock and can be changed while running
+    clock_hz: u64,
+    sound_output: Option<Box<dyn SoundOutput>>,
+    // changed only through `transition_to`
+    state: EmulatorState,
+    // the main loop takes all its times from it
+    clock: Box<dyn Clock>,
+    memory_window: Option<(MemoryWindow, MemoryView)>,
+    // whether the quirks of the current rom came from the quirk database instead of the
+    // config, and have to be undone for the next rom
+    known_quirks_applied: bool,
+    // whether the XO-CHIP header of the current rom switched the mode, which has to be
+    // undone for the next rom without a header
+    header_mode_applied: bool,
+    // the settings before any profile was applied, which a rom without a profile goes
+    // back to
+    settings_without_profile: ProfileConfig,
+    detect_quirks_without_profile: bool,
 }
 
-impl Chip8App {
-    pub fn new(config: Config) -> Self {
+impl<D: DisplayBackend> Chip8App<D> {
+    pub fn new(config: Config, display: D) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            let seed = rand::random();
+            eprintln!("Using random seed {}", seed);
+            seed
+        });
+        let mut chip8 = Chip8::with_seed(seed);
+        chip8
+            .cpu
+            .set_compatibility_mode_at_runtime(config.compat_mode);
+        chip8.cpu.set_strict_alignment(config.strict_alignment);
+        chip8.cpu.set_vf_result_not_flag(config.vf_result_not_flag);
+        chip8.cpu.set_scanline_accurate(config.scanline_accurate);
+        if let Some(depth) = config.max_call_depth {
+            chip8.cpu.set_max_call_depth(depth);
+        }
+        for &(instr_type, count) in &config.count_watchpoints {
+            chip8.add_count_watchpoint(instr_type, count);
+        }
+        for &address in &config.breakpoints {
+            chip8.add_breakpoint(address);
+        }
+        let call_graph = if config.call_graph {
+            let call_graph = Rc::new(RefCell::new(CallGraph::new()));
+            chip8.add_watcher(Box::new(Rc::clone(&call_graph)));
+            Some(call_graph)
+        } else {
+            None
+        };
+        let title_template = config
+            .title_template
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TITLE_TEMPLATE.to_string());
+        let opcode_log = if config.crash_log {
+            Some(OpcodeLog::default())
+        } else {
+            None
+        };
+        let clock_hz = config.clock_hz;
+        let settings_without_profile = config.profile_settings();
+        let detect_quirks_without_profile = config.detect_quirks;
         Chip8App {
             config,
-            chip8: Chip8::new(),
+            display,
+            chip8,
+            recent_roms: RecentRoms::load(),
+            title_template,
+            title: String::new(),
+            rom_name: String::new(),
+            timing_stats: None,
+            frame_histogram: None,
+            call_graph,
+            opcode_log,
+            turbo: false,
+            dense_screen: false,
+            playlist_index: 0,
+            playlist_ended: false,
+            step_log: None,
+            frame_cycles: 0,
+            clock_hz,
+            sound_output: None,
+            state: EmulatorState::Running,
+            clock: Box::new(SystemClock),
+            memory_window: None,
+            known_quirks_applied: false,
+            header_mode_applied: false,
+            settings_without_profile,
+            detect_quirks_without_profile,
         }
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        let contents = fs::read(self.config.rom_file_path.clone())?;
-        self.chip8.load_rom(&contents);
+    // a `FakeClock` makes the main loop run as many cycles per frame as it would in
+    // real time without waiting for it, e.g. for tests
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_window_title_template(&mut self, template: &str) {
+        self.title_template = template.to_string();
+    }
 
-        // WINDOW CREATION
-        let mut window = minifb::Window::new(
-            "Chipotto",
-            WINDOW_WIDTH,
-            WINDOW_HEIGHT,
-            WindowOptions {
-                resize: true,
-                scale: Scale::X8,
-                scale_mode: ScaleMode::AspectRatioStretch,
-                ..WindowOptions::default()
-            },
-        )?;
+    // only collected when enabled in the config, and once the rom is running
+    pub fn timing_stats(&self) -> Option<&TimingStats> {
+        self.timing_stats.as_ref()
+    }
+
+    pub fn call_graph_report(&self) -> Option<String> {
+        self.call_graph
+            .as_ref()
+            .map(|call_graph| call_graph.borrow().report())
+    }
+
+    // gets the sound event of every timer tick, on the emulator thread between two
+    // cycles, so it has to return quickly
+    pub fn set_sound_output(&mut self, output: Box<dyn SoundOutput>) {
+        self.sound_output = Some(output);
+    }
+
+    pub fn clock_hz(&self) -> u64 {
+        self.clock_hz
+    }
+
+    // the main loop picks up the new clock from the next cycle
+    pub fn set_clock_hz(&mut self, hz: u64) -> Result<(), ConfigError> {
+        if hz == 0 {
+            return Err(ConfigError::InvalidClock(hz.to_string()));
+        }
+        self.clock_hz = hz;
+        self.chip8.set_clock_hz(hz);
+        self.log_change(StepChange::ClockHz(hz));
+        Ok(())
+    }
+
+    // runs the emulator like `run`, but only for `duration` from the start of the rom, and
+    // returns how long each frame actually took
+    pub fn capture_framerate_histogram(
+        &mut self,
+        duration: Duration,
+    ) -> Result<FrameHistogram, ChipottoError> {
+        let target = Duration::from_micros(REFRESH_INTERVAL_MICROS);
+        self.frame_histogram = Some((FrameHistogram::new(target), duration));
+        let result = self.run();
+        let (histogram, _) = self.frame_histogram.take().unwrap();
+        result.map(|_| histogram)
+    }
+
+    pub fn run(&mut self) -> Result<(), ChipottoError> {
+        self.chip8.set_clock_hz(self.clock_hz);
+
+        #[cfg(feature = "http")]
+        let downloaded = match self.config.rom_url.clone() {
+            Some(url) => {
+                self.load_rom_url(&url)?;
+                true
+            }
+            None => false,
+        };
+        #[cfg(not(feature = "http"))]
+        let downloaded = false;
+        if !downloaded {
+            let rom_file_path = match self.config.rom_file_path.clone() {
+                Some(path) => path,
+                None => match self.run_launcher()? {
+                    Some(path) => path,
+                    // the window was closed before choosing a rom
+                    None => return Ok(()),
+                },
+            };
+            self.load_rom_file(&rom_file_path)?;
+        }
+        // the annotations and symbols files describe the rom given on the command line, not
+        // the ones loaded after it or picked in the launcher
+        if downloaded || self.config.rom_file_path.is_some() {
+            self.load_rom_annotations()?;
+        }
+        for (address, bytes) in &self.config.rom_patches {
+            self.chip8.inject_rom_patch(*address, bytes)?;
+        }
+
+        self.display.set_recent_roms(self.recent_roms.paths())?;
+        // only the rom given on the command line is logged, from its first cycle
+        if self.config.step_log.is_some() {
+            let mut step_log = StepLog::new(
+                self.chip8.seed(),
+                self.clock_hz,
+                self.chip8.cpu.compatibility_mode(),
+                self.chip8.rom(),
+            );
+            step_log.set_quirks(self.chip8.step_quirks());
+            step_log.custom_font = self.chip8.custom_font().map(|font| font.to_vec());
+            self.step_log = Some(step_log);
+        }
+
+        let mut recorder = match &self.config.record_input {
+            Some(path) => Some(InputRecorder::new(BufWriter::new(File::create(path)?))),
+            None => None,
+        };
+        let mut replay = match &self.config.replay_input {
+            Some(path) => Some(InputReplay::from_reader(File::open(path)?)?),
+            None => None,
+        };
+        let mut p2p_keypad = match self.config.p2p {
+            Some((listen, peer)) => {
+                Some(P2PKeypad::bind(listen, peer, self.config.p2p_remote_keys)?)
+            }
+            None => None,
+        };
+        // whether the last exchange with the peer failed, so that an outage is only
+        // reported once
+        let mut p2p_failing = false;
+        // lockstep needs the same number of cycles in every frame on both sides, which
+        // only vsync guarantees, and turbo would break it
+        let session = SessionInfo::new(self.chip8.seed(), self.clock_hz, self.chip8.rom());
+        let mut lockstep = match self.config.lockstep {
+            Some(LockstepRole::Host(listen)) => Some(LockstepSession::host(
+                listen,
+                self.config.p2p_remote_keys,
+                &session,
+            )?),
+            Some(LockstepRole::Connect(addr)) => Some(LockstepSession::connect(addr, &session)?),
+            None => None,
+        };
+        let mut vsync = self.config.vsync || lockstep.is_some();
+        let mut state_sync = match self.config.state_sync {
+            Some(addr) => Some(StateSync::start_server(addr)?),
+            None => None,
+        };
+        let mut frame_number: u64 = 0;
+        // the frames run while not paused, which the recorded and replayed keys are
+        // numbered by
+        let mut input_frame: u64 = 0;
+        if self.config.multi_window {
+            self.memory_window = Some((MemoryWindow::new()?, MemoryView::new()));
+        }
 
         // vars for main loop
-        let mut last_cycle_update = Instant::now();
-        let mut last_timers_update = Instant::now();
-        let mut last_screen_refresh = Instant::now();
-        let cycle_duration = Duration::from_micros((1_000_000 / self.config.clock_hz) as u64);
-        let timers_duration = Duration::from_micros(TIMERS_INTERVAL_MICROS);
+        let mut last_cycle_update = self.clock.now();
+        let mut last_screen_refresh = self.clock.now();
         let frame_duration = Duration::from_micros(REFRESH_INTERVAL_MICROS);
+        let metrics_interval = self.config.metrics_interval.map(Duration::from_secs);
+        let mut last_metrics_report = self.clock.now();
+        let mut fps = 0;
+        let mut frames_since_fps_update = 0;
+        let mut last_fps_update = self.clock.now();
+        if vsync {
+            self.display.limit_update_rate(Some(frame_duration));
+        }
+        // with vsync the cycles are timed by frame, so the frames are measured instead,
+        // otherwise the cycles are measured against the starting clock
+        if self.config.timing_stats {
+            self.timing_stats = Some(TimingStats::new(if vsync {
+                frame_duration
+            } else {
+                Duration::from_micros(1_000_000 / self.clock_hz)
+            }));
+        }
+        let mut last_batch = self.clock.now();
+        let capture_end = self
+            .frame_histogram
+            .as_ref()
+            .map(|(_, duration)| self.clock.now() + *duration);
 
         // MAIN LOOP
-        while window.is_open() && !window.is_key_down(Key::Escape) {
-            if last_cycle_update.elapsed() >= cycle_duration {
-                self.handle_keypad(&window);
-                self.chip8.cpu_cycle();
-                last_cycle_update = Instant::now();
+        while self.display.is_open()
+            && !self.playlist_ended
+            && capture_end.map_or(true, |end| self.clock.now() < end)
+        {
+            if let Some(action) = self.display.menu_action() {
+                if lockstep.is_some() {
+                    eprintln!("The menu is disabled in lockstep");
+                } else {
+                    self.handle_menu(action);
+                    // the recent roms might have changed
+                    self.display.set_recent_roms(self.recent_roms.paths())?;
+                }
             }
-            if last_timers_update.elapsed() >= timers_duration {
-                self.chip8.timers_tick();
-                last_timers_update = Instant::now();
+            // the profile of a rom loaded from the menu can turn vsync on or off
+            if vsync != (self.config.vsync || lockstep.is_some()) {
+                vsync = !vsync;
+                self.display
+                    .limit_update_rate(Some(frame_duration).filter(|_| vsync));
             }
-            if last_screen_refresh.elapsed() >= frame_duration {
-                self.refresh_screen(&mut window);
-                last_screen_refresh = Instant::now();
+            // read on every iteration as the clock can change while running
+            let cycle_duration = Duration::from_micros(1_000_000 / self.clock_hz);
+            // with vsync the display blocks every update until the next frame is due, and
+            // the cpu cycles of a whole frame are run in one go before it
+            let cycles_per_frame = (self.clock_hz / TIMERS_HZ).max(1);
+            // turbo is left out of the timing stats, which would only measure the host
+            let turbo = self.turbo;
+            let timing_stats = self.timing_stats.as_mut().filter(|_| !turbo);
+            // while stopped only the screen and the hotkeys are handled, the step hotkey
+            // running the cycles one at a time
+            if let EmulatorState::Running = self.state {
+                if vsync {
+                    if let Some(stats) = timing_stats {
+                        stats.record(self.clock.elapsed_since(last_batch));
+                    }
+                    last_batch = self.clock.now();
+                    for _ in 0..cycles_per_frame {
+                        self.cpu_cycle()?;
+                    }
+                    // in turbo the rest of the frame is spent running cycles too
+                    while self.turbo
+                        && self.clock.elapsed_since(last_screen_refresh) < frame_duration
+                    {
+                        self.cpu_cycle()?;
+                    }
+                } else {
+                    let elapsed = self.clock.elapsed_since(last_cycle_update);
+                    if self.turbo || elapsed >= cycle_duration {
+                        if let Some(stats) = timing_stats {
+                            stats.record(elapsed);
+                        }
+                        self.cpu_cycle()?;
+                        last_cycle_update = self.clock.now();
+                    }
+                }
+            }
+            // the cycle count starts again from 0 with each rom
+            if self
+                .config
+                .playlist_duration
+                .is_some_and(|cycles| self.chip8.metrics().cycles_total >= cycles)
+            {
+                self.advance_playlist()?;
+            }
+            if vsync || self.clock.elapsed_since(last_screen_refresh) >= frame_duration {
+                // the display only updates the key states when the screen is refreshed
+                let host_keys = self.display.host_keys_pressed();
+                self.turbo = lockstep.is_none()
+                    && self
+                        .config
+                        .turbo_key
+                        .is_some_and(|key| self.display.is_turbo_key_down(key));
+                // while paused nothing runs, which neither the recording nor the lockstep
+                // peer has to know about
+                let running = matches!(self.state, EmulatorState::Running);
+                match replay.as_mut() {
+                    Some(replay) => self
+                        .chip8
+                        .set_keys_from_bitmask(replay.keys_at(input_frame)),
+                    None => self.handle_keypad(),
+                }
+                if let Some(p2p_keypad) = p2p_keypad.as_mut() {
+                    let local_keys = self.chip8.keypad.as_bitmask();
+                    // the peer keeps its last known keys until the network comes back
+                    match p2p_keypad.send(local_keys).and_then(|()| p2p_keypad.poll()) {
+                        Ok(()) => p2p_failing = false,
+                        Err(err) => {
+                            if !p2p_failing {
+                                eprintln!("Could not exchange keys with the peer: {}", err);
+                            }
+                            p2p_failing = true;
+                        }
+                    }
+                    self.chip8
+                        .set_keys_from_bitmask(p2p_keypad.merge(local_keys));
+                }
+                if let Some(session) = lockstep.as_mut().filter(|_| running) {
+                    let local_keys = self.chip8.keypad.as_bitmask();
+                    let keys = session.exchange(local_keys, state_checksum(&self.chip8))?;
+                    self.chip8.set_keys_from_bitmask(keys);
+                }
+                if let Some(recorder) = recorder.as_mut().filter(|_| running) {
+                    recorder.record(input_frame, self.chip8.keypad.as_bitmask())?;
+                }
+                if running {
+                    input_frame += 1;
+                }
+                if let Some(step_log) = self.step_log.as_mut() {
+                    step_log.push_frame(self.frame_cycles, self.chip8.keypad.as_bitmask());
+                }
+                self.frame_cycles = 0;
+                for host_key in host_keys {
+                    if let HostKey::Hotkey(hotkey) = host_key {
+                        match self.config.hotkeys.get(&hotkey).copied() {
+                            // the peer would not follow
+                            Some(action) if lockstep.is_some() && action.changes_emulation() => {
+                                eprintln!("{:?} is disabled in lockstep", action)
+                            }
+                            Some(action) => self.handle_action(action)?,
+                            None => {}
+                        }
+                    }
+                }
+                frames_since_fps_update += 1;
+                if self.clock.elapsed_since(last_fps_update) >= Duration::from_secs(1) {
+                    fps = frames_since_fps_update;
+                    frames_since_fps_update = 0;
+                    last_fps_update = self.clock.now();
+                }
+                self.update_title(fps);
+                self.refresh_screen()?;
+                self.refresh_memory_window(frame_number)?;
+                // a viewer going away does not stop the emulator
+                if let Some(sync) = state_sync.as_mut() {
+                    if let Err(err) = sync.send(&self.chip8) {
+                        eprintln!("State sync stopped: {}", err);
+                        state_sync = None;
+                    }
+                }
+                frame_number += 1;
+                if let Some((histogram, _)) = self.frame_histogram.as_mut() {
+                    histogram.record(self.clock.elapsed_since(last_screen_refresh));
+                }
+                last_screen_refresh = self.clock.now();
+            }
+            if let Some(interval) = metrics_interval {
+                if self.clock.elapsed_since(last_metrics_report) >= interval {
+                    println!("{}", self.chip8.metrics().report());
+                    last_metrics_report = self.clock.now();
+                }
             }
         }
 
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.flush()?;
+        }
+        if let Some(path) = &self.config.dump_state_path {
+            ExitState::capture(&self.chip8).write(path)?;
+        }
+        if let (Some(path), Some(step_log)) = (&self.config.step_log, &self.step_log) {
+            fs::write(
+                path,
+                serde_json::to_string(step_log).map_err(io::Error::from)?,
+            )?;
+        }
+        if let Some(path) = &self.config.save_annotations {
+            save_annotations(path, self.chip8.memory.annotations())?;
+        }
         Ok(())
     }
 
-    fn handle_keypad(&mut self, window: &Window) {
-        for (i, k) in KEYS.iter().enumerate() {
-            if window.is_key_down(*k) {
-                self.chip8.keypad.set_down(i as u8, true);
-            } else {
-                self.chip8.keypad.set_down(i as u8, false);
+    pub fn state(&self) -> &EmulatorState {
+        &self.state
+    }
+
+    pub fn transition_to(&mut self, new_state: EmulatorState) -> Result<(), TransitionError> {
+        if !self.state.can_transition_to(&new_state) {
+            return Err(TransitionError {
+                from: self.state.clone(),
+                to: new_state,
+            });
+        }
+        self.state = new_state;
+        Ok(())
+    }
+
+    // the cycles of a frame stop as soon as the emulator leaves the running state, which a
+    // watchpoint or a breakpoint may do at any cycle
+    fn cpu_cycle(&mut self) -> Result<(), ChipottoError> {
+        if let EmulatorState::Running = self.state {
+            self.execute_cycle()?;
+            if self.chip8.is_breakpoint(self.chip8.cpu.pc()) {
+                self.transition_to(EmulatorState::BreakpointHit(self.chip8.cpu.pc()))?;
+                eprintln!(
+                    "Breakpoint hit, {}\n{}",
+                    self.resume_hint(),
+                    self.chip8.cpu.reg_trace()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // the pause key might have been remapped in the config file
+    fn resume_hint(&self) -> String {
+        match key_of(&self.config.hotkeys, Action::Pause) {
+            Some(key) => format!("press {} to resume", key),
+            None => "press the pause key to resume".to_string(),
+        }
+    }
+
+    fn execute_cycle(&mut self) -> Result<(), ChipottoError> {
+        if self.config.print_opcodes || self.opcode_log.is_some() {
+            self.log_opcode();
+        }
+        let draws = self.chip8.metrics().frame_draws_total;
+        let drawing = self.chip8.cpu.is_drawing();
+        if let Err(err) = self.chip8.cpu_cycle() {
+            if let Some(opcode_log) = &self.opcode_log {
+                eprintln!("Last executed opcodes:\n{}", opcode_log.dump());
             }
+            self.transition_to(EmulatorState::Error(err.clone()))?;
+            return Err(err.into());
+        }
+        self.frame_cycles += 1;
+        // a scanline accurate draw is only on screen once its last row is drawn
+        let drew = self.chip8.metrics().frame_draws_total != draws || drawing;
+        if drew && !self.chip8.cpu.is_drawing() {
+            self.check_dense_screen();
+        }
+        if let Some(event) = self.chip8.take_sound_event() {
+            if let Some(output) = self.sound_output.as_mut() {
+                if self.chip8.cpu.compatibility_mode() == CompatibilityMode::XoChip {
+                    output.audio_pattern(self.chip8.cpu.audio_pattern());
+                } else {
+                    output.audio_pattern(&PatternBuffer::SQUARE_WAVE);
+                }
+                output.sound_event(event);
+            }
+        }
+        // the watchpoints of the core pause it, which the app takes over
+        if self.chip8.is_paused() {
+            self.chip8.resume();
+            self.transition_to(EmulatorState::Paused)?;
+            eprintln!(
+                "Halted by a watchpoint, {}\n{}",
+                self.resume_hint(),
+                self.chip8.cpu.reg_trace()
+            );
+        }
+        Ok(())
+    }
+
+    // logs the instruction about to be executed, so that the crash log includes the one
+    // that failed
+    fn log_opcode(&mut self) {
+        // the rows of a scanline accurate draw do not run the instruction at the pc
+        if self.chip8.cpu.is_drawing() {
+            return;
+        }
+        let pc = self.chip8.cpu.pc();
+        let opcode = match self.chip8.memory.try_read_word(pc) {
+            Ok(opcode) => opcode,
+            Err(_) => return,
+        };
+        if self.config.print_opcodes {
+            eprintln!("{}", self.annotated_opcode(pc, opcode));
+        }
+        if let Some(opcode_log) = self.opcode_log.as_mut() {
+            opcode_log.push(pc, opcode);
         }
     }
 
-    fn refresh_screen(&self, window: &mut Window) {
-        let buffer: Vec<u32> = self
+    fn annotated_opcode(&self, pc: u16, opcode: u16) -> String {
+        let line = format_opcode(pc, opcode);
+        match Instr::decode(opcode, self.chip8.cpu.compatibility_mode()) {
+            Some(instr) => {
+                let comment = Annotator::annotate(&instr, &self.chip8.cpu.state());
+                format!("{:<32}{}", line, comment)
+            }
+            None => line,
+        }
+    }
+
+    // warns once each time the screen becomes denser than the threshold, as roms drawing
+    // on a full screen collide with everything
+    fn check_dense_screen(&mut self) {
+        let threshold = match self.config.dense_screen_threshold {
+            Some(threshold) => threshold as usize,
+            None => return,
+        };
+        let lit = self.chip8.frame_buffer.count_lit_pixels();
+        let dense = lit * 100 > threshold * BUFFER_WIDTH * BUFFER_HEIGHT;
+        if dense && !self.dense_screen {
+            eprintln!(
+                "Warning: {} of {} pixels are lit after the draw at {:04X}",
+                lit,
+                BUFFER_WIDTH * BUFFER_HEIGHT,
+                self.chip8.cpu.last_draw_address().unwrap_or_default()
+            );
+        }
+        self.dense_screen = dense;
+    }
+
+    // lets the user pick one of the roms in the rom directory, with the arrow keys or
+    // the CHIP-8 keys 4 and 6 to move and enter or the CHIP-8 key 5 to select
+    fn run_launcher(&mut self) -> Result<Option<PathBuf>, ChipottoError> {
+        let mut library = RomLibrary::new(RomLibrary::scan(&self.config.rom_dir));
+        if library.entries().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no roms found in {}", self.config.rom_dir.display()),
+            )
+            .into());
+        }
+        let mut frame_buffer = FrameBuffer::default();
+        while self.display.is_open() {
+            for host_key in self.display.host_keys_pressed() {
+                match host_key {
+                    HostKey::Up => library.select_previous(),
+                    HostKey::Down => library.select_next(),
+                    HostKey::Select => {
+                        return Ok(library.selected().map(|entry| entry.path.clone()))
+                    }
+                    _ => {}
+                }
+            }
+            library.render(&mut frame_buffer);
+            self.display
+                .render(frame_buffer.get_buffer(), BUFFER_WIDTH, BUFFER_HEIGHT)?;
+            thread::sleep(Duration::from_micros(REFRESH_INTERVAL_MICROS));
+        }
+        Ok(None)
+    }
+
+    fn load_rom_file(&mut self, path: &Path) -> Result<(), ChipottoError> {
+        // before loading, so that the mode of an XO-CHIP header still wins over the profile
+        self.apply_rom_profile(path)?;
+        let info = self.chip8.load_rom_from_path(path)?;
+        self.prepare_rom(&info)?;
+        self.rom_name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.recent_roms.push(path);
+        if let Err(err) = self.recent_roms.save() {
+            eprintln!("Could not save recent roms: {}", err);
+        }
+        Ok(())
+    }
+
+    // downloaded roms are named after the last part of the url, and are not remembered
+    // among the recent roms
+    #[cfg(feature = "http")]
+    fn load_rom_url(&mut self, url: &str) -> Result<(), ChipottoError> {
+        let info = self
+            .chip8
+            .load_rom_from_url(url)
+            .map_err(ChipottoError::Download)?;
+        self.prepare_rom(&info)?;
+        let file_name = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+        self.rom_name = Path::new(file_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(())
+    }
+
+    // the settings of the previous rom's profile are undone by those of this one, or by
+    // the settings without a profile
+    fn apply_rom_profile(&mut self, path: &Path) -> Result<(), ChipottoError> {
+        let profile = self.config.rom_profile(path);
+        let base = &self.settings_without_profile;
+        let clock_hz = profile.clock_hz.or(base.clock_hz).unwrap_or(self.clock_hz);
+        let color1 = profile.color1.or(base.color1).unwrap_or(self.config.color1);
+        let color2 = profile.color2.or(base.color2).unwrap_or(self.config.color2);
+        let compat_mode = profile
+            .compat_mode
+            .or(base.compat_mode)
+            .unwrap_or(self.config.compat_mode);
+        self.config.vsync = profile.vsync.or(base.vsync).unwrap_or(self.config.vsync);
+        // a mode chosen for the rom is not second-guessed by the quirk database
+        self.config.detect_quirks =
+            self.detect_quirks_without_profile && profile.compat_mode.is_none();
+        if clock_hz != self.clock_hz {
+            self.set_clock_hz(clock_hz)?;
+        }
+        if (color1, color2) != (self.config.color1, self.config.color2) {
+            self.config.color1 = color1;
+            self.config.color2 = color2;
+            self.display.set_colors(color1, color2);
+        }
+        self.config.compat_mode = compat_mode;
+        // the mode might also have been switched by the quirk database or a header
+        if compat_mode != self.chip8.cpu.compatibility_mode() {
+            self.chip8
+                .cpu
+                .set_compatibility_mode_at_runtime(compat_mode);
+        }
+        // the quirk database is not consulted for this rom, so it cannot undo its own
+        // quirks either
+        if !self.config.detect_quirks && self.known_quirks_applied {
+            self.chip8
+                .cpu
+                .set_vf_result_not_flag(self.config.vf_result_not_flag);
+            self.known_quirks_applied = false;
+        }
+        Ok(())
+    }
+
+    // resets the machine with the rom just loaded and applies the quirks and labels
+    fn prepare_rom(&mut self, info: &RomInfo) -> Result<(), ChipottoError> {
+        self.chip8.reset();
+        // the calls of the previous rom do not belong in the report of this one
+        if let Some(call_graph) = &self.call_graph {
+            call_graph.borrow_mut().clear();
+        }
+        let headered = info.format == RomFormat::OctoHeadered;
+        if self.header_mode_applied && !headered {
+            self.chip8
+                .cpu
+                .set_compatibility_mode_at_runtime(self.config.compat_mode);
+        }
+        self.header_mode_applied = headered;
+        if self.config.detect_quirks {
+            self.apply_known_quirks(info);
+        }
+        // a SUPER-CHIP rom runs in CHIP-8 mode without any error, only wrongly
+        if self.chip8.cpu.compatibility_mode() == CompatibilityMode::Chip8
+            && uses_superchip_instructions(self.chip8.rom())
+        {
+            eprintln!(
+                "ROM appears to use SUPER-CHIP instructions; consider --compat-mode superchip"
+            );
+        }
+        self.log_change(StepChange::LoadRom(self.chip8.rom().to_vec()));
+        self.log_change(StepChange::Quirks(self.chip8.step_quirks()));
+        Ok(())
+    }
+
+    fn load_rom_annotations(&mut self) -> Result<(), ChipottoError> {
+        if let Some(annotations_path) = &self.config.load_annotations {
+            for (address, label) in load_annotations(annotations_path)? {
+                self.chip8.memory.annotate(address, &label);
+            }
+        }
+        if let Some(symbols_path) = &self.config.load_symbols {
+            load_symbols(symbols_path, &mut self.chip8.memory)?;
+        }
+        Ok(())
+    }
+
+    // records a change made between two cycles, so that replaying the step log makes it at
+    // the same point
+    fn log_change(&mut self, change: StepChange) {
+        if let Some(step_log) = self.step_log.as_mut() {
+            step_log.push_event(self.frame_cycles, change);
+        }
+    }
+
+    // a rom missing from the database goes back to the configured quirks if the rom before
+    // it was found, and otherwise keeps the mode it runs in. the mode of an XO-CHIP header
+    // is never overridden
+    fn apply_known_quirks(&mut self, info: &RomInfo) {
+        let headered = info.format == RomFormat::OctoHeadered;
+        let (title, quirks) = match QuirkDatabase::identify(self.chip8.rom()) {
+            Some(known) => (known.title, known.quirks),
+            None => {
+                if self.known_quirks_applied {
+                    if !headered {
+                        self.chip8
+                            .cpu
+                            .set_compatibility_mode_at_runtime(self.config.compat_mode);
+                    }
+                    self.chip8
+                        .cpu
+                        .set_vf_result_not_flag(self.config.vf_result_not_flag);
+                    self.known_quirks_applied = false;
+                }
+                return;
+            }
+        };
+        eprintln!("Detected {}; applying known-good quirk set", title);
+        if !headered {
+            self.chip8
+                .cpu
+                .set_compatibility_mode_at_runtime(quirks.compat_mode);
+        }
+        self.known_quirks_applied = true;
+        self.chip8
+            .cpu
+            .set_vf_result_not_flag(quirks.vf_result_not_flag || self.config.vf_result_not_flag);
+    }
+
+    // resets the chip and loads the next rom of the playlist, going back to the first one
+    // after the last in loop mode
+    pub fn advance_playlist(&mut self) -> Result<(), ChipottoError> {
+        if self.config.playlist.is_empty() {
+            return Ok(());
+        }
+        let next = self.playlist_index + 1;
+        if next == self.config.playlist.len() && self.config.playlist_mode == PlaylistMode::Once {
+            self.playlist_ended = true;
+            return Ok(());
+        }
+        self.playlist_index = next % self.config.playlist.len();
+        let path = self.config.playlist[self.playlist_index].clone();
+        self.load_rom_file(&path)
+    }
+
+    fn handle_menu(&mut self, action: MenuAction) {
+        let result = match action {
+            MenuAction::OpenRom => self.open_rom_dialog(),
+            // a restarted game should not replay the same random numbers
+            MenuAction::Reset => {
+                self.chip8.reset_keep_seed();
+                self.log_change(StepChange::Reset);
+                Ok(())
+            }
+            MenuAction::RecentRom(i) => match self.recent_roms.paths().get(i) {
+                Some(path) => self.load_rom_file(&path.clone()),
+                None => Ok(()),
+            },
+        };
+        // a rom that fails to load should not bring down the whole application
+        if let Err(err) = result {
+            eprintln!("Could not load rom: {}", err);
+        }
+    }
+
+    #[cfg(feature = "native-dialogs")]
+    fn open_rom_dialog(&mut self) -> Result<(), ChipottoError> {
+        match rfd::FileDialog::new()
+            .add_filter("CHIP-8 ROM", &["ch8"])
+            .pick_file()
+        {
+            Some(path) => self.load_rom_file(&path),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "native-dialogs"))]
+    fn open_rom_dialog(&mut self) -> Result<(), ChipottoError> {
+        Ok(())
+    }
+
+    fn handle_action(&mut self, action: Action) -> Result<(), ChipottoError> {
+        match action {
+            Action::Pause => match self.state {
+                EmulatorState::Running => self.transition_to(EmulatorState::Paused)?,
+                _ => self.transition_to(EmulatorState::Running)?,
+            },
+            Action::Step => {
+                self.transition_to(EmulatorState::Debugging)?;
+                // already printed by the cycle when printing the opcodes
+                let pc = self.chip8.cpu.pc();
+                match self.chip8.memory.try_read_word(pc) {
+                    Ok(opcode) if !self.config.print_opcodes => {
+                        eprintln!("{}", self.annotated_opcode(pc, opcode))
+                    }
+                    _ => {}
+                }
+                self.execute_cycle()?;
+                eprintln!("{}", self.chip8.cpu.reg_trace());
+            }
+            Action::Reset => self.handle_menu(MenuAction::Reset),
+            Action::SpeedUp => self.set_clock_hz(self.clock_hz.saturating_mul(2))?,
+            Action::SlowDown => self.set_clock_hz((self.clock_hz / 2).max(1))?,
+            Action::CycleCompatibilityMode => self.cycle_compatibility_mode(),
+            Action::Screenshot => self.save_screenshot()?,
+        }
+        Ok(())
+    }
+
+    // named after the rom and the time, in seconds, so that screenshots do not overwrite
+    // each other
+    fn save_screenshot(&self) -> Result<(), ChipottoError> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = PathBuf::from(format!("{}-{}.bmp", self.rom_name, secs));
+        let rgb = |(r, g, b): (u8, u8, u8)| (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        let bmp = self
             .chip8
             .frame_buffer
-            .get_buffer()
-            .to_vec()
-            .iter()
-            .map(|b| match b {
-                0 => from_u8_rgb(
-                    self.config.color1.0,
-                    self.config.color1.1,
-                    self.config.color1.2,
-                ),
-                1 => from_u8_rgb(
-                    self.config.color2.0,
-                    self.config.color2.1,
-                    self.config.color2.2,
-                ),
-                _ => unreachable!("no such value should exist in the frame buffer"),
-            })
-            .collect();
-        window
-            .update_with_buffer(buffer.as_slice(), WINDOW_WIDTH, WINDOW_HEIGHT)
-            .unwrap();
+            .to_bmp(rgb(self.config.color1), rgb(self.config.color2));
+        fs::write(&path, bmp)?;
+        eprintln!("Saved screenshot to {}", path.display());
+        Ok(())
     }
+
+    pub fn hotkey_map(&self) -> &HotkeyMap {
+        &self.config.hotkeys
+    }
+
+    // some roms only reveal the quirks they expect once they misbehave, so the mode
+    // can be switched without restarting the rom
+    fn cycle_compatibility_mode(&mut self) {
+        let mode = self.chip8.cpu.compatibility_mode().next();
+        self.chip8.cpu.set_compatibility_mode_at_runtime(mode);
+        self.log_change(StepChange::Quirks(self.chip8.step_quirks()));
+    }
+
+    fn update_title(&mut self, fps: u32) {
+        let values = [
+            ("rom", self.rom_name.clone()),
+            ("clock", self.clock_hz.to_string()),
+            ("fps", fps.to_string()),
+            ("cycles", self.chip8.metrics().cycles_total.to_string()),
+            ("pc", format!("{:04X}", self.chip8.cpu.pc())),
+            ("mode", self.chip8.cpu.compatibility_mode().to_string()),
+        ];
+        let mut title = render_title(&self.title_template, &values);
+        if self.turbo {
+            title.push_str(" TURBO");
+        }
+        if !matches!(self.state, EmulatorState::Running) {
+            title.push_str(&format!(" [{}]", self.state));
+        }
+        if title != self.title {
+            self.display.set_title(&title);
+            self.title = title;
+        }
+    }
+
+    fn handle_keypad(&mut self) {
+        let mut keypad = Keypad::default();
+        self.display.handle_keys(&mut keypad);
+        self.chip8.set_keys_from_bitmask(keypad.as_bitmask());
+    }
+
+    fn refresh_screen(&mut self) -> Result<(), ChipottoError> {
+        let buffer = self.chip8.frame_buffer.get_buffer();
+        if self.config.grid {
+            let buffer = grid_overlay(buffer, BUFFER_WIDTH);
+            return self.display.render(&buffer, BUFFER_WIDTH, BUFFER_HEIGHT);
+        }
+        self.display.render(buffer, BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    // the writes are tracked every frame so that they fade out in frames, but the window
+    // is only redrawn at 10 Hz. closing it leaves the emulator running
+    fn refresh_memory_window(&mut self, frame_number: u64) -> Result<(), ChipottoError> {
+        let (window, view) = match self.memory_window.as_mut() {
+            Some(memory_window) => memory_window,
+            None => return Ok(()),
+        };
+        let memory = self.chip8.memory.dump_all();
+        view.track_writes(memory);
+        if frame_number % MEMORY_WINDOW_FRAMES != 0 {
+            return Ok(());
+        }
+        let state = self.chip8.cpu.state();
+        window.update(view.render(memory, state.pc, state.i))?;
+        if !window.is_open() {
+            self.memory_window = None;
+        }
+        Ok(())
+    }
+}
+
+// every 8th row and column of the background turns into the grid color, matching the
+// width of a sprite row. lit pixels are left alone so that the sprites stay readable
+fn grid_overlay(buffer: &[u8], width: usize) -> Vec<u8> {
+    buffer
+        .iter()
+        .enumerate()
+        .map(|(i, &pixel)| {
+            let on_grid = (i % width) % 8 == 0 || (i / width) % 8 == 0;
+            if pixel == 0 && on_grid {
+                GRID_PIXEL
+            } else {
+                pixel
+            }
+        })
+        .collect()
+}
+
+// replaces each {name} in the template with its value, unknown placeholders are kept as they are
+fn render_title(template: &str, values: &[(&str, String)]) -> String {
+    let mut title = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        title.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let placeholder = after.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &after[..end])
+                .map(|(_, value)| (end, value))
+        });
+        match placeholder {
+            Some((end, value)) => {
+                title.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                title.push('{');
+                rest = after;
+            }
+        }
+    }
+    title.push_str(rest);
+    title
 }
 
-fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
-    let (r, g, b) = (r as u32, g as u32, b as u32);
-    (r << 16) | (g << 8) | b
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::TurboKey;
+    use crate::timing::FakeClock;
+
+    // a window that is shown a number of frames, each taking a whole frame of the fake
+    // clock as vsync would
+    struct FakeDisplay {
+        clock: FakeClock,
+        frames_left: u32,
+    }
+
+    impl DisplayBackend for FakeDisplay {
+        fn render(&mut self, _: &[u8], _: usize, _: usize) -> Result<(), ChipottoError> {
+            self.clock
+                .advance(Duration::from_micros(REFRESH_INTERVAL_MICROS));
+            self.frames_left -= 1;
+            Ok(())
+        }
+
+        fn is_open(&self) -> bool {
+            self.frames_left > 0
+        }
+
+        fn handle_keys(&self, _: &mut Keypad) {}
+
+        fn is_turbo_key_down(&self, _: TurboKey) -> bool {
+            false
+        }
+
+        fn host_keys_pressed(&mut self) -> Vec<HostKey> {
+            Vec::new()
+        }
+
+        fn set_title(&mut self, _: &str) {}
+
+        fn limit_update_rate(&mut self, _: Option<Duration>) {}
+    }
+
+    #[test]
+    fn test_cycles_per_frame_with_vsync() {
+        // LD V0, 1 then JP 0x200
+        let path = std::env::temp_dir().join(format!("chipotto_app_{}.ch8", std::process::id()));
+        fs::write(&path, [0x60, 0x01, 0x12, 0x00]).unwrap();
+        let clock = FakeClock::new();
+        let display = FakeDisplay {
+            clock: clock.clone(),
+            frames_left: 6,
+        };
+        let config = Config::new(Some(path.clone()))
+            .seed(0)
+            .clock_hz(600)
+            .vsync(true);
+        let mut app = Chip8App::new(config, display);
+        app.recent_roms = RecentRoms::in_memory();
+        app.set_clock(Box::new(clock));
+        let result = app.run();
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        // 600Hz is 10 cycles in each of the 6 frames
+        assert_eq!(60, app.chip8.metrics().cycles_total);
+    }
+
+    #[test]
+    fn test_grid_overlay() {
+        let mut buffer = vec![0; 16 * 10];
+        buffer[0] = 1;
+        buffer[9] = 1;
+        let overlaid = grid_overlay(&buffer, 16);
+        // the first row is all grid, except for the lit pixel at its start
+        assert_eq!(1, overlaid[0]);
+        assert_eq!(GRID_PIXEL, overlaid[1]);
+        assert_eq!(1, overlaid[9]);
+        assert_eq!(GRID_PIXEL, overlaid[16]);
+        assert_eq!(0, overlaid[17]);
+        assert_eq!(GRID_PIXEL, overlaid[16 + 8]);
+        assert_eq!(GRID_PIXEL, overlaid[8 * 16 + 3]);
+        assert_eq!(0, overlaid[9 * 16 + 3]);
+    }
+
+    #[test]
+    fn test_render_title() {
+        let values = [
+            ("rom", "Pong".to_string()),
+            ("clock", "500".to_string()),
+            ("fps", "60".to_string()),
+            ("cycles", "1234".to_string()),
+            ("pc", "0200".to_string()),
+        ];
+        assert_eq!(
+            "Pong @ 500Hz | 60 fps",
+            render_title("{rom} @ {clock}Hz | {fps} fps", &values)
+        );
+        assert_eq!(
+            "1234 cycles, PC={pc {unknown} 0200}",
+            render_title("{cycles} cycles, PC={pc {unknown} {pc}}", &values)
+        );
+    }
 }
@@ -0,0 +1,600 @@
+use crate::core::instr::Instr;
+use crate::core::memory::ROM_START_ADDRESS;
+use crate::disasm::mnemonic_and_operands;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// how deep #include can nest, which also stops files that include themselves
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub enum PreprocessorError {
+    Io(PathBuf, io::Error),
+    IncludeDepth(PathBuf),
+    // the line number, starting from 1, and the directive missing its argument
+    MissingArgument(usize, String),
+    UnmatchedElse(usize),
+    UnmatchedEndif(usize),
+    UnterminatedIf,
+}
+
+impl Display for PreprocessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessorError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            PreprocessorError::IncludeDepth(path) => write!(
+                f,
+                "{}: includes nested more than {} levels deep",
+                path.display(),
+                MAX_INCLUDE_DEPTH
+            ),
+            PreprocessorError::MissingArgument(line, directive) => {
+                write!(f, "line {}: {} expects a name", line, directive)
+            }
+            PreprocessorError::UnmatchedElse(line) => {
+                write!(f, "line {}: #else without #ifdef", line)
+            }
+            PreprocessorError::UnmatchedEndif(line) => {
+                write!(f, "line {}: #endif without #ifdef", line)
+            }
+            PreprocessorError::UnterminatedIf => write!(f, "#ifdef without #endif"),
+        }
+    }
+}
+
+impl Error for PreprocessorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PreprocessorError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// an open #ifdef or #ifndef block
+struct Condition {
+    parent_active: bool,
+    holds: bool,
+    in_else: bool,
+}
+
+impl Condition {
+    fn active(&self) -> bool {
+        self.parent_active && self.holds != self.in_else
+    }
+}
+
+// runs on the source before the assembler reads it: expands #define macros, pastes
+// #include files and drops the lines of #ifdef and #ifndef blocks whose condition fails.
+// lines starting with # that are not directives, like octo comments, are kept. included
+// paths are relative to the directory of the file including them
+pub struct Preprocessor {
+    defines: HashMap<String, String>,
+}
+
+impl Preprocessor {
+    // the includes of a source that does not come from a file are relative to the current
+    // directory
+    pub fn process(
+        source: &str,
+        defines: &HashMap<String, String>,
+    ) -> Result<String, PreprocessorError> {
+        let mut preprocessor = Preprocessor {
+            defines: defines.clone(),
+        };
+        let mut output = String::new();
+        preprocessor.expand(source, Path::new(""), 0, &mut output)?;
+        Ok(output)
+    }
+
+    pub fn process_file(
+        path: &Path,
+        defines: &HashMap<String, String>,
+    ) -> Result<String, PreprocessorError> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| PreprocessorError::Io(path.to_path_buf(), err))?;
+        let mut preprocessor = Preprocessor {
+            defines: defines.clone(),
+        };
+        let mut output = String::new();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        preprocessor.expand(&source, dir, 0, &mut output)?;
+        Ok(output)
+    }
+
+    fn expand(
+        &mut self,
+        source: &str,
+        dir: &Path,
+        depth: usize,
+        output: &mut String,
+    ) -> Result<(), PreprocessorError> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let active = conditions.last().map_or(true, Condition::active);
+            let trimmed = line.trim();
+            let directive = trimmed.split_whitespace().next().unwrap_or("");
+            let argument = trimmed[directive.len()..].trim();
+            let name = || {
+                argument
+                    .split_whitespace()
+                    .next()
+                    .ok_or_else(|| PreprocessorError::MissingArgument(i + 1, directive.into()))
+            };
+            match directive {
+                "#ifdef" | "#ifndef" => {
+                    let defined = self.defines.contains_key(name()?);
+                    conditions.push(Condition {
+                        parent_active: active,
+                        holds: defined == (directive == "#ifdef"),
+                        in_else: false,
+                    });
+                }
+                "#else" => match conditions.last_mut() {
+                    Some(condition) if !condition.in_else => condition.in_else = true,
+                    _ => return Err(PreprocessorError::UnmatchedElse(i + 1)),
+                },
+                "#endif" => {
+                    conditions
+                        .pop()
+                        .ok_or(PreprocessorError::UnmatchedEndif(i + 1))?;
+                }
+                _ if !active => {}
+                "#define" => {
+                    let name = name()?;
+                    let value = argument[name.len()..].trim();
+                    self.defines.insert(name.to_string(), value.to_string());
+                }
+                "#include" => {
+                    let path = dir.join(argument.trim_matches('"'));
+                    if argument.is_empty() {
+                        return Err(PreprocessorError::MissingArgument(i + 1, directive.into()));
+                    }
+                    if depth == MAX_INCLUDE_DEPTH {
+                        return Err(PreprocessorError::IncludeDepth(path));
+                    }
+                    let contents = fs::read_to_string(&path)
+                        .map_err(|err| PreprocessorError::Io(path.clone(), err))?;
+                    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                    self.expand(&contents, dir, depth + 1, output)?;
+                }
+                _ => {
+                    output.push_str(&self.substitute(line));
+                    output.push('\n');
+                }
+            }
+        }
+        if conditions.is_empty() {
+            Ok(())
+        } else {
+            Err(PreprocessorError::UnterminatedIf)
+        }
+    }
+
+    // replaces the words that name a macro with its value
+    fn substitute(&self, line: &str) -> String {
+        let mut result = String::with_capacity(line.len());
+        let mut word = String::new();
+        for c in line.chars().chain(std::iter::once('\n')) {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                word.push(c);
+                continue;
+            }
+            result.push_str(self.defines.get(&word).unwrap_or(&word));
+            word.clear();
+            result.push(c);
+        }
+        result.pop();
+        result
+    }
+}
+
+// the columns of the formatted source, counted from 0
+const MNEMONIC_COLUMN: usize = 8;
+const OPERANDS_COLUMN: usize = 16;
+const COMMENT_COLUMN: usize = 40;
+
+// lays out instructions as assembly source: the labels from `symbols` on their own line
+// at the left margin, the mnemonics indented, then the operands and, as a comment, the
+// address of the instruction each in their own column. jump and call targets that have
+// a label are written by name. the output only depends on the instructions and the
+// symbols, so formatting the same listing twice gives the same text
+pub struct Formatter;
+
+impl Formatter {
+    pub fn format(instrs: &[(u16, Instr)], symbols: &HashMap<u16, String>) -> String {
+        let mut output = String::new();
+        for &(address, instr) in instrs {
+            if let Some(label) = symbols.get(&address) {
+                writeln!(output, "{}:", label).unwrap();
+            }
+            let (mnemonic, operands) = mnemonic_and_operands(instr, symbols);
+            let mut line = format!("{:indent$}{}", "", mnemonic, indent = MNEMONIC_COLUMN);
+            pad_to(&mut line, OPERANDS_COLUMN);
+            line.push_str(&operands);
+            pad_to(&mut line, COMMENT_COLUMN);
+            writeln!(output, "{}; {:04X}", line, address).unwrap();
+        }
+        output
+    }
+}
+
+// at least one space, so that a long field never runs into the next one
+fn pad_to(line: &mut String, column: usize) {
+    let width = column.max(line.len() + 1);
+    line.extend(std::iter::repeat(' ').take(width - line.len()));
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AssemblerError {
+    // the line number, starting from 1, and the text that could not be read
+    InvalidInstruction(usize, String),
+    UnknownLabel(usize, String),
+    DuplicateLabel(usize, String),
+}
+
+impl Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblerError::InvalidInstruction(line, text) => {
+                write!(f, "line {}: invalid instruction {}", line, text)
+            }
+            AssemblerError::UnknownLabel(line, label) => {
+                write!(f, "line {}: unknown label {}", line, label)
+            }
+            AssemblerError::DuplicateLabel(line, label) => {
+                write!(f, "line {}: label {} is already defined", line, label)
+            }
+        }
+    }
+}
+
+impl Error for AssemblerError {}
+
+// an operand as `Instr` displays it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    V(usize),
+    Value(u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Audio,
+    Pitch,
+}
+
+// reads back what `Formatter` writes: one instruction per line in the syntax `Instr`
+// displays, labels followed by a colon on their own line and comments after a
+// semicolon. numbers are decimal unless they start with 0x, and the first instruction
+// is placed at the start of the rom
+pub struct Assembler;
+
+impl Assembler {
+    pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+        // first pass: give the labels the address of the instruction following them
+        let mut labels = HashMap::new();
+        let mut lines = Vec::new();
+        let mut address = ROM_START_ADDRESS;
+        for (i, line) in source.lines().enumerate() {
+            let code = line.split(';').next().unwrap_or("").trim();
+            if let Some(label) = code.strip_suffix(':') {
+                if labels.insert(label.trim().to_string(), address).is_some() {
+                    return Err(AssemblerError::DuplicateLabel(i + 1, label.into()));
+                }
+            } else if !code.is_empty() {
+                lines.push((i + 1, code));
+                address = address.wrapping_add(2);
+            }
+        }
+
+        // second pass: encode the instructions now that every label is known
+        let mut rom = Vec::with_capacity(lines.len() * 2);
+        for (line, code) in lines {
+            let instr = parse_instr(line, code, &labels)?;
+            rom.extend_from_slice(&encode(instr).to_be_bytes());
+        }
+        Ok(rom)
+    }
+}
+
+fn parse_instr(
+    line: usize,
+    code: &str,
+    labels: &HashMap<String, u16>,
+) -> Result<Instr, AssemblerError> {
+    let (mnemonic, operands) = match code.find(char::is_whitespace) {
+        Some(space) => (&code[..space], code[space..].trim()),
+        None => (code, ""),
+    };
+    let operands = operands
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .map(|operand| {
+            parse_operand(operand, labels)
+                .ok_or_else(|| AssemblerError::UnknownLabel(line, operand.into()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let byte = |value: u16| value <= 0xFF;
+    let address = |value: u16| value <= 0xFFF;
+    use Operand::*;
+    let instr = match (mnemonic.to_ascii_uppercase().as_str(), operands.as_slice()) {
+        ("CLS", []) => Instr::Cls,
+        ("RET", []) => Instr::Ret,
+        ("HALT", []) => Instr::Halt,
+        ("SYS", &[Value(nnn)]) if address(nnn) => Instr::Sys(nnn),
+        ("JP", &[Value(nnn)]) if address(nnn) => Instr::Jp(nnn),
+        ("JP", &[V(0), Value(nnn)]) if address(nnn) => Instr::JpV0(nnn),
+        ("CALL", &[Value(nnn)]) if address(nnn) => Instr::Call(nnn),
+        ("SE", &[V(x), Value(kk)]) if byte(kk) => Instr::SeVxKK(x, kk as u8),
+        ("SE", &[V(x), V(y)]) => Instr::SeVxVy(x, y),
+        ("SNE", &[V(x), Value(kk)]) if byte(kk) => Instr::SneVxKK(x, kk as u8),
+        ("SNE", &[V(x), V(y)]) => Instr::SneVxVy(x, y),
+        ("SLT", &[V(x), V(y)]) => Instr::SeLtVxVy(x, y),
+        ("SGT", &[V(x), V(y)]) => Instr::SeGtVxVy(x, y),
+        ("LD", &[V(x), Value(kk)]) if byte(kk) => Instr::LdVxKK(x, kk as u8),
+        ("LD", &[V(x), V(y)]) => Instr::LdVxVy(x, y),
+        ("LD", &[I, Value(nnn)]) if address(nnn) => Instr::LdI(nnn),
+        ("LD", &[V(x), Dt]) => Instr::LdVxDT(x),
+        ("LD", &[V(x), K]) => Instr::LdVxK(x),
+        ("LD", &[Dt, V(x)]) => Instr::LdDTVx(x),
+        ("LD", &[St, V(x)]) => Instr::LdSTVx(x),
+        ("LD", &[F, V(x)]) => Instr::LdFVx(x),
+        ("LD", &[B, V(x)]) => Instr::LdBVx(x),
+        ("LD", &[I, V(x)]) | ("LD", &[IndirectI, V(x)]) => Instr::LdIVx(x),
+        ("LD", &[V(x), I]) | ("LD", &[V(x), IndirectI]) => Instr::LdVxI(x),
+        ("LD", &[Audio, IndirectI]) => Instr::StoreAudioPattern,
+        ("LD", &[Pitch, V(x)]) => Instr::LdPitchVx(x),
+        ("ADD", &[V(x), Value(kk)]) if byte(kk) => Instr::AddVxKK(x, kk as u8),
+        ("ADD", &[V(x), V(y)]) => Instr::AddVxVy(x, y),
+        ("ADD", &[I, V(x)]) => Instr::AddIVx(x),
+        ("OR", &[V(x), V(y)]) => Instr::OrVxVy(x, y),
+        ("AND", &[V(x), V(y)]) => Instr::AndVxVy(x, y),
+        ("XOR", &[V(x), V(y)]) => Instr::XorVxVy(x, y),
+        ("SUB", &[V(x), V(y)]) => Instr::SubVxVy(x, y),
+        ("SUBN", &[V(x), V(y)]) => Instr::SubnVxVy(x, y),
+        ("SHR", &[V(x), V(y)]) => Instr::ShrVx(x, y),
+        ("SHL", &[V(x), V(y)]) => Instr::ShlVx(x, y),
+        ("RND", &[V(x), Value(kk)]) if byte(kk) => Instr::RndVxKK(x, kk as u8),
+        ("DRW", &[V(x), V(y), Value(n)]) if n <= 0xF => Instr::DrwVxVyN(x, y, n as usize),
+        ("SKP", &[V(x)]) => Instr::SkpVx(x),
+        ("SKPN", &[V(x)]) => Instr::SknpVx(x),
+        _ => return Err(AssemblerError::InvalidInstruction(line, code.into())),
+    };
+    Ok(instr)
+}
+
+// None for a word that is neither a register, a number nor a known label
+fn parse_operand(text: &str, labels: &HashMap<String, u16>) -> Option<Operand> {
+    let operand = match text.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        "AUDIO" => Operand::Audio,
+        "PITCH" => Operand::Pitch,
+        upper => {
+            // `Instr` writes the registers in decimal, but V0 to VF is the usual spelling
+            let register = upper.strip_prefix('V').and_then(|x| {
+                x.parse::<usize>()
+                    .ok()
+                    .or_else(|| usize::from_str_radix(x, 16).ok())
+                    .filter(|&x| x < 16)
+            });
+            if let Some(x) = register {
+                return Some(Operand::V(x));
+            }
+            let value = match upper.strip_prefix("0X") {
+                Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                None => text.parse().ok(),
+            };
+            return value
+                .or_else(|| labels.get(text).copied())
+                .map(Operand::Value);
+        }
+    };
+    Some(operand)
+}
+
+fn encode(instr: Instr) -> u16 {
+    let x = |x: usize| (x as u16) << 8;
+    let xy = |x: usize, y: usize| ((x as u16) << 8) | ((y as u16) << 4);
+    let xkk = |x: usize, kk: u8| ((x as u16) << 8) | kk as u16;
+    match instr {
+        Instr::Cls => 0x00E0,
+        Instr::Ret => 0x00EE,
+        Instr::Sys(nnn) => nnn,
+        Instr::Jp(nnn) => 0x1000 | nnn,
+        Instr::Call(nnn) => 0x2000 | nnn,
+        Instr::SeVxKK(vx, kk) => 0x3000 | xkk(vx, kk),
+        Instr::SneVxKK(vx, kk) => 0x4000 | xkk(vx, kk),
+        Instr::SeVxVy(vx, vy) => 0x5000 | xy(vx, vy),
+        Instr::SeLtVxVy(vx, vy) => 0x5001 | xy(vx, vy),
+        Instr::SeGtVxVy(vx, vy) => 0x5002 | xy(vx, vy),
+        Instr::LdVxKK(vx, kk) => 0x6000 | xkk(vx, kk),
+        Instr::AddVxKK(vx, kk) => 0x7000 | xkk(vx, kk),
+        Instr::LdVxVy(vx, vy) => 0x8000 | xy(vx, vy),
+        Instr::OrVxVy(vx, vy) => 0x8001 | xy(vx, vy),
+        Instr::AndVxVy(vx, vy) => 0x8002 | xy(vx, vy),
+        Instr::XorVxVy(vx, vy) => 0x8003 | xy(vx, vy),
+        Instr::AddVxVy(vx, vy) => 0x8004 | xy(vx, vy),
+        Instr::SubVxVy(vx, vy) => 0x8005 | xy(vx, vy),
+        Instr::ShrVx(vx, vy) => 0x8006 | xy(vx, vy),
+        Instr::SubnVxVy(vx, vy) => 0x8007 | xy(vx, vy),
+        Instr::ShlVx(vx, vy) => 0x800E | xy(vx, vy),
+        Instr::SneVxVy(vx, vy) => 0x9000 | xy(vx, vy),
+        Instr::LdI(nnn) => 0xA000 | nnn,
+        Instr::JpV0(nnn) => 0xB000 | nnn,
+        Instr::RndVxKK(vx, kk) => 0xC000 | xkk(vx, kk),
+        Instr::DrwVxVyN(vx, vy, n) => 0xD000 | xy(vx, vy) | n as u16,
+        Instr::SkpVx(vx) => 0xE09E | x(vx),
+        Instr::SknpVx(vx) => 0xE0A1 | x(vx),
+        Instr::StoreAudioPattern => 0xF002,
+        Instr::LdVxDT(vx) => 0xF007 | x(vx),
+        Instr::LdVxK(vx) => 0xF00A | x(vx),
+        Instr::LdDTVx(vx) => 0xF015 | x(vx),
+        Instr::LdSTVx(vx) => 0xF018 | x(vx),
+        Instr::AddIVx(vx) => 0xF01E | x(vx),
+        Instr::LdFVx(vx) => 0xF029 | x(vx),
+        Instr::LdBVx(vx) => 0xF033 | x(vx),
+        Instr::LdPitchVx(vx) => 0xF03A | x(vx),
+        Instr::LdIVx(vx) => 0xF055 | x(vx),
+        Instr::LdVxI(vx) => 0xF065 | x(vx),
+        Instr::Halt => 0xFFFF,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::instr::InstrIter;
+
+    fn defines(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_define_and_ifdef() {
+        let source = "#define SPEED 3\n\
+                      v0 := SPEED\n\
+                      #ifdef DEBUG\n\
+                      v1 := LIVES\n\
+                      #else\n\
+                      v1 := 0\n\
+                      #endif\n\
+                      # octo comment\n";
+        assert_eq!(
+            "v0 := 3\nv1 := 0\n# octo comment\n",
+            Preprocessor::process(source, &HashMap::new()).unwrap()
+        );
+        let debug = defines(&[("DEBUG", ""), ("LIVES", "5")]);
+        assert_eq!(
+            "v0 := 3\nv1 := 5\n# octo comment\n",
+            Preprocessor::process(source, &debug).unwrap()
+        );
+        assert!(matches!(
+            Preprocessor::process("#endif", &debug),
+            Err(PreprocessorError::UnmatchedEndif(1))
+        ));
+        assert!(matches!(
+            Preprocessor::process("#ifndef DEBUG\n", &debug),
+            Err(PreprocessorError::UnterminatedIf)
+        ));
+    }
+
+    #[test]
+    fn test_include() {
+        let path = std::env::temp_dir().join(format!("chipotto_include_{}.8o", std::process::id()));
+        fs::write(&path, "#define SPRITE 0x300\ni := SPRITE\n").unwrap();
+        let source = format!("#include \"{}\"\nsprite v0 v1 5\n", path.display());
+        let result = Preprocessor::process(&source, &HashMap::new());
+
+        fs::write(&path, format!("#include {}\n", path.display())).unwrap();
+        let recursive = Preprocessor::process(&source, &HashMap::new());
+        fs::remove_file(&path).unwrap();
+        assert_eq!("i := 0x300\nsprite v0 v1 5\n", result.unwrap());
+        assert!(matches!(recursive, Err(PreprocessorError::IncludeDepth(_))));
+    }
+
+    #[test]
+    fn test_include_relative_to_file() {
+        let dir = std::env::temp_dir().join(format!("chipotto_include_dir_{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("main.8o"),
+            "#include \"lib/sprites.8o\"\nsprite v0 v1 5\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/sprites.8o"),
+            "#include \"defines.8o\"\ni := SPRITE\n",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/defines.8o"), "#define SPRITE 0x300\n").unwrap();
+        let result = Preprocessor::process_file(&dir.join("main.8o"), &HashMap::new());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!("i := 0x300\nsprite v0 v1 5\n", result.unwrap());
+    }
+
+    #[test]
+    fn test_format() {
+        let instrs = [
+            (0x200, Instr::LdVxKK(0, 2)),
+            (0x202, Instr::Call(0x206)),
+            (0x204, Instr::Jp(0x204)),
+            (0x206, Instr::Ret),
+        ];
+        let mut symbols = HashMap::new();
+        symbols.insert(0x206, "draw".to_string());
+        assert_eq!(
+            "        LD      V0, 2                   ; 0200\n\
+             \x20       CALL    draw                    ; 0202\n\
+             \x20       JP      0x204                   ; 0204\n\
+             draw:\n\
+             \x20       RET                             ; 0206\n",
+            Formatter::format(&instrs, &symbols)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rom = [
+            0x00, 0xE0, // 0200: CLS
+            0x6A, 0x10, // 0202: LD VA, 16
+            0xA3, 0x00, // 0204: LD I, 0x300
+            0x22, 0x10, // 0206: CALL 0x210
+            0x3A, 0x20, // 0208: SE VA, 32
+            0x12, 0x08, // 020A: JP 0x208
+            0x8A, 0xBE, // 020C: SHL VA, VB
+            0xB2, 0x00, // 020E: JP V0, 0x200
+            0xDA, 0xB5, // 0210: DRW VA, VB, 5
+            0xF3, 0x33, // 0212: LD B, V3
+            0xF3, 0x55, // 0214: LD [I], V3
+            0xF3, 0x65, // 0216: LD V3, [I]
+            0xE1, 0xA1, // 0218: SKNP V1
+            0x00, 0xEE, // 021A: RET
+        ];
+        let instrs: Vec<(u16, Instr)> = InstrIter::new(&rom).collect();
+        let mut symbols = HashMap::new();
+        symbols.insert(0x208, "wait".to_string());
+        symbols.insert(0x210, "draw".to_string());
+        let source = Formatter::format(&instrs, &symbols);
+        let assembled = Assembler::assemble(&source).unwrap();
+        assert_eq!(rom.to_vec(), assembled);
+        // formatting what was assembled gives the same source
+        let reassembled: Vec<(u16, Instr)> = InstrIter::new(&assembled).collect();
+        assert_eq!(source, Formatter::format(&reassembled, &symbols));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(
+            Err(AssemblerError::InvalidInstruction(2, "LD V0, 256".into())),
+            Assembler::assemble("CLS\nLD V0, 256\n")
+        );
+        assert_eq!(
+            Err(AssemblerError::UnknownLabel(1, "start".into())),
+            Assembler::assemble("JP start\n")
+        );
+        assert_eq!(
+            Err(AssemblerError::DuplicateLabel(3, "start".into())),
+            Assembler::assemble("start:\nCLS\nstart:\n")
+        );
+    }
+}
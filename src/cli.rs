@@ -0,0 +1,439 @@
+use crate::config::DEFAULT_CLOCK_HZ;
+use crate::core::chip8::{Chip8, HaltReason, TIMERS_HZ};
+use crate::core::instr::Instr;
+use crate::error::ChipottoError;
+use crate::opcode_log::format_opcode;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// the commands of the shell with their arguments, in the order `help` lists them
+const COMMANDS: [(&str, &str); 12] = [
+    ("load", "load PATH"),
+    ("step", "step N"),
+    ("run", "run"),
+    ("pause", "pause"),
+    ("regs", "regs"),
+    ("mem", "mem ADDR LEN"),
+    ("fill", "fill ADDR LEN BYTE"),
+    ("breakpoint", "breakpoint ADDR"),
+    ("jump", "jump ADDR"),
+    ("disasm", "disasm ADDR LEN"),
+    ("help", "help"),
+    ("exit", "exit"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum ShellCommand {
+    Load(PathBuf),
+    // runs this many cycles
+    Step(u64),
+    Run,
+    Pause,
+    Regs,
+    // hex dump of LEN bytes
+    Mem(u16, u16),
+    // sets LEN bytes to BYTE
+    Fill(u16, u16, u8),
+    Breakpoint(u16),
+    // moves the program counter
+    Jump(u16),
+    // disassembles LEN instructions
+    Disasm(u16, u16),
+    Help,
+    Exit,
+}
+
+// addresses and bytes are hexadecimal, with or without a 0x prefix, and counts decimal
+impl FromStr for ShellCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        let usage = || {
+            let usage = COMMANDS.iter().find(|(command, _)| *command == name);
+            match usage {
+                Some((_, usage)) => format!("usage: {}", usage),
+                None => format!("unknown command '{}', see help", name),
+            }
+        };
+        let address = |arg: &str| {
+            let digits = arg.strip_prefix("0x").unwrap_or(arg);
+            u16::from_str_radix(digits, 16)
+                .ok()
+                .filter(|address| *address <= 0xFFF)
+        };
+        let byte = |arg: &str| u8::from_str_radix(arg.strip_prefix("0x").unwrap_or(arg), 16).ok();
+        let command = match (name, args.as_slice()) {
+            ("load", [path]) => Some(ShellCommand::Load(PathBuf::from(path))),
+            ("step", []) => Some(ShellCommand::Step(1)),
+            ("step", [n]) => n.parse().ok().map(ShellCommand::Step),
+            ("run", []) => Some(ShellCommand::Run),
+            ("pause", []) => Some(ShellCommand::Pause),
+            ("regs", []) => Some(ShellCommand::Regs),
+            ("mem", [addr, len]) => address(addr)
+                .zip(len.parse().ok())
+                .map(|(addr, len)| ShellCommand::Mem(addr, len)),
+            ("fill", [addr, len, value]) => match (address(addr), len.parse().ok(), byte(value)) {
+                (Some(addr), Some(len), Some(value)) => Some(ShellCommand::Fill(addr, len, value)),
+                _ => None,
+            },
+            ("breakpoint", [addr]) => address(addr).map(ShellCommand::Breakpoint),
+            ("jump", [addr]) => address(addr).map(ShellCommand::Jump),
+            ("disasm", [addr, len]) => address(addr)
+                .zip(len.parse().ok())
+                .map(|(addr, len)| ShellCommand::Disasm(addr, len)),
+            ("help", []) => Some(ShellCommand::Help),
+            ("exit", []) => Some(ShellCommand::Exit),
+            _ => None,
+        };
+        command.ok_or_else(usage)
+    }
+}
+
+// a headless emulator driven by the commands of the shell
+struct Shell {
+    chip8: Chip8,
+    running: bool,
+}
+
+impl Shell {
+    fn new() -> Self {
+        let mut chip8 = Chip8::new();
+        chip8.set_clock_hz(DEFAULT_CLOCK_HZ);
+        Shell {
+            chip8,
+            running: false,
+        }
+    }
+
+    // the text to print for the command
+    fn execute(&mut self, command: ShellCommand) -> String {
+        match command {
+            ShellCommand::Load(path) => {
+                self.running = false;
+                match self.chip8.load_rom_from_path(&path) {
+                    Ok(info) => {
+                        self.chip8.reset();
+                        format!("loaded {} bytes from {}", info.size, path.display())
+                    }
+                    Err(err) => format!("could not load {}: {}", path.display(), err),
+                }
+            }
+            ShellCommand::Step(n) => {
+                self.running = false;
+                for _ in 0..n {
+                    if let Err(err) = self.chip8.cpu_cycle() {
+                        return format!("error: {}\n{}", err, self.chip8.cpu.reg_trace());
+                    }
+                }
+                self.chip8.cpu.reg_trace()
+            }
+            ShellCommand::Run => {
+                self.running = true;
+                String::from("running, pause stops the program")
+            }
+            ShellCommand::Pause => {
+                self.running = false;
+                format!("paused\n{}", self.chip8.cpu.reg_trace())
+            }
+            ShellCommand::Regs => self.chip8.cpu.reg_trace(),
+            ShellCommand::Mem(address, len) => {
+                if address as usize + len as usize > self.chip8.memory.dump_all().len() {
+                    return String::from("the area goes past the end of memory");
+                }
+                self.chip8.memory.hexdump(address, len)
+            }
+            ShellCommand::Fill(address, len, byte) => {
+                match self.chip8.memory.fill(address, len, byte) {
+                    Ok(()) => format!("filled {} bytes at 0x{:03X}", len, address),
+                    Err(err) => format!("error: {}", err),
+                }
+            }
+            ShellCommand::Breakpoint(address) => {
+                self.chip8.add_breakpoint(address);
+                format!("breakpoint at 0x{:03X}", address)
+            }
+            // the address is checked like the jump instructions check theirs
+            ShellCommand::Jump(address) => match self.chip8.cpu.set_pc(address) {
+                Ok(()) => self.chip8.cpu.reg_trace(),
+                Err(err) => format!("error: {}", err),
+            },
+            ShellCommand::Disasm(address, len) => {
+                let mut listing = String::new();
+                for n in 0..len {
+                    let pc = address + 2 * n;
+                    match self.chip8.memory.read_word_safe(pc) {
+                        Some(opcode) => writeln!(listing, "{}", format_opcode(pc, opcode)).unwrap(),
+                        None => break,
+                    }
+                }
+                listing.trim_end().to_string()
+            }
+            ShellCommand::Help => {
+                let usages: Vec<&str> = COMMANDS.iter().map(|(_, usage)| *usage).collect();
+                format!(
+                    "{}\naddresses are hexadecimal and counts decimal",
+                    usages.join("\n")
+                )
+            }
+            ShellCommand::Exit => String::new(),
+        }
+    }
+
+    // runs the cycles of one frame, returning why the program stopped if it did
+    fn run_frame(&mut self) -> Option<String> {
+        let cycles = (DEFAULT_CLOCK_HZ / TIMERS_HZ).max(1);
+        let pc = self.chip8.cpu.pc();
+        let stopped = match self.chip8.run_until_halt(cycles) {
+            // a frame is too short for `run_until_halt` to notice a jump to itself
+            HaltReason::Timeout if self.jumps_to_itself(pc) => {
+                String::from("the program loops on itself")
+            }
+            HaltReason::Timeout => return None,
+            HaltReason::Breakpoint(address) => format!("breakpoint hit at 0x{:03X}", address),
+            HaltReason::Error(err) => format!("error: {}", err),
+            HaltReason::SelfLoop => String::from("the program loops on itself"),
+            HaltReason::Halt => String::from("the program halted"),
+        };
+        self.running = false;
+        Some(format!("{}\n{}", stopped, self.chip8.cpu.reg_trace()))
+    }
+
+    fn jumps_to_itself(&self, pc: u16) -> bool {
+        self.chip8.cpu.pc() == pc
+            && self.chip8.memory.read_word_safe(pc).and_then(Instr::from) == Some(Instr::Jp(pc))
+    }
+}
+
+// owns the emulator, so that a program keeps running between two commands. each command
+// gets one reply, and a program stopping on its own is printed right away
+fn emulator_thread(commands: Receiver<ShellCommand>, replies: Sender<String>) {
+    let mut shell = Shell::new();
+    let frame_duration = Duration::from_micros(1_000_000 / TIMERS_HZ);
+    loop {
+        let command = if shell.running {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else {
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            }
+        };
+        if let Some(command) = command {
+            if replies.send(shell.execute(command)).is_err() {
+                return;
+            }
+        }
+        if shell.running {
+            // waiting first also lets the reply of run be printed before the program stops
+            thread::sleep(frame_duration);
+            if let Some(stopped) = shell.run_frame() {
+                println!("\n{}", stopped);
+            }
+        }
+    }
+}
+
+// completes the command names, and the path of load
+struct ShellHelper {
+    filenames: FilenameCompleter,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before_cursor = &line[..pos];
+        if before_cursor.starts_with("load ") {
+            return self.filenames.complete(line, pos, ctx);
+        }
+        if before_cursor.contains(' ') {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = COMMANDS
+            .iter()
+            .filter(|(name, _)| name.starts_with(before_cursor))
+            .map(|(name, _)| Pair {
+                display: name.to_string(),
+                replacement: format!("{} ", name),
+            })
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config/chipotto/shell_history"))
+}
+
+// a prompt to load, run and inspect roms without a window, started with --shell. the history is kept in ~/.config/chipotto/shell_history
+pub fn interactive_shell() -> Result<(), ChipottoError> {
+    let (command_sender, commands) = mpsc::channel();
+    let (reply_sender, replies) = mpsc::channel();
+    thread::spawn(move || emulator_thread(commands, reply_sender));
+
+    let mut editor = Editor::<ShellHelper>::new();
+    editor.set_helper(Some(ShellHelper {
+        filenames: FilenameCompleter::new(),
+    }));
+    let history = history_path();
+    if let Some(path) = &history {
+        // there is no history before the first run
+        let _ = editor.load_history(path);
+    }
+    println!("chipotto shell, type help for the commands");
+    loop {
+        let line = match editor.readline("chipotto> ") {
+            Ok(line) => line,
+            // Ctrl-C only clears the line, like in a shell
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err).into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+        let command = match ShellCommand::from_str(&line) {
+            Ok(command) => command,
+            Err(usage) => {
+                println!("{}", usage);
+                continue;
+            }
+        };
+        if command == ShellCommand::Exit {
+            break;
+        }
+        command_sender
+            .send(command)
+            .map_err(|_| stopped_thread_error())?;
+        let reply = replies.recv().map_err(|_| stopped_thread_error())?;
+        if !reply.is_empty() {
+            println!("{}", reply);
+        }
+    }
+    if let Some(path) = &history {
+        save_history(&mut editor, path)?;
+    }
+    Ok(())
+}
+
+fn stopped_thread_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the emulator thread stopped")
+}
+
+fn save_history(editor: &mut Editor<ShellHelper>, path: &Path) -> Result<(), ChipottoError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    editor
+        .save_history(path)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Ok(ShellCommand::Step(10)), "step 10".parse());
+        assert_eq!(Ok(ShellCommand::Step(1)), "step".parse());
+        assert_eq!(Ok(ShellCommand::Mem(0x300, 16)), "mem 0x300 16".parse());
+        assert_eq!(
+            Ok(ShellCommand::Breakpoint(0x20A)),
+            "breakpoint 20a".parse()
+        );
+        assert_eq!(
+            Err(String::from("usage: disasm ADDR LEN")),
+            "disasm 0x200".parse::<ShellCommand>()
+        );
+        assert!("mem 0x1000 1".parse::<ShellCommand>().is_err());
+        assert_eq!(Ok(ShellCommand::Jump(0x200)), "jump 0x200".parse());
+        assert_eq!(
+            Ok(ShellCommand::Fill(0x300, 4, 0xAA)),
+            "fill 0x300 4 aa".parse()
+        );
+        assert!("fill 0x300 4 0x100".parse::<ShellCommand>().is_err());
+    }
+
+    #[test]
+    fn test_execute() {
+        let rom_path =
+            std::env::temp_dir().join(format!("chipotto_shell_{}.ch8", std::process::id()));
+        // 0x200: LD V0, 0x2A; 0x202: ADD V0, 1; 0x204: JP 0x202
+        fs::write(&rom_path, [0x60, 0x2A, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        let mut shell = Shell::new();
+        let loaded = shell.execute(ShellCommand::Load(rom_path.clone()));
+        fs::remove_file(&rom_path).unwrap();
+        assert!(loaded.starts_with("loaded 6 bytes"));
+
+        assert!(shell.execute(ShellCommand::Step(2)).contains("PC=0204"));
+        assert_eq!(
+            "[0200] 0x602A LD V0, 42\n[0202] 0x7001 ADD V0, 1",
+            shell.execute(ShellCommand::Disasm(0x200, 2))
+        );
+        assert_eq!(
+            "0x0200: 60 2A 70 01",
+            shell.execute(ShellCommand::Mem(0x200, 4))
+        );
+
+        shell.execute(ShellCommand::Breakpoint(0x204));
+        shell.execute(ShellCommand::Run);
+        let stopped = shell.run_frame().unwrap();
+        assert!(stopped.starts_with("breakpoint hit at 0x204"));
+        assert!(!shell.running);
+
+        assert!(shell.execute(ShellCommand::Jump(0x202)).contains("PC=0202"));
+        assert!(shell
+            .execute(ShellCommand::Jump(0x203))
+            .starts_with("error: "));
+        assert!(shell
+            .execute(ShellCommand::Jump(0x100))
+            .starts_with("error: "));
+
+        shell.execute(ShellCommand::Fill(0x300, 3, 0xAA));
+        assert_eq!(
+            "0x0300: AA AA AA 00",
+            shell.execute(ShellCommand::Mem(0x300, 4))
+        );
+        assert!(shell
+            .execute(ShellCommand::Fill(0xFFF, 2, 0))
+            .starts_with("error: "));
+    }
+}
@@ -0,0 +1,614 @@
+use crate::core::compat::CompatibilityMode;
+use crate::display::TurboKey;
+use color_map::ColorMap;
+use hotkeys::{Action, HotkeyMap};
+use profiles::ProfileConfig;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub mod color_map;
+pub mod hotkeys;
+pub mod profiles;
+
+// the clock the emulator runs programs at unless told otherwise
+pub const DEFAULT_CLOCK_HZ: u64 = 500;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    InvalidClock(String),
+    InvalidColor(String),
+    InvalidSeed(String),
+    InvalidMetricsInterval(String),
+    InvalidCompatibilityMode(String),
+    InvalidAddress(String),
+    InvalidKeyMask(String),
+    InvalidWatchpoint(String),
+    InvalidPatch(String),
+    InvalidTurboKey(String),
+    // the turbo key and the action bound to the same key
+    TurboKeyConflict(String, Action),
+    InvalidHotkey(String),
+    InvalidDenseScreenThreshold(String),
+    InvalidConfigFile(String),
+    InvalidColorMap(String),
+    InvalidPlaylistMode(String),
+    InvalidPlaylistDuration(String),
+    InvalidMaxCallDepth(String),
+    InvalidBreakpoint(String),
+    MissingRom(PathBuf),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidClock(clock) => write!(f, "invalid cpu clock '{}'", clock),
+            ConfigError::InvalidColor(color) => write!(f, "invalid rgb color '{}'", color),
+            ConfigError::InvalidSeed(seed) => write!(f, "invalid random seed '{}'", seed),
+            ConfigError::InvalidMetricsInterval(interval) => {
+                write!(f, "invalid metrics interval '{}'", interval)
+            }
+            ConfigError::InvalidCompatibilityMode(mode) => {
+                write!(f, "invalid compatibility mode '{}'", mode)
+            }
+            ConfigError::InvalidAddress(addr) => write!(f, "invalid socket address '{}'", addr),
+            ConfigError::InvalidKeyMask(mask) => write!(f, "invalid key mask '{}'", mask),
+            ConfigError::InvalidWatchpoint(watchpoint) => {
+                write!(f, "invalid watchpoint '{}'", watchpoint)
+            }
+            ConfigError::InvalidPatch(patch) => write!(f, "invalid rom patch '{}'", patch),
+            ConfigError::InvalidTurboKey(key) => write!(f, "invalid turbo key '{}'", key),
+            ConfigError::TurboKeyConflict(key, action) => {
+                write!(f, "turbo key '{}' is also bound to {:?}", key, action)
+            }
+            ConfigError::InvalidHotkey(err) => write!(f, "invalid hotkey: {}", err),
+            ConfigError::InvalidDenseScreenThreshold(threshold) => {
+                write!(f, "invalid dense screen threshold '{}'", threshold)
+            }
+            ConfigError::InvalidConfigFile(err) => write!(f, "invalid config file: {}", err),
+            ConfigError::InvalidColorMap(err) => write!(f, "invalid color map: {}", err),
+            ConfigError::InvalidPlaylistMode(mode) => write!(f, "invalid playlist mode '{}'", mode),
+            ConfigError::InvalidPlaylistDuration(duration) => {
+                write!(f, "invalid playlist duration '{}'", duration)
+            }
+            ConfigError::InvalidMaxCallDepth(depth) => {
+                write!(f, "invalid maximum call depth '{}'", depth)
+            }
+            ConfigError::InvalidBreakpoint(address) => {
+                write!(f, "invalid breakpoint '{}'", address)
+            }
+            ConfigError::MissingRom(path) => {
+                write!(f, "rom file '{}' does not exist", path.display())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+// what happens after the last rom of the playlist
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PlaylistMode {
+    // starts again from the first rom
+    #[default]
+    Loop,
+    // closes the emulator
+    Once,
+}
+
+impl FromStr for PlaylistMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "loop" => Ok(PlaylistMode::Loop),
+            "once" => Ok(PlaylistMode::Once),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Config {
+    // when no rom is given a launcher lists the roms found in `rom_dir`
+    pub(crate) rom_file_path: Option<PathBuf>,
+    // downloaded and run instead of `rom_file_path`
+    #[cfg(feature = "http")]
+    pub(crate) rom_url: Option<String>,
+    pub(crate) rom_dir: PathBuf,
+    pub(crate) clock_hz: u64,
+    pub(crate) color1: (u8, u8, u8),
+    pub(crate) color2: (u8, u8, u8),
+    // the XO-CHIP colors of plane 2 and of the pixels set in both planes
+    pub(crate) color3: (u8, u8, u8),
+    pub(crate) color4: (u8, u8, u8),
+    pub(crate) seed: Option<u64>,
+    pub(crate) compat_mode: CompatibilityMode,
+    // whether roms found in the quirk database run with their known quirks, which is
+    // turned off by choosing a mode by hand
+    pub(crate) detect_quirks: bool,
+    pub(crate) record_input: Option<PathBuf>,
+    pub(crate) replay_input: Option<PathBuf>,
+    // where the cycles and keys of each frame are written at exit, for `Chip8::replay`
+    pub(crate) step_log: Option<PathBuf>,
+    // where the machine state is written when the window is closed
+    pub(crate) dump_state_path: Option<PathBuf>,
+    // address labels read after loading the rom and written at exit
+    pub(crate) load_annotations: Option<PathBuf>,
+    pub(crate) save_annotations: Option<PathBuf>,
+    // assembler symbol file read after loading the rom
+    pub(crate) load_symbols: Option<PathBuf>,
+    pub(crate) metrics_interval: Option<u64>,
+    pub(crate) title_template: Option<String>,
+    // not the monitor's vsync, which minifb has no access to: the frames are paced by
+    // sleeping until the next one is due
+    pub(crate) vsync: bool,
+    // draws a line of the grid color every 8 pixels across the background
+    pub(crate) grid: bool,
+    // opens a second window with a hex dump of the memory
+    pub(crate) multi_window: bool,
+    pub(crate) timing_stats: bool,
+    pub(crate) call_graph: bool,
+    pub(crate) print_opcodes: bool,
+    // prints the last executed opcodes when the emulator stops with an error
+    pub(crate) crash_log: bool,
+    // percentage of lit pixels above which a warning is printed
+    pub(crate) dense_screen_threshold: Option<u8>,
+    pub(crate) strict_alignment: bool,
+    pub(crate) vf_result_not_flag: bool,
+    pub(crate) scanline_accurate: bool,
+    pub(crate) turbo_key: Option<TurboKey>,
+    pub(crate) hotkeys: HotkeyMap,
+    // address to listen on and address of the peer sharing the keypad
+    pub(crate) p2p: Option<(SocketAddr, SocketAddr)>,
+    pub(crate) p2p_remote_keys: u16,
+    pub(crate) lockstep: Option<LockstepRole>,
+    // address a viewer of the state connects to
+    pub(crate) state_sync: Option<SocketAddr>,
+    pub(crate) count_watchpoints: Vec<(u8, u64)>,
+    // addresses the emulator pauses at before running the instruction there
+    pub(crate) breakpoints: Vec<u16>,
+    // (address, bytes) pairs applied to the rom given on the command line
+    pub(crate) rom_patches: Vec<(u16, Vec<u8>)>,
+    // all the roms given on the command line, the first one being `rom_file_path`
+    pub(crate) playlist: Vec<PathBuf>,
+    pub(crate) playlist_mode: PlaylistMode,
+    // cycles each rom of the playlist runs for, forever when not set
+    pub(crate) playlist_duration: Option<u64>,
+    // the stack size of the cpu when not set
+    pub(crate) max_call_depth: Option<usize>,
+    // applied to each rom loaded, see `Config::rom_profile`
+    pub(crate) profiles: BTreeMap<String, ProfileConfig>,
+    // the settings given on the command line, which the profiles do not override
+    pub(crate) command_line_settings: ProfileConfig,
+}
+
+impl Config {
+    // the profile named like the rom file, ignoring case
+    pub fn resolve_profile<'a>(
+        rom_path: &Path,
+        profiles: &'a BTreeMap<String, ProfileConfig>,
+    ) -> Option<&'a ProfileConfig> {
+        let stem = rom_path.file_stem()?.to_string_lossy();
+        profiles.get(&stem.to_ascii_lowercase())
+    }
+
+    // the settings of the profile of the rom that were not given on the command line
+    pub fn rom_profile(&self, rom_path: &Path) -> ProfileConfig {
+        let mut profile = Config::resolve_profile(rom_path, &self.profiles)
+            .cloned()
+            .unwrap_or_default();
+        let given = &self.command_line_settings;
+        if given.clock_hz.is_some() {
+            profile.clock_hz = None;
+        }
+        if given.color1.is_some() {
+            profile.color1 = None;
+        }
+        if given.color2.is_some() {
+            profile.color2 = None;
+        }
+        if given.compat_mode.is_some() {
+            profile.compat_mode = None;
+        }
+        if given.vsync.is_some() {
+            profile.vsync = None;
+        }
+        profile
+    }
+
+    // the current value of every setting a profile can change
+    pub fn profile_settings(&self) -> ProfileConfig {
+        ProfileConfig {
+            clock_hz: Some(self.clock_hz),
+            color1: Some(self.color1),
+            color2: Some(self.color2),
+            compat_mode: Some(self.compat_mode),
+            vsync: Some(self.vsync),
+        }
+    }
+
+    // checks the values that are valid on their own but not for running the emulator,
+    // returning all the problems found rather than only the first one
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        if self.clock_hz == 0 {
+            errors.push(ConfigError::InvalidClock(self.clock_hz.to_string()));
+        }
+        if self.metrics_interval == Some(0) {
+            errors.push(ConfigError::InvalidMetricsInterval("0".to_string()));
+        }
+        if self.playlist_duration == Some(0) {
+            errors.push(ConfigError::InvalidPlaylistDuration("0".to_string()));
+        }
+        if self.max_call_depth == Some(0) {
+            errors.push(ConfigError::InvalidMaxCallDepth("0".to_string()));
+        }
+        if let Some(threshold) = self.dense_screen_threshold.filter(|t| *t > 100) {
+            errors.push(ConfigError::InvalidDenseScreenThreshold(
+                threshold.to_string(),
+            ));
+        }
+        if let Some(key) = self.turbo_key {
+            if let Some(action) = self.hotkeys.get(&key.hotkey()) {
+                errors.push(ConfigError::TurboKeyConflict(
+                    key.hotkey().to_string(),
+                    *action,
+                ));
+            }
+        }
+        for rom in &self.playlist {
+            if !rom.is_file() {
+                errors.push(ConfigError::MissingRom(rom.clone()));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn new(rom_file_path: Option<PathBuf>) -> Self {
+        Config {
+            rom_file_path,
+            #[cfg(feature = "http")]
+            rom_url: None,
+            rom_dir: PathBuf::from("roms"),
+            clock_hz: DEFAULT_CLOCK_HZ,
+            color1: (0x00, 0x00, 0x00),
+            color2: (0xFF, 0xFF, 0xFF),
+            color3: (0xAA, 0xAA, 0xAA),
+            color4: (0x55, 0x55, 0x55),
+            seed: None,
+            compat_mode: CompatibilityMode::default(),
+            detect_quirks: true,
+            record_input: None,
+            replay_input: None,
+            step_log: None,
+            dump_state_path: None,
+            load_annotations: None,
+            save_annotations: None,
+            load_symbols: None,
+            metrics_interval: None,
+            title_template: None,
+            vsync: false,
+            grid: false,
+            multi_window: false,
+            timing_stats: false,
+            call_graph: false,
+            print_opcodes: false,
+            crash_log: false,
+            dense_screen_threshold: None,
+            strict_alignment: false,
+            vf_result_not_flag: false,
+            scanline_accurate: false,
+            turbo_key: None,
+            hotkeys: hotkeys::default_hotkeys(),
+            p2p: None,
+            p2p_remote_keys: 0xFF00,
+            lockstep: None,
+            state_sync: None,
+            count_watchpoints: Vec::new(),
+            breakpoints: Vec::new(),
+            rom_patches: Vec::new(),
+            playlist: Vec::new(),
+            playlist_mode: PlaylistMode::default(),
+            playlist_duration: None,
+            max_call_depth: None,
+            profiles: BTreeMap::new(),
+            command_line_settings: ProfileConfig::default(),
+        }
+    }
+
+    pub fn rom_dir(mut self, dir: PathBuf) -> Self {
+        self.rom_dir = dir;
+        self
+    }
+
+    #[cfg(feature = "http")]
+    pub fn rom_url(mut self, url: String) -> Self {
+        self.rom_url = Some(url);
+        self
+    }
+
+    pub fn clock_hz(mut self, clock: u64) -> Self {
+        self.clock_hz = clock;
+        self
+    }
+
+    pub fn color1(mut self, color: (u8, u8, u8)) -> Self {
+        self.color1 = color;
+        self
+    }
+
+    pub fn color2(mut self, color: (u8, u8, u8)) -> Self {
+        self.color2 = color;
+        self
+    }
+
+    pub fn color3(mut self, color: (u8, u8, u8)) -> Self {
+        self.color3 = color;
+        self
+    }
+
+    pub fn color4(mut self, color: (u8, u8, u8)) -> Self {
+        self.color4 = color;
+        self
+    }
+
+    // sets all four colors at once, in the order of the plane masks
+    pub fn color_map(self, color_map: ColorMap) -> Self {
+        self.color1(color_map.bg)
+            .color2(color_map.p1)
+            .color3(color_map.p2)
+            .color4(color_map.both)
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn compat_mode(mut self, mode: CompatibilityMode) -> Self {
+        self.compat_mode = mode;
+        self
+    }
+
+    pub fn detect_quirks(mut self, detect_quirks: bool) -> Self {
+        self.detect_quirks = detect_quirks;
+        self
+    }
+
+    pub fn dump_state_path(mut self, path: PathBuf) -> Self {
+        self.dump_state_path = Some(path);
+        self
+    }
+
+    pub fn load_annotations(mut self, path: PathBuf) -> Self {
+        self.load_annotations = Some(path);
+        self
+    }
+
+    pub fn save_annotations(mut self, path: PathBuf) -> Self {
+        self.save_annotations = Some(path);
+        self
+    }
+
+    pub fn load_symbols(mut self, path: PathBuf) -> Self {
+        self.load_symbols = Some(path);
+        self
+    }
+
+    pub fn step_log(mut self, path: PathBuf) -> Self {
+        self.step_log = Some(path);
+        self
+    }
+
+    pub fn record_input(mut self, path: PathBuf) -> Self {
+        self.record_input = Some(path);
+        self
+    }
+
+    pub fn replay_input(mut self, path: PathBuf) -> Self {
+        self.replay_input = Some(path);
+        self
+    }
+
+    pub fn p2p(mut self, listen: SocketAddr, peer: SocketAddr) -> Self {
+        self.p2p = Some((listen, peer));
+        self
+    }
+
+    // bit mask of the keys controlled by the peer
+    pub fn p2p_remote_keys(mut self, mask: u16) -> Self {
+        self.p2p_remote_keys = mask;
+        self
+    }
+
+    pub fn lockstep(mut self, role: LockstepRole) -> Self {
+        self.lockstep = Some(role);
+        self
+    }
+
+    pub fn state_sync(mut self, addr: SocketAddr) -> Self {
+        self.state_sync = Some(addr);
+        self
+    }
+
+    // halts the emulator once the instruction type has been executed `count` times
+    pub fn count_watchpoint(mut self, instr_type: u8, count: u64) -> Self {
+        self.count_watchpoints.push((instr_type, count));
+        self
+    }
+
+    // pauses the emulator when the program counter reaches the address
+    pub fn breakpoint(mut self, address: u16) -> Self {
+        self.breakpoints.push(address);
+        self
+    }
+
+    pub fn rom_patch(mut self, address: u16, bytes: Vec<u8>) -> Self {
+        self.rom_patches.push((address, bytes));
+        self
+    }
+
+    pub fn playlist(mut self, roms: Vec<PathBuf>) -> Self {
+        self.playlist = roms;
+        self
+    }
+
+    pub fn playlist_mode(mut self, mode: PlaylistMode) -> Self {
+        self.playlist_mode = mode;
+        self
+    }
+
+    pub fn playlist_duration(mut self, cycles: u64) -> Self {
+        self.playlist_duration = Some(cycles);
+        self
+    }
+
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    pub fn timing_stats(mut self, timing_stats: bool) -> Self {
+        self.timing_stats = timing_stats;
+        self
+    }
+
+    pub fn turbo_key(mut self, key: TurboKey) -> Self {
+        self.turbo_key = Some(key);
+        self
+    }
+
+    pub fn profiles(mut self, profiles: BTreeMap<String, ProfileConfig>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    pub fn command_line_settings(mut self, settings: ProfileConfig) -> Self {
+        self.command_line_settings = settings;
+        self
+    }
+
+    pub fn hotkeys(mut self, hotkeys: HotkeyMap) -> Self {
+        self.hotkeys = hotkeys;
+        self
+    }
+
+    pub fn strict_alignment(mut self, strict: bool) -> Self {
+        self.strict_alignment = strict;
+        self
+    }
+
+    pub fn vf_result_not_flag(mut self, vf_result_not_flag: bool) -> Self {
+        self.vf_result_not_flag = vf_result_not_flag;
+        self
+    }
+
+    pub fn scanline_accurate(mut self, scanline_accurate: bool) -> Self {
+        self.scanline_accurate = scanline_accurate;
+        self
+    }
+
+    pub fn dense_screen_threshold(mut self, percentage: u8) -> Self {
+        self.dense_screen_threshold = Some(percentage);
+        self
+    }
+
+    pub fn call_graph(mut self, call_graph: bool) -> Self {
+        self.call_graph = call_graph;
+        self
+    }
+
+    pub fn print_opcodes(mut self, print_opcodes: bool) -> Self {
+        self.print_opcodes = print_opcodes;
+        self
+    }
+
+    pub fn crash_log(mut self, crash_log: bool) -> Self {
+        self.crash_log = crash_log;
+        self
+    }
+
+    pub fn vsync(mut self, vsync: bool) -> Self {
+        self.vsync = vsync;
+        self
+    }
+
+    pub fn grid(mut self, grid: bool) -> Self {
+        self.grid = grid;
+        self
+    }
+
+    pub fn multi_window(mut self, multi_window: bool) -> Self {
+        self.multi_window = multi_window;
+        self
+    }
+
+    pub fn title_template(mut self, template: String) -> Self {
+        self.title_template = Some(template);
+        self
+    }
+
+    // prints the emulator metrics every `secs` seconds
+    pub fn metrics_interval(mut self, secs: u64) -> Self {
+        self.metrics_interval = Some(secs);
+        self
+    }
+}
+
+// how an instance joins a lockstep session
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockstepRole {
+    // waits for the peer on this address, the peer controlling the p2p remote keys
+    Host(SocketAddr),
+    Connect(SocketAddr),
+}
+
+// parses a color given as 6 hex digits, optionally prefixed with # or 0x
+pub fn rgb_from_hex(hex: &str) -> Result<(u8, u8, u8), ConfigError> {
+    let mut hex_trimmed = hex.trim_start_matches('#');
+    hex_trimmed = hex_trimmed.trim_start_matches("0x");
+    if hex_trimmed.len() != 6 || !hex_trimmed.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ConfigError::InvalidColor(hex.to_string()));
+    }
+    let component = |i: usize| u8::from_str_radix(&hex_trimmed[i..i + 2], 16).unwrap();
+    Ok((component(0), component(2), component(4)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        assert!(Config::new(None).validate().is_ok());
+
+        let config = Config::new(None)
+            .clock_hz(0)
+            .playlist(vec![PathBuf::from("missing.ch8")])
+            .dense_screen_threshold(101)
+            .turbo_key(TurboKey::F(12));
+        let errors: Vec<String> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            vec![
+                "invalid cpu clock '0'",
+                "invalid dense screen threshold '101'",
+                "turbo key 'F12' is also bound to Screenshot",
+                "rom file 'missing.ch8' does not exist",
+            ],
+            errors
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use super::{rgb_from_hex, ConfigError};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::Path;
+
+// the four colors of the XO-CHIP planes, read from a toml file such as
+// bg = "#000000"
+// p1 = "#FF0000"
+// p2 = "#0000FF"
+// both = "#FF00FF"
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ColorMap {
+    #[serde(deserialize_with = "deserialize_color")]
+    pub bg: (u8, u8, u8),
+    #[serde(deserialize_with = "deserialize_color")]
+    pub p1: (u8, u8, u8),
+    #[serde(deserialize_with = "deserialize_color")]
+    pub p2: (u8, u8, u8),
+    #[serde(deserialize_with = "deserialize_color")]
+    pub both: (u8, u8, u8),
+}
+
+impl ColorMap {
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(|err| ConfigError::InvalidColorMap(err.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| ConfigError::InvalidColorMap(format!("{}: {}", path.display(), err)))?;
+        ColorMap::parse(&contents)
+    }
+}
+
+fn deserialize_color<'de, D>(deserializer: D) -> Result<(u8, u8, u8), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hex = String::deserialize(deserializer)?;
+    rgb_from_hex(&hex).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let color_map = ColorMap::parse(
+            "bg = \"#000000\"\np1 = \"#FF0000\"\np2 = \"#0000FF\"\nboth = \"#FF00FF\"",
+        )
+        .unwrap();
+        assert_eq!((0x00, 0x00, 0x00), color_map.bg);
+        assert_eq!((0xFF, 0x00, 0x00), color_map.p1);
+        assert_eq!((0x00, 0x00, 0xFF), color_map.p2);
+        assert_eq!((0xFF, 0x00, 0xFF), color_map.both);
+        // all four colors are needed
+        assert!(ColorMap::parse("bg = \"#000000\"\np1 = \"#FF0000\"").is_err());
+        assert!(ColorMap::parse(
+            "bg = \"black\"\np1 = \"#FF0000\"\np2 = \"#0000FF\"\nboth = \"#FF00FF\""
+        )
+        .is_err());
+    }
+}
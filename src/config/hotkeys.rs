@@ -0,0 +1,233 @@
+use super::profiles::read_config_file;
+use super::ConfigError;
+use crate::display::ASCII_KEYS;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// what the keys outside the CHIP-8 keypad do
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    Pause,
+    Reset,
+    // double and halve the cpu clock
+    SpeedUp,
+    SlowDown,
+    CycleCompatibilityMode,
+    // saves the screen as a BMP file in the working directory
+    Screenshot,
+    // runs a single cycle and prints the registers, stopping the emulator if it runs
+    Step,
+}
+
+impl Action {
+    // everything but screenshots changes how the emulation runs, which the peer of a
+    // lockstep session would not follow
+    pub fn changes_emulation(self) -> bool {
+        !matches!(self, Action::Screenshot)
+    }
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pause" => Ok(Action::Pause),
+            "reset" => Ok(Action::Reset),
+            "speed_up" => Ok(Action::SpeedUp),
+            "slow_down" => Ok(Action::SlowDown),
+            "cycle_mode" => Ok(Action::CycleCompatibilityMode),
+            "screenshot" => Ok(Action::Screenshot),
+            "step" => Ok(Action::Step),
+            _ => Err(()),
+        }
+    }
+}
+
+// the keys that can be bound, the same in the window and in the terminal
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HotkeyCode {
+    // a lowercase letter, a digit, or one of = + -
+    Char(char),
+    // F1 to F12
+    F(u8),
+    Tab,
+    Space,
+    Backspace,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Hotkey {
+    pub ctrl: bool,
+    pub code: HotkeyCode,
+}
+
+impl Hotkey {
+    pub fn new(code: HotkeyCode) -> Self {
+        Hotkey { ctrl: false, code }
+    }
+
+    pub fn ctrl(code: HotkeyCode) -> Self {
+        Hotkey { ctrl: true, code }
+    }
+}
+
+// keys are written like "P", "F12", "=", "Tab" or "Ctrl+M"
+impl FromStr for Hotkey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_ascii_lowercase();
+        let (ctrl, key) = match lowercase.strip_prefix("ctrl+") {
+            Some(key) => (true, key),
+            None => (false, lowercase.as_str()),
+        };
+        let code = match key {
+            "tab" => HotkeyCode::Tab,
+            "space" => HotkeyCode::Space,
+            "backspace" => HotkeyCode::Backspace,
+            _ if key.len() == 1 => {
+                let c = key.chars().next().unwrap();
+                if !(c.is_ascii_alphanumeric() || "=+-".contains(c)) {
+                    return Err(());
+                }
+                HotkeyCode::Char(c)
+            }
+            _ => match key.strip_prefix('f').map(u8::from_str) {
+                Some(Ok(n)) if (1..=12).contains(&n) => HotkeyCode::F(n),
+                _ => return Err(()),
+            },
+        };
+        Ok(Hotkey { ctrl, code })
+    }
+}
+
+// written the way the config file takes them
+impl Display for Hotkey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        match self.code {
+            HotkeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            HotkeyCode::F(n) => write!(f, "F{}", n),
+            HotkeyCode::Tab => write!(f, "Tab"),
+            HotkeyCode::Space => write!(f, "Space"),
+            HotkeyCode::Backspace => write!(f, "Backspace"),
+        }
+    }
+}
+
+pub type HotkeyMap = HashMap<Hotkey, Action>;
+
+// the key bound to the action, the first one by name when there are several so that it is
+// always the same
+pub fn key_of(hotkeys: &HotkeyMap, action: Action) -> Option<String> {
+    hotkeys
+        .iter()
+        .filter(|(_, bound_action)| **bound_action == action)
+        .map(|(hotkey, _)| hotkey.to_string())
+        .min()
+}
+
+// the keys the emulator has always used, + sharing its key with = on most layouts
+pub fn default_hotkeys() -> HotkeyMap {
+    let mut hotkeys = HashMap::new();
+    hotkeys.insert(Hotkey::new(HotkeyCode::Char('p')), Action::Pause);
+    hotkeys.insert(
+        Hotkey::ctrl(HotkeyCode::Char('m')),
+        Action::CycleCompatibilityMode,
+    );
+    hotkeys.insert(Hotkey::new(HotkeyCode::Char('=')), Action::SpeedUp);
+    hotkeys.insert(Hotkey::new(HotkeyCode::Char('+')), Action::SpeedUp);
+    hotkeys.insert(Hotkey::new(HotkeyCode::Char('-')), Action::SlowDown);
+    hotkeys.insert(Hotkey::new(HotkeyCode::F(12)), Action::Screenshot);
+    hotkeys.insert(Hotkey::new(HotkeyCode::F(10)), Action::Step);
+    hotkeys
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    hotkeys: HashMap<String, String>,
+}
+
+// the default hotkeys, with the actions of the config file moved to their new key
+pub fn load_hotkeys() -> Result<HotkeyMap, ConfigError> {
+    match read_config_file()? {
+        Some(contents) => parse_hotkeys(&contents),
+        None => Ok(default_hotkeys()),
+    }
+}
+
+// the [hotkeys] table of the toml config file maps actions to keys, e.g. pause = "P"
+pub fn parse_hotkeys(contents: &str) -> Result<HotkeyMap, ConfigError> {
+    let file = toml::from_str::<ConfigFile>(contents)
+        .map_err(|err| ConfigError::InvalidConfigFile(err.to_string()))?;
+    let mut hotkeys = default_hotkeys();
+    let mut bound = HashMap::new();
+    for (action_name, key) in &file.hotkeys {
+        let action = Action::from_str(action_name)
+            .map_err(|_| ConfigError::InvalidHotkey(format!("unknown action '{}'", action_name)))?;
+        let hotkey = Hotkey::from_str(key)
+            .map_err(|_| ConfigError::InvalidHotkey(format!("unknown key '{}'", key)))?;
+        if let HotkeyCode::Char(c) = hotkey.code {
+            if !hotkey.ctrl && ASCII_KEYS.contains(&c) {
+                return Err(ConfigError::InvalidHotkey(format!(
+                    "'{}' is a key of the CHIP-8 keypad",
+                    key
+                )));
+            }
+        }
+        if let Some(other) = bound.insert(hotkey, action_name) {
+            return Err(ConfigError::InvalidHotkey(format!(
+                "'{}' is bound to both {} and {}",
+                key, other, action_name
+            )));
+        }
+        hotkeys.retain(|_, bound_action| *bound_action != action);
+        hotkeys.insert(hotkey, action);
+    }
+    Ok(hotkeys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hotkeys() {
+        let hotkeys = parse_hotkeys("[hotkeys]\npause = \"F5\"\nreset = \"Ctrl+R\"").unwrap();
+        assert_eq!(
+            Some(&Action::Pause),
+            hotkeys.get(&Hotkey::new(HotkeyCode::F(5)))
+        );
+        // the default key of a remapped action is free again
+        assert_eq!(None, hotkeys.get(&Hotkey::new(HotkeyCode::Char('p'))));
+        assert_eq!(
+            Some(&Action::Reset),
+            hotkeys.get(&Hotkey::ctrl(HotkeyCode::Char('r')))
+        );
+        assert_eq!(
+            Some(&Action::SpeedUp),
+            hotkeys.get(&Hotkey::new(HotkeyCode::Char('=')))
+        );
+        assert_eq!(default_hotkeys(), parse_hotkeys("").unwrap());
+        assert_eq!(Some("F5".to_string()), key_of(&hotkeys, Action::Pause));
+        assert_eq!(Some("Ctrl+R".to_string()), key_of(&hotkeys, Action::Reset));
+        assert_eq!(None, key_of(&default_hotkeys(), Action::Reset));
+    }
+
+    #[test]
+    fn test_parse_hotkeys_errors() {
+        // W is the keypad key 5, but Ctrl+W is free
+        assert!(parse_hotkeys("[hotkeys]\npause = \"W\"").is_err());
+        assert!(parse_hotkeys("[hotkeys]\npause = \"Ctrl+W\"").is_ok());
+        assert!(parse_hotkeys("[hotkeys]\npause = \"F13\"").is_err());
+        assert!(parse_hotkeys("[hotkeys]\nrewind = \"F1\"").is_err());
+        assert!(parse_hotkeys("[hotkeys]\npause = \"F1\"\nreset = \"f1\"").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use super::{rgb_from_hex, ConfigError};
+use crate::core::compat::CompatibilityMode;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// the settings that can be set per rom, the ones left out keep their value
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub clock_hz: Option<u64>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub color1: Option<(u8, u8, u8)>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub color2: Option<(u8, u8, u8)>,
+    #[serde(deserialize_with = "deserialize_compat_mode")]
+    pub compat_mode: Option<CompatibilityMode>,
+    pub vsync: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileConfig>,
+}
+
+// reads the profiles from the config file, a missing file simply has no profiles
+pub fn load_profiles() -> Result<BTreeMap<String, ProfileConfig>, ConfigError> {
+    match read_config_file()? {
+        Some(contents) => parse_profiles(&contents),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+// the contents of the config file, None when there is none
+pub(super) fn read_config_file() -> Result<Option<String>, ConfigError> {
+    let path = match config_file_path() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(ConfigError::InvalidConfigFile(format!(
+            "{}: {}",
+            path.display(),
+            err
+        ))),
+    }
+}
+
+// the profiles are the [profiles.<name>] tables of the toml config file. the names are
+// matched with the rom file names ignoring case, so they are kept in lowercase and two
+// names differing only in case are refused
+pub fn parse_profiles(contents: &str) -> Result<BTreeMap<String, ProfileConfig>, ConfigError> {
+    let file = toml::from_str::<ConfigFile>(contents)
+        .map_err(|err| ConfigError::InvalidConfigFile(err.to_string()))?;
+    let mut profiles = BTreeMap::new();
+    for (name, profile) in file.profiles {
+        if profiles
+            .insert(name.to_ascii_lowercase(), profile)
+            .is_some()
+        {
+            return Err(ConfigError::InvalidConfigFile(format!(
+                "more than one profile is named {} ignoring case",
+                name
+            )));
+        }
+    }
+    Ok(profiles)
+}
+
+fn config_file_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config/chipotto/config.toml"))
+}
+
+fn deserialize_color<'de, D>(deserializer: D) -> Result<Option<(u8, u8, u8)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hex = String::deserialize(deserializer)?;
+    rgb_from_hex(&hex).map(Some).map_err(D::Error::custom)
+}
+
+fn deserialize_compat_mode<'de, D>(deserializer: D) -> Result<Option<CompatibilityMode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mode = String::deserialize(deserializer)?;
+    CompatibilityMode::from_str(&mode)
+        .map(Some)
+        .map_err(|_| D::Error::custom(ConfigError::InvalidCompatibilityMode(mode)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::path::Path;
+
+    #[test]
+    fn test_profile_resolved_by_rom_name() {
+        let profiles = parse_profiles(
+            r##"
+            [profiles.spaceinvaders]
+            clock_hz = 600
+            color1 = "#000011"
+            color2 = "#00FFFF"
+
+            [profiles.pong]
+            compat_mode = "chip8"
+            "##,
+        )
+        .unwrap();
+        // the clock given on the command line is kept
+        let config = Config::new(None)
+            .profiles(profiles)
+            .command_line_settings(ProfileConfig {
+                clock_hz: Some(700),
+                ..ProfileConfig::default()
+            });
+        let profile = config.rom_profile(Path::new("roms/SpaceInvaders.ch8"));
+        assert_eq!(None, profile.clock_hz);
+        assert_eq!(Some((0x00, 0x00, 0x11)), profile.color1);
+        assert_eq!(Some((0x00, 0xFF, 0xFF)), profile.color2);
+        assert_eq!(None, profile.compat_mode);
+        assert_eq!(
+            ProfileConfig::default(),
+            config.rom_profile(Path::new("tetris.ch8"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        assert!(parse_profiles("[profiles.pong]\ncolor1 = \"blue\"").is_err());
+        assert!(parse_profiles("[profiles.pong]\nclock = 600").is_err());
+        assert!(parse_profiles("").unwrap().is_empty());
+        assert!(parse_profiles("[profiles.pong]\n[profiles.PONG]").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+// number of bytes of an XO-CHIP audio pattern, played as 128 1-bit samples
+pub const PATTERN_SIZE: usize = 16;
+const PATTERN_BITS: usize = PATTERN_SIZE * 8;
+
+// the pitch register value at which the pattern plays at 4000 samples per second
+const DEFAULT_PITCH: u8 = 64;
+#[cfg(not(feature = "no-std"))]
+const BASE_RATE_HZ: f32 = 4000.0;
+// the pitch register moves the rate by a 48th of an octave per step
+#[cfg(not(feature = "no-std"))]
+const STEPS_PER_OCTAVE: f32 = 48.0;
+
+// what the sound output should do after a timer tick
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SoundEvent {
+    // the sound timer was set since the previous tick
+    Started,
+    // the sound timer ran out, or was cleared, since the previous tick
+    Stopped,
+    // the sound keeps playing for this many more ticks
+    Continuing(u8),
+    // no sound before nor after the tick
+    Silent,
+}
+
+// told about every timer tick, e.g. to start and stop a beeper
+pub trait SoundOutput {
+    fn sound_event(&mut self, event: SoundEvent);
+
+    // the pattern to play, given before each event
+    fn audio_pattern(&mut self, _pattern: &PatternBuffer) {}
+}
+
+// the 1-bit XO-CHIP audio pattern loaded by F002 and played in a loop while the sound
+// timer is running, at a rate set by the pitch register through FX3A
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PatternBuffer {
+    pub data: [u8; PATTERN_SIZE],
+    pub pitch: u8,
+}
+
+impl Default for PatternBuffer {
+    fn default() -> Self {
+        PatternBuffer {
+            data: [0; PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+impl PatternBuffer {
+    // a 250 Hz square wave, the sound of the modes without audio patterns
+    pub const SQUARE_WAVE: PatternBuffer = PatternBuffer {
+        data: [
+            0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+            0xFF, 0x00,
+        ],
+        pitch: DEFAULT_PITCH,
+    };
+
+    // the bits are played from the most significant bit of the first byte
+    pub fn bit(&self, index: usize) -> bool {
+        let index = index % PATTERN_BITS;
+        (self.data[index / 8] >> (7 - index % 8)) & 1 == 1
+    }
+
+    // pattern samples per second, 4000 * 2^((pitch - 64) / 48)
+    #[cfg(not(feature = "no-std"))]
+    pub fn playback_rate(&self) -> f32 {
+        BASE_RATE_HZ * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / STEPS_PER_OCTAVE)
+    }
+
+    // fills an output buffer running at `output_rate` samples per second with the
+    // pattern, as -1.0 and 1.0. `position` is the position in the pattern, in pattern
+    // samples, which carries on from one buffer to the next
+    #[cfg(not(feature = "no-std"))]
+    pub fn fill(&self, output_rate: u32, position: &mut f32, out: &mut [f32]) {
+        let step = self.playback_rate() / output_rate as f32;
+        for sample in out {
+            *sample = if self.bit(*position as usize) {
+                1.0
+            } else {
+                -1.0
+            };
+            *position = (*position + step) % PATTERN_BITS as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit() {
+        let mut pattern = PatternBuffer::default();
+        pattern.data[0] = 0b1000_0001;
+        pattern.data[15] = 0b0000_0001;
+        assert!(pattern.bit(0));
+        assert!(!pattern.bit(1));
+        assert!(pattern.bit(7));
+        assert!(pattern.bit(127));
+        // the pattern loops
+        assert!(pattern.bit(128));
+    }
+
+    #[cfg(not(feature = "no-std"))]
+    #[test]
+    fn test_fill() {
+        let mut pattern = PatternBuffer::default();
+        assert_eq!(4000.0, pattern.playback_rate());
+        pattern.pitch = 112;
+        assert_eq!(8000.0, pattern.playback_rate());
+
+        // alternating bytes of ones and zeros, read 2 bits per output sample
+        for (i, byte) in pattern.data.iter_mut().enumerate() {
+            *byte = if i % 2 == 0 { 0xFF } else { 0x00 };
+        }
+        let mut position = 0.0;
+        let mut out = [0.0; 8];
+        pattern.fill(4000, &mut position, &mut out);
+        assert_eq!([1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0], out);
+        assert_eq!(16.0, position);
+        position = 126.0;
+        pattern.fill(4000, &mut position, &mut out[..2]);
+        assert_eq!(2.0, position);
+    }
+}
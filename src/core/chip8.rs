@@ -1,45 +1,1127 @@
-use super::cpu::Cpu;
+use super::audio::SoundEvent;
+use super::compat::CompatibilityMode;
+use super::cpu::{random_seed, Cpu, CpuError, CpuState, Cycle, RegisterInfo};
 use super::framebuffer::FrameBuffer;
-use super::keypad::Keypad;
-use super::memory::Memory;
+use super::instr::Instr;
+use super::keypad::{Keypad, KEY_COUNT};
+use super::memory::{Memory, FONT_SIZE, ROM_START_ADDRESS};
+use super::metrics::Chip8Metrics;
+use super::step_log::{FrameSnapshot, StepChange, StepLog, StepQuirks};
+use super::timer::TimerAccumulator;
+use super::watcher::Chip8Watcher;
+#[cfg(feature = "no-std")]
+use alloc::boxed::Box;
+#[cfg(feature = "no-std")]
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use rand::Rng;
+#[cfg(not(feature = "no-std"))]
+use std::error::Error;
+#[cfg(feature = "http")]
+use std::io::Read;
+#[cfg(not(feature = "no-std"))]
+use std::{fs, io, path::Path};
+
+pub const TIMERS_HZ: u64 = 60;
+const DEFAULT_CLOCK_HZ: u64 = 500;
+
+// memory from 0x200 up to 0xFFF is available to the program
+const MAX_ROM_SIZE: usize = 0x1000 - ROM_START_ADDRESS as usize;
+
+// magic bytes some XO-CHIP roms are distributed with, in front of the program
+const OCTO_HEADER: &[u8] = b"OCTO\0";
+
+#[derive(Debug)]
+pub enum RomLoadError {
+    Empty,
+    TooLarge(usize),
+    #[cfg(not(feature = "no-std"))]
+    Io(io::Error),
+}
+
+impl Display for RomLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            RomLoadError::Empty => write!(f, "rom is empty"),
+            RomLoadError::TooLarge(size) => write!(
+                f,
+                "rom is {} bytes long, but at most {} bytes fit in memory",
+                size, MAX_ROM_SIZE
+            ),
+            #[cfg(not(feature = "no-std"))]
+            RomLoadError::Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+impl Error for RomLoadError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RomFormat {
+    Plain,
+    // starts with the `OCTO\0` header of XO-CHIP roms
+    OctoHeadered,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RomInfo {
+    pub format: RomFormat,
+    // the size of the program, without the header
+    pub size: usize,
+}
+
+#[derive(Debug)]
+pub enum RomPatchError {
+    OutsideRomArea { address: u16, len: usize },
+}
+
+impl Display for RomPatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            RomPatchError::OutsideRomArea { address, len } => write!(
+                f,
+                "patch of {} bytes at address {:#05X} does not fit in the rom area",
+                len, address
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+impl Error for RomPatchError {}
+
+// a save state that does not fit this emulator, most likely a corrupted one
+#[derive(Debug)]
+pub enum StateError {
+    MemorySize { expected: usize, found: usize },
+    FrameBufferSize { expected: usize, found: usize },
+    Cpu(CpuError),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            StateError::MemorySize { expected, found } => write!(
+                f,
+                "state holds {} bytes of memory instead of {}",
+                found, expected
+            ),
+            StateError::FrameBufferSize { expected, found } => {
+                write!(f, "state holds {} pixels instead of {}", found, expected)
+            }
+            StateError::Cpu(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StateError::Cpu(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// number of cycles the program counter has to stay put for a program to count as halted
+const SELF_LOOP_CYCLES: u64 = 100;
+
+// why `Chip8::run_until_halt` stopped
+#[derive(Debug)]
+pub enum HaltReason {
+    Breakpoint(u16),
+    Timeout,
+    Error(CpuError),
+    // the program jumps to itself, which is how many roms end
+    SelfLoop,
+    // the program ran a HALT, see `Cpu::set_test_mode`
+    Halt,
+}
+
+#[derive(Clone)]
+pub struct Chip8State {
+    pub cpu: CpuState,
+    pub memory: Vec<u8>,
+    pub frame_buffer: Vec<u8>,
+    pub keys: [bool; KEY_COUNT],
+}
 
 pub struct Chip8 {
     paused: bool,
+    rom: Vec<u8>,
+    seed: u64,
+    // decides which cpu cycles also decrement the timers
+    timer_accumulator: TimerAccumulator,
+    watchers: Vec<Box<dyn Chip8Watcher>>,
+    metrics: Chip8Metrics,
+    // (instruction type, count) pairs that pause the emulator once the instruction
+    // type has been executed count times
+    count_watchpoints: Vec<(u8, u64)>,
+    // addresses at which `run_until_halt` stops
+    breakpoints: Vec<u16>,
+    // replaces the built-in font sprites when set
+    custom_font: Option<[u8; FONT_SIZE]>,
+    // the sound event of the last timer tick, until it is taken
+    sound_event: Option<SoundEvent>,
     pub cpu: Cpu,
     pub frame_buffer: FrameBuffer,
     pub memory: Memory,
     pub keypad: Keypad,
 }
 
+// the watchers are left out of the clone, as they belong to whoever attached them
+impl Clone for Chip8 {
+    fn clone(&self) -> Self {
+        Chip8 {
+            paused: self.paused,
+            rom: self.rom.clone(),
+            seed: self.seed,
+            timer_accumulator: self.timer_accumulator.clone(),
+            watchers: Vec::new(),
+            metrics: self.metrics.clone(),
+            count_watchpoints: self.count_watchpoints.clone(),
+            breakpoints: self.breakpoints.clone(),
+            custom_font: self.custom_font,
+            sound_event: self.sound_event,
+            cpu: self.cpu.clone(),
+            frame_buffer: self.frame_buffer.clone(),
+            memory: self.memory.clone(),
+            keypad: self.keypad.clone(),
+        }
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
 impl Chip8 {
     pub fn new() -> Self {
+        Chip8::with_seed(random_seed())
+    }
+
+    // the seed initializes the random number generator used by the RND instruction
+    pub fn with_seed(seed: u64) -> Self {
         Chip8 {
             paused: false,
-            cpu: Cpu::new(),
+            rom: Vec::new(),
+            seed,
+            timer_accumulator: TimerAccumulator::from_clock(DEFAULT_CLOCK_HZ, TIMERS_HZ),
+            watchers: Vec::new(),
+            metrics: Chip8Metrics::default(),
+            count_watchpoints: Vec::new(),
+            breakpoints: Vec::new(),
+            custom_font: None,
+            sound_event: None,
+            cpu: Cpu::with_seed(seed),
             frame_buffer: FrameBuffer::default(),
             memory: Memory::new(),
             keypad: Keypad::default(),
         }
     }
 
-    pub fn load_rom(&mut self, contents: &[u8]) {
+    // the font is kept across resets, so that roms relying on a different character
+    // set keep working
+    pub fn with_custom_font(mut self, font_data: [u8; FONT_SIZE]) -> Self {
+        self.memory.load_font(&font_data);
+        self.custom_font = Some(font_data);
+        self
+    }
+
+    pub fn load_rom(&mut self, contents: &[u8]) -> Result<(), RomLoadError> {
+        if contents.is_empty() {
+            return Err(RomLoadError::Empty);
+        }
+        if contents.len() > MAX_ROM_SIZE {
+            return Err(RomLoadError::TooLarge(contents.len()));
+        }
         self.memory.load_rom(contents);
+        // the addresses of another rom mean something else
+        self.memory.clear_annotations();
+        self.rom = contents.to_vec();
+        Ok(())
+    }
+
+    // loads a rom file as distributed, stripping the header of XO-CHIP roms and switching
+    // to the XO-CHIP compatibility mode for them
+    pub fn load_rom_image(&mut self, contents: &[u8]) -> Result<RomInfo, RomLoadError> {
+        let (format, program) = match contents.strip_prefix(OCTO_HEADER) {
+            Some(program) => (RomFormat::OctoHeadered, program),
+            None => (RomFormat::Plain, contents),
+        };
+        self.load_rom(program)?;
+        if format == RomFormat::OctoHeadered {
+            self.cpu
+                .set_compatibility_mode_at_runtime(CompatibilityMode::XoChip);
+        }
+        Ok(RomInfo {
+            format,
+            size: program.len(),
+        })
+    }
+
+    #[cfg(not(feature = "no-std"))]
+    pub fn load_rom_from_path(&mut self, path: &Path) -> Result<RomInfo, RomLoadError> {
+        let contents = fs::read(path).map_err(RomLoadError::Io)?;
+        self.load_rom_image(&contents)
+    }
+
+    // downloads the rom before loading it. a text response, most likely an error page,
+    // is refused, and so is a rom announced or found to be too large, without reading
+    // more than fits in memory
+    #[cfg(feature = "http")]
+    pub fn load_rom_from_url(&mut self, url: &str) -> Result<RomInfo, Box<dyn Error>> {
+        let max_image_size = MAX_ROM_SIZE + OCTO_HEADER.len();
+        let response = reqwest::blocking::get(url)?.error_for_status()?;
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if content_type.starts_with("text/") {
+            return Err(format!("{} is not a rom but {}", url, content_type).into());
+        }
+        if let Some(size) = response.content_length() {
+            if size as usize > max_image_size {
+                return Err(RomLoadError::TooLarge(size as usize).into());
+            }
+        }
+        let mut contents = Vec::new();
+        response
+            .take(max_image_size as u64 + 1)
+            .read_to_end(&mut contents)?;
+        Ok(self.load_rom_image(&contents)?)
+    }
+
+    // the program as loaded at each reset, with any patches applied
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // runs a recorded session again without a display, returning the screen at the end of
+    // each frame. the session is deterministic, so the same log always gives the same
+    // frames. a replay stops early at the first cpu error, like the session did
+    pub fn replay(log: &StepLog) -> Vec<FrameSnapshot> {
+        let mut chip8 = Chip8::with_seed(log.seed);
+        let mut snapshots = Vec::with_capacity(log.frames.len());
+        if let Some(font) = &log.custom_font {
+            match <[u8; FONT_SIZE]>::try_from(&font[..]) {
+                Ok(font) => chip8 = chip8.with_custom_font(font),
+                Err(_) => return snapshots,
+            }
+        }
+        chip8.set_step_quirks(log.quirks());
+        chip8.set_clock_hz(log.clock_hz);
+        if chip8.load_rom(&log.rom).is_err() {
+            return snapshots;
+        }
+        for (frame, step) in log.frames.iter().enumerate() {
+            let mut events = step.events.iter().peekable();
+            for cycle in 0..=step.cycles {
+                while let Some(event) = events.next_if(|event| event.after_cycles <= cycle) {
+                    if chip8.apply_step_change(&event.change).is_err() {
+                        return snapshots;
+                    }
+                }
+                if cycle < step.cycles && chip8.cpu_cycle().is_err() {
+                    return snapshots;
+                }
+            }
+            chip8.set_keys_from_bitmask(step.keys);
+            snapshots.push(FrameSnapshot {
+                frame: frame as u64,
+                cycles: chip8.metrics().cycles_total,
+                buffer: chip8.frame_buffer.get_buffer().to_vec(),
+            });
+        }
+        snapshots
     }
 
-    pub fn cpu_cycle(&mut self) {
+    fn apply_step_change(&mut self, change: &StepChange) -> Result<(), RomLoadError> {
+        match change {
+            StepChange::Reset => self.reset_keep_seed(),
+            StepChange::LoadRom(rom) => {
+                self.load_rom(rom)?;
+                self.reset();
+            }
+            StepChange::ClockHz(hz) => self.set_clock_hz(*hz),
+            StepChange::Quirks(quirks) => self.set_step_quirks(*quirks),
+        }
+        Ok(())
+    }
+
+    // the quirks recorded in a step log
+    pub fn step_quirks(&self) -> StepQuirks {
+        StepQuirks {
+            compat_mode: self.cpu.compatibility_mode(),
+            strict_alignment: self.cpu.strict_alignment(),
+            vf_result_not_flag: self.cpu.vf_result_not_flag(),
+            scanline_accurate: self.cpu.scanline_accurate(),
+        }
+    }
+
+    pub fn set_step_quirks(&mut self, quirks: StepQuirks) {
         self.cpu
-            .cycle(&mut self.frame_buffer, &mut self.memory, &mut self.keypad);
+            .set_compatibility_mode_at_runtime(quirks.compat_mode);
+        self.cpu.set_strict_alignment(quirks.strict_alignment);
+        self.cpu.set_vf_result_not_flag(quirks.vf_result_not_flag);
+        self.cpu.set_scanline_accurate(quirks.scanline_accurate);
+    }
+
+    pub fn custom_font(&self) -> Option<&[u8; FONT_SIZE]> {
+        self.custom_font.as_ref()
+    }
+
+    // overwrites part of the loaded rom, both in memory and in the copy used when resetting,
+    // but never in the rom file itself
+    pub fn inject_rom_patch(&mut self, address: u16, bytes: &[u8]) -> Result<(), RomPatchError> {
+        let outside = || RomPatchError::OutsideRomArea {
+            address,
+            len: bytes.len(),
+        };
+        let offset = (address as usize)
+            .checked_sub(ROM_START_ADDRESS as usize)
+            .ok_or_else(outside)?;
+        if offset + bytes.len() > MAX_ROM_SIZE {
+            return Err(outside());
+        }
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.memory.write_data_unchecked(address, bytes);
+        Ok(())
+    }
+
+    // restarts the machine with the last loaded rom
+    pub fn reset(&mut self) {
+        let mode = self.cpu.compatibility_mode();
+        let strict_alignment = self.cpu.strict_alignment();
+        let vf_result_not_flag = self.cpu.vf_result_not_flag();
+        let scanline_accurate = self.cpu.scanline_accurate();
+        let watchdog_threshold = self.cpu.watchdog_threshold();
+        let max_call_depth = self.cpu.max_call_depth();
+        let test_mode = self.cpu.test_mode();
+        self.cpu = Cpu::with_seed(self.seed);
+        self.cpu.set_compatibility_mode_at_runtime(mode);
+        self.cpu.set_strict_alignment(strict_alignment);
+        self.cpu.set_vf_result_not_flag(vf_result_not_flag);
+        self.cpu.set_scanline_accurate(scanline_accurate);
+        self.cpu.set_watchdog_threshold(watchdog_threshold);
+        self.cpu.set_max_call_depth(max_call_depth);
+        self.cpu.set_test_mode(test_mode);
+        self.frame_buffer = FrameBuffer::default();
+        let annotations = self.memory.annotations().clone();
+        self.memory = Memory::new();
+        for (address, label) in &annotations {
+            self.memory.annotate(*address, label);
+        }
+        if let Some(font) = &self.custom_font {
+            self.memory.load_font(font);
+        }
+        self.keypad = Keypad::default();
+        self.timer_accumulator.reset();
+        self.sound_event = None;
+        self.metrics = Chip8Metrics::default();
+        self.memory.load_rom(&self.rom);
+    }
+
+    // like `reset`, but the random numbers carry on from where they are instead of starting
+    // over from the seed, so that a restarted game plays differently while a whole session
+    // still only depends on the seed
+    pub fn reset_keep_seed(&mut self) {
+        let rng = self.cpu.rng().clone();
+        self.reset();
+        self.cpu.set_rng(rng);
+    }
+
+    // starts the program from arbitrary registers, for fuzz testing
+    pub fn randomize_registers(&mut self, rng: &mut impl Rng) {
+        self.cpu.randomize_registers(rng);
+    }
+
+    // the value of Vx read as signed, boolean and decimal digits, for display
+    pub fn map_register(&self, vx: u8) -> RegisterInfo {
+        self.cpu.register_info((vx & 0x0F) as usize)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn set_cycles_per_timer_tick(&mut self, cycles: u64) {
+        self.timer_accumulator = TimerAccumulator::new(cycles as f64);
+    }
+
+    // the timers tick at 60 Hz whatever the clock, even when it is not a multiple of 60
+    pub fn set_clock_hz(&mut self, clock_hz: u64) {
+        self.timer_accumulator = TimerAccumulator::from_clock(clock_hz, TIMERS_HZ);
+    }
+
+    pub fn metrics(&self) -> &Chip8Metrics {
+        &self.metrics
+    }
+
+    // the instruction type is the one given by `Instr::type_index`
+    pub fn add_count_watchpoint(&mut self, instr_discriminant: u8, count: u64) {
+        self.count_watchpoints.push((instr_discriminant, count));
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.push(address);
+    }
+
+    pub fn is_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
     }
 
-    pub fn timers_tick(&mut self) {
-        self.cpu.update_timers();
+    // the watchers are notified in the order they were added
+    pub fn add_watcher(&mut self, watcher: Box<dyn Chip8Watcher>) {
+        self.watchers.push(watcher);
     }
 
-    pub fn pause() {
-        unimplemented!()
+    pub fn take_watchers(&mut self) -> Vec<Box<dyn Chip8Watcher>> {
+        core::mem::take(&mut self.watchers)
     }
 
-    pub fn resume() {
-        unimplemented!()
+    // updates the keypad, notifying the watchers only when the state of the key changes
+    pub fn set_key(&mut self, key: u8, down: bool) {
+        if self.keypad.is_down(key) == down {
+            return;
+        }
+        self.keypad.set_down(key, down);
+        for watcher in self.watchers.iter_mut() {
+            watcher.on_key_event(key, down);
+        }
+    }
+
+    pub fn set_keys_from_bitmask(&mut self, mask: u16) {
+        for key in 0..KEY_COUNT as u8 {
+            self.set_key(key, mask & (1 << key) != 0);
+        }
+    }
+
+    pub fn cpu_cycle(&mut self) -> Result<(), CpuError> {
+        let cycle = self
+            .cpu
+            .cycle(&mut self.frame_buffer, &mut self.memory, &mut self.keypad)?;
+        // VF only holds the collision once the last row of a scanline accurate draw is drawn
+        let collided = !self.cpu.is_drawing() && self.cpu.register(0xF) == 1;
+        let instr = match cycle {
+            Cycle::Executed(instr) => instr,
+            // the other rows of a draw only finish the instruction counted with the first one
+            Cycle::DrawRow(_) => {
+                if !self.cpu.is_drawing() {
+                    if collided {
+                        self.metrics.record_collision();
+                    }
+                    for watcher in self.watchers.iter_mut() {
+                        watcher.on_frame(self.frame_buffer.get_buffer());
+                    }
+                }
+                if self.timer_accumulator.cycle() {
+                    self.sound_event = Some(self.timers_tick());
+                }
+                return Ok(());
+            }
+        };
+        self.metrics.record_cycle(&instr, collided);
+        let instr_type = instr.type_index();
+        let executed = self.metrics.instructions_per_type[instr_type];
+        if self
+            .count_watchpoints
+            .iter()
+            .any(|&(watched, count)| watched as usize == instr_type && count == executed)
+        {
+            self.pause();
+        }
+        if !self.watchers.is_empty() {
+            let state = self.cpu.state();
+            let drawn = match instr {
+                Instr::Cls => true,
+                Instr::DrwVxVyN(..) => !self.cpu.is_drawing(),
+                _ => false,
+            };
+            for watcher in self.watchers.iter_mut() {
+                watcher.on_cpu_cycle(&state, &instr);
+                if drawn {
+                    watcher.on_frame(self.frame_buffer.get_buffer());
+                }
+            }
+        }
+        if self.timer_accumulator.cycle() {
+            self.sound_event = Some(self.timers_tick());
+        }
+        Ok(())
+    }
+
+    // runs cycles until the program counter reaches a breakpoint, the cpu fails, the program
+    // halts or loops on itself, or `timeout_cycles` cycles have been run. a breakpoint stops the run
+    // when the program counter moves to it, so that running again from it goes on
+    pub fn run_until_halt(&mut self, timeout_cycles: u64) -> HaltReason {
+        let mut unchanged_pc_cycles = 0;
+        for _ in 0..timeout_cycles {
+            let pc = self.cpu.pc();
+            if let Err(err) = self.cpu_cycle() {
+                return HaltReason::Error(err);
+            }
+            if self.cpu.is_halted() {
+                return HaltReason::Halt;
+            }
+            if self.cpu.pc() == pc {
+                unchanged_pc_cycles += 1;
+                if unchanged_pc_cycles == SELF_LOOP_CYCLES {
+                    return HaltReason::SelfLoop;
+                }
+            } else {
+                unchanged_pc_cycles = 0;
+                if self.breakpoints.contains(&self.cpu.pc()) {
+                    return HaltReason::Breakpoint(self.cpu.pc());
+                }
+            }
+        }
+        HaltReason::Timeout
+    }
+
+    // the event of the last tick run by `cpu_cycle`, if any tick ran since the last call
+    pub fn take_sound_event(&mut self) -> Option<SoundEvent> {
+        self.sound_event.take()
+    }
+
+    pub fn timers_tick(&mut self) -> SoundEvent {
+        self.cpu.tick_delay_timer();
+        let sound_event = self.cpu.tick_sound_timer();
+        // the timers tick at the refresh rate, so each tick ends a frame
+        #[cfg(feature = "pixel-history")]
+        self.frame_buffer.push_history();
+        self.metrics.record_timer_tick();
+        for watcher in self.watchers.iter_mut() {
+            watcher.on_timer_tick(self.cpu.delay_timer(), self.cpu.sound_timer());
+        }
+        sound_event
+    }
+
+    pub fn save_state(&self) -> Chip8State {
+        Chip8State {
+            cpu: self.cpu.state(),
+            memory: self.memory.snapshot(),
+            frame_buffer: self.frame_buffer.get_buffer().to_vec(),
+            keys: self.keypad.all_keys_snapshot(),
+        }
+    }
+
+    // nothing is restored from a state that does not fit
+    pub fn load_state(&mut self, state: &Chip8State) -> Result<(), StateError> {
+        let expected = self.memory.dump_all().len();
+        if state.memory.len() != expected {
+            return Err(StateError::MemorySize {
+                expected,
+                found: state.memory.len(),
+            });
+        }
+        let expected = self.frame_buffer.get_buffer().len();
+        if state.frame_buffer.len() != expected {
+            return Err(StateError::FrameBufferSize {
+                expected,
+                found: state.frame_buffer.len(),
+            });
+        }
+        self.cpu.restore(&state.cpu).map_err(StateError::Cpu)?;
+        self.memory.restore(&state.memory);
+        self.frame_buffer.restore(&state.frame_buffer);
+        self.keypad.restore(state.keys);
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::framebuffer::PACKED_BUFFER_SIZE;
+    use crate::core::memory::FONT_SPRITES;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_save_load_state() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x60, 0x2A, 0x12, 0x00]).unwrap();
+        chip8.cpu_cycle().unwrap();
+        chip8.keypad.set_down(0x5, true);
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(chip8.cpu.state(), restored.cpu.state());
+        assert_eq!(0x60, restored.memory.read_byte(0x200));
+        assert!(restored.keypad.is_down(0x5));
+        assert_eq!(Some(0x5), restored.keypad.get_down_key());
+
+        let mut truncated = chip8.save_state();
+        truncated.memory.truncate(0x200);
+        assert!(matches!(
+            restored.load_state(&truncated),
+            Err(StateError::MemorySize {
+                expected: 0x1000,
+                found: 0x200
+            })
+        ));
+        truncated.memory = state.memory.clone();
+        truncated.frame_buffer.clear();
+        assert!(matches!(
+            restored.load_state(&truncated),
+            Err(StateError::FrameBufferSize { found: 0, .. })
+        ));
+        truncated.frame_buffer = state.frame_buffer.clone();
+        truncated.cpu.stack = vec![0x200; 17];
+        assert!(matches!(
+            restored.load_state(&truncated),
+            Err(StateError::Cpu(CpuError::StackOverflow))
+        ));
+    }
+
+    #[test]
+    fn test_clone_is_independent() {
+        let mut chip8 = Chip8::with_seed(3);
+        // LD V0, 0x2A; RND V1, 0xFF; DRW V0, V0, 1; JP 0x200
+        chip8
+            .load_rom(&[0x60, 0x2A, 0xC1, 0xFF, 0xD0, 0x01, 0x12, 0x00])
+            .unwrap();
+        chip8.cpu_cycle().unwrap();
+        let mut clone = chip8.clone();
+        for _ in 0..3 {
+            clone.cpu_cycle().unwrap();
+        }
+        clone.memory.write_byte(0x300, 0xFF);
+        clone.keypad.set_down(0x1, true);
+        assert_eq!(0x202, chip8.cpu.pc());
+        assert_eq!(0x00, chip8.memory.read_byte(0x300));
+        assert!(!chip8.keypad.is_down(0x1));
+        assert_eq!(0, chip8.frame_buffer.count_lit_pixels());
+        assert_eq!(1, chip8.metrics().cycles_total);
+
+        // the clone continues with the same random numbers as the original
+        for _ in 0..3 {
+            chip8.cpu_cycle().unwrap();
+        }
+        assert_eq!(clone.cpu.state(), chip8.cpu.state());
+        assert_eq!(
+            clone.frame_buffer.get_buffer(),
+            chip8.frame_buffer.get_buffer()
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x60, 0x2A, 0x12, 0x00]).unwrap();
+        chip8.cpu_cycle().unwrap();
+        chip8.memory.write_byte(0x300, 0xFF);
+        chip8.cpu.set_max_call_depth(4);
+        chip8.reset();
+        assert_eq!(Chip8::new().cpu.state(), chip8.cpu.state());
+        // the settings of the cpu are kept
+        assert_eq!(4, chip8.cpu.max_call_depth());
+        assert_eq!(0x00, chip8.memory.read_byte(0x300));
+        assert_eq!(0x60, chip8.memory.read_byte(0x200));
+        assert_eq!(0, chip8.metrics().cycles_total);
+    }
+
+    #[test]
+    fn test_reset_keep_seed() {
+        // RND V0, 0xFF
+        let rom = [0xC0, 0xFF];
+        let mut chip8 = Chip8::with_seed(7);
+        chip8.load_rom(&rom).unwrap();
+        chip8.cpu_cycle().unwrap();
+        let first = chip8.cpu.register(0);
+        chip8.reset();
+        chip8.cpu_cycle().unwrap();
+        assert_eq!(first, chip8.cpu.register(0));
+
+        // the second number of the sequence this time
+        chip8.reset_keep_seed();
+        chip8.cpu_cycle().unwrap();
+        assert_ne!(first, chip8.cpu.register(0));
+        assert_eq!(0x202, chip8.cpu.pc());
+    }
+
+    #[test]
+    fn test_load_octo_headered_rom() {
+        let mut chip8 = Chip8::new();
+        let info = chip8.load_rom_image(&[0x60, 0x2A]).unwrap();
+        assert_eq!(RomFormat::Plain, info.format);
+        assert_eq!(CompatibilityMode::SuperChip, chip8.cpu.compatibility_mode());
+
+        let info = chip8
+            .load_rom_image(&[b'O', b'C', b'T', b'O', 0, 0x60, 0x2A])
+            .unwrap();
+        assert_eq!(RomFormat::OctoHeadered, info.format);
+        assert_eq!(2, info.size);
+        assert_eq!(0x60, chip8.memory.read_byte(0x200));
+        assert_eq!(CompatibilityMode::XoChip, chip8.cpu.compatibility_mode());
+        assert!(matches!(
+            chip8.load_rom_image(b"OCTO\0"),
+            Err(RomLoadError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_run_until_halt() {
+        let mut chip8 = Chip8::with_seed(0);
+        chip8
+            .load_rom(&[
+                0x70, 0x01, // 0x200: ADD V0, 0x01
+                0x30, 0x03, // 0x202: SE V0, 0x03
+                0x12, 0x00, // 0x204: JP 0x200
+                0x12, 0x06, // 0x206: JP 0x206
+            ])
+            .unwrap();
+        assert!(matches!(chip8.run_until_halt(5), HaltReason::Timeout));
+        chip8.reset();
+        chip8.add_breakpoint(0x206);
+        assert!(matches!(
+            chip8.run_until_halt(1000),
+            HaltReason::Breakpoint(0x206)
+        ));
+        assert!(matches!(chip8.run_until_halt(1000), HaltReason::SelfLoop));
+        assert_eq!(8 + SELF_LOOP_CYCLES, chip8.metrics().cycles_total);
+
+        chip8.load_rom(&[0x00, 0xEE]).unwrap();
+        chip8.reset();
+        assert!(matches!(
+            chip8.run_until_halt(1000),
+            HaltReason::Error(CpuError::StackUnderflow)
+        ));
+    }
+
+    #[test]
+    fn test_run_until_halt_instruction() {
+        let mut chip8 = Chip8::with_seed(0);
+        chip8
+            .load_rom(&[
+                0x60, 0x2A, // 0x200: LD V0, 0x2A
+                0xFF, 0xFF, // 0x202: HALT
+            ])
+            .unwrap();
+        assert!(matches!(
+            chip8.run_until_halt(1000),
+            HaltReason::Error(CpuError::UnknownOpcode(0xFFFF))
+        ));
+        chip8.cpu.set_test_mode(true);
+        chip8.reset();
+        assert!(matches!(chip8.run_until_halt(1000), HaltReason::Halt));
+        assert_eq!(2, chip8.metrics().cycles_total);
+        assert_eq!(0x202, chip8.cpu.pc());
+        // the test mode survives a reset, which clears the halt
+        chip8.reset();
+        assert!(!chip8.cpu.is_halted());
+        assert!(chip8.cpu.test_mode());
+    }
+
+    #[test]
+    fn test_replay_matches_recording() {
+        let rom = [
+            0xC0, 0x3F, // 0x200: RND V0, 0x3F
+            0xC1, 0x1F, // 0x202: RND V1, 0x1F
+            0x62, 0x05, // 0x204: LD V2, 0x05
+            0xE2, 0x9E, // 0x206: SKP V2
+            0x12, 0x00, // 0x208: JP 0x200
+            0xD0, 0x11, // 0x20A: DRW V0, V1, 1
+            0x12, 0x00, // 0x20C: JP 0x200
+        ];
+        let mut chip8 = Chip8::with_seed(42);
+        chip8.set_clock_hz(700);
+        chip8.load_rom(&rom).unwrap();
+        let mut log = StepLog::new(42, 700, chip8.cpu.compatibility_mode(), chip8.rom());
+        let mut recorded = Vec::new();
+        for frame in 0..100u64 {
+            let cycles = 11 + (frame % 2) as u32;
+            for _ in 0..cycles {
+                chip8.cpu_cycle().unwrap();
+            }
+            let keys = if frame % 3 == 0 { 1 << 5 } else { 0 };
+            chip8.set_keys_from_bitmask(keys);
+            log.push_frame(cycles, keys);
+            recorded.push(chip8.frame_buffer.get_buffer().to_vec());
+        }
+        assert!(chip8.metrics().frame_draws_total > 0);
+
+        let replayed = Chip8::replay(&log);
+        assert_eq!(100, replayed.len());
+        for (snapshot, buffer) in replayed.iter().zip(&recorded) {
+            assert_eq!(buffer, &snapshot.buffer, "frame {}", snapshot.frame);
+        }
+        assert_eq!(replayed, Chip8::replay(&log));
+    }
+
+    #[test]
+    fn test_replay_applies_events() {
+        let rom = [
+            0xC0, 0x3F, // 0x200: RND V0, 0x3F
+            0xC1, 0x1F, // 0x202: RND V1, 0x1F
+            0xD0, 0x15, // 0x204: DRW V0, V1, 5
+            0x12, 0x00, // 0x206: JP 0x200
+        ];
+        let other_rom = [
+            0x60, 0x08, // 0x200: LD V0, 0x08
+            0xF0, 0x29, // 0x202: LD F, V0
+            0xD1, 0x15, // 0x204: DRW V1, V1, 5
+            0x71, 0x05, // 0x206: ADD V1, 0x05
+            0x12, 0x04, // 0x208: JP 0x204
+        ];
+        let mut chip8 = Chip8::with_seed(7);
+        chip8.load_rom(&rom).unwrap();
+        let mut log = StepLog::new(7, 500, chip8.cpu.compatibility_mode(), chip8.rom());
+        let mut recorded = Vec::new();
+        for frame in 0..40u32 {
+            for cycle in 0..10 {
+                match (frame, cycle) {
+                    (10, 4) => {
+                        chip8.reset_keep_seed();
+                        log.push_event(cycle, StepChange::Reset);
+                    }
+                    (20, 0) => {
+                        let mut quirks = chip8.step_quirks();
+                        quirks.scanline_accurate = true;
+                        chip8.set_step_quirks(quirks);
+                        log.push_event(cycle, StepChange::Quirks(quirks));
+                    }
+                    (25, 3) => {
+                        chip8.set_clock_hz(60);
+                        log.push_event(cycle, StepChange::ClockHz(60));
+                    }
+                    (30, 7) => {
+                        chip8.load_rom(&other_rom).unwrap();
+                        chip8.reset();
+                        log.push_event(cycle, StepChange::LoadRom(other_rom.to_vec()));
+                    }
+                    _ => {}
+                }
+                chip8.cpu_cycle().unwrap();
+            }
+            log.push_frame(10, 0);
+            recorded.push(chip8.frame_buffer.get_buffer().to_vec());
+        }
+
+        let replayed = Chip8::replay(&log);
+        assert_eq!(40, replayed.len());
+        for (snapshot, buffer) in replayed.iter().zip(&recorded) {
+            assert_eq!(buffer, &snapshot.buffer, "frame {}", snapshot.frame);
+        }
+    }
+
+    #[test]
+    fn test_custom_font() {
+        let mut font = [0; FONT_SIZE];
+        for (i, byte) in font.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut chip8 = Chip8::new().with_custom_font(font);
+        // LD V0, 0x03; LD F, V0
+        chip8.load_rom(&[0x60, 0x03, 0xF0, 0x29]).unwrap();
+        chip8.reset();
+        chip8.cpu_cycle().unwrap();
+        chip8.cpu_cycle().unwrap();
+        let i = chip8.cpu.state().i;
+        let sprite: Vec<u8> = (i..i + 5).map(|a| chip8.memory.read_byte(a)).collect();
+        assert_eq!(vec![15, 16, 17, 18, 19], sprite);
+    }
+
+    #[test]
+    fn test_randomize_registers() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let mut chip8 = Chip8::with_seed(0);
+            chip8.randomize_registers(&mut rng);
+            let state = chip8.cpu.state();
+            assert!((0x200..0x1000).contains(&state.i));
+            assert!(state.stack.len() <= 16);
+            assert!(state
+                .stack
+                .iter()
+                .all(|addr| (0x200..0x1000).contains(addr)));
+            // the program itself still starts at the beginning of the rom
+            assert_eq!(0x200, state.pc);
+        }
+    }
+
+    #[test]
+    fn test_inject_rom_patch() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x60, 0x2A, 0x12, 0x00]).unwrap();
+        chip8.inject_rom_patch(0x202, &[0x8E, 0x12]).unwrap();
+        assert_eq!(0x8E12, chip8.memory.read_word(0x202));
+        chip8.reset();
+        assert_eq!(0x8E12, chip8.memory.read_word(0x202));
+        assert!(chip8.inject_rom_patch(0x1FF, &[0x00]).is_err());
+        assert!(chip8.inject_rom_patch(0xFFF, &[0x00, 0x00]).is_err());
+        assert!(chip8.inject_rom_patch(0xFFF, &[0xAB]).is_ok());
+    }
+
+    #[test]
+    fn test_timers_follow_cpu_cycles() {
+        let mut chip8 = Chip8::new();
+        // LD V0, 0xFF; LD DT, V0; JP 0x204
+        chip8
+            .load_rom(&[0x60, 0xFF, 0xF0, 0x15, 0x12, 0x04])
+            .unwrap();
+        chip8.set_cycles_per_timer_tick(8);
+        for _ in 0..7 {
+            chip8.cpu_cycle().unwrap();
+        }
+        assert_eq!(0xFF, chip8.cpu.state().dt);
+        chip8.cpu_cycle().unwrap();
+        assert_eq!(0xFE, chip8.cpu.state().dt);
+        for _ in 0..8 {
+            chip8.cpu_cycle().unwrap();
+        }
+        assert_eq!(0xFD, chip8.cpu.state().dt);
+    }
+
+    #[test]
+    fn test_count_watchpoint_pauses() {
+        let mut chip8 = Chip8::new();
+        // LD V0, 0x00; DRW V0, V0, 1; JP 0x202
+        chip8
+            .load_rom(&[0x60, 0x00, 0xD0, 0x01, 0x12, 0x02])
+            .unwrap();
+        let drw = Instr::DrwVxVyN(0, 0, 0).type_index() as u8;
+        chip8.add_count_watchpoint(drw, 3);
+        let mut cycles = 0;
+        while !chip8.is_paused() && cycles < 100 {
+            chip8.cpu_cycle().unwrap();
+            cycles += 1;
+        }
+        assert_eq!(6, cycles);
+        assert_eq!(3, chip8.metrics().instructions_per_type[drw as usize]);
+    }
+
+    struct RecordingWatcher {
+        events: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Chip8Watcher for RecordingWatcher {
+        fn on_cpu_cycle(&mut self, state: &CpuState, instr: &Instr) {
+            self.events
+                .borrow_mut()
+                .push(format!("cycle {:?} pc={:X}", instr, state.pc));
+        }
+
+        fn on_timer_tick(&mut self, dt: u8, st: u8) {
+            self.events
+                .borrow_mut()
+                .push(format!("timer {} {}", dt, st));
+        }
+
+        fn on_frame(&mut self, buffer: &[u8]) {
+            let lit = buffer.iter().filter(|pixel| **pixel != 0).count();
+            self.events.borrow_mut().push(format!("frame {}", lit));
+        }
+
+        fn on_key_event(&mut self, key: u8, down: bool) {
+            self.events
+                .borrow_mut()
+                .push(format!("key {} {}", key, down));
+        }
+    }
+
+    #[test]
+    fn test_scanline_accurate_draw_counted_once() {
+        let mut chip8 = Chip8::new();
+        chip8.cpu.set_scanline_accurate(true);
+        // DRW V0, V0, 5 twice at the same place, the second one colliding
+        chip8.load_rom(&[0xD0, 0x05, 0xD0, 0x05]).unwrap();
+        let drw = Instr::DrwVxVyN(0, 0, 5).type_index();
+        chip8.add_count_watchpoint(drw as u8, 2);
+        for _ in 0..5 {
+            chip8.cpu_cycle().unwrap();
+        }
+        assert_eq!(1, chip8.metrics().instructions_per_type[drw]);
+        assert!(!chip8.is_paused());
+        for _ in 0..5 {
+            chip8.cpu_cycle().unwrap();
+        }
+        let metrics = chip8.metrics();
+        assert_eq!(
+            (2, 2),
+            (metrics.cycles_total, metrics.instructions_per_type[drw])
+        );
+        assert_eq!(1, metrics.collision_count);
+        assert!(chip8.is_paused());
+    }
+
+    #[test]
+    fn test_draw_font_sprite() {
+        let mut chip8 = Chip8::new();
+        // LD V0, 0x05; LD F, V0; LD V1, 8; LD V2, 2; DRW V1, V2, 5
+        chip8
+            .load_rom(&[0x60, 0x05, 0xF0, 0x29, 0x61, 0x08, 0x62, 0x02, 0xD1, 0x25])
+            .unwrap();
+        for _ in 0..5 {
+            chip8.cpu_cycle().unwrap();
+        }
+        // the sprite starts on a byte boundary, so each of its rows is a byte of the screen
+        let mut expected = [0u8; PACKED_BUFFER_SIZE];
+        for (row, byte) in FONT_SPRITES[5 * 5..6 * 5].iter().enumerate() {
+            expected[(2 + row) * 8 + 1] = *byte;
+        }
+        assert_eq!(expected, chip8.frame_buffer.as_1bpp_packed());
+    }
+
+    #[test]
+    fn test_watcher_events() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut chip8 = Chip8::new();
+        chip8.add_watcher(Box::new(RecordingWatcher {
+            events: Rc::clone(&events),
+        }));
+        // every watcher gets the same events
+        let other_events = Rc::new(RefCell::new(Vec::new()));
+        chip8.add_watcher(Box::new(RecordingWatcher {
+            events: Rc::clone(&other_events),
+        }));
+        // LD V0, 0x05; LD F, V0; DRW V0, V0, 5
+        chip8
+            .load_rom(&[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05])
+            .unwrap();
+        chip8.set_key(0x3, true);
+        chip8.set_key(0x3, true);
+        chip8.set_cycles_per_timer_tick(3);
+        for _ in 0..3 {
+            chip8.cpu_cycle().unwrap();
+        }
+        assert_eq!(
+            vec![
+                "key 3 true",
+                "cycle LdVxKK(0, 5) pc=202",
+                "cycle LdFVx(0) pc=204",
+                "cycle DrwVxVyN(0, 0, 5) pc=206",
+                "frame 14",
+                "timer 0 0",
+            ],
+            *events.borrow()
+        );
+        assert_eq!(*events.borrow(), *other_events.borrow());
     }
 }
@@ -0,0 +1,455 @@
+use super::instr::Instr;
+use super::memory::ROM_START_ADDRESS;
+#[cfg(feature = "no-std")]
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+#[cfg(not(feature = "no-std"))]
+use std::collections::{BTreeMap, BTreeSet};
+
+// emits a rust function per subroutine of the rom, with a match arm per basic block.
+// the output is meant to be read to understand what a rom does rather than compiled
+pub fn emit(rom: &[u8]) -> String {
+    let program = Program::analyze(rom);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// generated by chipotto from a {} byte CHIP-8 rom",
+        rom.len()
+    )
+    .unwrap();
+    for &entry in &program.entries {
+        writeln!(out).unwrap();
+        program.emit_function(&mut out, entry);
+    }
+    out
+}
+
+pub(crate) struct Block {
+    // address, opcode and decoded instruction of each instruction in the block
+    pub(crate) instrs: Vec<(u16, u16, Option<Instr>)>,
+    // the address the block continues at when its last instruction does not transfer control
+    pub(crate) fallthrough: Option<u16>,
+}
+
+struct Program {
+    blocks: BTreeMap<u16, Block>,
+    // entry points of the emitted functions: the start of the rom, the targets of CALL, and
+    // jump targets shared by more than one function
+    entries: BTreeSet<u16>,
+    // the function each block is emitted in
+    owners: BTreeMap<u16, u16>,
+}
+
+impl Program {
+    fn analyze(rom: &[u8]) -> Self {
+        let (leaders, calls) = find_leaders(rom);
+        let blocks: BTreeMap<u16, Block> = leaders
+            .iter()
+            .map(|&leader| (leader, build_block(rom, leader, &leaders)))
+            .collect();
+        let mut entries: BTreeSet<u16> = calls.into_iter().collect();
+        entries.insert(ROM_START_ADDRESS);
+
+        // a block reachable from several functions becomes a function of its own, which
+        // the others then tail call
+        loop {
+            let owners = assign_owners(&blocks, &entries);
+            let mut shared = Vec::new();
+            for (start, block) in &blocks {
+                let owner = match owners.get(start) {
+                    Some(owner) => owner,
+                    None => continue,
+                };
+                for target in successors(block) {
+                    if owners.get(&target).is_some_and(|other| other != owner)
+                        && !entries.contains(&target)
+                    {
+                        shared.push(target);
+                    }
+                }
+            }
+            if shared.is_empty() {
+                return Program {
+                    blocks,
+                    entries,
+                    owners,
+                };
+            }
+            entries.extend(shared);
+        }
+    }
+
+    fn emit_function(&self, out: &mut String, entry: u16) {
+        writeln!(out, "fn sub_{:03X}() {{", entry).unwrap();
+        writeln!(
+            out,
+            "    let [mut v0, mut v1, mut v2, mut v3, mut v4, mut v5, mut v6, mut v7, \
+             mut v8, mut v9, mut va, mut vb, mut vc, mut vd, mut ve, mut vf] = [0u8; 16];"
+        )
+        .unwrap();
+        writeln!(out, "    let mut i: u16 = 0;").unwrap();
+        writeln!(out, "    let mut pc: u16 = 0x{:03X};", entry).unwrap();
+        writeln!(out, "    loop {{").unwrap();
+        writeln!(out, "        match pc {{").unwrap();
+        for (&start, block) in &self.blocks {
+            if self.owners.get(&start) != Some(&entry) {
+                continue;
+            }
+            writeln!(out, "            0x{:03X} => {{", start).unwrap();
+            for &(addr, opcode, instr) in &block.instrs {
+                for line in self.translate(entry, addr, opcode, instr) {
+                    writeln!(out, "                {}", line).unwrap();
+                }
+            }
+            if let Some(next) = block.fallthrough {
+                writeln!(out, "                {}", self.goto(entry, next)).unwrap();
+            }
+            writeln!(out, "            }}").unwrap();
+        }
+        writeln!(
+            out,
+            "            _ => unreachable!(\"no block at {{:#05X}}\", pc),"
+        )
+        .unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "}}").unwrap();
+    }
+
+    // jumps within the function move to another match arm, jumps out of it become calls
+    fn goto(&self, function: u16, target: u16) -> String {
+        if self.owners.get(&target) == Some(&function) {
+            format!("pc = 0x{:03X};", target)
+        } else if self.entries.contains(&target) {
+            format!("return sub_{:03X}();", target)
+        } else {
+            format!("return; // 0x{:03X} is outside of the rom", target)
+        }
+    }
+
+    fn skip_if(&self, function: u16, addr: u16, cond: String) -> String {
+        format!(
+            "if {} {{ {} }} else {{ {} }}",
+            cond,
+            self.goto(function, addr + 4),
+            self.goto(function, addr + 2)
+        )
+    }
+
+    fn translate(
+        &self,
+        function: u16,
+        addr: u16,
+        opcode: u16,
+        instr: Option<Instr>,
+    ) -> Vec<String> {
+        let instr = match instr {
+            Some(instr) => instr,
+            None => {
+                return vec![format!(
+                    "panic!(\"unknown opcode 0x{:04X} at 0x{:03X}\");",
+                    opcode, addr
+                )]
+            }
+        };
+        let v = |x: usize| format!("v{:x}", x);
+        let mut lines = vec![format!("// 0x{:03X}: {}", addr, instr)];
+        match instr {
+            Instr::Cls => lines.push("display.clear();".into()),
+            Instr::Ret => lines.push("return;".into()),
+            Instr::Sys(_) => lines.push("// ignored by modern interpreters".into()),
+            Instr::Jp(nnn) => lines.push(self.goto(function, nnn)),
+            Instr::Call(nnn) => lines.push(format!("sub_{:03X}();", nnn)),
+            Instr::SeVxKK(x, kk) => {
+                lines.push(self.skip_if(function, addr, format!("{} == 0x{:02X}", v(x), kk)))
+            }
+            Instr::SneVxKK(x, kk) => {
+                lines.push(self.skip_if(function, addr, format!("{} != 0x{:02X}", v(x), kk)))
+            }
+            Instr::SeVxVy(x, y) => {
+                lines.push(self.skip_if(function, addr, format!("{} == {}", v(x), v(y))))
+            }
+            Instr::SneVxVy(x, y) => {
+                lines.push(self.skip_if(function, addr, format!("{} != {}", v(x), v(y))))
+            }
+            // as read by CHIP-8E
+            Instr::SeLtVxVy(x, y) => {
+                lines.push(self.skip_if(function, addr, format!("{} < {}", v(x), v(y))))
+            }
+            Instr::SeGtVxVy(x, y) => {
+                lines.push(self.skip_if(function, addr, format!("{} > {}", v(x), v(y))))
+            }
+            Instr::LdVxKK(x, kk) => lines.push(format!("{} = 0x{:02X};", v(x), kk)),
+            Instr::AddVxKK(x, kk) => {
+                lines.push(format!("{0} = {0}.wrapping_add(0x{1:02X});", v(x), kk))
+            }
+            Instr::LdVxVy(x, y) => lines.push(format!("{} = {};", v(x), v(y))),
+            Instr::OrVxVy(x, y) => lines.push(format!("{} |= {};", v(x), v(y))),
+            Instr::AndVxVy(x, y) => lines.push(format!("{} &= {};", v(x), v(y))),
+            Instr::XorVxVy(x, y) => lines.push(format!("{} ^= {};", v(x), v(y))),
+            Instr::AddVxVy(x, y) => {
+                lines.push(format!(
+                    "let (res, carry) = {}.overflowing_add({});",
+                    v(x),
+                    v(y)
+                ));
+                lines.push(format!("{} = res;", v(x)));
+                lines.push("vf = carry as u8;".into());
+            }
+            Instr::SubVxVy(x, y) => {
+                lines.push(format!(
+                    "let (res, borrow) = {}.overflowing_sub({});",
+                    v(x),
+                    v(y)
+                ));
+                lines.push(format!("{} = res;", v(x)));
+                lines.push("vf = !borrow as u8;".into());
+            }
+            Instr::SubnVxVy(x, y) => {
+                lines.push(format!(
+                    "let (res, borrow) = {}.overflowing_sub({});",
+                    v(y),
+                    v(x)
+                ));
+                lines.push(format!("{} = res;", v(x)));
+                lines.push("vf = !borrow as u8;".into());
+            }
+            Instr::ShrVx(x, _) => {
+                lines.push(format!("let shifted_out = {} & 1;", v(x)));
+                lines.push(format!("{} >>= 1;", v(x)));
+                lines.push("vf = shifted_out;".into());
+            }
+            Instr::ShlVx(x, _) => {
+                lines.push(format!("let shifted_out = {} >> 7;", v(x)));
+                lines.push(format!("{} <<= 1;", v(x)));
+                lines.push("vf = shifted_out;".into());
+            }
+            Instr::LdI(nnn) => lines.push(format!("i = 0x{:03X};", nnn)),
+            Instr::JpV0(nnn) => {
+                lines.push(format!("pc = 0x{:03X} + v0 as u16;", nnn));
+                lines.push("// the targets of computed jumps are not known".into());
+            }
+            Instr::RndVxKK(x, kk) => {
+                lines.push(format!("{} = rand::random::<u8>() & 0x{:02X};", v(x), kk))
+            }
+            Instr::DrwVxVyN(x, y, n) => lines.push(format!(
+                "vf = display.draw({}, {}, &memory[i as usize..i as usize + {}]) as u8;",
+                v(x),
+                v(y),
+                n
+            )),
+            Instr::SkpVx(x) => {
+                lines.push(self.skip_if(function, addr, format!("keypad.is_down({})", v(x))))
+            }
+            Instr::SknpVx(x) => {
+                lines.push(self.skip_if(function, addr, format!("!keypad.is_down({})", v(x))))
+            }
+            Instr::LdVxDT(x) => lines.push(format!("{} = timers.delay;", v(x))),
+            Instr::LdVxK(x) => lines.push(format!("{} = keypad.wait_for_key();", v(x))),
+            Instr::LdDTVx(x) => lines.push(format!("timers.delay = {};", v(x))),
+            Instr::LdSTVx(x) => lines.push(format!("timers.sound = {};", v(x))),
+            Instr::AddIVx(x) => lines.push(format!("i += {} as u16;", v(x))),
+            Instr::LdFVx(x) => lines.push(format!("i = {} as u16 * 5;", v(x))),
+            Instr::LdBVx(x) => {
+                lines.push(format!("memory[i as usize] = {} / 100;", v(x)));
+                lines.push(format!("memory[i as usize + 1] = {} / 10 % 10;", v(x)));
+                lines.push(format!("memory[i as usize + 2] = {} % 10;", v(x)));
+            }
+            Instr::LdIVx(x) => {
+                for r in 0..=x {
+                    lines.push(format!("memory[i as usize + {}] = {};", r, v(r)));
+                }
+            }
+            Instr::LdVxI(x) => {
+                for r in 0..=x {
+                    lines.push(format!("{} = memory[i as usize + {}];", v(r), r));
+                }
+            }
+            Instr::StoreAudioPattern => lines.push(
+                "audio.pattern.copy_from_slice(&memory[i as usize..i as usize + 16]);".into(),
+            ),
+            Instr::LdPitchVx(x) => lines.push(format!("audio.pitch = {};", v(x))),
+            Instr::Halt => lines.push("return;".into()),
+        }
+        lines
+    }
+}
+
+fn decode(rom: &[u8], addr: u16) -> Option<(u16, Option<Instr>)> {
+    let offset = addr.checked_sub(ROM_START_ADDRESS)? as usize;
+    let bytes = rom.get(offset..offset + 2)?;
+    let opcode = ((bytes[0] as u16) << 8) | bytes[1] as u16;
+    Some((opcode, Instr::from(opcode)))
+}
+
+// instructions after which execution does not simply continue with the next one
+fn ends_block(instr: Option<Instr>) -> bool {
+    matches!(
+        instr,
+        None | Some(Instr::Ret)
+            | Some(Instr::Jp(_))
+            | Some(Instr::JpV0(_))
+            | Some(Instr::SeVxKK(..))
+            | Some(Instr::SneVxKK(..))
+            | Some(Instr::SeVxVy(..))
+            | Some(Instr::SneVxVy(..))
+            | Some(Instr::SeLtVxVy(..))
+            | Some(Instr::SeGtVxVy(..))
+            | Some(Instr::SkpVx(_))
+            | Some(Instr::SknpVx(_))
+    )
+}
+
+// addresses the last instruction of a block transfers control to, calls excluded
+fn branch_targets(addr: u16, instr: Option<Instr>) -> Vec<u16> {
+    match instr {
+        Some(Instr::Jp(nnn)) => vec![nnn],
+        Some(Instr::SeVxKK(..))
+        | Some(Instr::SneVxKK(..))
+        | Some(Instr::SeVxVy(..))
+        | Some(Instr::SneVxVy(..))
+        | Some(Instr::SeLtVxVy(..))
+        | Some(Instr::SeGtVxVy(..))
+        | Some(Instr::SkpVx(_))
+        | Some(Instr::SknpVx(_)) => vec![addr + 2, addr + 4],
+        _ => Vec::new(),
+    }
+}
+
+pub(crate) fn successors(block: &Block) -> Vec<u16> {
+    let mut targets = match block.instrs.last() {
+        Some(&(addr, _, instr)) => branch_targets(addr, instr),
+        None => Vec::new(),
+    };
+    targets.extend(block.fallthrough);
+    targets
+}
+
+// follows the control flow from the start of the rom, so that data is not mistaken for code.
+// returns the addresses starting a basic block and the targets of CALL
+pub(crate) fn find_leaders(rom: &[u8]) -> (BTreeSet<u16>, BTreeSet<u16>) {
+    let mut leaders = BTreeSet::new();
+    let mut calls = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![ROM_START_ADDRESS];
+    leaders.insert(ROM_START_ADDRESS);
+    while let Some(mut addr) = pending.pop() {
+        while visited.insert(addr) {
+            let instr = match decode(rom, addr) {
+                Some((_, instr)) => instr,
+                None => break,
+            };
+            if let Some(Instr::Call(nnn)) = instr {
+                calls.insert(nnn);
+                leaders.insert(nnn);
+                pending.push(nnn);
+            }
+            if ends_block(instr) {
+                for target in branch_targets(addr, instr) {
+                    leaders.insert(target);
+                    pending.push(target);
+                }
+                break;
+            }
+            addr += 2;
+        }
+    }
+    // targets outside of the rom have no block
+    leaders.retain(|&addr| decode(rom, addr).is_some());
+    (leaders, calls)
+}
+
+pub(crate) fn build_block(rom: &[u8], start: u16, leaders: &BTreeSet<u16>) -> Block {
+    let mut instrs = Vec::new();
+    let mut addr = start;
+    while let Some((opcode, instr)) = decode(rom, addr) {
+        instrs.push((addr, opcode, instr));
+        if ends_block(instr) {
+            return Block {
+                instrs,
+                fallthrough: None,
+            };
+        }
+        addr += 2;
+        if leaders.contains(&addr) {
+            break;
+        }
+    }
+    Block {
+        instrs,
+        fallthrough: Some(addr),
+    }
+}
+
+// each block belongs to the first function, in address order, that reaches it
+fn assign_owners(blocks: &BTreeMap<u16, Block>, entries: &BTreeSet<u16>) -> BTreeMap<u16, u16> {
+    let mut owners: BTreeMap<u16, u16> = entries
+        .iter()
+        .filter(|entry| blocks.contains_key(entry))
+        .map(|&entry| (entry, entry))
+        .collect();
+    for &entry in entries {
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            if let Some(block) = blocks.get(&start) {
+                for target in successors(block) {
+                    if blocks.contains_key(&target) && !owners.contains_key(&target) {
+                        owners.insert(target, entry);
+                        pending.push(target);
+                    }
+                }
+            }
+        }
+    }
+    owners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_functions_and_blocks() {
+        let rom = [
+            0x60, 0x05, // 0x200: LD V0, 5
+            0x22, 0x08, // 0x202: CALL 0x208
+            0x12, 0x04, // 0x204: JP 0x204
+            0xFF, 0xFF, // 0x206: data, never executed
+            0x30, 0x05, // 0x208: SE V0, 5
+            0x70, 0x01, // 0x20A: ADD V0, 1
+            0x00, 0xEE, // 0x20C: RET
+        ];
+        let code = emit(&rom);
+        assert_eq!(2, code.matches("fn sub_").count());
+        assert!(code.contains("fn sub_200() {"));
+        assert!(code.contains("fn sub_208() {"));
+        assert!(code.contains("v0 = 0x05;"));
+        assert!(code.contains("sub_208();"));
+        assert!(code.contains("0x204 => {"));
+        assert!(code.contains("pc = 0x204;"));
+        assert!(code.contains("if v0 == 0x05 { pc = 0x20C; } else { pc = 0x20A; }"));
+        assert!(code.contains("v0 = v0.wrapping_add(0x01);"));
+        assert!(!code.contains("0x206"));
+    }
+
+    #[test]
+    fn test_shared_jump_target_becomes_function() {
+        let rom = [
+            0x22, 0x06, // 0x200: CALL 0x206
+            0x12, 0x08, // 0x202: JP 0x208
+            0x00, 0x00, // 0x204: padding
+            0x12, 0x08, // 0x206: JP 0x208
+            0x00, 0xE0, // 0x208: CLS
+            0x00, 0xEE, // 0x20A: RET
+        ];
+        let code = emit(&rom);
+        assert!(code.contains("fn sub_208() {"));
+        assert_eq!(2, code.matches("return sub_208();").count());
+    }
+}
@@ -0,0 +1,193 @@
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// the interpreters that ROMs were written for disagree on the behavior of a few instructions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CompatibilityMode {
+    // the original COSMAC VIP interpreter
+    Chip8,
+    // the HP-48 interpreter
+    Chip48,
+    // SUPER-CHIP 1.1, which the emulator has always followed
+    #[default]
+    SuperChip,
+    // XO-CHIP, which follows the original interpreter for these instructions
+    XoChip,
+    // CHIP-8E, the 1979 expansion of the original interpreter with extra skip instructions
+    Chip8E,
+}
+
+impl Display for CompatibilityMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CompatibilityMode::Chip8 => write!(f, "CHIP-8"),
+            CompatibilityMode::Chip48 => write!(f, "CHIP-48"),
+            CompatibilityMode::SuperChip => write!(f, "SUPER-CHIP"),
+            CompatibilityMode::XoChip => write!(f, "XO-CHIP"),
+            CompatibilityMode::Chip8E => write!(f, "CHIP-8E"),
+        }
+    }
+}
+
+impl FromStr for CompatibilityMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(CompatibilityMode::Chip8),
+            "chip48" | "chip-48" => Ok(CompatibilityMode::Chip48),
+            "superchip" | "super-chip" | "schip" => Ok(CompatibilityMode::SuperChip),
+            "xochip" | "xo-chip" => Ok(CompatibilityMode::XoChip),
+            "chip8e" | "chip-8e" => Ok(CompatibilityMode::Chip8E),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CompatibilityMode {
+    pub fn next(self) -> Self {
+        match self {
+            CompatibilityMode::Chip8 => CompatibilityMode::Chip48,
+            CompatibilityMode::Chip48 => CompatibilityMode::SuperChip,
+            CompatibilityMode::SuperChip => CompatibilityMode::XoChip,
+            CompatibilityMode::XoChip => CompatibilityMode::Chip8E,
+            CompatibilityMode::Chip8E => CompatibilityMode::Chip8,
+        }
+    }
+
+    // SHR and SHL shift Vx in place instead of storing the shifted Vy in Vx
+    pub fn shifts_vx_in_place(self) -> bool {
+        !matches!(
+            self,
+            CompatibilityMode::Chip8 | CompatibilityMode::XoChip | CompatibilityMode::Chip8E
+        )
+    }
+
+    // the amount I is incremented by after storing or loading V0 through Vx
+    pub fn load_store_i_increment(self, x: usize) -> u16 {
+        match self {
+            CompatibilityMode::Chip8 | CompatibilityMode::XoChip | CompatibilityMode::Chip8E => {
+                x as u16 + 1
+            }
+            CompatibilityMode::Chip48 => x as u16,
+            CompatibilityMode::SuperChip => 0,
+        }
+    }
+}
+
+// whether the rom contains opcodes that only SUPER-CHIP interpreters know: the scroll,
+// exit and resolution instructions 00FB to 00FF, the large font FX30, the flag registers
+// FX75 and FX85, and the 16x16 sprites of DXY0. the rom is read as aligned opcodes, so
+// sprite data can give a false positive now and then
+pub fn uses_superchip_instructions(rom: &[u8]) -> bool {
+    rom.chunks_exact(2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .any(|opcode| match opcode & 0xF000 {
+            0x0000 => (0x00FB..=0x00FF).contains(&opcode),
+            0xD000 => opcode & 0x000F == 0,
+            0xF000 => matches!(opcode & 0x00FF, 0x30 | 0x75 | 0x85),
+            _ => false,
+        })
+}
+
+// the behaviors a rom needs to run correctly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuirkSet {
+    pub compat_mode: CompatibilityMode,
+    // see `Cpu::set_vf_result_not_flag`
+    pub vf_result_not_flag: bool,
+}
+
+#[derive(Debug)]
+pub struct KnownRom {
+    pub title: &'static str,
+    // SHA-256 of the program, without any header
+    pub sha256: [u8; 32],
+    pub quirks: QuirkSet,
+}
+
+const fn quirks(compat_mode: CompatibilityMode) -> QuirkSet {
+    QuirkSet {
+        compat_mode,
+        vf_result_not_flag: false,
+    }
+}
+
+// roms known to need other quirks than the default ones, new entries are checked against
+// the output of `sha256sum`. roms that run fine in the default mode are left out
+const KNOWN_ROMS: &[KnownRom] = &[
+    KnownRom {
+        title: "Space Invaders",
+        sha256: [
+            0x00, 0xBB, 0x70, 0x01, 0xDE, 0x52, 0xB5, 0x62, 0xD9, 0x83, 0x57, 0xD3, 0x33, 0x75,
+            0x40, 0x6C, 0x6D, 0x03, 0x08, 0x46, 0x6D, 0x7D, 0x95, 0xC2, 0x58, 0x05, 0x37, 0xD2,
+            0x45, 0x1A, 0x75, 0xCD,
+        ],
+        quirks: quirks(CompatibilityMode::SuperChip),
+    },
+    KnownRom {
+        title: "the corax89 opcode test",
+        sha256: [
+            0x67, 0x75, 0x9C, 0xF9, 0xF5, 0xB2, 0x7D, 0xB6, 0x6F, 0x07, 0x69, 0xEA, 0x8F, 0xD0,
+            0xB3, 0x0B, 0xA2, 0x20, 0xF4, 0x6D, 0x6F, 0x19, 0xF8, 0xBA, 0x4F, 0xD4, 0x10, 0x8D,
+            0x98, 0x6C, 0xE0, 0xAB,
+        ],
+        quirks: quirks(CompatibilityMode::SuperChip),
+    },
+];
+
+// recognizes roms by their hash, so that they run with the right quirks without the
+// user having to know which interpreter they were written for
+pub struct QuirkDatabase;
+
+impl QuirkDatabase {
+    pub fn lookup(rom: &[u8]) -> Option<QuirkSet> {
+        QuirkDatabase::identify(rom).map(|known| known.quirks)
+    }
+
+    pub fn identify(rom: &[u8]) -> Option<&'static KnownRom> {
+        let digest = Sha256::digest(rom);
+        KNOWN_ROMS
+            .iter()
+            .find(|known| known.sha256[..] == digest[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uses_superchip_instructions() {
+        // LD V0, 0x30 and DRW V0, V1, 5
+        assert!(!uses_superchip_instructions(&[0x60, 0x30, 0xD0, 0x15]));
+        // HIGH
+        assert!(uses_superchip_instructions(&[0x60, 0x30, 0x00, 0xFF]));
+        // DRW V0, V1, 0
+        assert!(uses_superchip_instructions(&[0xD0, 0x10]));
+        // LD R, V3
+        assert!(uses_superchip_instructions(&[0xF3, 0x75]));
+        // the opcodes are aligned, so 0x00 0xFE across two of them does not count
+        assert!(!uses_superchip_instructions(&[0x60, 0x00, 0xFE, 0x00]));
+    }
+
+    #[test]
+    fn test_quirk_database() {
+        let space_invaders = include_bytes!("../../roms/Space Invaders [David Winter].ch8");
+        assert_eq!(
+            "Space Invaders",
+            QuirkDatabase::identify(space_invaders).unwrap().title
+        );
+        assert_eq!(
+            Some(quirks(CompatibilityMode::SuperChip)),
+            QuirkDatabase::lookup(space_invaders)
+        );
+        // a single changed byte is another rom
+        let mut patched = space_invaders.to_vec();
+        patched[0] ^= 1;
+        assert_eq!(None, QuirkDatabase::lookup(&patched));
+    }
+}
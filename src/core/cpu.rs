@@ -1,58 +1,548 @@
+use super::audio::{PatternBuffer, SoundEvent, PATTERN_SIZE};
+use super::compat::CompatibilityMode;
+use super::debugger::Watchdog;
 use super::framebuffer::FrameBuffer;
 use super::instr::Instr;
 use super::keypad::Keypad;
-use super::memory::Memory;
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use super::memory::{Memory, MemoryError};
+#[cfg(feature = "no-std")]
+use alloc::string::String;
+#[cfg(feature = "no-std")]
+use alloc::vec::Vec;
+#[cfg(feature = "no-std")]
+use arrayvec::ArrayVec;
+use core::fmt;
+use core::fmt::{Debug, Display, Formatter, Write};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "no-std"))]
+use std::error::Error;
 
 const PC_START: u16 = 0x200;
+// the last address a two byte instruction fits at
+const PC_END: u16 = 0xFFE;
 const STACK_SIZE: usize = 16;
+// ends a test rom in test mode
+const HALT_OPCODE: u16 = 0xFFFF;
 
+#[cfg(not(feature = "no-std"))]
+type Stack = Vec<u16>;
+#[cfg(feature = "no-std")]
+type Stack = ArrayVec<u16, STACK_SIZE>;
+
+#[derive(Clone, Debug)]
+pub enum CpuError {
+    UnknownOpcode(u16),
+    StackOverflow,
+    StackUnderflow,
+    // instructions always start at even addresses
+    MisalignedPc(u16),
+    // jump outside the rom area of the memory
+    AddressOutOfRange(u16),
+    // the number of nested calls the refused call would have made
+    MaxCallDepthExceeded { depth: usize },
+    Memory(MemoryError),
+    // the program stayed on this address without changing any state, see `Watchdog`
+    Watchdog(u16),
+}
+
+impl Display for CpuError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode(opcode) => write!(f, "unknown opcode {:#06X}", opcode),
+            CpuError::StackOverflow => write!(f, "stack overflow"),
+            CpuError::StackUnderflow => write!(f, "return with empty stack"),
+            CpuError::MisalignedPc(pc) => write!(f, "misaligned program counter {:#05X}", pc),
+            CpuError::AddressOutOfRange(address) => {
+                write!(f, "jump to {:#05X} outside of the program memory", address)
+            }
+            CpuError::MaxCallDepthExceeded { depth } => {
+                write!(f, "call nested {} deep, over the maximum call depth", depth)
+            }
+            CpuError::Memory(err) => write!(f, "{}", err),
+            CpuError::Watchdog(pc) => {
+                write!(f, "stuck at {:#05X} without changing any state", pc)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+impl Error for CpuError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CpuError::Memory(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<MemoryError> for CpuError {
+    fn from(err: MemoryError) -> Self {
+        CpuError::Memory(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CpuState {
+    pub pc: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub stack: Vec<u16>,
+    // missing from the states saved before XO-CHIP audio
+    #[serde(default)]
+    pub audio: PatternBuffer,
+}
+
+// the ways the value of a register is commonly read by programs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterInfo {
+    pub raw: u8,
+    pub as_i8: i8,
+    pub as_bool: bool,
+    // hundreds, tens and units, as stored by LD B, Vx
+    pub as_bcd: (u8, u8, u8),
+}
+
+impl From<u8> for RegisterInfo {
+    fn from(raw: u8) -> Self {
+        RegisterInfo {
+            raw,
+            as_i8: raw as i8,
+            as_bool: raw != 0,
+            as_bcd: (raw / 100, raw / 10 % 10, raw % 10),
+        }
+    }
+}
+
+impl Display for RegisterInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (hundreds, tens, units) = self.as_bcd;
+        write!(
+            f,
+            "0x{:02X} ({}, {}, {}-{}-{})",
+            self.raw, self.as_i8, self.as_bool, hundreds, tens, units
+        )
+    }
+}
+
+#[derive(Clone)]
 pub struct Cpu {
-    pc: u16,         // program counter
-    v: [u8; 16],     // Vx registers
-    i: u16,          // I register
-    dt: u8,          // delay timer
-    st: u8,          // sound timer
-    stack: Vec<u16>, // stack
-    rng: ThreadRng,
+    pc: u16,      // program counter
+    v: [u8; 16],  // Vx registers
+    i: u16,       // I register
+    dt: u8,       // delay timer
+    st: u8,       // sound timer
+    stack: Stack, // stack
+    audio: PatternBuffer,
+    // whether the sound timer was running at the last tick
+    sound_playing: bool,
+    rng: SmallRng,
+    mode: CompatibilityMode,
+    // fail on odd program counters instead of fetching a misaligned word
+    strict_alignment: bool,
+    // whether VF ends up holding the result rather than the flag when it is also Vx
+    vf_result_not_flag: bool,
+    // calls nesting deeper than this fail, which catches runaway recursion early
+    max_call_depth: usize,
+    // draw sprites one row per cycle, like the original interpreter
+    scanline_accurate: bool,
+    pending_draw: Option<PendingDraw>,
+    // address of the last DRW run, which a scanline accurate draw is still drawing long
+    // after the program counter moved past it
+    draw_address: Option<u16>,
+    watchdog: Option<Watchdog>,
+    // runs FFFF as `Instr::Halt` instead of failing on it
+    test_mode: bool,
+    // set once a HALT ran, until the next restore
+    halted: bool,
+}
+
+// what a call to `Cpu::cycle` did
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cycle {
+    Executed(Instr),
+    // drew one more row of the sprite of a scanline accurate DRW, which is part of the
+    // instruction already executed
+    DrawRow(Instr),
+}
+
+// a sprite drawn one row per cycle, with the rows still to draw
+#[derive(Clone, Copy, Debug)]
+struct PendingDraw {
+    instr: Instr,
+    x: u8,
+    y: u8,
+    sprite: [u8; 15],
+    rows: usize,
+    next_row: usize,
+    collided: bool,
+}
+
+impl Debug for Cpu {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.reg_trace())?;
+        write!(f, "stack: {:04X?}", &self.stack[..])
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
+        Cpu::with_seed(random_seed())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
         Cpu {
             pc: PC_START,
             v: [0; 16],
             i: 0,
             dt: 0,
             st: 0,
-            stack: Vec::with_capacity(STACK_SIZE),
-            rng: rand::thread_rng(),
+            stack: Stack::new(),
+            audio: PatternBuffer::default(),
+            sound_playing: false,
+            rng: SmallRng::seed_from_u64(seed),
+            mode: CompatibilityMode::default(),
+            strict_alignment: false,
+            vf_result_not_flag: false,
+            max_call_depth: STACK_SIZE,
+            scanline_accurate: false,
+            pending_draw: None,
+            draw_address: None,
+            watchdog: None,
+            test_mode: false,
+            halted: false,
+        }
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            v: self.v,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            stack: self.stack.to_vec(),
+            audio: self.audio,
         }
     }
 
-    pub fn update_timers(&mut self) {
+    // a state with more return addresses than the stack holds is refused as a whole
+    pub fn restore(&mut self, state: &CpuState) -> Result<(), CpuError> {
+        if state.stack.len() > STACK_SIZE {
+            return Err(CpuError::StackOverflow);
+        }
+        self.pc = state.pc;
+        self.v = state.v;
+        self.i = state.i;
+        self.dt = state.dt;
+        self.st = state.st;
+        self.stack.clear();
+        self.stack.extend(state.stack.iter().copied());
+        self.audio = state.audio;
+        // the state does not hold a sprite being drawn row by row
+        self.pending_draw = None;
+        self.draw_address = None;
+        self.halted = false;
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            watchdog.reset();
+        }
+        Ok(())
+    }
+
+    // fills V0 to VF, I and the stack with random values, I and the return addresses
+    // pointing somewhere in the program memory
+    pub fn randomize_registers<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        rng.fill(&mut self.v);
+        self.i = rng.gen_range(PC_START, 0x1000);
+        self.stack.clear();
+        for _ in 0..rng.gen_range(0, STACK_SIZE + 1) {
+            self.stack.push(rng.gen_range(PC_START, 0x1000));
+        }
+    }
+
+    // formats all registers on a single line so that two states can be easily diffed
+    pub fn reg_trace(&self) -> String {
+        let mut trace = String::new();
+        for (i, v) in self.v.iter().enumerate() {
+            write!(trace, "V{:X}={} ", i, RegisterInfo::from(*v)).unwrap();
+        }
+        write!(
+            trace,
+            "I={:04X} PC={:04X} DT={:02X} ST={:02X} SP={}",
+            self.i,
+            self.pc,
+            self.dt,
+            self.st,
+            self.stack.len()
+        )
+        .unwrap();
+        trace
+    }
+
+    pub fn compatibility_mode(&self) -> CompatibilityMode {
+        self.mode
+    }
+
+    // the mode is checked on every instruction, so it takes effect from the next cycle
+    pub fn set_compatibility_mode_at_runtime(&mut self, mode: CompatibilityMode) {
+        self.mode = mode;
+    }
+
+    // off by default, as some roms jump over data of odd length, which the original
+    // interpreter handled just fine
+    pub fn set_strict_alignment(&mut self, strict: bool) {
+        self.strict_alignment = strict;
+    }
+
+    pub fn strict_alignment(&self) -> bool {
+        self.strict_alignment
+    }
+
+    // off by default, following the spec where VF always holds the flag of an arithmetic
+    // instruction even when it is the destination register
+    pub fn set_vf_result_not_flag(&mut self, vf_result_not_flag: bool) {
+        self.vf_result_not_flag = vf_result_not_flag;
+    }
+
+    pub fn vf_result_not_flag(&self) -> bool {
+        self.vf_result_not_flag
+    }
+
+    // off by default. when on, DRW takes one cycle per sprite row and the cycles of the
+    // following rows run no other instruction, VF being set once the last row is drawn
+    pub fn set_scanline_accurate(&mut self, scanline_accurate: bool) {
+        self.scanline_accurate = scanline_accurate;
+    }
+
+    pub fn scanline_accurate(&self) -> bool {
+        self.scanline_accurate
+    }
+
+    pub(crate) fn rng(&self) -> &SmallRng {
+        &self.rng
+    }
+
+    pub(crate) fn set_rng(&mut self, rng: SmallRng) {
+        self.rng = rng;
+    }
+
+    // stores the result of an arithmetic instruction in Vx and its flag in VF, the
+    // order only mattering when x is F
+    fn set_result_and_flag(&mut self, x: usize, result: u8, flag: u8) {
+        if self.vf_result_not_flag {
+            self.v[0xF] = flag;
+            self.v[x] = result;
+        } else {
+            self.v[x] = result;
+            self.v[0xF] = flag;
+        }
+    }
+
+    // played while the sound timer is running
+    pub fn audio_pattern(&self) -> &PatternBuffer {
+        &self.audio
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // moves execution to an address of the program memory, refusing odd addresses
+    pub fn set_pc(&mut self, addr: u16) -> Result<(), CpuError> {
+        self.jump(addr, true)
+    }
+
+    // the jump instructions only refuse odd addresses with strict alignment, since some
+    // roms jump to them on purpose
+    fn jump(&mut self, addr: u16, check_alignment: bool) -> Result<(), CpuError> {
+        if !(PC_START..=PC_END).contains(&addr) {
+            return Err(CpuError::AddressOutOfRange(addr));
+        }
+        if check_alignment && addr & 1 != 0 {
+            return Err(CpuError::MisalignedPc(addr));
+        }
+        self.pc = addr;
+        Ok(())
+    }
+
+    // the return addresses, the one of the innermost call last
+    pub fn dump_stack(&self) -> Vec<u16> {
+        self.stack.to_vec()
+    }
+
+    // the number of calls not returned from yet
+    pub fn call_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    #[deprecated(note = "renamed to `call_depth`")]
+    pub fn stack_depth(&self) -> usize {
+        self.call_depth()
+    }
+
+    // the stack holds 16 return addresses whatever the limit, so only lower limits have
+    // an effect
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    // fails with `CpuError::Watchdog` once the program counter stays on one address for
+    // `threshold` cycles in a row without changing the registers or the memory. 0 turns
+    // the watchdog off, as it is by default since many roms end by jumping to themselves
+    pub fn set_watchdog_threshold(&mut self, threshold: u64) {
+        self.watchdog = if threshold == 0 {
+            None
+        } else {
+            Some(Watchdog::new(threshold))
+        };
+    }
+
+    // test roms can end with FFFF, which otherwise is an unknown opcode
+    pub fn set_test_mode(&mut self, test_mode: bool) {
+        self.test_mode = test_mode;
+    }
+
+    pub fn test_mode(&self) -> bool {
+        self.test_mode
+    }
+
+    // whether the program ran a HALT, after which the cycles stay on it
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn watchdog_threshold(&self) -> u64 {
+        self.watchdog.as_ref().map_or(0, Watchdog::threshold)
+    }
+
+    pub fn register(&self, x: usize) -> u8 {
+        self.v[x]
+    }
+
+    pub fn register_info(&self, x: usize) -> RegisterInfo {
+        RegisterInfo::from(self.v[x])
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    pub fn tick_delay_timer(&mut self) {
         if self.dt > 0 {
             self.dt -= 1;
         }
+    }
+
+    // the sound plays from the tick that finds the timer running to the tick that finds
+    // it stopped, so even ST = 1 is heard for a tick
+    pub fn tick_sound_timer(&mut self) -> SoundEvent {
+        let was_playing = self.sound_playing;
+        self.sound_playing = self.st > 0;
         if self.st > 0 {
             self.st -= 1;
         }
+        match (was_playing, self.sound_playing) {
+            (false, true) => SoundEvent::Started,
+            (true, false) => SoundEvent::Stopped,
+            (true, true) => SoundEvent::Continuing(self.st),
+            (false, false) => SoundEvent::Silent,
+        }
     }
 
-    pub fn cycle(&mut self, frame_buffer: &mut FrameBuffer, mem: &mut Memory, keypad: &mut Keypad) {
-        let opcode = self.fetch(mem);
+    pub fn cycle(
+        &mut self,
+        frame_buffer: &mut FrameBuffer,
+        mem: &mut Memory,
+        keypad: &mut Keypad,
+    ) -> Result<Cycle, CpuError> {
+        if let Some(draw) = self.pending_draw {
+            self.draw_row(draw, frame_buffer);
+            return Ok(Cycle::DrawRow(draw.instr));
+        }
+        let address = self.pc;
+        let opcode = self.fetch(mem)?;
         self.skip(); // we read two bytes from memory so we need to increment pc by 2
-        let instr = self.decode(opcode);
-        self.exec(instr, frame_buffer, mem, keypad);
+        let instr = self.decode(opcode)?;
+        if let Instr::DrwVxVyN(..) = instr {
+            self.draw_address = Some(address);
+        }
+        self.exec(instr, frame_buffer, mem, keypad)?;
+        self.check_watchdog(instr)?;
+        Ok(Cycle::Executed(instr))
+    }
+
+    // whether a scanline accurate draw still has rows to draw, VF being set after the last
+    pub fn is_drawing(&self) -> bool {
+        self.pending_draw.is_some()
     }
 
-    fn fetch(&self, mem: &Memory) -> u16 {
-        mem.read_word(self.pc)
+    pub fn last_draw_address(&self) -> Option<u16> {
+        self.draw_address
     }
 
-    fn decode(&self, opcode: u16) -> Instr {
-        Instr::from(opcode)
+    fn check_watchdog(&mut self, instr: Instr) -> Result<(), CpuError> {
+        let watchdog = match self.watchdog.as_mut() {
+            Some(watchdog) => watchdog,
+            None => return Ok(()),
+        };
+        // waiting for a key is not being stuck, however long the wait
+        if let Instr::LdVxK(_) = instr {
+            watchdog.reset();
+            return Ok(());
+        }
+        let memory_written = matches!(instr, Instr::LdBVx(_) | Instr::LdIVx(_));
+        if watchdog.check(self.pc, &self.v, self.i, memory_written) {
+            return Err(CpuError::Watchdog(self.pc));
+        }
+        Ok(())
+    }
+
+    fn fetch(&self, mem: &Memory) -> Result<u16, CpuError> {
+        // e.g. after a JP V0 with an odd V0
+        if self.strict_alignment && self.pc & 1 != 0 {
+            return Err(CpuError::MisalignedPc(self.pc));
+        }
+        Ok(mem.try_read_word(self.pc)?)
+    }
+
+    fn decode(&self, opcode: u16) -> Result<Instr, CpuError> {
+        if self.test_mode && opcode == HALT_OPCODE {
+            return Ok(Instr::Halt);
+        }
+        Instr::decode(opcode, self.mode).ok_or(CpuError::UnknownOpcode(opcode))
+    }
+
+    // draws the next row of a scanline accurate draw, finishing it after the last row
+    fn draw_row(&mut self, mut draw: PendingDraw, frame_buffer: &mut FrameBuffer) {
+        let row = draw.next_row;
+        let y = draw.y.wrapping_add(row as u8);
+        draw.collided |= frame_buffer.draw(draw.x, y, &draw.sprite[row..row + 1]);
+        frame_buffer.set_changed(true);
+        draw.next_row += 1;
+        if draw.next_row == draw.rows {
+            self.v[0x0F] = draw.collided as u8;
+            self.pending_draw = None;
+        } else {
+            self.pending_draw = Some(draw);
+        }
     }
 
     fn exec(
@@ -61,7 +551,7 @@ impl Cpu {
         frame_buffer: &mut FrameBuffer,
         mem: &mut Memory,
         keypad: &mut Keypad,
-    ) {
+    ) -> Result<(), CpuError> {
         match instr {
             Instr::Cls => {
                 // Clear the display.
@@ -70,42 +560,48 @@ impl Cpu {
             }
             Instr::Ret => {
                 // Return from a subroutine.
-                if let Some(address) = self.stack.pop() {
-                    self.pc = address;
-                }
+                self.pc = self.stack.pop().ok_or(CpuError::StackUnderflow)?;
             }
             Instr::Jp(nnn) => {
                 // Jump to location nnn.
-                self.pc = nnn;
+                self.jump(nnn, self.strict_alignment)?;
             }
             Instr::Call(nnn) => {
                 // Call subroutine at nnn.
+                if self.stack.len() == STACK_SIZE {
+                    return Err(CpuError::StackOverflow);
+                }
+                if self.stack.len() >= self.max_call_depth {
+                    return Err(CpuError::MaxCallDepthExceeded {
+                        depth: self.stack.len() + 1,
+                    });
+                }
                 self.stack.push(self.pc);
                 self.pc = nnn;
             }
             Instr::SeVxKK(x, kk) => {
                 // Skip next instruction if Vx = kk.
-                if self.v[x] == kk {
-                    self.skip();
-                }
+                self.skip_if(self.v[x] == kk);
             }
             Instr::SneVxKK(x, kk) => {
                 // Skip next instruction if Vx != kk.
-                if self.v[x] != kk {
-                    self.skip();
-                }
+                self.skip_if(self.v[x] != kk);
             }
             Instr::SeVxVy(x, y) => {
                 // Skip next instruction if Vx = Vy.
-                if self.v[x] == self.v[y] {
-                    self.skip();
-                }
+                self.skip_if(self.v[x] == self.v[y]);
             }
             Instr::SneVxVy(x, y) => {
                 // Skip next instruction if Vx != Vy.
-                if self.v[x] != self.v[y] {
-                    self.skip();
-                }
+                self.skip_if(self.v[x] != self.v[y]);
+            }
+            Instr::SeLtVxVy(x, y) => {
+                // Skip next instruction if Vx < Vy (CHIP-8E).
+                self.skip_if(self.v[x] < self.v[y]);
+            }
+            Instr::SeGtVxVy(x, y) => {
+                // Skip next instruction if Vx > Vy (CHIP-8E).
+                self.skip_if(self.v[x] > self.v[y]);
             }
             Instr::LdVxKK(x, kk) => {
                 // Set Vx = kk.
@@ -134,30 +630,29 @@ impl Cpu {
             Instr::AddVxVy(x, y) => {
                 // Set Vx = Vx + Vy, set VF = carry.
                 let (res, overflow) = self.v[x].overflowing_add(self.v[y]);
-                self.v[0xF] = overflow as u8;
-                self.v[x] = res;
+                self.set_result_and_flag(x, res, overflow as u8);
             }
             Instr::SubVxVy(x, y) => {
                 // Set Vx = Vx - Vy, set VF = NOT borrow.
                 let (res, overflow) = self.v[x].overflowing_sub(self.v[y]);
-                self.v[0xF] = !overflow as u8;
-                self.v[x] = res;
+                self.set_result_and_flag(x, res, !overflow as u8);
             }
             Instr::SubnVxVy(x, y) => {
                 // Set Vx = Vy - Vx, set VF = NOT borrow.
                 let (res, overflow) = self.v[y].overflowing_sub(self.v[x]);
-                self.v[0xF] = !overflow as u8;
-                self.v[x] = res;
+                self.set_result_and_flag(x, res, !overflow as u8);
             }
-            Instr::ShrVx(x) => {
-                // Set Vx = Vx SHR 1.
-                self.v[0xF] = x as u8 & 1;
-                self.v[x] >>= 1;
+            Instr::ShrVx(x, y) => {
+                // Set Vx = Vx SHR 1 (Vy SHR 1 on the original CHIP-8), set VF = shifted out bit.
+                let src = if self.mode.shifts_vx_in_place() { x } else { y };
+                let shifted_out = self.v[src] & 1;
+                self.set_result_and_flag(x, self.v[src] >> 1, shifted_out);
             }
-            Instr::ShlVx(x) => {
-                // Set Vx = Vx SHL 1.
-                self.v[0xF] = (x as u8 & 0x80) >> 7;
-                self.v[x] <<= 1;
+            Instr::ShlVx(x, y) => {
+                // Set Vx = Vx SHL 1 (Vy SHL 1 on the original CHIP-8), set VF = shifted out bit.
+                let src = if self.mode.shifts_vx_in_place() { x } else { y };
+                let shifted_out = (self.v[src] & 0x80) >> 7;
+                self.set_result_and_flag(x, self.v[src] << 1, shifted_out);
             }
             Instr::LdI(nnn) => {
                 // Set I = nnn.
@@ -165,7 +660,7 @@ impl Cpu {
             }
             Instr::JpV0(nnn) => {
                 // Jump to location nnn + V0.
-                self.pc = nnn + (self.v[0] as u16);
+                self.jump(nnn + (self.v[0] as u16), self.strict_alignment)?;
             }
             Instr::RndVxKK(x, kk) => {
                 // Set Vx = random byte AND kk.
@@ -174,25 +669,35 @@ impl Cpu {
             }
             Instr::DrwVxVyN(x, y, n) => {
                 // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-                let coll = frame_buffer.draw(
-                    self.v[x] as u8,
-                    self.v[y] as u8,
-                    mem.read_data(self.i, n as u16).as_slice(),
-                );
+                // sprites are at most 15 bytes long, so they are read without allocating
+                let mut sprite = [0u8; 15];
+                let sprite = &mut sprite[..n];
+                mem.copy_to_slice(self.i, sprite)?;
+                if self.scanline_accurate && n > 0 {
+                    let mut draw = PendingDraw {
+                        instr,
+                        x: self.v[x],
+                        y: self.v[y],
+                        sprite: [0; 15],
+                        rows: n,
+                        next_row: 0,
+                        collided: false,
+                    };
+                    draw.sprite[..n].copy_from_slice(sprite);
+                    self.draw_row(draw, frame_buffer);
+                    return Ok(());
+                }
+                let coll = frame_buffer.draw(self.v[x], self.v[y], sprite);
                 frame_buffer.set_changed(true);
                 self.v[0x0F] = coll as u8;
             }
             Instr::SkpVx(x) => {
                 // Skip next instruction if key with the value of Vx is pressed.
-                if keypad.is_down(self.v[x]) {
-                    self.skip();
-                }
+                self.skip_if(keypad.is_down(self.v[x]));
             }
             Instr::SknpVx(x) => {
                 // Skip next instruction if key with the value of Vx is not pressed.
-                if !keypad.is_down(self.v[x]) {
-                    self.skip();
-                }
+                self.skip_if(!keypad.is_down(self.v[x]));
             }
             Instr::LdVxDT(x) => {
                 // Set Vx = delay timer value.
@@ -228,32 +733,67 @@ impl Cpu {
                 let hundreds = num / 100;
                 let tens = (num % 100) / 10;
                 let digits = num % 10;
-                mem.write_byte(self.i, hundreds);
-                mem.write_byte(self.i + 1, tens);
-                mem.write_byte(self.i + 2, digits);
+                mem.write_data(self.i, &[hundreds, tens, digits])?;
             }
             Instr::LdIVx(x) => {
                 // Store registers V0 through Vx in memory starting at location I.
-                mem.write_data(self.i, &self.v[0..=x])
+                mem.write_data(self.i, &self.v[0..=x])?;
+                self.i += self.mode.load_store_i_increment(x);
             }
             Instr::LdVxI(x) => {
                 // Read registers V0 through Vx from memory starting at location I.
-                mem.copy_into(&mut self.v, self.i, (x + 1) as u16);
+                mem.copy_to_slice(self.i, &mut self.v[..=x])?;
+                self.i += self.mode.load_store_i_increment(x);
+            }
+            Instr::StoreAudioPattern => {
+                // Load the 16 byte audio pattern from memory starting at location I (XO-CHIP).
+                let mut pattern = [0; PATTERN_SIZE];
+                mem.copy_to_slice(self.i, &mut pattern)?;
+                self.audio.data = pattern;
+            }
+            Instr::LdPitchVx(x) => {
+                // Set the audio pitch register = Vx (XO-CHIP).
+                self.audio.pitch = self.v[x];
+            }
+            Instr::Halt => {
+                // Stop the program on this instruction (test mode).
+                self.pc -= 2;
+                self.halted = true;
             }
             _ => {}
         }
+        Ok(())
     }
 
     fn skip(&mut self) {
         self.pc += 2;
     }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.skip();
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+pub(crate) fn random_seed() -> u64 {
+    rand::random()
+}
+
+// without std there is no source of entropy, so a fixed seed is used instead
+#[cfg(feature = "no-std")]
+pub(crate) fn random_seed() -> u64 {
+    0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::debugger::DEFAULT_WATCHDOG_THRESHOLD;
 
     #[test]
+    #[allow(non_snake_case)]
     fn test_exec_LdBVx() {
         // TODO: create frame buffer, memory and keypad only, not entire chip8
         let mut frame_buffer = FrameBuffer::default();
@@ -263,9 +803,469 @@ mod tests {
         cpu.i = 0x210;
         cpu.v[0] = 139;
         let instr = Instr::LdBVx(0);
-        cpu.exec(instr, &mut frame_buffer, &mut mem, &mut keypad);
+        cpu.exec(instr, &mut frame_buffer, &mut mem, &mut keypad)
+            .unwrap();
         assert_eq!(1, mem.read_byte(cpu.i));
         assert_eq!(3, mem.read_byte(cpu.i + 1));
         assert_eq!(9, mem.read_byte(cpu.i + 2))
     }
+
+    #[test]
+    fn test_exec_stack_errors() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        let res = cpu.exec(Instr::Ret, &mut frame_buffer, &mut mem, &mut keypad);
+        assert!(matches!(res, Err(CpuError::StackUnderflow)));
+        for _ in 0..STACK_SIZE {
+            cpu.exec(Instr::Call(0x300), &mut frame_buffer, &mut mem, &mut keypad)
+                .unwrap();
+        }
+        let res = cpu.exec(Instr::Call(0x300), &mut frame_buffer, &mut mem, &mut keypad);
+        assert!(matches!(res, Err(CpuError::StackOverflow)));
+    }
+
+    #[test]
+    fn test_exec_memory_out_of_bounds() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        // I points to the last byte, which has room for a single one
+        cpu.exec(Instr::LdI(0xFFF), &mut frame_buffer, &mut mem, &mut keypad)
+            .unwrap();
+        for instr in [
+            Instr::LdVxI(1),
+            Instr::LdIVx(1),
+            Instr::LdBVx(0),
+            Instr::DrwVxVyN(0, 0, 2),
+            Instr::StoreAudioPattern,
+        ] {
+            let res = cpu.exec(instr, &mut frame_buffer, &mut mem, &mut keypad);
+            assert!(matches!(res, Err(CpuError::Memory(_))), "{:?}", instr);
+        }
+        assert!(cpu
+            .exec(
+                Instr::DrwVxVyN(0, 0, 1),
+                &mut frame_buffer,
+                &mut mem,
+                &mut keypad
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        // JP 0x200, then LD V0, K at 0x202
+        mem.write_data(0x200, &[0x12, 0x00, 0xF0, 0x0A]).unwrap();
+        cpu.set_watchdog_threshold(DEFAULT_WATCHDOG_THRESHOLD);
+        let mut cycles = 0;
+        let err = loop {
+            cycles += 1;
+            if let Err(err) = cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad) {
+                break err;
+            }
+        };
+        // the first cycle lands on 0x200, then it takes the whole threshold to fire
+        assert_eq!(DEFAULT_WATCHDOG_THRESHOLD + 1, cycles);
+        assert!(matches!(err, CpuError::Watchdog(0x200)));
+
+        // waiting for a key press never fires it
+        cpu.set_watchdog_threshold(10);
+        cpu.set_pc(0x202).unwrap();
+        for _ in 0..100 {
+            cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        }
+        assert_eq!(0x202, cpu.pc());
+        cpu.set_watchdog_threshold(0);
+        assert_eq!(0, cpu.watchdog_threshold());
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        assert_eq!(STACK_SIZE, cpu.max_call_depth());
+        cpu.set_max_call_depth(2);
+        for _ in 0..2 {
+            cpu.exec(Instr::Call(0x300), &mut frame_buffer, &mut mem, &mut keypad)
+                .unwrap();
+        }
+        let res = cpu.exec(Instr::Call(0x300), &mut frame_buffer, &mut mem, &mut keypad);
+        assert!(matches!(
+            res,
+            Err(CpuError::MaxCallDepthExceeded { depth: 3 })
+        ));
+        assert_eq!(2, cpu.call_depth());
+        // returning makes room for another call
+        cpu.exec(Instr::Ret, &mut frame_buffer, &mut mem, &mut keypad)
+            .unwrap();
+        cpu.exec(Instr::Call(0x300), &mut frame_buffer, &mut mem, &mut keypad)
+            .unwrap();
+        assert_eq!(2, cpu.call_depth());
+    }
+
+    #[test]
+    fn test_tick_sound_timer() {
+        let mut cpu = Cpu::with_seed(0);
+        assert_eq!(SoundEvent::Silent, cpu.tick_sound_timer());
+        cpu.st = 2;
+        assert_eq!(SoundEvent::Started, cpu.tick_sound_timer());
+        assert_eq!(SoundEvent::Continuing(0), cpu.tick_sound_timer());
+        assert_eq!(SoundEvent::Stopped, cpu.tick_sound_timer());
+        assert_eq!(SoundEvent::Silent, cpu.tick_sound_timer());
+        // clearing the timer stops the sound at the next tick
+        cpu.st = 5;
+        cpu.tick_sound_timer();
+        cpu.st = 0;
+        assert_eq!(SoundEvent::Stopped, cpu.tick_sound_timer());
+    }
+
+    #[test]
+    fn test_restore_refuses_overfull_stack() {
+        let mut cpu = Cpu::new();
+        let mut state = cpu.state();
+        state.pc = 0x300;
+        state.stack = vec![PC_START; STACK_SIZE + 1];
+        assert!(matches!(cpu.restore(&state), Err(CpuError::StackOverflow)));
+        assert_eq!(PC_START, cpu.pc());
+        state.stack.pop();
+        cpu.restore(&state).unwrap();
+        assert_eq!(STACK_SIZE, cpu.call_depth());
+    }
+
+    #[test]
+    fn test_dump_stack() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        for address in &[0x300, 0x310, 0x320, 0x330, 0x340] {
+            cpu.exec(
+                Instr::Call(*address),
+                &mut frame_buffer,
+                &mut mem,
+                &mut keypad,
+            )
+            .unwrap();
+        }
+        assert_eq!(5, cpu.call_depth());
+        assert_eq!(vec![PC_START, 0x300, 0x310, 0x320, 0x330], cpu.dump_stack());
+        cpu.exec(Instr::Ret, &mut frame_buffer, &mut mem, &mut keypad)
+            .unwrap();
+        assert_eq!(vec![PC_START, 0x300, 0x310, 0x320], cpu.dump_stack());
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_exec_RndVxKK_seeded() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        let mut cpu1 = Cpu::with_seed(42);
+        let mut cpu2 = Cpu::with_seed(42);
+        for x in 0..16 {
+            cpu1.exec(
+                Instr::RndVxKK(x, 0xFF),
+                &mut frame_buffer,
+                &mut mem,
+                &mut keypad,
+            )
+            .unwrap();
+            cpu2.exec(
+                Instr::RndVxKK(x, 0xFF),
+                &mut frame_buffer,
+                &mut mem,
+                &mut keypad,
+            )
+            .unwrap();
+        }
+        assert_eq!(cpu1.v, cpu2.v);
+    }
+
+    #[test]
+    fn test_scanline_accurate_draw() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        // DRW V0, V1, 3; LD V2, 1
+        mem.write_data(PC_START, &[0xD0, 0x13, 0x62, 0x01]).unwrap();
+        mem.write_data(0x300, &[0xFF, 0xFF, 0xFF]).unwrap();
+        let mut cpu = Cpu::with_seed(0);
+        cpu.set_scanline_accurate(true);
+        cpu.i = 0x300;
+        cpu.v[0xF] = 7;
+        for rows in 1..=3 {
+            let cycle = cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+            let instr = Instr::DrwVxVyN(0, 1, 3);
+            if rows == 1 {
+                assert_eq!(Cycle::Executed(instr), cycle);
+            } else {
+                assert_eq!(Cycle::DrawRow(instr), cycle);
+            }
+            assert_eq!(rows < 3, cpu.is_drawing());
+            assert_eq!(Some(PC_START), cpu.last_draw_address());
+            assert_eq!(rows * 8, frame_buffer.count_lit_pixels());
+            assert_eq!(PC_START + 2, cpu.pc);
+        }
+        // VF is only set by the last row
+        assert_eq!(0, cpu.v[0xF]);
+        cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        assert_eq!(1, cpu.v[2]);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_exec_LdIVx_out_of_bounds() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        cpu.i = 0xffe;
+        let res = cpu.exec(Instr::LdIVx(3), &mut frame_buffer, &mut mem, &mut keypad);
+        assert!(matches!(res, Err(CpuError::Memory(_))));
+    }
+
+    #[test]
+    fn test_misaligned_pc() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        // JP 0x201; LD V0, 0x00 at an odd address
+        mem.load_rom(&[0x12, 0x01, 0x60, 0x00]);
+        cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        assert_eq!(0x201, cpu.pc);
+        cpu.set_strict_alignment(true);
+        let res = cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad);
+        assert!(matches!(res, Err(CpuError::MisalignedPc(0x201))));
+    }
+
+    #[test]
+    fn test_set_pc() {
+        let mut cpu = Cpu::new();
+        cpu.set_pc(0x2A0).unwrap();
+        assert_eq!(0x2A0, cpu.pc);
+        assert!(matches!(
+            cpu.set_pc(0x2A1),
+            Err(CpuError::MisalignedPc(0x2A1))
+        ));
+        assert!(matches!(
+            cpu.set_pc(0x1FE),
+            Err(CpuError::AddressOutOfRange(0x1FE))
+        ));
+        assert!(matches!(
+            cpu.set_pc(0x1000),
+            Err(CpuError::AddressOutOfRange(0x1000))
+        ));
+        assert_eq!(0x2A0, cpu.pc);
+
+        let mut frame_buffer = FrameBuffer::default();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        cpu.v[0] = 0xFF;
+        let res = cpu.exec(Instr::JpV0(0xF80), &mut frame_buffer, &mut mem, &mut keypad);
+        assert!(matches!(res, Err(CpuError::AddressOutOfRange(0x107F))));
+    }
+
+    #[test]
+    fn test_switch_compatibility_mode_mid_execution() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        // SHR V0, V1; LD [I], V1; SHR V0, V1; LD [I], V1
+        mem.load_rom(&[0x80, 0x16, 0xF1, 0x55, 0x80, 0x16, 0xF1, 0x55]);
+        cpu.v[0] = 0x03;
+        cpu.v[1] = 0x06;
+        cpu.i = 0x300;
+        cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        assert_eq!(0x01, cpu.v[0]);
+        assert_eq!(0x01, cpu.v[0xF]);
+        assert_eq!(0x300, cpu.i);
+
+        cpu.set_compatibility_mode_at_runtime(CompatibilityMode::Chip8);
+        cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        cpu.cycle(&mut frame_buffer, &mut mem, &mut keypad).unwrap();
+        assert_eq!(0x03, cpu.v[0]);
+        assert_eq!(0x00, cpu.v[0xF]);
+        assert_eq!(0x302, cpu.i);
+    }
+
+    #[test]
+    fn test_chip8e_skips() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        cpu.v[0] = 0x01;
+        cpu.v[1] = 0xF0;
+        let mut skips = |cpu: &mut Cpu, instr| {
+            let pc = cpu.pc;
+            cpu.exec(instr, &mut frame_buffer, &mut mem, &mut keypad)
+                .unwrap();
+            cpu.pc != pc
+        };
+        assert!(skips(&mut cpu, Instr::SeLtVxVy(0, 1)));
+        assert!(!skips(&mut cpu, Instr::SeLtVxVy(1, 0)));
+        assert!(skips(&mut cpu, Instr::SeGtVxVy(1, 0)));
+        assert!(!skips(&mut cpu, Instr::SeGtVxVy(0, 0)));
+        // read as SE Vx, Vy outside of CHIP-8E
+        assert_eq!(Some(Instr::SeVxVy(2, 3)), Instr::from(0x5231));
+        assert_eq!(
+            Some(Instr::SeVxVy(2, 3)),
+            Instr::decode(0x5232, CompatibilityMode::XoChip)
+        );
+        assert_eq!(
+            Some(Instr::SeLtVxVy(2, 3)),
+            Instr::decode(0x5231, CompatibilityMode::Chip8E)
+        );
+        assert_eq!(
+            Some(Instr::SeGtVxVy(2, 3)),
+            Instr::decode(0x5232, CompatibilityMode::Chip8E)
+        );
+    }
+
+    #[test]
+    fn test_audio_pattern_and_pitch() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        let pattern: Vec<u8> = (0..16).collect();
+        mem.write_data(0x300, &pattern).unwrap();
+        cpu.i = 0x300;
+        cpu.v[3] = 112;
+        // LD AUDIO, [I]; LD PITCH, V3
+        for opcode in [0xF002, 0xF33A] {
+            let instr = Instr::decode(opcode, CompatibilityMode::XoChip).unwrap();
+            cpu.exec(instr, &mut frame_buffer, &mut mem, &mut keypad)
+                .unwrap();
+        }
+        assert_eq!(pattern[..], cpu.audio_pattern().data[..]);
+        assert_eq!(112, cpu.audio_pattern().pitch);
+        assert_eq!(cpu.audio, cpu.state().audio);
+    }
+
+    #[test]
+    fn test_vf_as_destination() {
+        let mut frame_buffer = FrameBuffer::default();
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        let mut keypad = Keypad::default();
+        let mut exec = |cpu: &mut Cpu, instr| {
+            cpu.exec(instr, &mut frame_buffer, &mut mem, &mut keypad)
+                .unwrap()
+        };
+        cpu.v[0xF] = 0xF0;
+        cpu.v[1] = 0x20;
+        exec(&mut cpu, Instr::AddVxVy(0xF, 1));
+        assert_eq!(0x01, cpu.v[0xF]);
+        cpu.v[0xF] = 0x10;
+        exec(&mut cpu, Instr::SubVxVy(0xF, 1));
+        assert_eq!(0x00, cpu.v[0xF]);
+
+        cpu.set_vf_result_not_flag(true);
+        cpu.v[0xF] = 0xF0;
+        exec(&mut cpu, Instr::AddVxVy(0xF, 1));
+        assert_eq!(0x10, cpu.v[0xF]);
+        exec(&mut cpu, Instr::SubnVxVy(0xF, 1));
+        assert_eq!(0x10, cpu.v[0xF]);
+        exec(&mut cpu, Instr::ShlVx(0xF, 0xF));
+        assert_eq!(0x20, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn test_register_info_bcd() {
+        assert_eq!((0, 0, 0), RegisterInfo::from(0).as_bcd);
+        assert_eq!((2, 5, 5), RegisterInfo::from(255).as_bcd);
+        assert_eq!((1, 2, 8), RegisterInfo::from(128).as_bcd);
+        assert_eq!((0, 9, 9), RegisterInfo::from(99).as_bcd);
+        assert_eq!(-128, RegisterInfo::from(128).as_i8);
+        assert!(!RegisterInfo::from(0).as_bool);
+        assert_eq!(
+            "0x8D (-115, true, 1-4-1)",
+            RegisterInfo::from(0x8D).to_string()
+        );
+    }
+
+    #[test]
+    fn test_reg_trace() {
+        let mut cpu = Cpu::new();
+        cpu.v[1] = 0xFF;
+        cpu.v[0xA] = 0x3C;
+        cpu.v[0xF] = 0x01;
+        cpu.i = 0x2F0;
+        cpu.pc = 0x202;
+        cpu.dt = 0x10;
+        cpu.stack.push(0x200);
+        let zero = "0x00 (0, false, 0-0-0)";
+        assert_eq!(
+            format!(
+                "V0={0} V1=0xFF (-1, true, 2-5-5) V2={0} V3={0} V4={0} V5={0} V6={0} V7={0} \
+                 V8={0} V9={0} VA=0x3C (60, true, 0-6-0) VB={0} VC={0} VD={0} VE={0} \
+                 VF=0x01 (1, true, 0-0-1) I=02F0 PC=0202 DT=10 ST=00 SP=1",
+                zero
+            ),
+            cpu.reg_trace()
+        );
+        assert!(format!("{:?}", cpu).starts_with(&cpu.reg_trace()));
+    }
+
+    // runs the instruction on a fresh cpu with V0 to VE set, returning the cpu and memory
+    fn exec_with(instr: Instr, v: &[(usize, u8)], i: u16) -> (Cpu, Memory) {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        for &(x, value) in v {
+            cpu.v[x] = value;
+        }
+        cpu.i = i;
+        cpu.exec(
+            instr,
+            &mut FrameBuffer::default(),
+            &mut mem,
+            &mut Keypad::default(),
+        )
+        .unwrap();
+        (cpu, mem)
+    }
+
+    // VF is left out of the operands, since writing the flag would overwrite them
+    quickcheck::quickcheck! {
+        fn add_vxvy_carry_is_correct(a: u8, b: u8, x: u8, y: u8) -> bool {
+            let (x, y) = (x as usize % 0xF, y as usize % 0xF);
+            // with x == y both operands are b
+            let a = if x == y { b } else { a };
+            let (cpu, _) = exec_with(Instr::AddVxVy(x, y), &[(x, a), (y, b)], 0);
+            let sum = a as u16 + b as u16;
+            cpu.v[x] == sum as u8 && cpu.v[0xF] == (sum > 0xFF) as u8
+        }
+
+        fn sub_vxvy_borrow_is_correct(a: u8, b: u8, x: u8, y: u8) -> bool {
+            let (x, y) = (x as usize % 0xF, y as usize % 0xF);
+            let a = if x == y { b } else { a };
+            let (cpu, _) = exec_with(Instr::SubVxVy(x, y), &[(x, a), (y, b)], 0);
+            cpu.v[x] == a.wrapping_sub(b) && cpu.v[0xF] == (a >= b) as u8
+        }
+
+        fn bcd_round_trip(n: u8) -> bool {
+            let (_, mem) = exec_with(Instr::LdBVx(0), &[(0, n)], 0x300);
+            let digits = [mem.read_byte(0x300), mem.read_byte(0x301), mem.read_byte(0x302)];
+            digits.iter().all(|&digit| digit < 10)
+                && digits[0] as u16 * 100 + digits[1] as u16 * 10 + digits[2] as u16 == n as u16
+        }
+
+        fn rnd_masked_never_exceeds_mask(kk: u8) -> bool {
+            let (cpu, _) = exec_with(Instr::RndVxKK(0, kk), &[], 0);
+            cpu.v[0] & !kk == 0
+        }
+    }
 }
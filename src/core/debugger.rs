@@ -0,0 +1,72 @@
+// a threshold that lets a program sit on one address for about 20 seconds at 500 Hz
+pub const DEFAULT_WATCHDOG_THRESHOLD: u64 = 10_000;
+
+// notices a program stuck on a single address, like a jump to itself, that leaves the
+// registers and the memory untouched. many roms end that way on purpose, so it is up to
+// the caller to tell such a halt from a bug
+#[derive(Clone, Debug)]
+pub struct Watchdog {
+    threshold: u64,
+    pc: u16,
+    v: [u8; 16],
+    i: u16,
+    // cycles that ended on `pc` with the same registers, in a row
+    repeats: u64,
+}
+
+impl Watchdog {
+    pub fn new(threshold: u64) -> Self {
+        Watchdog {
+            threshold,
+            pc: 0,
+            v: [0; 16],
+            i: 0,
+            repeats: 0,
+        }
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    // called after every cycle with the state the cycle ended with, returns true once the
+    // state has stayed the same for `threshold` cycles in a row, and starts counting again
+    pub fn check(&mut self, pc: u16, v: &[u8; 16], i: u16, memory_written: bool) -> bool {
+        if memory_written || pc != self.pc || *v != self.v || i != self.i {
+            self.pc = pc;
+            self.v = *v;
+            self.i = i;
+            self.repeats = 0;
+            return false;
+        }
+        self.repeats += 1;
+        if self.repeats < self.threshold {
+            return false;
+        }
+        self.repeats = 0;
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.repeats = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut watchdog = Watchdog::new(3);
+        let v = [0; 16];
+        assert!(!watchdog.check(0x200, &v, 0, false));
+        assert!(!watchdog.check(0x200, &v, 0, false));
+        assert!(!watchdog.check(0x200, &v, 0, false));
+        // a write to memory counts as progress
+        assert!(!watchdog.check(0x200, &v, 0, true));
+        assert!(!watchdog.check(0x200, &v, 0, false));
+        assert!(!watchdog.check(0x200, &v, 0, false));
+        assert!(watchdog.check(0x200, &v, 0, false));
+    }
+}
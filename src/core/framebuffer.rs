@@ -1,9 +1,76 @@
-const BUFFER_WIDTH: usize = 64;
-const BUFFER_HEIGHT: usize = 32;
+use super::memory::FONT_SPRITES;
+#[cfg(all(feature = "pixel-history", feature = "no-std"))]
+use alloc::vec;
+#[cfg(feature = "no-std")]
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+#[cfg(not(feature = "no-std"))]
+use std::error::Error;
 
+pub const BUFFER_WIDTH: usize = 64;
+pub const BUFFER_HEIGHT: usize = 32;
+// size of the buffer packed 8 pixels per byte
+pub const PACKED_BUFFER_SIZE: usize = BUFFER_WIDTH * BUFFER_HEIGHT / 8;
+
+// number of frames kept in the pixel history
+#[cfg(feature = "pixel-history")]
+pub const PIXEL_HISTORY_DEPTH: usize = 32;
+
+// XO-CHIP has two 1-bit drawing planes
+const PLANE_COUNT: u8 = 2;
+
+// the font glyphs are 4 pixels wide and 5 rows tall
+const FONT_GLYPH_SIZE: usize = 5;
+// horizontal distance between two characters drawn by `draw_text`, one blank column apart
+const FONT_GLYPH_ADVANCE: u8 = 5;
+
+// size of the BMP file header and the BITMAPINFOHEADER that follows it
+const BMP_HEADER_SIZE: usize = 54;
+
+// why run-length encoded pixels could not be decoded
+#[derive(Debug, PartialEq)]
+pub enum RleError {
+    // the last run has a count but no value
+    OddLength,
+    InvalidPixel(u8),
+    // the number of pixels all the runs add up to
+    PixelCount(usize),
+}
+
+impl Display for RleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            RleError::OddLength => write!(f, "the last run has no pixel value"),
+            RleError::InvalidPixel(value) => write!(f, "invalid pixel value {}", value),
+            RleError::PixelCount(count) => write!(
+                f,
+                "the runs hold {} pixels instead of {}",
+                count,
+                BUFFER_WIDTH * BUFFER_HEIGHT
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+impl Error for RleError {}
+
+#[derive(Clone)]
 pub struct FrameBuffer {
+    // each pixel holds one bit per plane, so its value is the composite color index:
+    // 0 = no plane, 1 = plane 0 only, 2 = plane 1 only, 3 = both planes
     buffer: [u8; BUFFER_WIDTH * BUFFER_HEIGHT],
     has_changed: bool,
+    // bit mask of the planes selected for drawing
+    plane: u8,
+    // circular buffer of the last frames, each one a copy of the buffer
+    #[cfg(feature = "pixel-history")]
+    history: Vec<u8>,
+    #[cfg(feature = "pixel-history")]
+    history_next: usize,
+    #[cfg(feature = "pixel-history")]
+    history_len: usize,
 }
 
 impl Default for FrameBuffer {
@@ -11,6 +78,13 @@ impl Default for FrameBuffer {
         FrameBuffer {
             buffer: [0u8; BUFFER_WIDTH * BUFFER_HEIGHT],
             has_changed: false,
+            plane: 0b01,
+            #[cfg(feature = "pixel-history")]
+            history: vec![0; BUFFER_WIDTH * BUFFER_HEIGHT * PIXEL_HISTORY_DEPTH],
+            #[cfg(feature = "pixel-history")]
+            history_next: 0,
+            #[cfg(feature = "pixel-history")]
+            history_len: 0,
         }
     }
 }
@@ -20,6 +94,126 @@ impl FrameBuffer {
         &self.buffer
     }
 
+    pub fn restore(&mut self, buffer: &[u8]) {
+        self.buffer.copy_from_slice(buffer);
+        self.has_changed = true;
+    }
+
+    // (count, value) pairs for each run of equal pixels, runs longer than 255 pixels
+    // being split
+    pub fn encode_rle(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut pixels = self.buffer.iter().peekable();
+        while let Some(&value) = pixels.next() {
+            let mut count: u8 = 1;
+            while count < u8::MAX && pixels.peek() == Some(&&value) {
+                pixels.next();
+                count += 1;
+            }
+            data.push(count);
+            data.push(value);
+        }
+        data
+    }
+
+    // the runs have to cover the whole screen exactly, so that a truncated or corrupted
+    // encoding is not taken for a screen
+    pub fn decode_rle(data: &[u8]) -> Result<FrameBuffer, RleError> {
+        if data.len() % 2 != 0 {
+            return Err(RleError::OddLength);
+        }
+        let mut frame_buffer = FrameBuffer::default();
+        let mut start = 0;
+        for run in data.chunks_exact(2) {
+            let (count, value) = (run[0] as usize, run[1]);
+            if value >= 1 << PLANE_COUNT {
+                return Err(RleError::InvalidPixel(value));
+            }
+            if let Some(pixels) = frame_buffer.buffer.get_mut(start..start + count) {
+                pixels.fill(value);
+            }
+            start += count;
+        }
+        if start != frame_buffer.buffer.len() {
+            return Err(RleError::PixelCount(start));
+        }
+        frame_buffer.has_changed = true;
+        Ok(frame_buffer)
+    }
+
+    // 8 pixels per byte, the leftmost one in the most significant bit like in sprites, with
+    // the pixels lit in any plane set
+    pub fn as_1bpp_packed(&self) -> [u8; PACKED_BUFFER_SIZE] {
+        let mut data = [0u8; PACKED_BUFFER_SIZE];
+        for (byte, pixels) in data.iter_mut().zip(self.buffer.chunks_exact(8)) {
+            *byte = pixels
+                .iter()
+                .fold(0, |byte, &pixel| (byte << 1) | (pixel != 0) as u8);
+        }
+        data
+    }
+
+    // the inverse of `as_1bpp_packed`, the set pixels being lit in plane 0
+    pub fn from_1bpp_packed(data: &[u8; PACKED_BUFFER_SIZE]) -> FrameBuffer {
+        let mut frame_buffer = FrameBuffer::default();
+        for (pixels, byte) in frame_buffer.buffer.chunks_exact_mut(8).zip(data.iter()) {
+            for (bit, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = (byte >> (7 - bit)) & 1;
+            }
+        }
+        frame_buffer.has_changed = true;
+        frame_buffer
+    }
+
+    // 24-bit BMP image of the screen, one image pixel per screen pixel, with the lit
+    // pixels of any plane in color1 and the others in color0. the colors are ARGB, the
+    // alpha being dropped
+    pub fn to_bmp(&self, color0: u32, color1: u32) -> Vec<u8> {
+        // each row is padded to a multiple of 4 bytes
+        let row_size = (BUFFER_WIDTH * 3 + 3) & !3;
+        let image_size = row_size * BUFFER_HEIGHT;
+        let file_size = BMP_HEADER_SIZE + image_size;
+
+        let mut bmp = Vec::with_capacity(file_size);
+        bmp.extend_from_slice(b"BM");
+        bmp.extend_from_slice(&(file_size as u32).to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&(BMP_HEADER_SIZE as u32).to_le_bytes());
+        // BITMAPINFOHEADER
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&(BUFFER_WIDTH as i32).to_le_bytes());
+        bmp.extend_from_slice(&(BUFFER_HEIGHT as i32).to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&24u16.to_le_bytes());
+        // no compression, then the image size and 2835 pixels per meter (72 dpi) both ways
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&(image_size as u32).to_le_bytes());
+        bmp.extend_from_slice(&2835u32.to_le_bytes());
+        bmp.extend_from_slice(&2835u32.to_le_bytes());
+        // no palette
+        bmp.extend_from_slice(&[0; 8]);
+
+        // the rows are stored bottom-up, and each pixel as blue, green, red
+        for row in self.buffer.chunks(BUFFER_WIDTH).rev() {
+            for &pixel in row {
+                let color = if pixel != 0 { color1 } else { color0 };
+                bmp.extend_from_slice(&[color as u8, (color >> 8) as u8, (color >> 16) as u8]);
+            }
+            bmp.resize(bmp.len() + row_size - BUFFER_WIDTH * 3, 0);
+        }
+        bmp
+    }
+
+    // pixels drawn in any plane count as lit
+    pub fn count_lit_pixels(&self) -> usize {
+        self.buffer.iter().filter(|pixel| **pixel != 0).count()
+    }
+
+    // CRC-32 of the pixels, the same as `testing::frame_checksum` of the buffer
+    pub fn checksum(&self) -> u32 {
+        crc32(&self.buffer)
+    }
+
     pub fn has_changed(&self) -> bool {
         self.has_changed
     }
@@ -28,6 +222,14 @@ impl FrameBuffer {
         self.has_changed = changed
     }
 
+    pub fn plane(&self) -> u8 {
+        self.plane
+    }
+
+    pub fn set_plane(&mut self, plane: u8) {
+        self.plane = plane & 0b11;
+    }
+
     pub fn clear(&mut self) {
         self.buffer.iter_mut().for_each(|pixel| *pixel = 0)
     }
@@ -41,33 +243,165 @@ impl FrameBuffer {
     }
 
     pub fn draw(&mut self, x: u8, y: u8, data: &[u8]) -> bool {
+        self.draw_xo_plane(self.plane, x, y, data)
+    }
+
+    // draws the sprite in each of the planes whose bit is set in `plane`
+    pub fn draw_xo_plane(&mut self, plane: u8, x: u8, y: u8, data: &[u8]) -> bool {
+        let mut collided = false;
+        for bit in 0..PLANE_COUNT {
+            let mask = 1 << bit;
+            if plane & mask != 0 {
+                collided |= self.draw_plane_mask(mask, x, y, data);
+            }
+        }
+        collided
+    }
+
+    // draws the hex digits of the text with the built-in font, 5 pixels apart, leaving a
+    // gap for any other character. the glyphs are xored like sprites, and the result
+    // tells whether any of them turned a pixel off
+    pub fn draw_text(&mut self, x: u8, y: u8, text: &str) -> bool {
+        let mut collided = false;
+        let mut x = x;
+        for c in text.chars() {
+            if let Some(digit) = c.to_digit(16) {
+                let start = digit as usize * FONT_GLYPH_SIZE;
+                collided |= self.draw(x, y, &FONT_SPRITES[start..start + FONT_GLYPH_SIZE]);
+            }
+            x = x.wrapping_add(FONT_GLYPH_ADVANCE);
+        }
+        collided
+    }
+
+    // a blank buffer with only the sprite drawn in it, in the selected planes and wrapping
+    // around like a real draw, leaving this buffer untouched
+    pub fn draw_sprite_preview(&self, x: u8, y: u8, data: &[u8]) -> FrameBuffer {
+        let mut preview = FrameBuffer {
+            plane: self.plane,
+            ..FrameBuffer::default()
+        };
+        for (row, byte) in data.iter().enumerate() {
+            for col in 0..8 {
+                if (byte >> (7 - col)) & 0x01 == 1 {
+                    let x_idx = (x as usize + col) % BUFFER_WIDTH;
+                    let y_idx = (y as usize + row) % BUFFER_HEIGHT;
+                    preview.set_pixel(x_idx, y_idx, self.plane);
+                }
+            }
+        }
+        preview
+    }
+
+    // the (x, y) coordinates of the pixels that drawing the sprite in the selected planes
+    // would turn off, in drawing order, without drawing it
+    pub fn sprite_collision_region(&self, x: u8, y: u8, data: &[u8]) -> Vec<(u8, u8)> {
+        let mut collisions = Vec::new();
+        for (row, byte) in data.iter().enumerate() {
+            for col in 0..8 {
+                if (byte >> (7 - col)) & 0x01 == 1 {
+                    let x_idx = (x as usize + col) % BUFFER_WIDTH;
+                    let y_idx = (y as usize + row) % BUFFER_HEIGHT;
+                    if self.get_pixel(x_idx, y_idx) & self.plane != 0 {
+                        collisions.push((x_idx as u8, y_idx as u8));
+                    }
+                }
+            }
+        }
+        collisions
+    }
+
+    // the top-left corners of every place the sprite shows on screen, row by row, wrapping
+    // around the edges like a draw. the pixels of the sprite's bounding box have to match
+    // exactly, lit in any plane or unlit, so that a narrow sprite such as a font glyph is
+    // still found next to other drawings. a blank sprite is found nowhere
+    pub fn find_sprite(&self, data: &[u8]) -> Vec<(u8, u8)> {
+        let columns = data.iter().fold(0u8, |columns, byte| columns | byte);
+        if columns == 0 {
+            return Vec::new();
+        }
+        let width = 8 - columns.trailing_zeros() as usize;
+        let matches_at = |x: usize, y: usize| {
+            data.iter().enumerate().all(|(row, byte)| {
+                (0..width).all(|col| {
+                    let lit = (byte >> (7 - col)) & 0x01 == 1;
+                    let x_idx = (x + col) % BUFFER_WIDTH;
+                    let y_idx = (y + row) % BUFFER_HEIGHT;
+                    (self.get_pixel(x_idx, y_idx) != 0) == lit
+                })
+            })
+        };
+        let mut positions = Vec::new();
+        for y in 0..BUFFER_HEIGHT {
+            for x in 0..BUFFER_WIDTH {
+                if matches_at(x, y) {
+                    positions.push((x as u8, y as u8));
+                }
+            }
+        }
+        positions
+    }
+
+    fn draw_plane_mask(&mut self, mask: u8, x: u8, y: u8, data: &[u8]) -> bool {
         // each byte will represent a pixel on the screen; this means that when we get a byte
         // in input, we first need to transform each bit in a byte with values 0 or 1 (on/off).
         // one byte in input is equivalent to 8 bytes in the buffer.
         let mut collided = false;
         for (row, byte) in data.iter().enumerate() {
-            let row = row as u8;
             for col in 0..8 {
                 let new_val = (byte >> (7 - col)) & 0x01;
                 if new_val == 1 {
-                    let x_idx = ((x + col) as usize) % BUFFER_WIDTH;
-                    let y_idx = ((y + row) as usize) % BUFFER_HEIGHT;
+                    let x_idx = (x as usize + col) % BUFFER_WIDTH;
+                    let y_idx = (y as usize + row) % BUFFER_HEIGHT;
                     let old_val = self.get_pixel(x_idx, y_idx);
-                    if old_val == 1 {
+                    if old_val & mask != 0 {
                         collided = true;
                     }
-                    self.set_pixel(x_idx, y_idx, new_val ^ old_val);
+                    self.set_pixel(x_idx, y_idx, old_val ^ mask);
                 }
             }
         }
         collided
     }
 
+    // saves the current frame in the history, overwriting the oldest one when it is full
+    #[cfg(feature = "pixel-history")]
+    pub fn push_history(&mut self) {
+        let frame_size = self.buffer.len();
+        let start = self.history_next * frame_size;
+        self.history[start..start + frame_size].copy_from_slice(&self.buffer);
+        self.history_next = (self.history_next + 1) % PIXEL_HISTORY_DEPTH;
+        self.history_len = (self.history_len + 1).min(PIXEL_HISTORY_DEPTH);
+    }
+
+    // values the pixel held in the last `depth` frames of the history, oldest first, or none
+    // for a pixel outside the screen. a sprite that flickers alternates between 0 and 1
+    // while a stable one keeps the same value
+    #[cfg(feature = "pixel-history")]
+    pub fn pixel_history(&self, x: u8, y: u8, depth: usize) -> Option<Vec<u8>> {
+        if x as usize >= BUFFER_WIDTH || y as usize >= BUFFER_HEIGHT {
+            return None;
+        }
+        let frame_size = self.buffer.len();
+        let offset = y as usize * BUFFER_WIDTH + x as usize;
+        let depth = depth.min(self.history_len);
+        let values = (0..depth)
+            .rev()
+            .map(|age| {
+                let frame =
+                    (self.history_next + PIXEL_HISTORY_DEPTH - 1 - age) % PIXEL_HISTORY_DEPTH;
+                self.history[frame * frame_size + offset]
+            })
+            .collect();
+        Some(values)
+    }
+
+    #[cfg(not(feature = "no-std"))]
     pub fn dump(&self) {
         for y in 0..BUFFER_HEIGHT {
             for x in 0..BUFFER_WIDTH {
                 let val = self.buffer[y * BUFFER_WIDTH + x];
-                if val == 1 {
+                if val != 0 {
                     print!(".");
                 } else {
                     print!("X");
@@ -78,10 +412,48 @@ impl FrameBuffer {
     }
 }
 
+// the CRC-32 used by zip and png, computed bit by bit since it only runs once per frame
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_to_bmp() {
+        let mut frame_buffer = FrameBuffer::default();
+        // the top left pixel lit, the bottom left one not
+        frame_buffer.set_pixel(0, 0, 0x01);
+        let bmp = frame_buffer.to_bmp(0xFF00_0000, 0xFFFF_FFFF);
+        assert_eq!(b"BM", &bmp[..2]);
+        let file_size = BMP_HEADER_SIZE + BUFFER_WIDTH * BUFFER_HEIGHT * 3;
+        assert_eq!(file_size, bmp.len());
+        assert_eq!(
+            file_size as u32,
+            u32::from_le_bytes([bmp[2], bmp[3], bmp[4], bmp[5]])
+        );
+        // 24 bits per pixel
+        assert_eq!(24, bmp[28]);
+        // the first row of the image is the bottom one of the screen
+        assert_eq!([0x00, 0x00, 0x00], bmp[54..57]);
+        let top_row = BMP_HEADER_SIZE + (BUFFER_HEIGHT - 1) * BUFFER_WIDTH * 3;
+        assert_eq!([0xFF, 0xFF, 0xFF], bmp[top_row..top_row + 3]);
+        assert_eq!([0x00, 0x00, 0x00], bmp[top_row + 3..top_row + 6]);
+    }
+
     #[test]
     fn test_set_get_pixel() {
         let mut frame_buffer = FrameBuffer::default();
@@ -90,11 +462,182 @@ mod tests {
         assert_eq!(0x01, frame_buffer.get_pixel(54, 13))
     }
 
+    #[test]
+    fn test_sprite_collision_region() {
+        let mut frame_buffer = FrameBuffer::default();
+        // a 2x2 block in the bottom right corner
+        frame_buffer.draw(62, 30, &[0b1100_0000, 0b1100_0000]);
+        let before = frame_buffer.get_buffer().to_vec();
+        // a 3x3 block starting one pixel up and left wraps around both edges
+        let sprite = [0b1110_0000, 0b1110_0000, 0b1110_0000];
+        assert_eq!(
+            vec![(63, 31)],
+            frame_buffer.sprite_collision_region(63, 31, &sprite)
+        );
+        assert_eq!(
+            vec![(62, 30), (63, 30), (62, 31), (63, 31)],
+            frame_buffer.sprite_collision_region(62, 30, &sprite)
+        );
+        assert!(frame_buffer
+            .sprite_collision_region(0, 0, &sprite)
+            .is_empty());
+        assert_eq!(before, frame_buffer.get_buffer());
+        // pixels lit in another plane do not collide
+        frame_buffer.set_plane(0b10);
+        assert!(frame_buffer
+            .sprite_collision_region(62, 30, &sprite)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_find_sprite() {
+        let mut frame_buffer = FrameBuffer::default();
+        let ball = [0b0110_0000, 0b0110_0000];
+        frame_buffer.draw(10, 5, &ball);
+        // wrapping around the bottom right corner
+        frame_buffer.draw(63, 31, &ball);
+        // the glyphs of "00" are right next to each other
+        frame_buffer.draw_text(20, 20, "00");
+        assert_eq!(vec![(10, 5), (63, 31)], frame_buffer.find_sprite(&ball));
+        assert_eq!(
+            vec![(20, 20), (25, 20)],
+            frame_buffer.find_sprite(&FONT_SPRITES[..5])
+        );
+        assert!(frame_buffer.find_sprite(&[0b1111_1111]).is_empty());
+        assert!(frame_buffer.find_sprite(&[0, 0]).is_empty());
+    }
+
+    #[test]
+    fn test_draw_sprite_preview() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.draw(0, 0, &[0b1111_0000]);
+        let preview = frame_buffer.draw_sprite_preview(62, 0, &[0b1111_0000, 0b1000_0000]);
+        assert_eq!(5, preview.count_lit_pixels());
+        // the preview is not xored with the pixels already lit at (0, 0) and (1, 0)
+        assert_eq!(0x01, preview.get_pixel(0, 0));
+        assert_eq!(0x01, preview.get_pixel(62, 1));
+        assert_eq!(0x00, preview.get_pixel(2, 0));
+        assert_eq!(4, frame_buffer.count_lit_pixels());
+    }
+
+    #[test]
+    fn test_1bpp_packed() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.set_pixel(0, 0, 0x01);
+        frame_buffer.set_pixel(7, 0, 0x01);
+        // a pixel only in plane 1 is packed as lit too
+        frame_buffer.set_pixel(9, 31, 0x02);
+        let packed = frame_buffer.as_1bpp_packed();
+        assert_eq!(0x81, packed[0]);
+        assert_eq!(0x40, packed[31 * 8 + 1]);
+        assert_eq!(3, packed.iter().map(|byte| byte.count_ones()).sum::<u32>());
+
+        let unpacked = FrameBuffer::from_1bpp_packed(&packed);
+        assert_eq!(0x01, unpacked.get_pixel(9, 31));
+        assert_eq!(packed, unpacked.as_1bpp_packed());
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut frame_buffer = FrameBuffer::default();
+        assert!(!frame_buffer.draw_text(0, 0, "CAFE"));
+        assert_eq!(50, frame_buffer.count_lit_pixels());
+        // the top row of C, then its left side
+        for x in 0..4 {
+            assert_eq!(0x01, frame_buffer.get_pixel(x, 0));
+        }
+        assert_eq!(0x01, frame_buffer.get_pixel(0, 2));
+        assert_eq!(0x00, frame_buffer.get_pixel(1, 2));
+        assert_eq!(0x00, frame_buffer.get_pixel(4, 0));
+        // the sides of A, 5 pixels to the right
+        assert_eq!(0x01, frame_buffer.get_pixel(5, 1));
+        assert_eq!(0x00, frame_buffer.get_pixel(6, 1));
+        assert_eq!(0x01, frame_buffer.get_pixel(8, 1));
+        // the bottom row of E
+        for x in 15..19 {
+            assert_eq!(0x01, frame_buffer.get_pixel(x, 4));
+        }
+        assert_eq!(0x00, frame_buffer.get_pixel(11, 4));
+
+        // unknown characters leave a gap, and drawing the same text again clears it
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.draw_text(0, 0, "1 1");
+        assert_eq!(0x00, frame_buffer.get_pixel(7, 0));
+        assert_eq!(0x01, frame_buffer.get_pixel(12, 0));
+        assert!(frame_buffer.draw_text(0, 0, "1 1"));
+        assert_eq!(0, frame_buffer.count_lit_pixels());
+
+        // text at the bottom right edge wraps around instead of overflowing the coordinates
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.draw_text(250, 254, "FFFF");
+        // the top row of the first F, at 250 % 64 = 58 and 254 % 32 = 30
+        for x in 58..62 {
+            assert_eq!(0x01, frame_buffer.get_pixel(x, 30));
+        }
+        // its third row wraps to the top of the screen
+        assert_eq!(0x01, frame_buffer.get_pixel(58, 0));
+        assert_eq!(4 * 11, frame_buffer.count_lit_pixels());
+    }
+
+    #[test]
+    fn test_count_lit_pixels() {
+        let mut frame_buffer = FrameBuffer::default();
+        assert_eq!(0, frame_buffer.count_lit_pixels());
+        frame_buffer.draw(0, 0, &[0b1001_0111, 0b1000_0000]);
+        frame_buffer.set_pixel(10, 10, 0x03);
+        assert_eq!(7, frame_buffer.count_lit_pixels());
+    }
+
+    #[test]
+    fn test_rle_known_pattern() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.draw(0, 0, &[0b1100_0000]);
+        frame_buffer.set_pixel(63, 31, 0x03);
+        // 2 lit pixels, 2045 unlit ones split in runs of at most 255, and the last pixel
+        let mut expected = vec![2, 1];
+        expected.extend([255, 0].repeat(8));
+        expected.extend([5, 0, 1, 3]);
+        assert_eq!(expected, frame_buffer.encode_rle());
+        assert_eq!(
+            frame_buffer.get_buffer(),
+            FrameBuffer::decode_rle(&expected).unwrap().get_buffer()
+        );
+
+        // a truncated or corrupted encoding is refused
+        let truncated = &expected[..expected.len() - 2];
+        assert_eq!(
+            Some(RleError::PixelCount(2047)),
+            FrameBuffer::decode_rle(truncated).err()
+        );
+        assert_eq!(
+            Some(RleError::OddLength),
+            FrameBuffer::decode_rle(&expected[..expected.len() - 1]).err()
+        );
+        let mut corrupted = expected.clone();
+        corrupted[1] = 4;
+        assert_eq!(
+            Some(RleError::InvalidPixel(4)),
+            FrameBuffer::decode_rle(&corrupted).err()
+        );
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let mut frame_buffer = FrameBuffer::default();
+        for i in 0..16u8 {
+            frame_buffer.draw(i * 4, i * 2, &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        }
+        let encoded = frame_buffer.encode_rle();
+        assert!(encoded.len() < 400, "{} bytes", encoded.len());
+        let decoded = FrameBuffer::decode_rle(&encoded).unwrap();
+        assert_eq!(frame_buffer.get_buffer(), decoded.get_buffer());
+    }
+
     #[test]
     fn test_draw_and_collision() {
         let mut frame_buffer = FrameBuffer::default();
         let collided = frame_buffer.draw(60, 2, &[0b1001_0111]);
-        assert_eq!(false, collided);
+        assert!(!collided);
         assert_eq!(0x01, frame_buffer.get_pixel(60, 2));
         assert_eq!(0x00, frame_buffer.get_pixel(61, 2));
         assert_eq!(0x00, frame_buffer.get_pixel(62, 2));
@@ -105,7 +648,7 @@ mod tests {
         assert_eq!(0x01, frame_buffer.get_pixel(3, 2));
 
         let collided = frame_buffer.draw(62, 2, &[0b1001_0110]);
-        assert_eq!(true, collided);
+        assert!(collided);
         assert_eq!(0x01, frame_buffer.get_pixel(62, 2));
         assert_eq!(0x01, frame_buffer.get_pixel(63, 2));
         assert_eq!(0x00, frame_buffer.get_pixel(0, 2));
@@ -115,4 +658,57 @@ mod tests {
         assert_eq!(0x01, frame_buffer.get_pixel(4, 2));
         assert_eq!(0x00, frame_buffer.get_pixel(5, 2));
     }
+
+    #[test]
+    fn test_draw_xo_plane_combinations() {
+        let mut frame_buffer = FrameBuffer::default();
+        assert!(!frame_buffer.draw_xo_plane(0b00, 0, 0, &[0x80]));
+        assert_eq!(0x00, frame_buffer.get_pixel(0, 0));
+        assert!(!frame_buffer.draw_xo_plane(0b01, 1, 0, &[0x80]));
+        assert_eq!(0x01, frame_buffer.get_pixel(1, 0));
+        assert!(!frame_buffer.draw_xo_plane(0b10, 2, 0, &[0x80]));
+        assert_eq!(0x02, frame_buffer.get_pixel(2, 0));
+        assert!(!frame_buffer.draw_xo_plane(0b11, 3, 0, &[0x80]));
+        assert_eq!(0x03, frame_buffer.get_pixel(3, 0));
+    }
+
+    #[test]
+    fn test_draw_xo_plane_collision() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.draw_xo_plane(0b10, 5, 5, &[0x80]);
+        assert!(!frame_buffer.draw_xo_plane(0b01, 5, 5, &[0x80]));
+        assert_eq!(0x03, frame_buffer.get_pixel(5, 5));
+        assert!(frame_buffer.draw_xo_plane(0b10, 5, 5, &[0x80]));
+        assert_eq!(0x01, frame_buffer.get_pixel(5, 5));
+    }
+
+    #[test]
+    fn test_draw_uses_selected_plane() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.set_plane(0b10);
+        frame_buffer.draw(0, 0, &[0x80]);
+        assert_eq!(0x02, frame_buffer.get_pixel(0, 0));
+    }
+
+    #[test]
+    #[cfg(feature = "pixel-history")]
+    fn test_pixel_history() {
+        let mut frame_buffer = FrameBuffer::default();
+        for _ in 0..PIXEL_HISTORY_DEPTH + 2 {
+            // the first pixel flickers while the second one stays lit
+            frame_buffer.draw(0, 0, &[0b1000_0000]);
+            frame_buffer.set_pixel(1, 0, 1);
+            frame_buffer.push_history();
+        }
+        assert_eq!(Some(vec![1, 0, 1, 0]), frame_buffer.pixel_history(0, 0, 4));
+        assert_eq!(Some(vec![1, 1, 1, 1]), frame_buffer.pixel_history(1, 0, 4));
+        assert_eq!(
+            Some(PIXEL_HISTORY_DEPTH),
+            frame_buffer
+                .pixel_history(0, 0, 100)
+                .map(|values| values.len())
+        );
+        assert_eq!(None, frame_buffer.pixel_history(64, 0, 4));
+        assert_eq!(None, frame_buffer.pixel_history(0, 32, 4));
+    }
 }
@@ -0,0 +1,145 @@
+use super::framebuffer::{BUFFER_HEIGHT, BUFFER_WIDTH};
+#[cfg(feature = "no-std")]
+use alloc::vec::Vec;
+
+// the 16 colors of the CGA palette, black first
+const DEFAULT_PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0xAA),
+    (0x00, 0xAA, 0x00),
+    (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00),
+    (0xAA, 0x00, 0xAA),
+    (0xAA, 0x55, 0x00),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0x55, 0x55, 0xFF),
+    (0x55, 0xFF, 0x55),
+    (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55),
+    (0xFF, 0x55, 0xFF),
+    (0xFF, 0xFF, 0x55),
+    (0xFF, 0xFF, 0xFF),
+];
+
+// frame buffer of the extended variant used by some homebrew, where each pixel holds a
+// 4-bit index into a palette of 16 colors. two pixels are packed in each byte, the
+// leftmost one in the high nibble
+#[derive(Clone)]
+pub struct FrameBuffer4bpp {
+    buffer: [u8; BUFFER_WIDTH * BUFFER_HEIGHT / 2],
+    palette: [(u8, u8, u8); 16],
+}
+
+impl Default for FrameBuffer4bpp {
+    fn default() -> Self {
+        FrameBuffer4bpp {
+            buffer: [0; BUFFER_WIDTH * BUFFER_HEIGHT / 2],
+            palette: DEFAULT_PALETTE,
+        }
+    }
+}
+
+impl FrameBuffer4bpp {
+    pub fn palette(&self) -> &[(u8, u8, u8); 16] {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: [(u8, u8, u8); 16]) {
+        self.palette = palette;
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|pixels| *pixels = 0)
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        let index = y * BUFFER_WIDTH + x;
+        let pixels = self.buffer[index / 2];
+        if index % 2 == 0 {
+            pixels >> 4
+        } else {
+            pixels & 0x0F
+        }
+    }
+
+    // only the low nibble of the color is used
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u8) {
+        let index = y * BUFFER_WIDTH + x;
+        let pixels = &mut self.buffer[index / 2];
+        if index % 2 == 0 {
+            *pixels = (*pixels & 0x0F) | (color << 4);
+        } else {
+            *pixels = (*pixels & 0xF0) | (color & 0x0F);
+        }
+    }
+
+    // like the 1-bit sprites, the color is xored into each pixel whose bit is set in the
+    // sprite, wrapping around the screen. a collision is any such pixel that was not black
+    pub fn draw(&mut self, x: u8, y: u8, data: &[u8], color: u8) -> bool {
+        let mut collided = false;
+        for (row, byte) in data.iter().enumerate() {
+            for col in 0..8 {
+                if (byte >> (7 - col)) & 0x01 == 1 {
+                    let x_idx = (x as usize + col) % BUFFER_WIDTH;
+                    let y_idx = (y as usize + row) % BUFFER_HEIGHT;
+                    let old_color = self.get_pixel(x_idx, y_idx);
+                    if old_color != 0 {
+                        collided = true;
+                    }
+                    self.set_pixel(x_idx, y_idx, old_color ^ (color & 0x0F));
+                }
+            }
+        }
+        collided
+    }
+
+    // the color of each pixel as 0RGB, ready to be shown in a window
+    pub fn to_rgb(&self) -> Vec<u32> {
+        (0..BUFFER_HEIGHT)
+            .flat_map(|y| (0..BUFFER_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (r, g, b) = self.palette[self.get_pixel(x, y) as usize];
+                ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixels_are_packed_in_nibbles() {
+        let mut frame_buffer = FrameBuffer4bpp::default();
+        frame_buffer.set_pixel(0, 0, 0x3);
+        frame_buffer.set_pixel(1, 0, 0xA);
+        frame_buffer.set_pixel(63, 31, 0xF);
+        assert_eq!(0x3, frame_buffer.get_pixel(0, 0));
+        assert_eq!(0xA, frame_buffer.get_pixel(1, 0));
+        assert_eq!(0x3A, frame_buffer.buffer[0]);
+        assert_eq!(
+            0x0F,
+            frame_buffer.buffer[BUFFER_WIDTH * BUFFER_HEIGHT / 2 - 1]
+        );
+
+        let rgb = frame_buffer.to_rgb();
+        assert_eq!(0x00AAAA, rgb[0]);
+        assert_eq!(0xFFFFFF, rgb[BUFFER_WIDTH * BUFFER_HEIGHT - 1]);
+        frame_buffer.clear();
+        assert_eq!(0, frame_buffer.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn test_draw_xors_color() {
+        let mut frame_buffer = FrameBuffer4bpp::default();
+        assert!(!frame_buffer.draw(62, 0, &[0b1010_0000], 0x5));
+        assert_eq!(0x5, frame_buffer.get_pixel(62, 0));
+        assert_eq!(0x0, frame_buffer.get_pixel(63, 0));
+        // the sprite wraps around to the left edge
+        assert_eq!(0x5, frame_buffer.get_pixel(0, 0));
+        assert!(frame_buffer.draw(62, 0, &[0b1000_0000], 0x6));
+        assert_eq!(0x3, frame_buffer.get_pixel(62, 0));
+    }
+}
@@ -1,6 +1,56 @@
-use std::fmt;
-use std::fmt::{Display, Formatter};
+use super::compat::CompatibilityMode;
+use super::memory::ROM_START_ADDRESS;
+use core::convert::TryFrom;
+use core::fmt;
+use core::fmt::{Display, Formatter};
 
+pub const INSTR_TYPE_COUNT: usize = 40;
+
+// names of the instruction types, in the same order as their indices
+pub const INSTR_TYPE_NAMES: [&str; INSTR_TYPE_COUNT] = [
+    "Cls",
+    "Ret",
+    "Sys",
+    "Jp",
+    "Call",
+    "SeVxKK",
+    "SneVxKK",
+    "SeVxVy",
+    "SneVxVy",
+    "LdVxKK",
+    "AddVxKK",
+    "LdVxVy",
+    "OrVxVy",
+    "AndVxVy",
+    "XorVxVy",
+    "AddVxVy",
+    "SubVxVy",
+    "SubnVxVy",
+    "ShrVx",
+    "ShlVx",
+    "LdI",
+    "JpV0",
+    "RndVxKK",
+    "DrwVxVyN",
+    "SkpVx",
+    "SknpVx",
+    "LdVxDT",
+    "LdVxK",
+    "LdDTVx",
+    "LdSTVx",
+    "AddIVx",
+    "LdFVx",
+    "LdBVx",
+    "LdIVx",
+    "LdVxI",
+    "SeLtVxVy",
+    "SeGtVxVy",
+    "StoreAudioPattern",
+    "LdPitchVx",
+    "Halt",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Instr {
     Cls,
     Ret,
@@ -20,8 +70,8 @@ pub enum Instr {
     AddVxVy(usize, usize),
     SubVxVy(usize, usize),
     SubnVxVy(usize, usize),
-    ShrVx(usize),
-    ShlVx(usize),
+    ShrVx(usize, usize),
+    ShlVx(usize, usize),
     LdI(u16),
     JpV0(u16),
     RndVxKK(usize, u8),
@@ -37,10 +87,88 @@ pub enum Instr {
     LdBVx(usize),
     LdIVx(usize),
     LdVxI(usize),
+    // 5XY1 and 5XY2, only decoded in CHIP-8E mode, see `Instr::decode`
+    SeLtVxVy(usize, usize),
+    SeGtVxVy(usize, usize),
+    // F002 and FX3A, only decoded in XO-CHIP mode, see `Instr::decode`
+    StoreAudioPattern,
+    LdPitchVx(usize),
+    // FFFF, only decoded by a cpu in test mode, see `Cpu::set_test_mode`, since roms
+    // often hold it as sprite data
+    Halt,
 }
 
 impl Instr {
-    pub fn from(opcode: u16) -> Instr {
+    // index of the instruction type, in the range 0..INSTR_TYPE_COUNT
+    pub fn type_index(&self) -> usize {
+        match *self {
+            Instr::Cls => 0,
+            Instr::Ret => 1,
+            Instr::Sys(..) => 2,
+            Instr::Jp(..) => 3,
+            Instr::Call(..) => 4,
+            Instr::SeVxKK(..) => 5,
+            Instr::SneVxKK(..) => 6,
+            Instr::SeVxVy(..) => 7,
+            Instr::SneVxVy(..) => 8,
+            Instr::LdVxKK(..) => 9,
+            Instr::AddVxKK(..) => 10,
+            Instr::LdVxVy(..) => 11,
+            Instr::OrVxVy(..) => 12,
+            Instr::AndVxVy(..) => 13,
+            Instr::XorVxVy(..) => 14,
+            Instr::AddVxVy(..) => 15,
+            Instr::SubVxVy(..) => 16,
+            Instr::SubnVxVy(..) => 17,
+            Instr::ShrVx(..) => 18,
+            Instr::ShlVx(..) => 19,
+            Instr::LdI(..) => 20,
+            Instr::JpV0(..) => 21,
+            Instr::RndVxKK(..) => 22,
+            Instr::DrwVxVyN(..) => 23,
+            Instr::SkpVx(..) => 24,
+            Instr::SknpVx(..) => 25,
+            Instr::LdVxDT(..) => 26,
+            Instr::LdVxK(..) => 27,
+            Instr::LdDTVx(..) => 28,
+            Instr::LdSTVx(..) => 29,
+            Instr::AddIVx(..) => 30,
+            Instr::LdFVx(..) => 31,
+            Instr::LdBVx(..) => 32,
+            Instr::LdIVx(..) => 33,
+            Instr::LdVxI(..) => 34,
+            Instr::SeLtVxVy(..) => 35,
+            Instr::SeGtVxVy(..) => 36,
+            Instr::StoreAudioPattern => 37,
+            Instr::LdPitchVx(..) => 38,
+            Instr::Halt => 39,
+        }
+    }
+
+    // decodes the big-endian opcode at the start of the slice, returning the number of
+    // bytes it takes, which is always 2. None when the slice is too short or the opcode
+    // is unknown
+    pub fn from_slice(bytes: &[u8]) -> Option<(Instr, usize)> {
+        let opcode = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+        Instr::from(opcode).map(|instr| (instr, 2))
+    }
+
+    // decodes the opcode like an interpreter of the mode, which knows the instructions of
+    // `from` and those of its own extension
+    pub fn decode(opcode: u16, mode: CompatibilityMode) -> Option<Instr> {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        match (mode, opcode & 0xF00F, opcode & 0xF0FF) {
+            (CompatibilityMode::Chip8E, 0x5001, _) => Some(Instr::SeLtVxVy(x, y)),
+            (CompatibilityMode::Chip8E, 0x5002, _) => Some(Instr::SeGtVxVy(x, y)),
+            (CompatibilityMode::XoChip, _, 0xF002) if x == 0 => Some(Instr::StoreAudioPattern),
+            (CompatibilityMode::XoChip, _, 0xF03A) => Some(Instr::LdPitchVx(x)),
+            _ => Instr::from(opcode),
+        }
+    }
+
+    // decodes the instructions every interpreter knows
+    pub fn from(opcode: u16) -> Option<Instr> {
         let nibbles = (
             ((opcode & 0xF000) >> 12) as u8,
             ((opcode & 0x0F00) >> 8) as u8,
@@ -53,7 +181,7 @@ impl Instr {
         let y = nibbles.2 as usize;
         let n = nibbles.3 as usize;
 
-        match nibbles {
+        let instr = match nibbles {
             (0, 0, 0xE, 0) => Instr::Cls,
             (0, 0, 0xE, 0xE) => Instr::Ret,
             (0, _, _, _) => Instr::Sys(nnn),
@@ -70,9 +198,9 @@ impl Instr {
             (8, _, _, 3) => Instr::XorVxVy(x, y),
             (8, _, _, 4) => Instr::AddVxVy(x, y),
             (8, _, _, 5) => Instr::SubVxVy(x, y),
-            (8, _, _, 6) => Instr::ShrVx(x),
+            (8, _, _, 6) => Instr::ShrVx(x, y),
             (8, _, _, 7) => Instr::SubnVxVy(x, y),
-            (8, _, _, 0xE) => Instr::ShlVx(x),
+            (8, _, _, 0xE) => Instr::ShlVx(x, y),
             (9, _, _, 0) => Instr::SneVxVy(x, y),
             (0xA, _, _, _) => Instr::LdI(nnn),
             (0xB, _, _, _) => Instr::JpV0(nnn),
@@ -89,8 +217,9 @@ impl Instr {
             (0xF, _, 3, 3) => Instr::LdBVx(x),
             (0xF, _, 5, 5) => Instr::LdIVx(x),
             (0xF, _, 6, 5) => Instr::LdVxI(x),
-            _ => unreachable!("unknown instruction"),
-        }
+            _ => return None,
+        };
+        Some(instr)
     }
 }
 
@@ -112,9 +241,9 @@ impl Display for Instr {
             Instr::XorVxVy(x, y) => write!(f, "XOR V{}, V{}", x, y),
             Instr::AddVxVy(x, y) => write!(f, "ADD V{}, V{}", x, y),
             Instr::SubVxVy(x, y) => write!(f, "SUB V{}, V{}", x, y),
-            Instr::ShrVx(x) => write!(f, "SHR V{}", x),
+            Instr::ShrVx(x, y) => write!(f, "SHR V{}, V{}", x, y),
             Instr::SubnVxVy(x, y) => write!(f, "SUBN V{}, V{}", x, y),
-            Instr::ShlVx(x) => write!(f, "SHR V{}", x),
+            Instr::ShlVx(x, y) => write!(f, "SHL V{}, V{}", x, y),
             Instr::SneVxVy(x, y) => write!(f, "SNE V{}, V{}", x, y),
             Instr::LdI(nnn) => write!(f, "LD I, {}", nnn),
             Instr::JpV0(nnn) => write!(f, "JP V0, {}", nnn),
@@ -131,7 +260,92 @@ impl Display for Instr {
             Instr::LdBVx(x) => write!(f, "LD B, V{}", x),
             Instr::LdIVx(x) => write!(f, "LD I, V{}", x),
             Instr::LdVxI(x) => write!(f, "LD V{}, I", x),
-            _ => unreachable!("unknown instruction"),
+            Instr::SeLtVxVy(x, y) => write!(f, "SLT V{}, V{}", x, y),
+            Instr::SeGtVxVy(x, y) => write!(f, "SGT V{}, V{}", x, y),
+            Instr::StoreAudioPattern => write!(f, "LD AUDIO, [I]"),
+            Instr::LdPitchVx(x) => write!(f, "LD PITCH, V{}", x),
+            Instr::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instr::Halt => write!(f, "HALT"),
         }
     }
 }
+
+// iterates over the instructions of a rom as (address, instruction) pairs, skipping the
+// words that are not valid instructions, like sprite data. the iteration ends at the top
+// of the address space
+pub struct InstrIter<'a> {
+    rom: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> InstrIter<'a> {
+    pub fn new(rom: &'a [u8]) -> Self {
+        InstrIter { rom, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for InstrIter<'a> {
+    type Item = (u16, Instr);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset + 1 < self.rom.len() {
+            let address = u16::try_from(self.offset)
+                .ok()
+                .and_then(|offset| ROM_START_ADDRESS.checked_add(offset))?;
+            let decoded = Instr::from_slice(&self.rom[self.offset..]);
+            self.offset += 2;
+            if let Some((instr, _)) = decoded {
+                return Some((address, instr));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_slice() {
+        assert_eq!(
+            Some((Instr::LdVxKK(0x1, 0x2A), 2)),
+            Instr::from_slice(&[0x61, 0x2A, 0xFF])
+        );
+        assert_eq!(None, Instr::from_slice(&[0x61]));
+    }
+
+    #[test]
+    fn test_decode_xochip_audio() {
+        assert_eq!(None, Instr::from(0xF002));
+        assert_eq!(None, Instr::decode(0xF33A, CompatibilityMode::SuperChip));
+        assert_eq!(
+            Some(Instr::StoreAudioPattern),
+            Instr::decode(0xF002, CompatibilityMode::XoChip)
+        );
+        assert_eq!(
+            Some(Instr::LdPitchVx(3)),
+            Instr::decode(0xF33A, CompatibilityMode::XoChip)
+        );
+        assert_eq!(None, Instr::decode(0xF102, CompatibilityMode::XoChip));
+    }
+
+    #[test]
+    fn test_display_sys() {
+        assert_eq!("SYS 0x1AB", Instr::Sys(0x1AB).to_string());
+    }
+
+    #[test]
+    fn test_display_shifts() {
+        assert_eq!("SHR V1, V2", Instr::ShrVx(1, 2).to_string());
+        assert_eq!("SHL V1, V2", Instr::ShlVx(1, 2).to_string());
+    }
+
+    #[test]
+    fn test_instr_iter_skips_unknown_opcodes() {
+        // CLS; an unknown opcode; JP 0x200 and a trailing byte
+        let rom = [0x00, 0xE0, 0xFF, 0xFF, 0x12, 0x00, 0x12];
+        let instrs: Vec<(u16, Instr)> = InstrIter::new(&rom).collect();
+        assert_eq!(vec![(0x200, Instr::Cls), (0x204, Instr::Jp(0x200))], instrs);
+    }
+}
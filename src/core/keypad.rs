@@ -1,5 +1,6 @@
-const KEY_COUNT: usize = 16;
+pub const KEY_COUNT: usize = 16;
 
+#[derive(Clone)]
 pub struct Keypad {
     keys: [bool; KEY_COUNT],
 }
@@ -21,6 +22,22 @@ impl Keypad {
         self.keys[idx as usize] = down;
     }
 
+    pub fn all_keys_snapshot(&self) -> [bool; KEY_COUNT] {
+        self.keys
+    }
+
+    pub fn restore(&mut self, snapshot: [bool; KEY_COUNT]) {
+        self.keys = snapshot;
+    }
+
+    // bit i of the mask is set when key i is down
+    pub fn as_bitmask(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, down)| mask | ((*down as u16) << i))
+    }
+
     pub fn get_down_key(&self) -> Option<u8> {
         for (i, b) in self.keys.iter().enumerate() {
             if *b {
@@ -30,3 +47,18 @@ impl Keypad {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_bitmask() {
+        let mut keypad = Keypad::default();
+        assert_eq!(0x0000, keypad.as_bitmask());
+        keypad.set_down(0x0, true);
+        keypad.set_down(0x4, true);
+        keypad.set_down(0xF, true);
+        assert_eq!(0x8011, keypad.as_bitmask());
+    }
+}
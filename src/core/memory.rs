@@ -1,8 +1,27 @@
+#[cfg(feature = "no-std")]
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::cmp::Ordering;
+use core::fmt;
+use core::fmt::{Display, Formatter, Write};
+use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "no-std"))]
+use std::collections::BTreeMap;
+#[cfg(not(feature = "no-std"))]
+use std::error::Error;
+
 // 4096B
 const MEM_SIZE: u16 = 0x1000;
-const ROM_START_ADDRESS: u16 = 0x200;
+pub(crate) const ROM_START_ADDRESS: u16 = 0x200;
+
+// 16 glyphs of 5 bytes each, for the hex digits
+pub const FONT_SIZE: usize = 80;
 
-const FONT_SPRITES: [u8; 80] = [
+pub(crate) const FONT_SPRITES: [u8; FONT_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 8
     0x20, 0x60, 0x20, 0x20, 0x70, // 9
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 0
@@ -21,24 +40,68 @@ const FONT_SPRITES: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // 7
 ];
 
+#[derive(Clone, Debug)]
+pub enum MemoryError {
+    OutOfBounds { address: u16, num_bytes: u16 },
+}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            MemoryError::OutOfBounds { address, num_bytes } => write!(
+                f,
+                "illegal memory access at address {} for {} bytes",
+                address, num_bytes
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+impl Error for MemoryError {}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Memory {
+    #[serde(with = "hex_bytes")]
     bytes: Vec<u8>,
+    // labels given to addresses, shown by `hexdump`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<u16, String>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         let mut mem = Memory {
             bytes: vec![0; MEM_SIZE as usize],
+            annotations: BTreeMap::new(),
         };
 
-        // load font sprites
-        mem.write_data(0x0, &FONT_SPRITES);
+        mem.load_font(&FONT_SPRITES);
 
         mem
     }
 
+    // the font sprites are stored at the start of memory
+    pub fn load_font(&mut self, font: &[u8; FONT_SIZE]) {
+        self.write_data_unchecked(0x0, font);
+    }
+
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+
+    pub fn restore(&mut self, bytes: &[u8]) {
+        self.bytes.copy_from_slice(bytes);
+    }
+
     pub fn load_rom(&mut self, contents: &[u8]) {
-        self.write_data(ROM_START_ADDRESS, contents);
+        self.write_data_unchecked(ROM_START_ADDRESS, contents);
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
@@ -56,35 +119,171 @@ impl Memory {
         ((self.bytes[address as usize] as u16) << 8) | self.bytes[(address + 1) as usize] as u16
     }
 
+    pub fn try_read_word(&self, address: u16) -> Result<u16, MemoryError> {
+        check_mem_access(address, 2)?;
+        Ok(self.read_word(address))
+    }
+
+    // None past the end of memory, for scans that simply stop there
+    pub fn read_byte_safe(&self, address: u16) -> Option<u8> {
+        self.bytes.get(address as usize).copied()
+    }
+
+    // None when either byte is past the end of memory
+    pub fn read_word_safe(&self, address: u16) -> Option<u16> {
+        self.try_read_word(address).ok()
+    }
+
+    // compares the `len` bytes at `a` with those at `b` like two byte strings, so that a
+    // test rom can branch on which one sorts first as well as on equality
+    pub fn compare(&self, a: u16, b: u16, len: u16) -> Result<Ordering, MemoryError> {
+        check_mem_access(a, len)?;
+        check_mem_access(b, len)?;
+        let range = |address: u16| address as usize..address as usize + len as usize;
+        Ok(self.bytes[range(a)].cmp(&self.bytes[range(b)]))
+    }
+
     pub fn write_word(&mut self, address: u16, word: u16) {
         check_legal_mem_access(address, 2);
         self.bytes[address as usize] = (word >> 8) as u8;
         self.bytes[(address + 1) as usize] = word as u8;
     }
 
+    // the program area from 0x200 to the end of memory, always 3584 bytes long
+    pub fn dump_rom_region(&self) -> &[u8] {
+        &self.bytes[ROM_START_ADDRESS as usize..]
+    }
+
+    // the whole memory including the font sprites, as stored in save states
+    pub fn dump_all(&self) -> &[u8] {
+        &self.bytes
+    }
+
     pub fn read_data(&self, address: u16, num_bytes: u16) -> Vec<u8> {
         check_legal_mem_access(address, num_bytes);
-        self.bytes[(address as usize)..((address + num_bytes) as usize)].to_vec()
+        self.bytes[address as usize..address as usize + num_bytes as usize].to_vec()
+    }
+
+    // fills the whole of `dest` from memory without allocating
+    pub fn copy_to_slice(&self, address: u16, dest: &mut [u8]) -> Result<(), MemoryError> {
+        check_mem_access(address, dest.len() as u16)?;
+        dest.copy_from_slice(&self.bytes[(address as usize)..(address as usize + dest.len())]);
+        Ok(())
+    }
+
+    pub fn write_data(&mut self, address: u16, data: &[u8]) -> Result<(), MemoryError> {
+        check_mem_access(address, data.len() as u16)?;
+        self.write_data_unchecked(address, data);
+        Ok(())
     }
 
-    pub fn write_data(&mut self, address: u16, data: &[u8]) {
+    // sets `len` bytes starting at `start` to `byte`, e.g. to zero a buffer of the program
+    pub fn fill(&mut self, start: u16, len: u16, byte: u8) -> Result<(), MemoryError> {
+        check_mem_access(start, len)?;
+        self.bytes[start as usize..start as usize + len as usize].fill(byte);
+        Ok(())
+    }
+
+    // panics if the data does not fit in memory
+    pub fn write_data_unchecked(&mut self, address: u16, data: &[u8]) {
         check_legal_mem_access(address, data.len() as u16);
-        self.bytes[(address as usize)..(address as usize + data.len())].copy_from_slice(&data[..]);
+        self.bytes[(address as usize)..(address as usize + data.len())].copy_from_slice(data);
+    }
+
+    // replaces the label of the address, if any
+    pub fn annotate(&mut self, address: u16, label: &str) {
+        self.annotations.insert(address, label.to_string());
+    }
+
+    pub fn annotation(&self, address: u16) -> Option<&str> {
+        self.annotations.get(&address).map(String::as_str)
+    }
+
+    pub fn annotations(&self) -> &BTreeMap<u16, String> {
+        &self.annotations
+    }
+
+    pub fn clear_annotations(&mut self) {
+        self.annotations.clear();
+    }
+
+    // 8 bytes per line, each line starting with its address and the label of the address
+    // if it has one. a labeled address always starts a new line. the dump stops at the end
+    // of memory
+    pub fn hexdump(&self, address: u16, num_bytes: u16) -> String {
+        let num_bytes = num_bytes.min(MEM_SIZE.saturating_sub(address));
+        let mut dump = String::new();
+        let mut line_len = 0;
+        for offset in 0..num_bytes {
+            let current = address + offset;
+            let label = self.annotation(current);
+            if line_len == 8 || (line_len > 0 && label.is_some()) {
+                dump.push('\n');
+                line_len = 0;
+            }
+            if line_len == 0 {
+                write!(dump, "0x{:04X}", current).unwrap();
+                if let Some(label) = label {
+                    write!(dump, " <{}>", label).unwrap();
+                }
+                dump.push(':');
+            }
+            write!(dump, " {:02X}", self.bytes[current as usize]).unwrap();
+            line_len += 1;
+        }
+        dump
     }
 
     pub fn copy_into(&mut self, dest: &mut [u8], address: u16, num_bytes: u16) {
         check_legal_mem_access(address, num_bytes);
-        dest[0..(num_bytes as usize)]
-            .copy_from_slice(&self.bytes[(address as usize)..((address + num_bytes) as usize)]);
+        dest[..num_bytes as usize]
+            .copy_from_slice(&self.bytes[address as usize..address as usize + num_bytes as usize]);
+    }
+}
+
+// the memory contents are serialized as a single hex string, which is a lot more compact
+// than an array of 4096 numbers while still being readable
+mod hex_bytes {
+    use super::MEM_SIZE;
+    #[cfg(feature = "no-std")]
+    use alloc::{string::String, vec::Vec};
+    use core::fmt::Write;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hex = String::with_capacity(bytes.len() * 2);
+        for byte in bytes {
+            write!(hex, "{:02x}", byte).unwrap();
+        }
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if !hex.is_ascii() || hex.len() != MEM_SIZE as usize * 2 {
+            return Err(D::Error::invalid_length(
+                hex.len(),
+                &"a hex string of 8192 digits",
+            ));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+fn check_mem_access(address: u16, num_bytes: u16) -> Result<(), MemoryError> {
+    if address as usize + num_bytes as usize > MEM_SIZE as usize {
+        return Err(MemoryError::OutOfBounds { address, num_bytes });
     }
+    Ok(())
 }
 
 fn check_legal_mem_access(address: u16, num_bytes: u16) {
-    if address + num_bytes > MEM_SIZE {
-        panic!(
-            "illegal memory access at address {} for {} bytes",
-            address, num_bytes
-        );
+    if let Err(err) = check_mem_access(address, num_bytes) {
+        panic!("{}", err);
     }
 }
 
@@ -104,16 +303,44 @@ mod tests {
         mem.write_byte(0xfff, 0xff);
     }
 
+    #[test]
+    fn test_dump_regions() {
+        let mut mem = Memory::new();
+        mem.load_rom(&[0x12, 0x00]);
+        assert_eq!(3584, mem.dump_rom_region().len());
+        assert_eq!(&[0x12, 0x00, 0x00], &mem.dump_rom_region()[..3]);
+        assert_eq!(MEM_SIZE as usize, mem.dump_all().len());
+        assert_eq!(FONT_SPRITES[..], mem.dump_all()[..FONT_SIZE]);
+    }
+
     #[test]
     fn test_write_data_valid() {
         let mut mem = Memory::new();
-        mem.write_data(0x0, &[0xf1, 0x1e, 0x5a, 0x1f]);
+        mem.write_data(0x0, &[0xf1, 0x1e, 0x5a, 0x1f]).unwrap();
         assert_eq!(0xf1, mem.read_byte(0x0));
         assert_eq!(0x1e, mem.read_byte(0x01));
         assert_eq!(0x5a, mem.read_byte(0x02));
         assert_eq!(0x1f, mem.read_byte(0x03));
     }
 
+    #[test]
+    fn test_copy_to_slice() {
+        let mut mem = Memory::new();
+        mem.write_data(0xffc, &[0x12, 0x34, 0x56, 0x78]).unwrap();
+        let mut dest = [0u8; 4];
+        mem.copy_to_slice(0xffc, &mut dest).unwrap();
+        assert_eq!([0x12, 0x34, 0x56, 0x78], dest);
+        assert_eq!(dest.to_vec(), mem.read_data(0xffc, 4));
+        assert!(mem.copy_to_slice(0xffd, &mut dest).is_err());
+    }
+
+    #[test]
+    fn test_write_data_out_of_bounds() {
+        let mut mem = Memory::new();
+        assert!(mem.write_data(0xffe, &[0x01, 0x02, 0x03]).is_err());
+        assert_eq!(0x00, mem.read_byte(0xffe));
+    }
+
     #[test]
     #[should_panic(expected = "illegal memory access at address")]
     fn test_read_byte_panic() {
@@ -121,6 +348,90 @@ mod tests {
         mem.read_byte(0x1000);
     }
 
+    #[test]
+    fn test_try_read_word_out_of_bounds() {
+        let mem = Memory::new();
+        assert_eq!(0x0000, mem.try_read_word(0xffe).unwrap());
+        assert!(mem.try_read_word(0xfff).is_err());
+    }
+
+    #[test]
+    fn test_read_safe() {
+        let mut mem = Memory::new();
+        mem.write_byte(0xfff, 0xab);
+        assert_eq!(Some(0xab), mem.read_byte_safe(0xfff));
+        assert_eq!(None, mem.read_byte_safe(0x1000));
+        assert_eq!(Some(0x00ab), mem.read_word_safe(0xffe));
+        assert_eq!(None, mem.read_word_safe(0xfff));
+        assert_eq!(None, mem.read_word_safe(0xffff));
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut mem = Memory::new();
+        mem.write_data(0x300, &[1, 2, 3]).unwrap();
+        mem.write_data(0x400, &[1, 2, 3]).unwrap();
+        mem.write_data(0x500, &[1, 3, 0]).unwrap();
+        assert_eq!(Ordering::Equal, mem.compare(0x300, 0x400, 3).unwrap());
+        assert_eq!(Ordering::Less, mem.compare(0x300, 0x500, 3).unwrap());
+        assert_eq!(Ordering::Greater, mem.compare(0x500, 0x300, 2).unwrap());
+        assert_eq!(Ordering::Equal, mem.compare(0x300, 0x500, 1).unwrap());
+        assert!(mem.compare(0x300, 0xffe, 3).is_err());
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut mem = Memory::new();
+        mem.fill(0x300, 4, 0xAA).unwrap();
+        assert_eq!(vec![0, 0xAA, 0xAA, 0xAA, 0xAA, 0], mem.read_data(0x2FF, 6));
+        mem.fill(0x301, 2, 0).unwrap();
+        assert_eq!(vec![0xAA, 0, 0, 0xAA], mem.read_data(0x300, 4));
+        mem.fill(0xFFF, 1, 0x11).unwrap();
+        assert_eq!(0x11, mem.read_byte(0xFFF));
+        assert!(mem.fill(0xFFF, 2, 0).is_err());
+        // nothing is written when the area does not fit
+        assert_eq!(0x11, mem.read_byte(0xFFF));
+    }
+
+    #[test]
+    fn test_serialize_as_hex_string() {
+        let mut mem = Memory::new();
+        mem.write_word(0xffe, 0xab12);
+        let json = serde_json::to_string(&mem).unwrap();
+        assert!(json.starts_with("{\"bytes\":\"f0909090f0206020"));
+        assert!(json.ends_with("ab12\"}"));
+        assert_eq!(MEM_SIZE as usize * 2 + 12, json.len());
+
+        let restored: Memory = serde_json::from_str(&json).unwrap();
+        assert_eq!(mem.snapshot(), restored.snapshot());
+        assert!(serde_json::from_str::<Memory>("{\"bytes\":\"00ff\"}").is_err());
+    }
+
+    #[test]
+    fn test_hexdump_with_annotations() {
+        let mut mem = Memory::new();
+        mem.write_data_unchecked(0x300, &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        mem.annotate(0x300, "sprite_data");
+        mem.annotate(0x303, "tail");
+        assert_eq!(
+            "0x02FE: 00 00\n0x0300 <sprite_data>: F0 90 90\n0x0303 <tail>: 90 F0 00 00 00 00 00 00\n0x030B: 00",
+            mem.hexdump(0x2FE, 14)
+        );
+
+        // the annotations only show up in the json when there are any
+        let json = serde_json::to_string(&mem).unwrap();
+        assert!(json.ends_with("\"annotations\":{\"768\":\"sprite_data\",\"771\":\"tail\"}}"));
+        let restored: Memory = serde_json::from_str(&json).unwrap();
+        assert_eq!(Some("sprite_data"), restored.annotation(0x300));
+
+        // a range going past the end of memory is cut short
+        assert_eq!(
+            "0x0FF8: 00 00 00 00 00 00 00 00",
+            Memory::new().hexdump(0xFF8, 0x20)
+        );
+        assert_eq!("", Memory::new().hexdump(0x1000, 4));
+    }
+
     #[test]
     #[should_panic(expected = "illegal memory access at address")]
     fn test_write_word_panic() {
@@ -0,0 +1,119 @@
+use super::instr::{Instr, INSTR_TYPE_COUNT, INSTR_TYPE_NAMES};
+#[cfg(feature = "no-std")]
+use alloc::string::String;
+use core::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chip8Metrics {
+    pub cycles_total: u64,
+    // indexed by `Instr::type_index`
+    pub instructions_per_type: [u64; INSTR_TYPE_COUNT],
+    pub frame_draws_total: u64,
+    pub collision_count: u64,
+    pub timer_ticks_total: u64,
+    // memory accesses are counted in bytes, including the fetch of each opcode
+    pub memory_reads: u64,
+    pub memory_writes: u64,
+}
+
+impl Default for Chip8Metrics {
+    fn default() -> Self {
+        Chip8Metrics {
+            cycles_total: 0,
+            instructions_per_type: [0; INSTR_TYPE_COUNT],
+            frame_draws_total: 0,
+            collision_count: 0,
+            timer_ticks_total: 0,
+            memory_reads: 0,
+            memory_writes: 0,
+        }
+    }
+}
+
+impl Chip8Metrics {
+    pub(crate) fn record_cycle(&mut self, instr: &Instr, collided: bool) {
+        self.cycles_total += 1;
+        self.instructions_per_type[instr.type_index()] += 1;
+        self.memory_reads += 2;
+        match *instr {
+            Instr::DrwVxVyN(_, _, n) => {
+                self.frame_draws_total += 1;
+                self.memory_reads += n as u64;
+                if collided {
+                    self.collision_count += 1;
+                }
+            }
+            Instr::LdBVx(_) => self.memory_writes += 3,
+            Instr::LdIVx(x) => self.memory_writes += x as u64 + 1,
+            Instr::LdVxI(x) => self.memory_reads += x as u64 + 1,
+            _ => {}
+        }
+    }
+
+    // the collision of a draw that finished after the cycle it was recorded in
+    pub(crate) fn record_collision(&mut self) {
+        self.collision_count += 1;
+    }
+
+    pub(crate) fn record_timer_tick(&mut self) {
+        self.timer_ticks_total += 1;
+    }
+
+    // instruction types that were never executed are left out of the table
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let rows = [
+            ("cycles", self.cycles_total),
+            ("frame draws", self.frame_draws_total),
+            ("collisions", self.collision_count),
+            ("timer ticks", self.timer_ticks_total),
+            ("memory reads", self.memory_reads),
+            ("memory writes", self.memory_writes),
+        ];
+        for (name, value) in rows.iter() {
+            writeln!(report, "{:<16}{:>12}", name, value).unwrap();
+        }
+        writeln!(report, "instructions:").unwrap();
+        for (name, count) in INSTR_TYPE_NAMES
+            .iter()
+            .zip(self.instructions_per_type.iter())
+            .filter(|(_, count)| **count > 0)
+        {
+            writeln!(report, "  {:<14}{:>12}", name, count).unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_cycle() {
+        let mut metrics = Chip8Metrics::default();
+        metrics.record_cycle(&Instr::DrwVxVyN(0, 1, 5), true);
+        metrics.record_cycle(&Instr::LdIVx(2), false);
+        metrics.record_timer_tick();
+        assert_eq!(2, metrics.cycles_total);
+        assert_eq!(1, metrics.frame_draws_total);
+        assert_eq!(1, metrics.collision_count);
+        assert_eq!(1, metrics.timer_ticks_total);
+        assert_eq!(9, metrics.memory_reads);
+        assert_eq!(3, metrics.memory_writes);
+        assert_eq!(
+            1,
+            metrics.instructions_per_type[Instr::DrwVxVyN(0, 0, 0).type_index()]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut metrics = Chip8Metrics::default();
+        metrics.record_cycle(&Instr::Cls, false);
+        let report = metrics.report();
+        assert!(report.starts_with("cycles                     1\n"));
+        assert!(report.ends_with("instructions:\n  Cls                      1\n"));
+        assert!(!report.contains("DrwVxVyN"));
+    }
+}
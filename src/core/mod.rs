@@ -1,6 +1,15 @@
+pub mod audio;
 pub mod chip8;
-mod cpu;
-mod framebuffer;
-mod instr;
-mod keypad;
-mod memory;
+pub mod codegen;
+pub mod compat;
+pub mod cpu;
+pub mod debugger;
+pub mod framebuffer;
+pub mod framebuffer4bpp;
+pub mod instr;
+pub mod keypad;
+pub mod memory;
+pub mod metrics;
+pub mod step_log;
+pub mod timer;
+pub mod watcher;
@@ -0,0 +1,121 @@
+use super::compat::CompatibilityMode;
+#[cfg(feature = "no-std")]
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+// what happened between two screen refreshes: the cpu cycles that were run, with the
+// changes made in between, followed by the keys being set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepFrame {
+    pub cycles: u32,
+    pub keys: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<StepEvent>,
+}
+
+// a change made to the machine outside of its program, after `after_cycles` of the
+// cycles of the frame
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepEvent {
+    pub after_cycles: u32,
+    pub change: StepChange,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StepChange {
+    // a reset carrying on with the random numbers, see `Chip8::reset_keep_seed`
+    Reset,
+    // another rom loaded and the machine reset
+    LoadRom(Vec<u8>),
+    ClockHz(u64),
+    Quirks(StepQuirks),
+}
+
+// the quirks of the cpu, see the setters of `Cpu`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepQuirks {
+    pub compat_mode: CompatibilityMode,
+    pub strict_alignment: bool,
+    pub vf_result_not_flag: bool,
+    pub scanline_accurate: bool,
+}
+
+// everything needed to run a session again exactly as it went, see `Chip8::replay`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepLog {
+    pub seed: u64,
+    pub clock_hz: u64,
+    pub compat_mode: CompatibilityMode,
+    // the other quirks, missing from the logs written before they were recorded
+    #[serde(default)]
+    pub strict_alignment: bool,
+    #[serde(default)]
+    pub vf_result_not_flag: bool,
+    #[serde(default)]
+    pub scanline_accurate: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_font: Option<Vec<u8>>,
+    // the rom as loaded in memory, with any patches applied
+    pub rom: Vec<u8>,
+    pub frames: Vec<StepFrame>,
+    // the events of the frame being recorded
+    #[serde(skip)]
+    pending_events: Vec<StepEvent>,
+}
+
+impl StepLog {
+    pub fn new(seed: u64, clock_hz: u64, compat_mode: CompatibilityMode, rom: &[u8]) -> Self {
+        StepLog {
+            seed,
+            clock_hz,
+            compat_mode,
+            strict_alignment: false,
+            vf_result_not_flag: false,
+            scanline_accurate: false,
+            custom_font: None,
+            rom: rom.to_vec(),
+            frames: Vec::new(),
+            pending_events: Vec::new(),
+        }
+    }
+
+    pub fn quirks(&self) -> StepQuirks {
+        StepQuirks {
+            compat_mode: self.compat_mode,
+            strict_alignment: self.strict_alignment,
+            vf_result_not_flag: self.vf_result_not_flag,
+            scanline_accurate: self.scanline_accurate,
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks: StepQuirks) {
+        self.compat_mode = quirks.compat_mode;
+        self.strict_alignment = quirks.strict_alignment;
+        self.vf_result_not_flag = quirks.vf_result_not_flag;
+        self.scanline_accurate = quirks.scanline_accurate;
+    }
+
+    // recorded in the frame in progress, after the cycles of the frame run so far
+    pub fn push_event(&mut self, after_cycles: u32, change: StepChange) {
+        self.pending_events.push(StepEvent {
+            after_cycles,
+            change,
+        });
+    }
+
+    pub fn push_frame(&mut self, cycles: u32, keys: u16) {
+        self.frames.push(StepFrame {
+            cycles,
+            keys,
+            events: core::mem::take(&mut self.pending_events),
+        });
+    }
+}
+
+// the screen at the end of a frame of a replayed session
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameSnapshot {
+    pub frame: u64,
+    pub cycles: u64,
+    pub buffer: Vec<u8>,
+}
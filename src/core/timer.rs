@@ -0,0 +1,68 @@
+// decides on which cpu cycles the 60 Hz timers tick. the ticks are counted in fractions
+// of a tick so that clock speeds that are not a multiple of 60 Hz do not drift, such as
+// 700 Hz where a tick is due every 11.667 cycles
+#[derive(Clone, Debug)]
+pub struct TimerAccumulator {
+    ticks_per_cycle: f64,
+    accumulator: f64,
+}
+
+// 1 / n does not always add up to exactly 1 after n cycles
+const ROUNDING_TOLERANCE: f64 = 1e-9;
+
+impl TimerAccumulator {
+    // there is at most one tick per cycle
+    pub fn new(cycles_per_tick: f64) -> Self {
+        TimerAccumulator {
+            ticks_per_cycle: 1.0 / cycles_per_tick.max(1.0),
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn from_clock(clock_hz: u64, timers_hz: u64) -> Self {
+        TimerAccumulator::new(clock_hz as f64 / timers_hz as f64)
+    }
+
+    // true when the timers tick on this cycle
+    pub fn cycle(&mut self) -> bool {
+        self.accumulator += self.ticks_per_cycle;
+        if self.accumulator >= 1.0 - ROUNDING_TOLERANCE {
+            self.accumulator -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(accumulator: &mut TimerAccumulator, cycles: u64) -> u64 {
+        (0..cycles).filter(|_| accumulator.cycle()).count() as u64
+    }
+
+    #[test]
+    fn test_no_drift_at_700_hz() {
+        let mut accumulator = TimerAccumulator::from_clock(700, 60);
+        // one second of cycles ticks exactly 60 times, an hour 216000 times
+        assert_eq!(60, ticks(&mut accumulator, 700));
+        assert_eq!(216_000 - 60, ticks(&mut accumulator, 700 * 3600 - 700));
+    }
+
+    #[test]
+    fn test_whole_cycles_per_tick() {
+        for cycles_per_tick in 1..20 {
+            let mut accumulator = TimerAccumulator::new(cycles_per_tick as f64);
+            for _ in 0..100 {
+                assert_eq!(0, ticks(&mut accumulator, cycles_per_tick - 1));
+                assert!(accumulator.cycle());
+            }
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use super::cpu::CpuState;
+use super::instr::Instr;
+
+// receives a callback for each observable state change of a `Chip8`
+pub trait Chip8Watcher {
+    fn on_cpu_cycle(&mut self, state: &CpuState, instr: &Instr);
+    fn on_timer_tick(&mut self, dt: u8, st: u8);
+    fn on_frame(&mut self, buffer: &[u8]);
+    fn on_key_event(&mut self, key: u8, down: bool);
+}
@@ -0,0 +1,373 @@
+use crate::core::codegen;
+use crate::core::cpu::CpuState;
+use crate::core::instr::Instr;
+use crate::core::memory::ROM_START_ADDRESS;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+// straight run of instructions entered only at its start and left only at its end
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instrs: Vec<(u16, Instr)>,
+    pub successors: Vec<u16>,
+}
+
+// control flow graph of a rom built by following the branches from the entry point,
+// so sprite data that is never jumped to does not end up in a block. the blocks are the
+// ones `codegen` emits, and the successors of a block also include the subroutines it calls
+#[derive(Debug, Default)]
+pub struct ControlFlowGraph {
+    pub blocks: HashMap<u16, BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    pub fn from_rom(rom: &[u8]) -> Self {
+        let (leaders, _) = codegen::find_leaders(rom);
+        let mut blocks: HashMap<u16, BasicBlock> = leaders
+            .iter()
+            .map(|&start| {
+                let block = codegen::build_block(rom, start, &leaders);
+                let instrs: Vec<(u16, Instr)> = block
+                    .instrs
+                    .iter()
+                    .filter_map(|&(address, _, instr)| Some((address, instr?)))
+                    .collect();
+                let mut successors: Vec<u16> = instrs
+                    .iter()
+                    .filter_map(|&(_, instr)| match instr {
+                        Instr::Call(nnn) => Some(nnn),
+                        _ => None,
+                    })
+                    .collect();
+                successors.extend(codegen::successors(&block));
+                let block = BasicBlock {
+                    start,
+                    instrs,
+                    successors,
+                };
+                (start, block)
+            })
+            // a leader on an unknown opcode starts no block
+            .filter(|(_, block)| !block.instrs.is_empty())
+            .collect();
+        // branches to addresses outside the rom or to unknown opcodes lead nowhere
+        let starts: BTreeSet<u16> = blocks.keys().copied().collect();
+        for block in blocks.values_mut() {
+            block.successors.retain(|a| starts.contains(a));
+        }
+        ControlFlowGraph { blocks }
+    }
+
+    // graphviz source with one box per block, listing its instructions
+    pub fn to_dot(&self) -> String {
+        let mut starts: Vec<&u16> = self.blocks.keys().collect();
+        starts.sort();
+        let mut dot =
+            String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for start in &starts {
+            let label: String = self.blocks[start]
+                .instrs
+                .iter()
+                .map(|(address, instr)| format!("{:04X}: {}\\l", address, instr))
+                .collect();
+            writeln!(dot, "    \"{:04X}\" [label=\"{}\"];", start, label).unwrap();
+        }
+        for start in &starts {
+            for successor in &self.blocks[start].successors {
+                writeln!(dot, "    \"{:04X}\" -> \"{:04X}\";", start, successor).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// listing of the whole rom meant for reading rather than re-assembling: every jump or
+// call target starts a new section, headed by its name from `symbols` when there is one,
+// and the operands line up in a column. targets named in `symbols` are printed by name
+pub fn pretty_print(rom: &[u8], symbols: &HashMap<u16, String>) -> String {
+    let targets: BTreeSet<u16> = (0..rom.len())
+        .step_by(2)
+        .filter_map(|offset| decode(rom, ROM_START_ADDRESS + offset as u16))
+        .filter_map(|instr| match instr {
+            Instr::Jp(nnn) | Instr::Call(nnn) => Some(nnn),
+            _ => None,
+        })
+        .collect();
+
+    let mut output = String::new();
+    for offset in (0..rom.len()).step_by(2) {
+        let address = ROM_START_ADDRESS + offset as u16;
+        let symbol = symbols.get(&address);
+        if (targets.contains(&address) || symbol.is_some()) && !output.is_empty() {
+            output.push('\n');
+        }
+        if let Some(name) = symbol {
+            writeln!(output, "{}:", name).unwrap();
+        }
+        let line = match decode(rom, address) {
+            Some(instr) => {
+                let (mnemonic, operands) = mnemonic_and_operands(instr, symbols);
+                if operands.is_empty() {
+                    mnemonic
+                } else {
+                    format!("{:<6}{}", mnemonic, operands)
+                }
+            }
+            // an odd trailing byte or an unknown opcode, most likely sprite data
+            None => format!("{:<6}{}", "DB", hex_bytes(&rom[offset..])),
+        };
+        writeln!(output, "    {:04X}  {}", address, line).unwrap();
+    }
+    output
+}
+
+// explains what an instruction is about to do given the registers it will run with, as
+// a comment to put after its mnemonic. the quirks of the compatibility modes are not
+// known here, so instructions that depend on them are described the CHIP-8 way
+pub struct Annotator;
+
+impl Annotator {
+    pub fn annotate(instr: &Instr, state: &CpuState) -> String {
+        let v = |x: usize| state.v[x];
+        let skips = |skip: bool| if skip { "skips" } else { "does not skip" };
+        let comment = match *instr {
+            Instr::Cls => "clear the screen".to_string(),
+            Instr::Ret => match state.stack.last() {
+                Some(address) => format!("return to 0x{:03X}", address),
+                None => "return with an empty stack".to_string(),
+            },
+            Instr::Sys(nnn) => format!("machine code routine at 0x{:03X}, ignored", nnn),
+            Instr::Jp(nnn) => format!("jump to 0x{:03X}", nnn),
+            Instr::Call(nnn) => format!("call 0x{:03X}", nnn),
+            Instr::SeVxKK(x, kk) => format!("V{:X} is {}, {}", x, v(x), skips(v(x) == kk)),
+            Instr::SneVxKK(x, kk) => format!("V{:X} is {}, {}", x, v(x), skips(v(x) != kk)),
+            Instr::SeVxVy(x, y) => {
+                format!("{} and {}, {}", v(x), v(y), skips(v(x) == v(y)))
+            }
+            Instr::SneVxVy(x, y) => {
+                format!("{} and {}, {}", v(x), v(y), skips(v(x) != v(y)))
+            }
+            Instr::SeLtVxVy(x, y) => {
+                format!("{} and {}, {}", v(x), v(y), skips(v(x) < v(y)))
+            }
+            Instr::SeGtVxVy(x, y) => {
+                format!("{} and {}, {}", v(x), v(y), skips(v(x) > v(y)))
+            }
+            Instr::LdVxKK(x, kk) => format!("V{:X} = {}", x, kk),
+            Instr::AddVxKK(x, kk) => {
+                format!("V{:X} = {} + {} = {}", x, v(x), kk, v(x).wrapping_add(kk))
+            }
+            Instr::LdVxVy(x, y) => format!("V{:X} = {}", x, v(y)),
+            Instr::OrVxVy(x, y) => format!("V{:X} = {} | {} = {}", x, v(x), v(y), v(x) | v(y)),
+            Instr::AndVxVy(x, y) => format!("V{:X} = {} & {} = {}", x, v(x), v(y), v(x) & v(y)),
+            Instr::XorVxVy(x, y) => format!("V{:X} = {} ^ {} = {}", x, v(x), v(y), v(x) ^ v(y)),
+            Instr::AddVxVy(x, y) => {
+                let (sum, carry) = v(x).overflowing_add(v(y));
+                format!(
+                    "V{:X} = {} + {} = {}, VF = {}",
+                    x,
+                    v(x),
+                    v(y),
+                    sum,
+                    carry as u8
+                )
+            }
+            Instr::SubVxVy(x, y) => {
+                let (diff, borrow) = v(x).overflowing_sub(v(y));
+                format!(
+                    "V{:X} = {} - {} = {}, VF = {}",
+                    x,
+                    v(x),
+                    v(y),
+                    diff,
+                    !borrow as u8
+                )
+            }
+            Instr::SubnVxVy(x, y) => {
+                let (diff, borrow) = v(y).overflowing_sub(v(x));
+                format!(
+                    "V{:X} = {} - {} = {}, VF = {}",
+                    x,
+                    v(y),
+                    v(x),
+                    diff,
+                    !borrow as u8
+                )
+            }
+            Instr::ShrVx(x, y) => format!("V{:X} = {} >> 1 = {}", x, v(y), v(y) >> 1),
+            Instr::ShlVx(x, y) => format!("V{:X} = {} << 1 = {}", x, v(y), v(y) << 1),
+            Instr::LdI(nnn) => format!("I = 0x{:03X}", nnn),
+            Instr::JpV0(nnn) => format!("jump to 0x{:03X}", nnn.wrapping_add(v(0) as u16)),
+            Instr::RndVxKK(x, kk) => format!("V{:X} = random & 0x{:02X}", x, kk),
+            Instr::DrwVxVyN(x, y, n) => format!("draw {} rows at ({}, {})", n, v(x), v(y)),
+            Instr::SkpVx(x) => format!("skip if key {:X} is down", v(x)),
+            Instr::SknpVx(x) => format!("skip if key {:X} is up", v(x)),
+            Instr::LdVxDT(x) => format!("V{:X} = delay timer = {}", x, state.dt),
+            Instr::LdVxK(x) => format!("wait for a key and store it in V{:X}", x),
+            Instr::LdDTVx(x) => format!("delay timer = {}", v(x)),
+            Instr::LdSTVx(x) => format!("sound timer = {}", v(x)),
+            Instr::AddIVx(x) => format!(
+                "I = 0x{:03X} + {} = 0x{:03X}",
+                state.i,
+                v(x),
+                state.i.wrapping_add(v(x) as u16)
+            ),
+            Instr::LdFVx(x) => format!("I = sprite of digit {:X}", v(x) & 0xF),
+            Instr::LdBVx(x) => format!(
+                "store BCD of {} ({},{},{}) at I",
+                v(x),
+                v(x) / 100,
+                v(x) / 10 % 10,
+                v(x) % 10
+            ),
+            Instr::LdIVx(x) => format!("store V0 to V{:X} at 0x{:03X}", x, state.i),
+            Instr::LdVxI(x) => format!("load V0 to V{:X} from 0x{:03X}", x, state.i),
+            Instr::StoreAudioPattern => format!("load the audio pattern from 0x{:03X}", state.i),
+            Instr::LdPitchVx(x) => format!("pitch = {}", v(x)),
+            Instr::Halt => "end the test rom".to_string(),
+        };
+        format!("; {}", comment)
+    }
+}
+
+// splits an instruction into its mnemonic and its operands, writing the jump and call
+// targets named in `symbols` by name
+pub(crate) fn mnemonic_and_operands(
+    instr: Instr,
+    symbols: &HashMap<u16, String>,
+) -> (String, String) {
+    match instr {
+        Instr::Jp(nnn) => ("JP".to_string(), target(nnn, symbols)),
+        Instr::Call(nnn) => ("CALL".to_string(), target(nnn, symbols)),
+        instr => {
+            let text = instr.to_string();
+            match text.find(' ') {
+                Some(space) => (text[..space].to_string(), text[space + 1..].to_string()),
+                None => (text, String::new()),
+            }
+        }
+    }
+}
+
+fn target(address: u16, symbols: &HashMap<u16, String>) -> String {
+    symbols
+        .get(&address)
+        .cloned()
+        .unwrap_or_else(|| format!("0x{:03X}", address))
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take(2)
+        .map(|byte| format!("0x{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn decode(rom: &[u8], address: u16) -> Option<Instr> {
+    let offset = address.checked_sub(ROM_START_ADDRESS)? as usize;
+    Instr::from_slice(rom.get(offset..)?).map(|(instr, _)| instr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::cpu::Cpu;
+
+    #[test]
+    fn test_from_rom_splits_blocks_at_branches() {
+        let rom = [
+            0x60, 0x00, // 0200: LD V0, 0
+            0x70, 0x01, // 0202: ADD V0, 1
+            0x30, 0x05, // 0204: SE V0, 5
+            0x12, 0x02, // 0206: JP 0x202
+            0x22, 0x0C, // 0208: CALL 0x20C
+            0x12, 0x0A, // 020A: JP 0x20A
+            0x00, 0xEE, // 020C: RET
+            0xF0, 0xF0, // sprite data, never reached
+        ];
+        let cfg = ControlFlowGraph::from_rom(&rom);
+        let mut starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+        starts.sort();
+        assert_eq!(vec![0x200, 0x202, 0x206, 0x208, 0x20A, 0x20C], starts);
+        assert_eq!(vec![0x202], cfg.blocks[&0x200].successors);
+        assert_eq!(2, cfg.blocks[&0x202].instrs.len());
+        assert_eq!(vec![0x206, 0x208], cfg.blocks[&0x202].successors);
+        assert_eq!(vec![0x20C, 0x20A], cfg.blocks[&0x208].successors);
+        assert!(cfg.blocks[&0x20C].successors.is_empty());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let cfg = ControlFlowGraph::from_rom(&[0x60, 0x02, 0x12, 0x00]);
+        assert_eq!(
+            "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n    \
+             \"0200\" [label=\"0200: LD V0, 2\\l0202: JP 512\\l\"];\n    \
+             \"0200\" -> \"0200\";\n}\n",
+            cfg.to_dot()
+        );
+    }
+
+    #[test]
+    fn test_annotate() {
+        let mut state = Cpu::with_seed(0).state();
+        state.v[0] = 10;
+        state.v[1] = 20;
+        assert_eq!(
+            "; draw 5 rows at (10, 20)",
+            Annotator::annotate(&Instr::DrwVxVyN(0, 1, 5), &state)
+        );
+        state.v[0] = 139;
+        assert_eq!(
+            "; store BCD of 139 (1,3,9) at I",
+            Annotator::annotate(&Instr::LdBVx(0), &state)
+        );
+        state.v[0] = 5;
+        assert_eq!(
+            "; jump to 0x305",
+            Annotator::annotate(&Instr::JpV0(0x300), &state)
+        );
+        assert_eq!(
+            "; V0 is 5, skips",
+            Annotator::annotate(&Instr::SeVxKK(0, 5), &state)
+        );
+        assert_eq!(
+            "; 5 and 20, does not skip",
+            Annotator::annotate(&Instr::SeGtVxVy(0, 1), &state)
+        );
+        // only SUPER-CHIP draws 16x16 sprites
+        assert_eq!(
+            "; draw 0 rows at (5, 20)",
+            Annotator::annotate(&Instr::DrwVxVyN(0, 1, 0), &state)
+        );
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let rom = [
+            0x22, 0x08, // 0200: CALL 0x208
+            0x12, 0x06, // 0202: JP 0x206
+            0x60, 0x01, // 0204: LD V0, 1
+            0x12, 0x06, // 0206: JP 0x206
+            0x00, 0xEE, // 0208: RET
+            0xF0, // trailing sprite byte
+        ];
+        let mut symbols = HashMap::new();
+        symbols.insert(0x208, "draw".to_string());
+        assert_eq!(
+            "    0200  CALL  draw\n    \
+             0202  JP    0x206\n    \
+             0204  LD    V0, 1\n\
+             \n    \
+             0206  JP    0x206\n\
+             \n\
+             draw:\n    \
+             0208  RET\n    \
+             020A  DB    0xF0\n",
+            pretty_print(&rom, &symbols)
+        );
+    }
+}
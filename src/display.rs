@@ -0,0 +1,626 @@
+use crate::config::hotkeys::{Hotkey, HotkeyCode};
+use crate::config::Config;
+use crate::core::keypad::{Keypad, KEY_COUNT};
+use crate::error::ChipottoError;
+use crate::memory_view::{MEMORY_VIEW_HEIGHT, MEMORY_VIEW_WIDTH};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, SetTitle};
+use crossterm::{event, execute, queue, terminal};
+use minifb::{Key, KeyRepeat, Menu, MenuHandle, Scale, ScaleMode, Window, WindowOptions};
+use std::io;
+use std::io::{Stdout, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WINDOW_WIDTH: usize = 64;
+const WINDOW_HEIGHT: usize = 32;
+
+// value of the background pixels covered by the grid overlay, after the four plane masks
+pub const GRID_PIXEL: u8 = 4;
+
+const MENU_OPEN_ROM: usize = 1;
+const MENU_RESET: usize = 2;
+// the id of a recent rom item is this base plus the index in the recent roms list
+const MENU_RECENT_ROM: usize = 100;
+
+const KEYS: [Key; KEY_COUNT] = [
+    Key::X,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Q,
+    Key::W,
+    Key::E,
+    Key::A,
+    Key::S,
+    Key::D,
+    Key::Z,
+    Key::C,
+    Key::Key4,
+    Key::R,
+    Key::F,
+    Key::V,
+];
+
+// the same layout as KEYS, for the characters read from the terminal
+pub(crate) const ASCII_KEYS: [char; KEY_COUNT] = [
+    'x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v',
+];
+
+// most terminals only report key presses, so a key is considered down for this long
+// after the last press or auto repeat
+const ASCII_KEY_HOLD: Duration = Duration::from_millis(150);
+
+const F_KEYS: [Key; 12] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+];
+
+// keys handled by the emulator itself rather than by the rom
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HostKey {
+    // moves and selects in the launcher
+    Up,
+    Down,
+    Select,
+    // any key that can be bound to an action, whether it is bound or not
+    Hotkey(Hotkey),
+}
+
+// keys outside the CHIP-8 keypad that can be held to run at maximum speed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TurboKey {
+    Tab,
+    Space,
+    Backspace,
+    // F1 to F12
+    F(u8),
+}
+
+impl FromStr for TurboKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tab" => Ok(TurboKey::Tab),
+            "space" => Ok(TurboKey::Space),
+            "backspace" => Ok(TurboKey::Backspace),
+            key => match key.strip_prefix('f').map(u8::from_str) {
+                Some(Ok(n)) if (1..=12).contains(&n) => Ok(TurboKey::F(n)),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+impl TurboKey {
+    // the hotkey of the same key, which cannot be bound to an action as well
+    pub fn hotkey(self) -> Hotkey {
+        Hotkey::new(match self {
+            TurboKey::Tab => HotkeyCode::Tab,
+            TurboKey::Space => HotkeyCode::Space,
+            TurboKey::Backspace => HotkeyCode::Backspace,
+            TurboKey::F(n) => HotkeyCode::F(n),
+        })
+    }
+
+    fn minifb_key(self) -> Key {
+        match self {
+            TurboKey::Tab => Key::Tab,
+            TurboKey::Space => Key::Space,
+            TurboKey::Backspace => Key::Backspace,
+            TurboKey::F(n) => F_KEYS[n as usize - 1],
+        }
+    }
+
+    fn key_code(self) -> KeyCode {
+        match self {
+            TurboKey::Tab => KeyCode::Tab,
+            TurboKey::Space => KeyCode::Char(' '),
+            TurboKey::Backspace => KeyCode::Backspace,
+            TurboKey::F(n) => KeyCode::F(n),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuAction {
+    OpenRom,
+    Reset,
+    // index in the recent roms list
+    RecentRom(usize),
+}
+
+// where the frame buffer is shown and the keys are read from
+pub trait DisplayBackend {
+    // draws a frame buffer holding the plane mask of each pixel
+    fn render(&mut self, buffer: &[u8], width: usize, height: usize) -> Result<(), ChipottoError>;
+
+    // false once the user asked to quit
+    fn is_open(&self) -> bool;
+
+    fn handle_keys(&self, keypad: &mut Keypad);
+
+    fn is_turbo_key_down(&self, key: TurboKey) -> bool;
+
+    // the host keys pressed since the last call, to be called once per frame
+    fn host_keys_pressed(&mut self) -> Vec<HostKey>;
+
+    fn set_title(&mut self, title: &str);
+
+    // makes render wait until at least frame_duration passed since the previous frame
+    fn limit_update_rate(&mut self, frame_duration: Option<Duration>);
+
+    // the colors of the first two planes, changed by the profile of each rom
+    fn set_colors(&mut self, _color1: (u8, u8, u8), _color2: (u8, u8, u8)) {}
+
+    // only backends with a menu bar show the recent roms
+    fn set_recent_roms(&mut self, _paths: &[PathBuf]) -> Result<(), ChipottoError> {
+        Ok(())
+    }
+
+    fn menu_action(&mut self) -> Option<MenuAction> {
+        None
+    }
+}
+
+pub struct MinifbDisplay {
+    window: Window,
+    // the color of each plane mask
+    palette: [(u8, u8, u8); 5],
+    menu_handle: Option<MenuHandle>,
+}
+
+impl MinifbDisplay {
+    pub fn new(config: &Config) -> Result<Self, ChipottoError> {
+        let window = Window::new(
+            "Chipotto",
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+            WindowOptions {
+                resize: true,
+                scale: Scale::X8,
+                scale_mode: ScaleMode::AspectRatioStretch,
+                ..WindowOptions::default()
+            },
+        )?;
+        Ok(MinifbDisplay {
+            window,
+            palette: [
+                config.color1,
+                config.color2,
+                config.color3,
+                config.color4,
+                midpoint(config.color1, config.color2),
+            ],
+            menu_handle: None,
+        })
+    }
+}
+
+impl DisplayBackend for MinifbDisplay {
+    fn render(&mut self, buffer: &[u8], width: usize, height: usize) -> Result<(), ChipottoError> {
+        let buffer: Vec<u32> = buffer
+            .iter()
+            .map(|pixel| match self.palette.get(*pixel as usize) {
+                Some((r, g, b)) => from_u8_rgb(*r, *g, *b),
+                None => unreachable!("no such value should exist in the frame buffer"),
+            })
+            .collect();
+        self.window
+            .update_with_buffer(buffer.as_slice(), width, height)?;
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    fn handle_keys(&self, keypad: &mut Keypad) {
+        for (i, k) in KEYS.iter().enumerate() {
+            keypad.set_down(i as u8, self.window.is_key_down(*k));
+        }
+    }
+
+    fn is_turbo_key_down(&self, key: TurboKey) -> bool {
+        self.window.is_key_down(key.minifb_key())
+    }
+
+    fn host_keys_pressed(&mut self) -> Vec<HostKey> {
+        let mut host_keys = Vec::new();
+        for key in self
+            .window
+            .get_keys_pressed(KeyRepeat::Yes)
+            .unwrap_or_default()
+        {
+            if key == Key::Up || key == KEYS[0x4] {
+                host_keys.push(HostKey::Up);
+            } else if key == Key::Down || key == KEYS[0x6] {
+                host_keys.push(HostKey::Down);
+            } else if key == Key::Enter || key == KEYS[0x5] {
+                host_keys.push(HostKey::Select);
+            }
+        }
+        let ctrl =
+            self.window.is_key_down(Key::LeftCtrl) || self.window.is_key_down(Key::RightCtrl);
+        for key in self
+            .window
+            .get_keys_pressed(KeyRepeat::No)
+            .unwrap_or_default()
+        {
+            if let Some(code) = hotkey_code(key) {
+                host_keys.push(HostKey::Hotkey(Hotkey { ctrl, code }));
+            }
+        }
+        host_keys
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn limit_update_rate(&mut self, frame_duration: Option<Duration>) {
+        self.window.limit_update_rate(frame_duration);
+    }
+
+    fn set_colors(&mut self, color1: (u8, u8, u8), color2: (u8, u8, u8)) {
+        self.palette[0] = color1;
+        self.palette[1] = color2;
+        self.palette[4] = midpoint(color1, color2);
+    }
+
+    // menus are only displayed on platforms with a menu bar (macOS and Windows)
+    fn set_recent_roms(&mut self, paths: &[PathBuf]) -> Result<(), ChipottoError> {
+        let mut menu = Menu::new("File")?;
+        if cfg!(feature = "native-dialogs") {
+            menu.add_item("Open ROM…", MENU_OPEN_ROM).build();
+        }
+        menu.add_item("Reset", MENU_RESET).build();
+        let mut recent_menu = Menu::new("Recent ROMs")?;
+        for (i, path) in paths.iter().enumerate() {
+            recent_menu
+                .add_item(&path.to_string_lossy(), MENU_RECENT_ROM + i)
+                .build();
+        }
+        menu.add_sub_menu("Recent ROMs", &recent_menu);
+        if let Some(handle) = self.menu_handle.take() {
+            self.window.remove_menu(handle);
+        }
+        self.menu_handle = Some(self.window.add_menu(&menu));
+        Ok(())
+    }
+
+    fn menu_action(&mut self) -> Option<MenuAction> {
+        match self.window.is_menu_pressed()? {
+            MENU_OPEN_ROM => Some(MenuAction::OpenRom),
+            MENU_RESET => Some(MenuAction::Reset),
+            menu_id => menu_id
+                .checked_sub(MENU_RECENT_ROM)
+                .map(MenuAction::RecentRom),
+        }
+    }
+}
+
+// the second window of --multi-window, showing a `MemoryView`
+pub struct MemoryWindow {
+    window: Window,
+}
+
+impl MemoryWindow {
+    pub fn new() -> Result<Self, ChipottoError> {
+        let mut window = Window::new(
+            "Chipotto memory",
+            MEMORY_VIEW_WIDTH,
+            MEMORY_VIEW_HEIGHT,
+            WindowOptions::default(),
+        )?;
+        // the main window already paces the loop
+        window.limit_update_rate(None);
+        Ok(MemoryWindow { window })
+    }
+
+    pub fn update(&mut self, pixels: &[u32]) -> Result<(), ChipottoError> {
+        self.window
+            .update_with_buffer(pixels, MEMORY_VIEW_WIDTH, MEMORY_VIEW_HEIGHT)?;
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+}
+
+// renders in the terminal with two characters per pixel, so that it can be used over
+// ssh or in a headless container
+pub struct AsciiDisplay {
+    stdout: Stdout,
+    open: bool,
+    // terminals supporting the kitty keyboard protocol also report key releases
+    reports_releases: bool,
+    // when each key was last pressed, None once it is released
+    key_presses: [Option<Instant>; KEY_COUNT],
+    // the same for the keys outside the keypad
+    other_key_presses: Vec<(KeyCode, Instant)>,
+    host_keys: Vec<HostKey>,
+    last_frame: Vec<u8>,
+    frame_duration: Option<Duration>,
+    last_render: Instant,
+}
+
+impl AsciiDisplay {
+    pub fn new() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+        }
+        Ok(AsciiDisplay {
+            stdout,
+            open: true,
+            reports_releases,
+            key_presses: [None; KEY_COUNT],
+            other_key_presses: Vec::new(),
+            host_keys: Vec::new(),
+            last_frame: Vec::new(),
+            frame_duration: None,
+            last_render: Instant::now(),
+        })
+    }
+
+    // whether a key pressed at the given time and not released since is still down
+    fn is_held(&self, pressed_at: Instant) -> bool {
+        self.reports_releases || pressed_at.elapsed() < ASCII_KEY_HOLD
+    }
+
+    fn read_events(&mut self) -> io::Result<()> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key_event) = event::read()? {
+                self.handle_key_event(key_event);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key_event: KeyEvent) {
+        let released = key_event.kind == KeyEventKind::Release;
+        let repeated = key_event.kind == KeyEventKind::Repeat;
+        let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        let code = match key_event.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        match ASCII_KEYS
+            .iter()
+            .position(|key| KeyCode::Char(*key) == code)
+        {
+            Some(i) if released => self.key_presses[i] = None,
+            Some(i) => self.key_presses[i] = Some(Instant::now()),
+            None => {
+                self.other_key_presses.retain(|(other, _)| *other != code);
+                if !released {
+                    self.other_key_presses.push((code, Instant::now()));
+                }
+            }
+        }
+        if released {
+            return;
+        }
+        let host_key = match code {
+            // raw mode turns Ctrl+C into a regular key press
+            KeyCode::Esc => {
+                self.open = false;
+                None
+            }
+            KeyCode::Char('c') if ctrl => {
+                self.open = false;
+                None
+            }
+            KeyCode::Up => Some(HostKey::Up),
+            KeyCode::Down => Some(HostKey::Down),
+            KeyCode::Enter => Some(HostKey::Select),
+            KeyCode::Char(c) if c == ASCII_KEYS[0x4] => Some(HostKey::Up),
+            KeyCode::Char(c) if c == ASCII_KEYS[0x6] => Some(HostKey::Down),
+            KeyCode::Char(c) if c == ASCII_KEYS[0x5] => Some(HostKey::Select),
+            _ => None,
+        };
+        self.host_keys.extend(host_key);
+        // Ctrl+M only gets here in the terminals telling it apart from enter, and a letter
+        // typed with shift is the same key as in the window
+        let hotkey_code = match code {
+            KeyCode::Char(' ') => Some(HotkeyCode::Space),
+            KeyCode::Char(c) => Some(HotkeyCode::Char(c.to_ascii_lowercase())),
+            KeyCode::F(n) => Some(HotkeyCode::F(n)),
+            KeyCode::Tab => Some(HotkeyCode::Tab),
+            KeyCode::Backspace => Some(HotkeyCode::Backspace),
+            _ => None,
+        };
+        if let Some(code) = hotkey_code.filter(|_| !repeated) {
+            self.host_keys.push(HostKey::Hotkey(Hotkey { ctrl, code }));
+        }
+    }
+}
+
+impl DisplayBackend for AsciiDisplay {
+    fn render(&mut self, buffer: &[u8], width: usize, height: usize) -> Result<(), ChipottoError> {
+        if let Some(frame_duration) = self.frame_duration {
+            if let Some(remaining) = frame_duration.checked_sub(self.last_render.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+        self.last_render = Instant::now();
+        if buffer == self.last_frame.as_slice() {
+            return Ok(());
+        }
+        for (y, row) in buffer.chunks(width).take(height).enumerate() {
+            let line: String = row
+                .iter()
+                .map(|pixel| match *pixel {
+                    0 => "░░",
+                    GRID_PIXEL => "▒▒",
+                    _ => "██",
+                })
+                .collect();
+            queue!(self.stdout, MoveTo(0, y as u16), Print(line))?;
+        }
+        self.stdout.flush()?;
+        self.last_frame = buffer.to_vec();
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn handle_keys(&self, keypad: &mut Keypad) {
+        for (i, pressed) in self.key_presses.iter().enumerate() {
+            keypad.set_down(i as u8, pressed.is_some_and(|at| self.is_held(at)));
+        }
+    }
+
+    fn is_turbo_key_down(&self, key: TurboKey) -> bool {
+        let code = key.key_code();
+        self.other_key_presses
+            .iter()
+            .any(|(other, at)| *other == code && self.is_held(*at))
+    }
+
+    fn host_keys_pressed(&mut self) -> Vec<HostKey> {
+        // a terminal that cannot be read from is treated like a closed window
+        if self.read_events().is_err() {
+            self.open = false;
+        }
+        std::mem::take(&mut self.host_keys)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        // not every terminal supports titles, and the title is not worth failing for
+        let _ = execute!(self.stdout, SetTitle(title));
+    }
+
+    fn limit_update_rate(&mut self, frame_duration: Option<Duration>) {
+        self.frame_duration = frame_duration;
+    }
+}
+
+impl Drop for AsciiDisplay {
+    // gives the terminal back in the state it was found in
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
+    let (r, g, b) = (r as u32, g as u32, b as u32);
+    (r << 16) | (g << 8) | b
+}
+
+// the hotkey code of a window key, the numpad + and - counting as the main ones
+fn hotkey_code(key: Key) -> Option<HotkeyCode> {
+    const LETTERS: [Key; 26] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+    ];
+    const DIGITS: [Key; 10] = [
+        Key::Key0,
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+    ];
+    let code = match key {
+        Key::Tab => HotkeyCode::Tab,
+        Key::Space => HotkeyCode::Space,
+        Key::Backspace => HotkeyCode::Backspace,
+        Key::Equal => HotkeyCode::Char('='),
+        Key::NumPadPlus => HotkeyCode::Char('+'),
+        Key::Minus | Key::NumPadMinus => HotkeyCode::Char('-'),
+        key => {
+            if let Some(i) = LETTERS.iter().position(|k| *k == key) {
+                HotkeyCode::Char((b'a' + i as u8) as char)
+            } else if let Some(i) = DIGITS.iter().position(|k| *k == key) {
+                HotkeyCode::Char((b'0' + i as u8) as char)
+            } else {
+                HotkeyCode::F(F_KEYS.iter().position(|k| *k == key)? as u8 + 1)
+            }
+        }
+    };
+    Some(code)
+}
+
+// the color halfway between two others, channel by channel
+fn midpoint(a: (u8, u8, u8), b: (u8, u8, u8)) -> (u8, u8, u8) {
+    let mid = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+    (mid(a.0, b.0), mid(a.1, b.1), mid(a.2, b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turbo_key_from_str() {
+        assert_eq!(Ok(TurboKey::Tab), TurboKey::from_str("Tab"));
+        assert_eq!(Ok(TurboKey::F(12)), TurboKey::from_str("f12"));
+        assert_eq!(Err(()), TurboKey::from_str("F13"));
+        // keys of the CHIP-8 keypad cannot be used
+        assert_eq!(Err(()), TurboKey::from_str("Q"));
+    }
+}
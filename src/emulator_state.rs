@@ -0,0 +1,76 @@
+use crate::core::cpu::CpuError;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+// what the main loop of the app does: only `Running` runs cycles on its own, `Debugging`
+// runs one cycle per step, and an `Error` is final
+#[derive(Clone, Debug)]
+pub enum EmulatorState {
+    Running,
+    Paused,
+    Debugging,
+    // stopped with the program counter at a breakpoint
+    BreakpointHit(u16),
+    Error(CpuError),
+}
+
+impl EmulatorState {
+    pub fn can_transition_to(&self, new_state: &EmulatorState) -> bool {
+        use EmulatorState::*;
+        match (self, new_state) {
+            // an error ends the run, so nothing follows it
+            (Error(_), _) => false,
+            (_, Error(_)) => true,
+            // breakpoints are only checked while running freely, not when stepping
+            (Running, BreakpointHit(_)) => true,
+            (_, BreakpointHit(_)) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for EmulatorState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorState::Running => write!(f, "running"),
+            EmulatorState::Paused => write!(f, "paused"),
+            EmulatorState::Debugging => write!(f, "debugging"),
+            EmulatorState::BreakpointHit(address) => {
+                write!(f, "breakpoint at {:04X}", address)
+            }
+            EmulatorState::Error(err) => write!(f, "error: {}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TransitionError {
+    pub from: EmulatorState,
+    pub to: EmulatorState,
+}
+
+impl Display for TransitionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot go from {} to {}", self.from, self.to)
+    }
+}
+
+impl Error for TransitionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_transition_to() {
+        use EmulatorState::*;
+        assert!(Running.can_transition_to(&BreakpointHit(0x200)));
+        assert!(!Paused.can_transition_to(&BreakpointHit(0x200)));
+        assert!(!Debugging.can_transition_to(&BreakpointHit(0x200)));
+        assert!(BreakpointHit(0x200).can_transition_to(&Debugging));
+        assert!(Paused.can_transition_to(&Running));
+        assert!(Debugging.can_transition_to(&Error(CpuError::StackUnderflow)));
+        assert!(!Error(CpuError::StackUnderflow).can_transition_to(&Running));
+    }
+}
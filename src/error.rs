@@ -0,0 +1,123 @@
+use crate::annotations::SymbolError;
+use crate::config::ConfigError;
+use crate::core::chip8::{RomLoadError, RomPatchError};
+use crate::core::cpu::CpuError;
+use crate::core::memory::MemoryError;
+use crate::emulator_state::TransitionError;
+use crate::net::sync::SyncError;
+use std::fmt::{Display, Formatter};
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum ChipottoError {
+    RomLoad(RomLoadError),
+    RomPatch(RomPatchError),
+    Symbols(SymbolError),
+    Config(ConfigError),
+    Cpu(CpuError),
+    Memory(MemoryError),
+    Window(minifb::Error),
+    Sync(SyncError),
+    Transition(TransitionError),
+    #[cfg(feature = "http")]
+    Download(Box<dyn error::Error>),
+    Io(io::Error),
+}
+
+impl Display for ChipottoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChipottoError::RomLoad(err) => write!(f, "could not load rom: {}", err),
+            ChipottoError::RomPatch(err) => write!(f, "could not patch rom: {}", err),
+            ChipottoError::Symbols(err) => write!(f, "could not load symbols: {}", err),
+            ChipottoError::Config(err) => write!(f, "invalid configuration: {}", err),
+            ChipottoError::Cpu(err) => write!(f, "cpu error: {}", err),
+            ChipottoError::Memory(err) => write!(f, "memory error: {}", err),
+            ChipottoError::Window(err) => write!(f, "window error: {}", err),
+            ChipottoError::Sync(err) => write!(f, "lockstep error: {}", err),
+            ChipottoError::Transition(err) => write!(f, "invalid state change: {}", err),
+            #[cfg(feature = "http")]
+            ChipottoError::Download(err) => write!(f, "could not download rom: {}", err),
+            ChipottoError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl error::Error for ChipottoError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ChipottoError::RomLoad(err) => Some(err),
+            ChipottoError::RomPatch(err) => Some(err),
+            ChipottoError::Symbols(err) => Some(err),
+            ChipottoError::Config(err) => Some(err),
+            ChipottoError::Cpu(err) => Some(err),
+            ChipottoError::Memory(err) => Some(err),
+            ChipottoError::Window(err) => Some(err),
+            ChipottoError::Sync(err) => Some(err),
+            ChipottoError::Transition(err) => Some(err),
+            #[cfg(feature = "http")]
+            ChipottoError::Download(err) => Some(err.as_ref()),
+            ChipottoError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<RomLoadError> for ChipottoError {
+    fn from(err: RomLoadError) -> Self {
+        ChipottoError::RomLoad(err)
+    }
+}
+
+impl From<RomPatchError> for ChipottoError {
+    fn from(err: RomPatchError) -> Self {
+        ChipottoError::RomPatch(err)
+    }
+}
+
+impl From<SymbolError> for ChipottoError {
+    fn from(err: SymbolError) -> Self {
+        ChipottoError::Symbols(err)
+    }
+}
+
+impl From<ConfigError> for ChipottoError {
+    fn from(err: ConfigError) -> Self {
+        ChipottoError::Config(err)
+    }
+}
+
+impl From<CpuError> for ChipottoError {
+    fn from(err: CpuError) -> Self {
+        ChipottoError::Cpu(err)
+    }
+}
+
+impl From<MemoryError> for ChipottoError {
+    fn from(err: MemoryError) -> Self {
+        ChipottoError::Memory(err)
+    }
+}
+
+impl From<minifb::Error> for ChipottoError {
+    fn from(err: minifb::Error) -> Self {
+        ChipottoError::Window(err)
+    }
+}
+
+impl From<SyncError> for ChipottoError {
+    fn from(err: SyncError) -> Self {
+        ChipottoError::Sync(err)
+    }
+}
+
+impl From<TransitionError> for ChipottoError {
+    fn from(err: TransitionError) -> Self {
+        ChipottoError::Transition(err)
+    }
+}
+
+impl From<io::Error> for ChipottoError {
+    fn from(err: io::Error) -> Self {
+        ChipottoError::Io(err)
+    }
+}
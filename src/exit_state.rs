@@ -0,0 +1,57 @@
+use crate::core::chip8::Chip8;
+use crate::core::cpu::CpuState;
+use crate::core::memory::Memory;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_EXIT_STATE_PATH: &str = "exit_state.json";
+
+// the machine state written when the emulator exits, for post-mortem debugging or to
+// turn a real run into a test fixture
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExitState {
+    pub cpu: CpuState,
+    pub memory: Memory,
+    // (run length, value) pairs, see `FrameBuffer::encode_rle`
+    pub frame_buffer_rle: Vec<u8>,
+    pub cycles: u64,
+}
+
+impl ExitState {
+    pub fn capture(chip8: &Chip8) -> Self {
+        ExitState {
+            cpu: chip8.cpu.state(),
+            memory: chip8.memory.clone(),
+            frame_buffer_rle: chip8.frame_buffer.encode_rle(),
+            cycles: chip8.metrics().cycles_total,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_state_json() {
+        let mut chip8 = Chip8::with_seed(0);
+        chip8.load_rom(&[0x60, 0x2A, 0x22, 0x06]).unwrap();
+        chip8.cpu_cycle().unwrap();
+        chip8.cpu_cycle().unwrap();
+        let json = serde_json::to_value(ExitState::capture(&chip8)).unwrap();
+        assert_eq!(0x206, json["cpu"]["pc"]);
+        assert_eq!(0x2A, json["cpu"]["v"][0]);
+        assert_eq!(serde_json::json!([0x204]), json["cpu"]["stack"]);
+        assert_eq!(2, json["cycles"]);
+        assert!(json["memory"]["bytes"]
+            .as_str()
+            .unwrap()
+            .contains("602a2206"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+use std::io::{Read, Write};
+
+// each entry is the frame number (u64) followed by the keypad bit mask (u16), little-endian
+const ENTRY_SIZE: usize = 10;
+
+pub struct InputRecorder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> InputRecorder<W> {
+    pub fn new(writer: W) -> Self {
+        InputRecorder { writer }
+    }
+
+    pub fn record(&mut self, frame_number: u64, keys: u16) -> io::Result<()> {
+        self.writer.write_all(&frame_number.to_le_bytes())?;
+        self.writer.write_all(&keys.to_le_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct InputReplay {
+    entries: Vec<(u64, u16)>,
+    next: usize,
+    keys: u16,
+}
+
+impl InputReplay {
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() % ENTRY_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated input recording",
+            ));
+        }
+        let entries = bytes
+            .chunks(ENTRY_SIZE)
+            .map(|entry| {
+                let mut frame_number = [0u8; 8];
+                frame_number.copy_from_slice(&entry[0..8]);
+                let keys = u16::from_le_bytes([entry[8], entry[9]]);
+                (u64::from_le_bytes(frame_number), keys)
+            })
+            .collect();
+        Ok(InputReplay {
+            entries,
+            next: 0,
+            keys: 0,
+        })
+    }
+
+    // frames must be requested in increasing order; the keys of the last recorded frame are kept
+    // once the recording is over
+    pub fn keys_at(&mut self, frame_number: u64) -> u16 {
+        while let Some((frame, keys)) = self.entries.get(self.next) {
+            if *frame > frame_number {
+                break;
+            }
+            self.keys = *keys;
+            self.next += 1;
+        }
+        self.keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut bytes = Vec::new();
+        let mut recorder = InputRecorder::new(&mut bytes);
+        recorder.record(0, 0x0000).unwrap();
+        recorder.record(1, 0x8001).unwrap();
+        recorder.record(3, 0x0010).unwrap();
+        assert_eq!(30, bytes.len());
+        assert_eq!(&[1, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x80], &bytes[10..20]);
+
+        let mut replay = InputReplay::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(0x0000, replay.keys_at(0));
+        assert_eq!(0x8001, replay.keys_at(1));
+        assert_eq!(0x8001, replay.keys_at(2));
+        assert_eq!(0x0010, replay.keys_at(3));
+        assert_eq!(0x0010, replay.keys_at(100));
+    }
+
+    #[test]
+    fn test_replay_truncated() {
+        assert!(InputReplay::from_reader(&[0u8; 11][..]).is_err());
+    }
+}
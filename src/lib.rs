@@ -0,0 +1,48 @@
+#![cfg_attr(feature = "no-std", no_std)]
+
+#[cfg(feature = "no-std")]
+extern crate alloc;
+
+#[cfg(not(feature = "no-std"))]
+pub mod annotations;
+#[cfg(not(feature = "no-std"))]
+pub mod app;
+#[cfg(not(feature = "no-std"))]
+pub mod assembler;
+#[cfg(not(feature = "no-std"))]
+pub mod cli;
+#[cfg(not(feature = "no-std"))]
+pub mod config;
+pub mod core;
+#[cfg(not(feature = "no-std"))]
+pub mod disasm;
+#[cfg(not(feature = "no-std"))]
+pub mod display;
+#[cfg(not(feature = "no-std"))]
+pub mod emulator_state;
+#[cfg(not(feature = "no-std"))]
+pub mod error;
+#[cfg(not(feature = "no-std"))]
+pub mod exit_state;
+#[cfg(not(feature = "no-std"))]
+pub mod input_log;
+#[cfg(not(feature = "no-std"))]
+pub mod memory_view;
+#[cfg(not(feature = "no-std"))]
+pub mod net;
+#[cfg(not(feature = "no-std"))]
+pub mod opcode_log;
+#[cfg(not(feature = "no-std"))]
+pub mod profiler;
+#[cfg(not(feature = "no-std"))]
+pub mod recent;
+#[cfg(not(feature = "no-std"))]
+pub mod rom_library;
+#[cfg(not(feature = "no-std"))]
+pub mod snapshot_test;
+#[cfg(feature = "sound")]
+pub mod sound;
+#[cfg(not(feature = "no-std"))]
+pub mod testing;
+#[cfg(not(feature = "no-std"))]
+pub mod timing;
@@ -1,55 +1,72 @@
-use crate::app::Chip8App;
-use clap::{App, Arg, ArgMatches};
-use std::path::PathBuf;
+use chipotto::app::Chip8App;
+use chipotto::cli::interactive_shell;
+use chipotto::config::color_map::ColorMap;
+use chipotto::config::profiles::ProfileConfig;
+use chipotto::config::{hotkeys, profiles};
+use chipotto::config::{rgb_from_hex, Config, ConfigError, LockstepRole, PlaylistMode};
+use chipotto::core::compat::CompatibilityMode;
+use chipotto::core::framebuffer::FrameBuffer;
+use chipotto::core::instr::INSTR_TYPE_NAMES;
+use chipotto::disasm::ControlFlowGraph;
+use chipotto::display::{AsciiDisplay, DisplayBackend, MinifbDisplay, TurboKey};
+use chipotto::error::ChipottoError;
+use chipotto::exit_state::DEFAULT_EXIT_STATE_PATH;
+use chipotto::net::StateViewer;
+use chipotto::testing::{snapshot_diff, GoldenGenerator};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
-use std::{error, process};
-
-mod core;
-
-mod app;
-
-pub struct Config {
-    rom_file_path: PathBuf,
-    clock_hz: u64,
-    color1: (u8, u8, u8),
-    color2: (u8, u8, u8),
-}
-
-impl Config {
-    pub fn new(rom_file_path: PathBuf) -> Self {
-        Config {
-            rom_file_path,
-            clock_hz: 500,
-            color1: (0x00, 0x00, 0x00),
-            color2: (0xFF, 0xFF, 0xFF),
-        }
-    }
-
-    pub fn clock_hz(mut self, clock: u64) -> Self {
-        self.clock_hz = clock;
-        self
-    }
-
-    pub fn color1(mut self, color: (u8, u8, u8)) -> Self {
-        self.color1 = color;
-        self
-    }
-
-    pub fn color2(mut self, color: (u8, u8, u8)) -> Self {
-        self.color2 = color;
-        self
-    }
-}
+use std::time::Duration;
 
 fn main() {
-    let args = App::new("Chipotto")
+    let app = App::new("Chipotto")
         .version("0.1")
         .author("okterakt")
         .about("Simple CHIP-8 emulator developed in Rust as a learning project.")
         .arg(
             Arg::with_name("ROM_FILE")
-                .required(true)
-                .help("ROM file containing program to run")
+                .help("ROM files containing programs to run one after the other, a launcher is shown when omitted")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("SHELL")
+                .long("shell")
+                .help("starts an interactive shell running roms without a window instead of the emulator")
+                .conflicts_with("ROM_FILE"),
+        )
+        .arg(
+            Arg::with_name("PRINT_CFG")
+                .long("print-cfg")
+                .help("prints the control flow graph of the first rom in graphviz dot format and exits")
+                .requires("ROM_FILE"),
+        )
+        .arg(
+            Arg::with_name("PLAYLIST_MODE")
+                .long("playlist-mode")
+                .help("what to do after the last rom, one of loop or once")
+                .takes_value(true)
+                .default_value("loop"),
+        )
+        .arg(
+            Arg::with_name("PLAYLIST_DURATION")
+                .long("playlist-duration")
+                .help("number of cycles to run each rom for before moving to the next one")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("MAX_CALL_DEPTH")
+                .long("max-call-depth")
+                .help("stops with an error when calls nest deeper than this, at most 16")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ROM_DIR")
+                .long("rom-dir")
+                .help("directory listed by the launcher")
                 .takes_value(true),
         )
         .arg(
@@ -59,6 +76,224 @@ fn main() {
                 .help("CPU clock in HZ")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("SEED")
+                .long("seed")
+                .help("seed for the random number generator")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("COMPAT_MODE")
+                .long("compat-mode")
+                .help("initial compatibility mode, one of chip8, chip48, superchip, xochip or chip8e (cycled with Ctrl+M)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DUMP_STATE_ON_EXIT")
+                .long("dump-state-on-exit")
+                .help("writes the cpu, memory and screen state to a json file when the window is closed"),
+        )
+        .arg(
+            Arg::with_name("DUMP_STATE_PATH")
+                .long("dump-state-path")
+                .help("file the state is written to with --dump-state-on-exit [default: exit_state.json]")
+                .takes_value(true)
+                .requires("DUMP_STATE_ON_EXIT"),
+        )
+        .arg(
+            Arg::with_name("LOAD_ANNOTATIONS")
+                .long("load-annotations")
+                .help("json file of address labels for the rom given on the command line")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("LOAD_SYMBOLS")
+                .long("load-symbols")
+                .help("assembler symbol file with one 'label = address' per line for the rom given on the command line")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SAVE_ANNOTATIONS")
+                .long("save-annotations")
+                .help("json file to write the address labels to at exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("STEP_LOG")
+                .long("step-log")
+                .help("file to write the cycles and keys of each frame to at exit, to replay the session exactly")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("RECORD_INPUT")
+                .long("record-input")
+                .help("file to record the keypad state of each frame to")
+                .takes_value(true)
+                .conflicts_with("REPLAY_INPUT"),
+        )
+        .arg(
+            Arg::with_name("REPLAY_INPUT")
+                .long("replay-input")
+                .help("file with recorded keypad states to replay instead of the live input")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("METRICS_INTERVAL")
+                .long("metrics-interval")
+                .help("print the emulator metrics every N seconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("P2P_LISTEN")
+                .long("p2p-listen")
+                .help("address to receive the keys of the other player on, e.g. 0.0.0.0:8765")
+                .takes_value(true)
+                .requires("P2P_PEER"),
+        )
+        .arg(
+            Arg::with_name("P2P_PEER")
+                .long("p2p-peer")
+                .help("address of the other player, e.g. 192.168.1.2:8765")
+                .takes_value(true)
+                .requires("P2P_LISTEN"),
+        )
+        .arg(
+            Arg::with_name("P2P_REMOTE_KEYS")
+                .long("p2p-remote-keys")
+                .help("hex bit mask of the keys controlled by the other player (default FF00)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("LOCKSTEP_HOST")
+                .long("lockstep-host")
+                .help("waits for another instance on this address and runs in lockstep with it, e.g. 0.0.0.0:8766")
+                .takes_value(true)
+                .conflicts_with_all(&["LOCKSTEP_CONNECT", "P2P_LISTEN"]),
+        )
+        .arg(
+            Arg::with_name("LOCKSTEP_CONNECT")
+                .long("lockstep-connect")
+                .help("joins an instance started with --lockstep-host, e.g. 192.168.1.2:8766")
+                .takes_value(true)
+                .conflicts_with("P2P_LISTEN"),
+        )
+        .arg(
+            Arg::with_name("STATE_SYNC")
+                .long("state-sync")
+                .help("waits for a viewer on this address and sends it the state after each frame, e.g. 127.0.0.1:8767")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("HALT_AFTER")
+                .long("halt-after")
+                .help("halts after an instruction type was executed N times, e.g. DrwVxVyN:100")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("BREAKPOINT")
+                .long("breakpoint")
+                .help("pauses when the program counter reaches this hex address, e.g. 0x230")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("PATCH")
+                .long("patch")
+                .help("bytes to patch the rom with after loading it, e.g. 0x230:8E12")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("VSYNC")
+                .long("vsync")
+                .help("runs each frame's cycles in a batch, then sleeps until the next 60 Hz frame"),
+        )
+        .arg(
+            Arg::with_name("GRID")
+                .long("grid")
+                .help("draws a faint grid every 8 pixels to help aligning sprites"),
+        )
+        .arg(
+            Arg::with_name("MULTI_WINDOW")
+                .long("multi-window")
+                .help("opens a second window showing the memory, with PC in green, I in blue and recent writes in red"),
+        )
+        .arg(
+            Arg::with_name("TURBO_KEY")
+                .long("turbo-key")
+                .help("key to hold to run at maximum speed, one of Tab, Space, Backspace or F1 to F12")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("WARN_DENSE_SCREEN")
+                .long("warn-dense-screen")
+                .help("warns when a draw leaves more than this percentage of the pixels lit, 80 by default")
+                .value_name("THRESHOLD")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true),
+        )
+        .arg(
+            Arg::with_name("STRICT_ALIGNMENT")
+                .long("strict-alignment")
+                .help("stops with an error when the program counter points to an odd address"),
+        )
+        .arg(
+            Arg::with_name("SCANLINE_ACCURATE")
+                .long("scanline-accurate")
+                .help("draws sprites one row per cycle like the original interpreter"),
+        )
+        .arg(
+            Arg::with_name("VF_RESULT_NOT_FLAG")
+                .long("vf-result-not-flag")
+                .help("keeps the result instead of the flag in VF when an arithmetic instruction writes to VF"),
+        )
+        .arg(
+            Arg::with_name("PRINT_OPCODES")
+                .long("print-opcodes")
+                .help("prints the address, opcode and mnemonic of each executed instruction"),
+        )
+        .arg(
+            Arg::with_name("CRASH_LOG")
+                .long("crash-log")
+                .help("prints the last 256 executed opcodes when the emulator stops with an error"),
+        )
+        .arg(
+            Arg::with_name("CALL_GRAPH")
+                .long("call-graph")
+                .help("prints the tree of subroutine calls with their cycles at exit"),
+        )
+        .arg(
+            Arg::with_name("TIMING_STATS")
+                .long("timing-stats")
+                .help("prints how far the actual cycle timing was from the target at exit"),
+        )
+        .arg(
+            Arg::with_name("PERF_HISTOGRAM")
+                .long("perf-histogram")
+                .value_name("SECS")
+                .help("runs for SECS seconds, then prints a histogram of the frame times")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TITLE_TEMPLATE")
+                .long("title-template")
+                .help("window title, with {rom}, {clock}, {fps}, {cycles}, {pc} and {mode} placeholders")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DISPLAY")
+                .long("display")
+                .help("where to display the emulator, ascii renders it in the terminal")
+                .takes_value(true)
+                .possible_values(&["window", "ascii"])
+                .default_value("window"),
+        )
         .arg(
             Arg::with_name("COLOR_1")
                 .long("color1")
@@ -71,41 +306,557 @@ fn main() {
                 .help("screen color 2")
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("COLOR_MAP")
+                .long("color-map")
+                .help("toml file with the bg, p1, p2 and both colors of the XO-CHIP planes, overrides --color1 and --color2")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("generate-goldens")
+                .about("runs every rom of a directory headlessly and writes its golden files")
+                .arg(Arg::with_name("ROM_DIR").required(true))
+                .arg(golden_cycles_arg())
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .long("output")
+                        .help("directory to write the .rle and .crc golden files to")
+                        .takes_value(true)
+                        .default_value("goldens"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify-goldens")
+                .about("runs every rom of a directory headlessly and compares it with its golden files")
+                .arg(Arg::with_name("ROM_DIR").required(true))
+                .arg(Arg::with_name("GOLDEN_DIR").required(true))
+                .arg(golden_cycles_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("shows the pixels that differ between two .rle frame snapshots, exiting with 1 if any does")
+                .arg(Arg::with_name("GOLDEN").required(true))
+                .arg(Arg::with_name("ACTUAL").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("monitor")
+                .about("prints the states sent by an instance started with --state-sync")
+                .arg(Arg::with_name("ADDR").required(true)),
+        );
+    #[cfg(feature = "http")]
+    let app = app.arg(
+        Arg::with_name("URL")
+            .long("url")
+            .value_name("URL")
+            .help("downloads the rom to run from a url instead of reading a file")
+            .takes_value(true)
+            .conflicts_with("ROM_FILE"),
+    );
+    let args = app.get_matches();
+
+    match args.subcommand() {
+        ("generate-goldens", Some(matches)) => process::exit(generate_goldens(matches)),
+        ("verify-goldens", Some(matches)) => process::exit(verify_goldens(matches)),
+        ("diff", Some(matches)) => process::exit(diff_snapshots(matches)),
+        ("monitor", Some(matches)) => process::exit(monitor_state(matches)),
+        _ => {}
+    }
+
+    if args.is_present("SHELL") {
+        if let Err(err) = interactive_shell() {
+            eprintln!("Application error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.is_present("PRINT_CFG") {
+        let path = args.value_of("ROM_FILE").unwrap();
+        match fs::read(path) {
+            Ok(rom) => print!("{}", ControlFlowGraph::from_rom(&rom).to_dot()),
+            Err(err) => {
+                eprintln!("Application error: {}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let config = parse_args(args).unwrap_or_else(|err| {
-        eprintln!("Command line arguments parsing error: {}", err);
+    let ascii_display = args.value_of("DISPLAY") == Some("ascii");
+    let perf_histogram = args
+        .value_of("PERF_HISTOGRAM")
+        .map(|secs| match secs.parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!(
+                    "Command line arguments parsing error: invalid perf histogram duration '{}'",
+                    secs
+                );
+                process::exit(1);
+            }
+        });
+    let config = parse_args(args).unwrap_or_else(|errors| {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        eprintln!(
+            "Command line arguments parsing error: {}",
+            errors.join("; ")
+        );
         process::exit(1);
     });
 
-    if let Err(err) = Chip8App::new(config).run() {
+    if let Err(err) = run(config, ascii_display, perf_histogram) {
         eprintln!("Application error: {}", err);
         process::exit(1);
     }
 }
 
-fn parse_args(matches: ArgMatches) -> Result<Config, Box<dyn error::Error>> {
-    let rom_file_path = PathBuf::from(matches.value_of("ROM_FILE").unwrap());
-    let mut config = Config::new(rom_file_path);
+fn golden_cycles_arg() -> Arg<'static, 'static> {
+    Arg::with_name("CYCLES")
+        .long("cycles")
+        .help("number of cycles to run each rom for")
+        .takes_value(true)
+        .default_value("500")
+}
+
+fn golden_generator(matches: &ArgMatches) -> Option<GoldenGenerator> {
+    let cycles = matches.value_of("CYCLES").unwrap();
+    match cycles.parse() {
+        Ok(cycles) => Some(GoldenGenerator::new(cycles)),
+        Err(_) => {
+            eprintln!(
+                "Command line arguments parsing error: invalid cycles '{}'",
+                cycles
+            );
+            None
+        }
+    }
+}
+
+// returns the exit code of the subcommand
+fn generate_goldens(matches: &ArgMatches) -> i32 {
+    let generator = match golden_generator(matches) {
+        Some(generator) => generator,
+        None => return 1,
+    };
+    let rom_dir = Path::new(matches.value_of("ROM_DIR").unwrap());
+    let output_dir = Path::new(matches.value_of("OUTPUT").unwrap());
+    match generator.generate(rom_dir, output_dir) {
+        Ok(results) => {
+            let mut failed = 0;
+            for (rom, result) in &results {
+                match result {
+                    Ok(()) => println!("{}", rom.display()),
+                    Err(err) => {
+                        println!("{}: {}", rom.display(), err);
+                        failed += 1;
+                    }
+                }
+            }
+            println!(
+                "wrote the goldens of {} roms, {} failed",
+                results.len() - failed,
+                failed
+            );
+            (failed > 0) as i32
+        }
+        Err(err) => {
+            eprintln!("Application error: {}", err);
+            1
+        }
+    }
+}
+
+// fails when any of the roms does not match its golden files
+fn verify_goldens(matches: &ArgMatches) -> i32 {
+    let generator = match golden_generator(matches) {
+        Some(generator) => generator,
+        None => return 1,
+    };
+    let rom_dir = Path::new(matches.value_of("ROM_DIR").unwrap());
+    let golden_dir = Path::new(matches.value_of("GOLDEN_DIR").unwrap());
+    let results = generator.verify(rom_dir, golden_dir);
+    let failed = results
+        .iter()
+        .filter(|(_, result)| !result.passed())
+        .count();
+    for (rom, result) in &results {
+        println!("{}: {}", rom.display(), result);
+    }
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    (failed > 0) as i32
+}
+
+// exits like diff(1): 0 when the snapshots are identical, 1 when they differ and 2 when
+// one of them cannot be read or is not a valid snapshot
+fn diff_snapshots(matches: &ArgMatches) -> i32 {
+    let read = |name| {
+        let path = matches.value_of(name).unwrap();
+        fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|rle| FrameBuffer::decode_rle(&rle).map_err(|err| err.to_string()))
+            .map_err(|err| {
+                eprintln!("Application error: {}: {}", path, err);
+            })
+    };
+    let (golden, actual) = match (read("GOLDEN"), read("ACTUAL")) {
+        (Ok(golden), Ok(actual)) => (golden, actual),
+        _ => return 2,
+    };
+    match snapshot_diff(&golden, &actual) {
+        Some(diff) => {
+            print!("{}", diff);
+            1
+        }
+        None => {
+            println!("the snapshots are identical");
+            0
+        }
+    }
+}
+
+// one line per frame, until the instance stops
+fn monitor_state(matches: &ArgMatches) -> i32 {
+    let addr = matches.value_of("ADDR").unwrap();
+    let addr = match SocketAddr::from_str(addr) {
+        Ok(addr) => addr,
+        Err(_) => {
+            eprintln!(
+                "Command line arguments parsing error: invalid address '{}'",
+                addr
+            );
+            return 2;
+        }
+    };
+    let mut viewer = match StateViewer::connect(addr) {
+        Ok(viewer) => viewer,
+        Err(err) => {
+            eprintln!("Application error: {}", err);
+            return 1;
+        }
+    };
+    loop {
+        match viewer.receive() {
+            Ok(Some(message)) => {
+                let cpu = &message.cpu;
+                let registers: Vec<String> = cpu.v.iter().map(|v| format!("{:02X}", v)).collect();
+                println!(
+                    "frame {:6} PC={:04X} I={:04X} DT={:02X} ST={:02X} V={} screen={:08X}",
+                    message.frame,
+                    cpu.pc,
+                    cpu.i,
+                    cpu.dt,
+                    cpu.st,
+                    registers.join(" "),
+                    message.frame_checksum
+                );
+            }
+            Ok(None) => return 0,
+            Err(err) => {
+                eprintln!("Application error: {}", err);
+                return 1;
+            }
+        }
+    }
+}
+
+fn run(
+    config: Config,
+    ascii_display: bool,
+    perf_histogram: Option<Duration>,
+) -> Result<(), ChipottoError> {
+    if ascii_display {
+        run_app(config, AsciiDisplay::new()?, perf_histogram)
+    } else {
+        let display = MinifbDisplay::new(&config)?;
+        run_app(config, display, perf_histogram)
+    }
+}
+
+fn run_app<D: DisplayBackend>(
+    config: Config,
+    display: D,
+    perf_histogram: Option<Duration>,
+) -> Result<(), ChipottoError> {
+    let mut app = Chip8App::new(config, display);
+    #[cfg(feature = "sound")]
+    match chipotto::sound::CpalOutput::new() {
+        Ok(output) => app.set_sound_output(Box::new(output)),
+        Err(err) => eprintln!("Could not open the audio output: {}", err),
+    }
+    let (result, histogram_report) = match perf_histogram {
+        Some(duration) => match app.capture_framerate_histogram(duration) {
+            Ok(histogram) => (Ok(()), Some(histogram.report())),
+            Err(err) => (Err(err), None),
+        },
+        None => (app.run(), None),
+    };
+    let timing_report = app.timing_stats().map(|stats| stats.report());
+    let call_graph_report = app.call_graph_report();
+    // the terminal display has to be closed before printing
+    drop(app);
+    for report in timing_report
+        .iter()
+        .chain(&call_graph_report)
+        .chain(&histogram_report)
+    {
+        println!("{}", report);
+    }
+    result
+}
+
+fn parse_args(matches: ArgMatches) -> Result<Config, Vec<ConfigError>> {
+    // parsing goes on after an invalid argument, so that all of them are reported at once
+    let mut errors = Vec::new();
+
+    let playlist: Vec<PathBuf> = matches
+        .values_of("ROM_FILE")
+        .map(|roms| roms.map(PathBuf::from).collect())
+        .unwrap_or_default();
+    let rom_file_path = playlist.first().cloned();
+    let mut config = Config::new(rom_file_path).playlist(playlist);
+    // the profiles are applied to each rom as it is loaded, except for the settings given
+    // on the command line, which take precedence
+    if let Some(profiles) = check(&mut errors, profiles::load_profiles()) {
+        config = config.profiles(profiles);
+    }
+    let mut command_line_settings = ProfileConfig::default();
+
+    if let Some(hotkeys) = check(&mut errors, hotkeys::load_hotkeys()) {
+        config = config.hotkeys(hotkeys);
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(url) = matches.value_of("URL") {
+        config = config.rom_url(url.to_string());
+    }
+
+    if let Some(dir) = matches.value_of("ROM_DIR") {
+        config = config.rom_dir(PathBuf::from(dir));
+    }
 
     if let Some(clock_hz) = matches.value_of("CLOCK_HZ") {
-        config = config.clock_hz(u64::from_str(clock_hz)?);
+        let clock_hz =
+            u64::from_str(clock_hz).map_err(|_| ConfigError::InvalidClock(clock_hz.to_string()));
+        if let Some(clock_hz) = check(&mut errors, clock_hz) {
+            config = config.clock_hz(clock_hz);
+            command_line_settings.clock_hz = Some(clock_hz);
+        }
+    }
+    if let Some(seed) = matches.value_of("SEED") {
+        let seed = u64::from_str(seed).map_err(|_| ConfigError::InvalidSeed(seed.to_string()));
+        if let Some(seed) = check(&mut errors, seed) {
+            config = config.seed(seed);
+        }
+    }
+    if let Some(mode) = matches.value_of("COMPAT_MODE") {
+        let mode = CompatibilityMode::from_str(mode)
+            .map_err(|_| ConfigError::InvalidCompatibilityMode(mode.to_string()));
+        if let Some(mode) = check(&mut errors, mode) {
+            config = config.compat_mode(mode).detect_quirks(false);
+            command_line_settings.compat_mode = Some(mode);
+        }
+    }
+    if matches.is_present("DUMP_STATE_ON_EXIT") {
+        let path = matches
+            .value_of("DUMP_STATE_PATH")
+            .unwrap_or(DEFAULT_EXIT_STATE_PATH);
+        config = config.dump_state_path(PathBuf::from(path));
+    }
+
+    if let Some(path) = matches.value_of("LOAD_ANNOTATIONS") {
+        config = config.load_annotations(PathBuf::from(path));
+    }
+    if let Some(path) = matches.value_of("LOAD_SYMBOLS") {
+        config = config.load_symbols(PathBuf::from(path));
+    }
+    if let Some(path) = matches.value_of("SAVE_ANNOTATIONS") {
+        config = config.save_annotations(PathBuf::from(path));
+    }
+
+    if let Some(path) = matches.value_of("STEP_LOG") {
+        config = config.step_log(PathBuf::from(path));
+    }
+    if let Some(path) = matches.value_of("RECORD_INPUT") {
+        config = config.record_input(PathBuf::from(path));
+    }
+    if let Some(path) = matches.value_of("REPLAY_INPUT") {
+        config = config.replay_input(PathBuf::from(path));
+    }
+    if let Some(interval) = matches.value_of("METRICS_INTERVAL") {
+        let interval = u64::from_str(interval)
+            .map_err(|_| ConfigError::InvalidMetricsInterval(interval.to_string()));
+        if let Some(interval) = check(&mut errors, interval) {
+            config = config.metrics_interval(interval);
+        }
+    }
+    let parse_addr = |addr: &str| {
+        SocketAddr::from_str(addr).map_err(|_| ConfigError::InvalidAddress(addr.to_string()))
+    };
+    if let (Some(listen), Some(peer)) =
+        (matches.value_of("P2P_LISTEN"), matches.value_of("P2P_PEER"))
+    {
+        let listen = check(&mut errors, parse_addr(listen));
+        let peer = check(&mut errors, parse_addr(peer));
+        if let (Some(listen), Some(peer)) = (listen, peer) {
+            config = config.p2p(listen, peer);
+        }
+    }
+    if let Some(mask) = matches.value_of("P2P_REMOTE_KEYS") {
+        let mask = u16::from_str_radix(mask.trim_start_matches("0x"), 16)
+            .map_err(|_| ConfigError::InvalidKeyMask(mask.to_string()));
+        if let Some(mask) = check(&mut errors, mask) {
+            config = config.p2p_remote_keys(mask);
+        }
+    }
+    if let Some(addr) = matches.value_of("LOCKSTEP_HOST") {
+        if let Some(addr) = check(&mut errors, parse_addr(addr)) {
+            config = config.lockstep(LockstepRole::Host(addr));
+        }
+    }
+    if let Some(addr) = matches.value_of("LOCKSTEP_CONNECT") {
+        if let Some(addr) = check(&mut errors, parse_addr(addr)) {
+            config = config.lockstep(LockstepRole::Connect(addr));
+        }
+    }
+    if let Some(addr) = matches.value_of("STATE_SYNC") {
+        if let Some(addr) = check(&mut errors, parse_addr(addr)) {
+            config = config.state_sync(addr);
+        }
+    }
+    for watchpoint in matches.values_of("HALT_AFTER").into_iter().flatten() {
+        let watchpoint = parse_count_watchpoint(watchpoint)
+            .ok_or_else(|| ConfigError::InvalidWatchpoint(watchpoint.to_string()));
+        if let Some((instr_type, count)) = check(&mut errors, watchpoint) {
+            config = config.count_watchpoint(instr_type, count);
+        }
+    }
+    for address in matches.values_of("BREAKPOINT").into_iter().flatten() {
+        let breakpoint = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+            .ok()
+            .filter(|address| *address <= 0xFFF)
+            .ok_or_else(|| ConfigError::InvalidBreakpoint(address.to_string()));
+        if let Some(breakpoint) = check(&mut errors, breakpoint) {
+            config = config.breakpoint(breakpoint);
+        }
+    }
+    for patch in matches.values_of("PATCH").into_iter().flatten() {
+        let patch = parse_patch(patch).ok_or_else(|| ConfigError::InvalidPatch(patch.to_string()));
+        if let Some((address, bytes)) = check(&mut errors, patch) {
+            config = config.rom_patch(address, bytes);
+        }
+    }
+    if matches.is_present("VSYNC") {
+        config = config.vsync(true);
+        command_line_settings.vsync = Some(true);
+    }
+    if let Some(mode) = matches.value_of("PLAYLIST_MODE") {
+        let mode = PlaylistMode::from_str(mode)
+            .map_err(|_| ConfigError::InvalidPlaylistMode(mode.to_string()));
+        if let Some(mode) = check(&mut errors, mode) {
+            config = config.playlist_mode(mode);
+        }
+    }
+    if let Some(cycles) = matches.value_of("PLAYLIST_DURATION") {
+        let cycles = u64::from_str(cycles)
+            .map_err(|_| ConfigError::InvalidPlaylistDuration(cycles.to_string()));
+        if let Some(cycles) = check(&mut errors, cycles) {
+            config = config.playlist_duration(cycles);
+        }
+    }
+    if let Some(depth) = matches.value_of("MAX_CALL_DEPTH") {
+        let depth =
+            usize::from_str(depth).map_err(|_| ConfigError::InvalidMaxCallDepth(depth.to_string()));
+        if let Some(depth) = check(&mut errors, depth) {
+            config = config.max_call_depth(depth);
+        }
+    }
+
+    if let Some(key) = matches.value_of("TURBO_KEY") {
+        let key =
+            TurboKey::from_str(key).map_err(|_| ConfigError::InvalidTurboKey(key.to_string()));
+        if let Some(key) = check(&mut errors, key) {
+            config = config.turbo_key(key);
+        }
+    }
+    if matches.is_present("WARN_DENSE_SCREEN") {
+        let threshold = matches.value_of("WARN_DENSE_SCREEN").unwrap_or("80");
+        let threshold = u8::from_str(threshold)
+            .map_err(|_| ConfigError::InvalidDenseScreenThreshold(threshold.to_string()));
+        if let Some(threshold) = check(&mut errors, threshold) {
+            config = config.dense_screen_threshold(threshold);
+        }
+    }
+    config = config.strict_alignment(matches.is_present("STRICT_ALIGNMENT"));
+    config = config.vf_result_not_flag(matches.is_present("VF_RESULT_NOT_FLAG"));
+    config = config.scanline_accurate(matches.is_present("SCANLINE_ACCURATE"));
+    config = config.print_opcodes(matches.is_present("PRINT_OPCODES"));
+    config = config.crash_log(matches.is_present("CRASH_LOG"));
+    config = config.call_graph(matches.is_present("CALL_GRAPH"));
+    config = config.timing_stats(matches.is_present("TIMING_STATS"));
+    config = config.grid(matches.is_present("GRID"));
+    config = config.multi_window(matches.is_present("MULTI_WINDOW"));
+    if let Some(template) = matches.value_of("TITLE_TEMPLATE") {
+        config = config.title_template(template.to_string());
     }
     if let Some(col1) = matches.value_of("COLOR_1") {
-        config = config.color1(rgb_from_hex(col1)?);
+        if let Some(color) = check(&mut errors, rgb_from_hex(col1)) {
+            config = config.color1(color);
+            command_line_settings.color1 = Some(color);
+        }
     }
     if let Some(col2) = matches.value_of("COLOR_2") {
-        config = config.color2(rgb_from_hex(col2)?);
+        if let Some(color) = check(&mut errors, rgb_from_hex(col2)) {
+            config = config.color2(color);
+            command_line_settings.color2 = Some(color);
+        }
     }
+    if let Some(path) = matches.value_of("COLOR_MAP") {
+        if let Some(color_map) = check(&mut errors, ColorMap::load(Path::new(path))) {
+            command_line_settings.color1 = Some(color_map.bg);
+            command_line_settings.color2 = Some(color_map.p1);
+            config = config.color_map(color_map);
+        }
+    }
+    config = config.command_line_settings(command_line_settings);
 
-    Ok(config)
+    // the values that could be parsed are checked too
+    if let Err(invalid) = config.validate() {
+        errors.extend(invalid);
+    }
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
+    }
+}
+
+// collects the error so that all of them are reported together
+fn check<T>(errors: &mut Vec<ConfigError>, result: Result<T, ConfigError>) -> Option<T> {
+    result.map_err(|err| errors.push(err)).ok()
 }
 
-fn rgb_from_hex(hex: &str) -> Result<(u8, u8, u8), Box<dyn error::Error>> {
-    let mut hex_trimmed = hex.trim_start_matches('#');
-    hex_trimmed = hex_trimmed.trim_start_matches("0x");
-    let r: u8 = u8::from_str_radix(&hex_trimmed[0..2], 16)?;
-    let g: u8 = u8::from_str_radix(&hex_trimmed[2..4], 16)?;
-    let b: u8 = u8::from_str_radix(&hex_trimmed[4..6], 16)?;
-    Ok((r, g, b))
+// parses an instruction type name and a count separated by a colon
+fn parse_count_watchpoint(watchpoint: &str) -> Option<(u8, u64)> {
+    let mut parts = watchpoint.splitn(2, ':');
+    let name = parts.next()?;
+    let count = u64::from_str(parts.next()?).ok()?;
+    let instr_type = INSTR_TYPE_NAMES
+        .iter()
+        .position(|instr| instr.eq_ignore_ascii_case(name))?;
+    Some((instr_type as u8, count))
+}
+
+// parses a hex address and the hex bytes to write there separated by a colon
+fn parse_patch(patch: &str) -> Option<(u16, Vec<u8>)> {
+    let mut parts = patch.splitn(2, ':');
+    let address = u16::from_str_radix(parts.next()?.trim_start_matches("0x"), 16).ok()?;
+    let hex = parts.next()?;
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    Some((address, bytes))
 }
@@ -0,0 +1,170 @@
+use crate::core::memory::FONT_SPRITES;
+
+pub const MEMORY_VIEW_WIDTH: usize = 512;
+pub const MEMORY_VIEW_HEIGHT: usize = 256;
+
+const MEM_SIZE: usize = 4096;
+const BYTES_PER_ROW: usize = 32;
+// rows of 7 pixels, the 5 of the font glyphs and a gap of 2
+const ROW_HEIGHT: usize = 7;
+const VISIBLE_ROWS: usize = MEMORY_VIEW_HEIGHT / ROW_HEIGHT;
+// the glyphs are 4 pixels wide, with a blank column after each one
+const GLYPH_ADVANCE: usize = 5;
+// two digits and a gap of 2 pixels
+const CELL_WIDTH: usize = 2 * GLYPH_ADVANCE + 2;
+// the 3 digit address of the row comes first
+const ADDRESS_WIDTH: usize = 4 * GLYPH_ADVANCE;
+const MARGIN: usize = 2;
+// the number of frames over which a written byte fades back to the text color
+const WRITE_FADE_FRAMES: u8 = 30;
+
+const BACKGROUND_COLOR: (u8, u8, u8) = (0x10, 0x10, 0x10);
+const ADDRESS_COLOR: (u8, u8, u8) = (0x70, 0x70, 0x70);
+const TEXT_COLOR: (u8, u8, u8) = (0xC0, 0xC0, 0xC0);
+const PC_COLOR: (u8, u8, u8) = (0x20, 0xE0, 0x20);
+const I_COLOR: (u8, u8, u8) = (0x40, 0x80, 0xFF);
+const WRITE_COLOR: (u8, u8, u8) = (0xFF, 0x20, 0x20);
+
+// a hex dump of the memory for the window opened by --multi-window, 32 bytes per row
+// around the program counter. the two bytes of the instruction at the program counter
+// are green, the byte I points to blue, and the bytes written recently red
+pub struct MemoryView {
+    previous: Vec<u8>,
+    // frames since each byte was last written, WRITE_FADE_FRAMES once faded out
+    write_ages: Vec<u8>,
+    pixels: Vec<u32>,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        MemoryView::new()
+    }
+}
+
+impl MemoryView {
+    pub fn new() -> Self {
+        MemoryView {
+            previous: Vec::new(),
+            write_ages: vec![WRITE_FADE_FRAMES; MEM_SIZE],
+            pixels: vec![0; MEMORY_VIEW_WIDTH * MEMORY_VIEW_HEIGHT],
+        }
+    }
+
+    // called once per frame, even when the view is not rendered, so that the writes fade
+    // out in frames. the first call only remembers the memory
+    pub fn track_writes(&mut self, memory: &[u8]) {
+        for (address, age) in self.write_ages.iter_mut().enumerate() {
+            let written = self
+                .previous
+                .get(address)
+                .is_some_and(|byte| Some(byte) != memory.get(address));
+            *age = if written {
+                0
+            } else {
+                age.saturating_add(1).min(WRITE_FADE_FRAMES)
+            };
+        }
+        self.previous.clear();
+        self.previous.extend_from_slice(memory);
+    }
+
+    // the view as 0RGB pixels, MEMORY_VIEW_WIDTH by MEMORY_VIEW_HEIGHT
+    pub fn render(&mut self, memory: &[u8], pc: u16, i: u16) -> &[u32] {
+        let background = from_rgb(BACKGROUND_COLOR);
+        self.pixels.iter_mut().for_each(|pixel| *pixel = background);
+        // the program counter stays in the first quarter of the view
+        let total_rows = MEM_SIZE / BYTES_PER_ROW;
+        let first_row = (pc as usize / BYTES_PER_ROW)
+            .saturating_sub(VISIBLE_ROWS / 4)
+            .min(total_rows - VISIBLE_ROWS);
+        for row in 0..VISIBLE_ROWS {
+            let row_address = (first_row + row) * BYTES_PER_ROW;
+            let y = row * ROW_HEIGHT + 1;
+            for (digit, shift) in [8, 4, 0].iter().enumerate() {
+                let nibble = (row_address >> shift) as u8 & 0xF;
+                self.draw_glyph(nibble, MARGIN + digit * GLYPH_ADVANCE, y, ADDRESS_COLOR);
+            }
+            for column in 0..BYTES_PER_ROW {
+                let address = row_address + column;
+                let byte = memory.get(address).copied().unwrap_or(0);
+                let color = self.byte_color(address, pc, i);
+                let x = MARGIN + ADDRESS_WIDTH + column * CELL_WIDTH;
+                self.draw_glyph(byte >> 4, x, y, color);
+                self.draw_glyph(byte & 0xF, x + GLYPH_ADVANCE, y, color);
+            }
+        }
+        &self.pixels
+    }
+
+    fn byte_color(&self, address: usize, pc: u16, i: u16) -> (u8, u8, u8) {
+        if address == pc as usize || address == pc as usize + 1 {
+            return PC_COLOR;
+        }
+        if address == i as usize {
+            return I_COLOR;
+        }
+        let age = self.write_ages[address];
+        let fade = |written: u8, text: u8| {
+            let (written, text) = (written as u32, text as u32);
+            let age = age as u32;
+            let fade_frames = WRITE_FADE_FRAMES as u32;
+            ((written * (fade_frames - age) + text * age) / fade_frames) as u8
+        };
+        (
+            fade(WRITE_COLOR.0, TEXT_COLOR.0),
+            fade(WRITE_COLOR.1, TEXT_COLOR.1),
+            fade(WRITE_COLOR.2, TEXT_COLOR.2),
+        )
+    }
+
+    // the hex digits of the built-in font, whose rows use the high 4 bits of their byte
+    fn draw_glyph(&mut self, digit: u8, x: usize, y: usize, color: (u8, u8, u8)) {
+        let color = from_rgb(color);
+        let glyph = &FONT_SPRITES[digit as usize * 5..digit as usize * 5 + 5];
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..4 {
+                if bits & (0x80 >> dx) != 0 {
+                    self.pixels[(y + dy) * MEMORY_VIEW_WIDTH + x + dx] = color;
+                }
+            }
+        }
+    }
+}
+
+fn from_rgb((r, g, b): (u8, u8, u8)) -> u32 {
+    ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the color of the top left pixel of the high digit of a byte in the first row, which
+    // the glyph of 0 lights
+    fn digit_color(pixels: &[u32], column: usize) -> u32 {
+        pixels[MEMORY_VIEW_WIDTH + MARGIN + ADDRESS_WIDTH + column * CELL_WIDTH]
+    }
+
+    #[test]
+    fn test_render_colors() {
+        let mut memory = vec![0; MEM_SIZE];
+        let mut view = MemoryView::new();
+        view.track_writes(&memory);
+        memory[0x3] = 0x05;
+        view.track_writes(&memory);
+
+        let pixels = view.render(&memory, 0x000, 0x002);
+        assert_eq!(from_rgb(PC_COLOR), digit_color(pixels, 0));
+        assert_eq!(from_rgb(PC_COLOR), digit_color(pixels, 1));
+        assert_eq!(from_rgb(I_COLOR), digit_color(pixels, 2));
+        assert_eq!(from_rgb(WRITE_COLOR), digit_color(pixels, 3));
+        assert_eq!(from_rgb(TEXT_COLOR), digit_color(pixels, 4));
+
+        // the write has faded out after 30 frames
+        for _ in 0..WRITE_FADE_FRAMES {
+            view.track_writes(&memory);
+        }
+        let pixels = view.render(&memory, 0x000, 0x002);
+        assert_eq!(from_rgb(TEXT_COLOR), digit_color(pixels, 3));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::core::chip8::Chip8;
+use crate::core::cpu::CpuState;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+
+pub mod sync;
+
+// shares the keypad with another instance over UDP so that two players on the same network
+// can play together, each of them controlling part of the 16 keys
+pub struct P2PKeypad {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    // bit mask of the keys controlled by the peer
+    remote_keys_mask: u16,
+    remote_keys: u16,
+}
+
+impl P2PKeypad {
+    pub fn bind<A: ToSocketAddrs>(
+        listen: A,
+        peer: SocketAddr,
+        remote_keys_mask: u16,
+    ) -> io::Result<Self> {
+        P2PKeypad::from_socket(UdpSocket::bind(listen)?, peer, remote_keys_mask)
+    }
+
+    pub fn from_socket(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        remote_keys_mask: u16,
+    ) -> io::Result<Self> {
+        // polling must never stall the emulation
+        socket.set_nonblocking(true)?;
+        Ok(P2PKeypad {
+            socket,
+            peer,
+            remote_keys_mask,
+            remote_keys: 0,
+        })
+    }
+
+    // each datagram holds the 16 keys as a little-endian bit mask
+    pub fn send(&self, keys: u16) -> io::Result<()> {
+        self.socket.send_to(&keys.to_le_bytes(), self.peer)?;
+        Ok(())
+    }
+
+    // reads all pending datagrams from the peer and keeps the most recent keys
+    pub fn poll(&mut self) -> io::Result<()> {
+        // one byte more than needed, so that longer datagrams are not truncated to a valid size
+        let mut buf = [0u8; 3];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((2, from)) if from == self.peer => {
+                    self.remote_keys = u16::from_le_bytes([buf[0], buf[1]])
+                }
+                // datagrams of other sizes or from other hosts are ignored
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                // some platforms report that the peer is not running yet
+                Err(err)
+                    if err.kind() == io::ErrorKind::ConnectionReset
+                        || err.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // replaces the keys controlled by the peer with its last known state
+    pub fn merge(&self, local_keys: u16) -> u16 {
+        (local_keys & !self.remote_keys_mask) | (self.remote_keys & self.remote_keys_mask)
+    }
+}
+
+// what `StateSync` sends after each frame
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateMessage {
+    pub frame: u64,
+    pub cpu: CpuState,
+    // `FrameBuffer::checksum` of the screen
+    pub frame_checksum: u32,
+}
+
+impl StateMessage {
+    pub fn capture(frame: u64, chip8: &Chip8) -> Self {
+        StateMessage {
+            frame,
+            cpu: chip8.cpu.state(),
+            frame_checksum: chip8.frame_buffer.checksum(),
+        }
+    }
+}
+
+// mirrors the state of a running instance to a single viewer over TCP, one line of JSON
+// per frame. nothing ever blocks the emulator: the frames are dropped while no viewer is
+// connected, and while the viewer is too slow to take them
+pub struct StateSync {
+    listener: TcpListener,
+    viewer: Option<TcpStream>,
+    // the part of the last line the socket could not take yet
+    pending: Vec<u8>,
+    frame: u64,
+}
+
+impl StateSync {
+    // listens for a viewer, which can connect at any time
+    pub fn start_server(addr: SocketAddr) -> io::Result<Self> {
+        StateSync::from_listener(TcpListener::bind(addr)?)
+    }
+
+    pub fn from_listener(listener: TcpListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(StateSync {
+            listener,
+            viewer: None,
+            pending: Vec::new(),
+            frame: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // called after each frame. once the viewer disconnects another one can take its place
+    pub fn send(&mut self, chip8: &Chip8) -> io::Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        if self.viewer.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.viewer = Some(stream);
+                    self.pending.clear();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+        // the frame is dropped while the previous line is still being sent
+        if self.pending.is_empty() {
+            let message = StateMessage::capture(frame, chip8);
+            self.pending = serde_json::to_vec(&message).map_err(io::Error::from)?;
+            self.pending.push(b'\n');
+        }
+        let connected = match self.viewer.as_mut() {
+            Some(viewer) => write_pending(viewer, &mut self.pending),
+            None => true,
+        };
+        if !connected {
+            self.viewer = None;
+        }
+        Ok(())
+    }
+}
+
+// writes as much of `pending` as the non-blocking writer takes, removing it from the
+// buffer. false once the writer is gone
+fn write_pending(writer: &mut impl Write, pending: &mut Vec<u8>) -> bool {
+    while !pending.is_empty() {
+        match writer.write(pending) {
+            Ok(0) => return false,
+            Ok(written) => {
+                pending.drain(..written);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+// the viewer side, reading the states sent by a `StateSync`
+pub struct StateViewer {
+    reader: BufReader<TcpStream>,
+}
+
+impl StateViewer {
+    pub fn connect(addr: SocketAddr) -> io::Result<Self> {
+        Ok(StateViewer {
+            reader: BufReader::new(TcpStream::connect(addr)?),
+        })
+    }
+
+    // blocks until the next state arrives, None once the emulator closed the connection
+    pub fn receive(&mut self) -> io::Result<Option<StateMessage>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let message = serde_json::from_str(&line).map_err(io::Error::from)?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_keys_are_merged_from_peer() {
+        let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr_a = socket_a.local_addr().unwrap();
+        let addr_b = socket_b.local_addr().unwrap();
+        let player_a = P2PKeypad::from_socket(socket_a, addr_b, 0x00FF).unwrap();
+        let mut player_b = P2PKeypad::from_socket(socket_b, addr_a, 0xFF00).unwrap();
+
+        player_a.send(0x0102).unwrap();
+        let start = Instant::now();
+        while player_b.merge(0) == 0 && start.elapsed() < Duration::from_secs(1) {
+            player_b.poll().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        // key 1 is controlled by player b itself, so only key 8 is taken from player a
+        assert_eq!(0x0100, player_b.merge(0x0000));
+        assert_eq!(0x0112, player_b.merge(0x0012));
+    }
+
+    #[test]
+    fn test_state_sync() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sync = StateSync::from_listener(listener).unwrap();
+        let mut chip8 = Chip8::new();
+        // LD V0, 0x2A then CLS
+        chip8.load_rom(&[0x60, 0x2A, 0x00, 0xE0]).unwrap();
+        // no viewer yet, so the frame is dropped
+        sync.send(&chip8).unwrap();
+        let mut viewer = StateViewer::connect(sync.local_addr().unwrap()).unwrap();
+        sync.send(&chip8).unwrap();
+        chip8.cpu_cycle().unwrap();
+        sync.send(&chip8).unwrap();
+        let checksum = chip8.frame_buffer.checksum();
+        drop(sync);
+
+        let first = viewer.receive().unwrap().unwrap();
+        let second = viewer.receive().unwrap().unwrap();
+        assert_eq!(
+            (1, 0x200, 0x00),
+            (first.frame, first.cpu.pc, first.cpu.v[0])
+        );
+        assert_eq!(
+            (2, 0x202, 0x2A),
+            (second.frame, second.cpu.pc, second.cpu.v[0])
+        );
+        assert_eq!(checksum, second.frame_checksum);
+        // the server dropped the connection
+        assert_eq!(None, viewer.receive().unwrap());
+    }
+
+    // takes `room` bytes, then would block
+    struct FullSocket {
+        written: Vec<u8>,
+        room: usize,
+    }
+
+    impl Write for FullSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let written = buf.len().min(self.room).min(3);
+            self.written.extend_from_slice(&buf[..written]);
+            self.room -= written;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_pending() {
+        let mut socket = FullSocket {
+            written: Vec::new(),
+            room: 5,
+        };
+        let mut pending = b"{\"frame\":1}\n".to_vec();
+        assert!(write_pending(&mut socket, &mut pending));
+        assert_eq!(b"{\"fra", &socket.written[..]);
+        assert_eq!(b"me\":1}\n", &pending[..]);
+
+        socket.room = 100;
+        assert!(write_pending(&mut socket, &mut pending));
+        assert_eq!(b"{\"frame\":1}\n", &socket.written[..]);
+        assert!(pending.is_empty());
+    }
+}
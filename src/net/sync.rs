@@ -0,0 +1,324 @@
+use crate::core::chip8::Chip8;
+use crate::testing::frame_checksum;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+// frame number, keys and state checksum, all little-endian
+const MESSAGE_SIZE: usize = 8 + 2 + 4;
+// seed and clock, little-endian, and the SHA-256 of the rom
+const SESSION_INFO_SIZE: usize = 8 + 8 + 32;
+
+// what both instances must agree on to run the same steps, checked when they connect
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    pub seed: u64,
+    pub clock_hz: u64,
+    pub rom_sha256: [u8; 32],
+}
+
+impl SessionInfo {
+    pub fn new(seed: u64, clock_hz: u64, rom: &[u8]) -> Self {
+        let mut rom_sha256 = [0; 32];
+        rom_sha256.copy_from_slice(&Sha256::digest(rom));
+        SessionInfo {
+            seed,
+            clock_hz,
+            rom_sha256,
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; SESSION_INFO_SIZE] {
+        let mut bytes = [0; SESSION_INFO_SIZE];
+        bytes[..8].copy_from_slice(&self.seed.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.clock_hz.to_le_bytes());
+        bytes[16..].copy_from_slice(&self.rom_sha256);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; SESSION_INFO_SIZE]) -> Self {
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&bytes[..8]);
+        let mut clock_hz = [0; 8];
+        clock_hz.copy_from_slice(&bytes[8..16]);
+        let mut rom_sha256 = [0; 32];
+        rom_sha256.copy_from_slice(&bytes[16..]);
+        SessionInfo {
+            seed: u64::from_le_bytes(seed),
+            clock_hz: u64::from_le_bytes(clock_hz),
+            rom_sha256,
+        }
+    }
+
+    // the first setting that differs, with the local and the remote value
+    fn mismatch(&self, remote: &SessionInfo) -> Option<SyncError> {
+        let hex = |sha256: &[u8; 32]| sha256.iter().map(|b| format!("{:02x}", b)).collect();
+        let (setting, local, remote) = if self.rom_sha256 != remote.rom_sha256 {
+            ("rom", hex(&self.rom_sha256), hex(&remote.rom_sha256))
+        } else if self.seed != remote.seed {
+            ("seed", self.seed.to_string(), remote.seed.to_string())
+        } else if self.clock_hz != remote.clock_hz {
+            (
+                "clock",
+                self.clock_hz.to_string(),
+                remote.clock_hz.to_string(),
+            )
+        } else {
+            return None;
+        };
+        Some(SyncError::Mismatch {
+            setting,
+            local,
+            remote,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Io(io::Error),
+    // the two instances no longer run the same program state
+    Desync {
+        frame: u64,
+        local: u32,
+        remote: u32,
+    },
+    // the peer sent the message of another frame
+    OutOfStep {
+        frame: u64,
+        remote_frame: u64,
+    },
+    // the peer runs another rom, or with another seed or clock
+    Mismatch {
+        setting: &'static str,
+        local: String,
+        remote: String,
+    },
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io(err) => write!(f, "{}", err),
+            SyncError::Desync {
+                frame,
+                local,
+                remote,
+            } => write!(
+                f,
+                "desync at frame {}: local state {:08X}, remote state {:08X}",
+                frame, local, remote
+            ),
+            SyncError::OutOfStep {
+                frame,
+                remote_frame,
+            } => write!(
+                f,
+                "expected frame {} from the peer, got frame {}",
+                frame, remote_frame
+            ),
+            SyncError::Mismatch {
+                setting,
+                local,
+                remote,
+            } => write!(
+                f,
+                "the peer runs with another {}: {} here, {} there",
+                setting, local, remote
+            ),
+        }
+    }
+}
+
+impl Error for SyncError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SyncError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SyncError {
+    fn from(err: io::Error) -> Self {
+        SyncError::Io(err)
+    }
+}
+
+// runs two instances in lockstep: every frame both send their keys along with a checksum
+// of their state, wait for the other one, and step with the same merged keypad. unlike
+// `P2PKeypad` nothing runs ahead, so the two stay identical as long as they use the same
+// rom, seed and clock, and a difference is reported as soon as it shows
+pub struct LockstepSession {
+    stream: TcpStream,
+    // bit mask of the keys controlled by the peer
+    remote_keys_mask: u16,
+    frame: u64,
+}
+
+impl LockstepSession {
+    // waits for the peer to connect, which then controls the keys of the mask
+    pub fn host(
+        listen: SocketAddr,
+        remote_keys_mask: u16,
+        info: &SessionInfo,
+    ) -> Result<Self, SyncError> {
+        LockstepSession::accept(TcpListener::bind(listen)?, remote_keys_mask, info)
+    }
+
+    pub fn accept(
+        listener: TcpListener,
+        remote_keys_mask: u16,
+        info: &SessionInfo,
+    ) -> Result<Self, SyncError> {
+        let (mut stream, _) = listener.accept()?;
+        stream.write_all(&remote_keys_mask.to_le_bytes())?;
+        handshake(&mut stream, info)?;
+        LockstepSession::from_stream(stream, remote_keys_mask)
+    }
+
+    // joins a hosting peer, controlling the keys it chose for us
+    pub fn connect(addr: SocketAddr, info: &SessionInfo) -> Result<Self, SyncError> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut mask = [0u8; 2];
+        stream.read_exact(&mut mask)?;
+        handshake(&mut stream, info)?;
+        LockstepSession::from_stream(stream, !u16::from_le_bytes(mask))
+    }
+
+    fn from_stream(stream: TcpStream, remote_keys_mask: u16) -> Result<Self, SyncError> {
+        // the messages are tiny and each one is waited for
+        stream.set_nodelay(true)?;
+        Ok(LockstepSession {
+            stream,
+            remote_keys_mask,
+            frame: 0,
+        })
+    }
+
+    // blocks until the peer sent its keys for the frame, and returns the keypad both
+    // instances run the frame with
+    pub fn exchange(&mut self, local_keys: u16, checksum: u32) -> Result<u16, SyncError> {
+        let mut message = [0u8; MESSAGE_SIZE];
+        message[..8].copy_from_slice(&self.frame.to_le_bytes());
+        message[8..10].copy_from_slice(&local_keys.to_le_bytes());
+        message[10..].copy_from_slice(&checksum.to_le_bytes());
+        self.stream.write_all(&message)?;
+        self.stream.read_exact(&mut message)?;
+
+        let mut frame = [0u8; 8];
+        frame.copy_from_slice(&message[..8]);
+        let remote_frame = u64::from_le_bytes(frame);
+        let remote_keys = u16::from_le_bytes([message[8], message[9]]);
+        let remote = u32::from_le_bytes([message[10], message[11], message[12], message[13]]);
+        if remote_frame != self.frame {
+            return Err(SyncError::OutOfStep {
+                frame: self.frame,
+                remote_frame,
+            });
+        }
+        if remote != checksum {
+            return Err(SyncError::Desync {
+                frame: self.frame,
+                local: checksum,
+                remote,
+            });
+        }
+        self.frame += 1;
+        Ok((local_keys & !self.remote_keys_mask) | (remote_keys & self.remote_keys_mask))
+    }
+}
+
+// both sides send their session info and refuse to start if the other differs, as the
+// session would desync sooner or later
+fn handshake(stream: &mut TcpStream, info: &SessionInfo) -> Result<(), SyncError> {
+    stream.write_all(&info.to_bytes())?;
+    let mut bytes = [0; SESSION_INFO_SIZE];
+    stream.read_exact(&mut bytes)?;
+    match info.mismatch(&SessionInfo::from_bytes(&bytes)) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// CRC-32 of the screen and the registers, which is enough to catch a divergence early
+pub fn state_checksum(chip8: &Chip8) -> u32 {
+    let state = chip8.cpu.state();
+    let mut bytes = chip8.frame_buffer.get_buffer().to_vec();
+    bytes.extend_from_slice(&state.v);
+    bytes.extend_from_slice(&state.pc.to_le_bytes());
+    bytes.extend_from_slice(&state.i.to_le_bytes());
+    bytes.push(state.dt);
+    frame_checksum(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_lockstep_exchange() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // hosting with the listener already bound, so the port is known before accepting
+        let info = SessionInfo::new(1, 500, &[0x12, 0x00]);
+        let host_info = info.clone();
+        let host = thread::spawn(move || {
+            let mut session = LockstepSession::accept(listener, 0xFF00, &host_info).unwrap();
+            let keys = session.exchange(0x0012, 42).unwrap();
+            (keys, session.exchange(0, 1))
+        });
+        let mut session = LockstepSession::connect(addr, &info).unwrap();
+        let keys = session.exchange(0x0134, 42).unwrap();
+        let desync = session.exchange(0, 2);
+        let (host_keys, host_desync) = host.join().unwrap();
+
+        // the host controls the low keys and the peer the high ones, on both sides
+        assert_eq!(0x0112, keys);
+        assert_eq!(0x0112, host_keys);
+        assert!(matches!(
+            desync,
+            Err(SyncError::Desync {
+                frame: 1,
+                local: 2,
+                remote: 1
+            })
+        ));
+        assert!(matches!(
+            host_desync,
+            Err(SyncError::Desync { frame: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_lockstep_handshake_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = thread::spawn(move || {
+            let info = SessionInfo::new(1, 500, &[0x12, 0x00]);
+            LockstepSession::accept(listener, 0xFF00, &info).map(|_| ())
+        });
+        let result = LockstepSession::connect(addr, &SessionInfo::new(2, 500, &[0x12, 0x00]));
+        let host_result = host.join().unwrap();
+
+        assert!(matches!(
+            result,
+            Err(SyncError::Mismatch {
+                setting: "seed",
+                ..
+            })
+        ));
+        assert!(matches!(
+            host_result,
+            Err(SyncError::Mismatch {
+                setting: "seed",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+use crate::core::instr::Instr;
+use std::collections::VecDeque;
+
+// number of opcodes kept for the crash log
+pub const OPCODE_LOG_SIZE: usize = 256;
+
+// ring buffer of the last executed opcodes with their addresses
+pub struct OpcodeLog {
+    entries: VecDeque<(u16, u16)>,
+}
+
+impl Default for OpcodeLog {
+    fn default() -> Self {
+        OpcodeLog {
+            entries: VecDeque::with_capacity(OPCODE_LOG_SIZE),
+        }
+    }
+}
+
+impl OpcodeLog {
+    pub fn push(&mut self, pc: u16, opcode: u16) {
+        if self.entries.len() == OPCODE_LOG_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((pc, opcode));
+    }
+
+    // one line per opcode, the oldest first
+    pub fn dump(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(pc, opcode)| format_opcode(*pc, *opcode))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+// the address, the raw opcode and its mnemonic
+pub fn format_opcode(pc: u16, opcode: u16) -> String {
+    let mnemonic = match Instr::from(opcode) {
+        Some(instr) => instr.to_string(),
+        None => "unknown".to_string(),
+    };
+    format!("[{:04X}] 0x{:04X} {}", pc, opcode, mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_keeps_last_opcodes() {
+        let mut log = OpcodeLog::default();
+        for i in 0..OPCODE_LOG_SIZE as u16 + 2 {
+            log.push(0x200 + i * 2, 0x6000 | i);
+        }
+        let dump = log.dump();
+        assert_eq!(OPCODE_LOG_SIZE, dump.lines().count());
+        assert_eq!(Some("[0204] 0x6002 LD V0, 2"), dump.lines().next());
+        assert_eq!("[0206] 0xFFFF unknown", format_opcode(0x206, 0xFFFF));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::core::cpu::CpuState;
+use crate::core::instr::Instr;
+use crate::core::memory::ROM_START_ADDRESS;
+use crate::core::watcher::Chip8Watcher;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+// the calls of a subroutine from one caller
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CallNode {
+    pub calls: u64,
+    // cycles spent in the subroutine, including the subroutines it called
+    pub cycles: u64,
+}
+
+// builds the tree of subroutine calls of a rom from its CALL and RET instructions.
+// as the watcher is owned by the `Chip8`, it is attached as a shared reference:
+// `chip8.add_watcher(Box::new(Rc::clone(&call_graph)))`
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    // keyed by (caller, callee), so that a subroutine called from several places is
+    // counted apart for each of them
+    nodes: HashMap<(u16, u16), CallNode>,
+    // the subroutines called from each one, in the order they were first called
+    children: HashMap<u16, Vec<u16>>,
+    // the calls being executed, with the cycle count when they were made
+    stack: Vec<((u16, u16), u64)>,
+    cycles: u64,
+}
+
+impl CallGraph {
+    pub fn new() -> Self {
+        CallGraph::default()
+    }
+
+    pub fn nodes(&self) -> &HashMap<(u16, u16), CallNode> {
+        &self.nodes
+    }
+
+    // forgets the calls recorded so far, for a rom starting over
+    pub fn clear(&mut self) {
+        *self = CallGraph::default();
+    }
+
+    fn record(&mut self, instr: &Instr) {
+        self.cycles += 1;
+        match *instr {
+            Instr::Call(address) => {
+                // the rom starts at the root of the call graph
+                let caller = self
+                    .stack
+                    .last()
+                    .map_or(ROM_START_ADDRESS, |((_, callee), _)| *callee);
+                let children = self.children.entry(caller).or_default();
+                if !children.contains(&address) {
+                    children.push(address);
+                }
+                self.nodes.entry((caller, address)).or_default().calls += 1;
+                self.stack.push(((caller, address), self.cycles));
+            }
+            // a return without a call is a stack underflow, which the cpu reports itself
+            Instr::Ret => {
+                if let Some((call, called_at)) = self.stack.pop() {
+                    self.nodes.entry(call).or_default().cycles += self.cycles - called_at;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // one line per subroutine, indented below each subroutine calling it, with the cycles
+    // of its calls from that caller. subroutines that have not returned yet are counted
+    // up to the last cycle
+    pub fn report(&self) -> String {
+        let mut cycles: HashMap<(u16, u16), u64> = self
+            .nodes
+            .iter()
+            .map(|(call, node)| (*call, node.cycles))
+            .collect();
+        for (call, called_at) in &self.stack {
+            *cycles.entry(*call).or_default() += self.cycles - called_at;
+        }
+        let mut report = String::new();
+        let mut path = Vec::new();
+        let _ = writeln!(
+            report,
+            "0x{:04X} ({} cycles)",
+            ROM_START_ADDRESS, self.cycles
+        );
+        self.write_children(&mut report, ROM_START_ADDRESS, &cycles, &mut path);
+        report.truncate(report.trim_end().len());
+        report
+    }
+
+    fn write_children(
+        &self,
+        report: &mut String,
+        caller: u16,
+        cycles: &HashMap<(u16, u16), u64>,
+        path: &mut Vec<u16>,
+    ) {
+        // recursive subroutines are listed once
+        if path.contains(&caller) {
+            return;
+        }
+        path.push(caller);
+        let indent = "  ".repeat(path.len());
+        for &callee in self.children.get(&caller).into_iter().flatten() {
+            let call_cycles = cycles.get(&(caller, callee)).copied().unwrap_or_default();
+            let _ = writeln!(
+                report,
+                "{}0x{:04X} ({} cycles)",
+                indent, callee, call_cycles
+            );
+            self.write_children(report, callee, cycles, path);
+        }
+        path.pop();
+    }
+}
+
+impl Chip8Watcher for Rc<RefCell<CallGraph>> {
+    fn on_cpu_cycle(&mut self, _state: &CpuState, instr: &Instr) {
+        self.borrow_mut().record(instr);
+    }
+
+    fn on_timer_tick(&mut self, _dt: u8, _st: u8) {}
+
+    fn on_frame(&mut self, _buffer: &[u8]) {}
+
+    fn on_key_event(&mut self, _key: u8, _down: bool) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::chip8::Chip8;
+
+    #[test]
+    fn test_call_graph_report() {
+        let call_graph = Rc::new(RefCell::new(CallGraph::new()));
+        let mut chip8 = Chip8::with_seed(0);
+        chip8.add_watcher(Box::new(Rc::clone(&call_graph)));
+        chip8
+            .load_rom(&[
+                0x22, 0x08, // 0x200: CALL 0x208
+                0x22, 0x0C, // 0x202: CALL 0x20C
+                0x22, 0x08, // 0x204: CALL 0x208
+                0x12, 0x06, // 0x206: JP 0x206
+                0x22, 0x0C, // 0x208: CALL 0x20C
+                0x00, 0xEE, // 0x20A: RET
+                0x60, 0x01, // 0x20C: LD V0, 0x01
+                0x00, 0xEE, // 0x20E: RET
+            ])
+            .unwrap();
+        for _ in 0..16 {
+            chip8.cpu_cycle().unwrap();
+        }
+        let mut call_graph = call_graph.borrow_mut();
+        assert_eq!(2, call_graph.nodes()[&(0x200, 0x208)].calls);
+        assert_eq!(2, call_graph.nodes()[&(0x208, 0x20C)].calls);
+        assert_eq!(1, call_graph.nodes()[&(0x200, 0x20C)].calls);
+        assert_eq!(
+            "0x0200 (16 cycles)\n  0x0208 (8 cycles)\n    0x020C (4 cycles)\n  0x020C (2 cycles)",
+            call_graph.report()
+        );
+        call_graph.clear();
+        assert_eq!("0x0200 (0 cycles)", call_graph.report());
+    }
+}
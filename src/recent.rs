@@ -0,0 +1,86 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MAX_RECENT_ROMS: usize = 5;
+
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+    // where the list is saved, none for a list only kept in memory
+    file: Option<PathBuf>,
+}
+
+impl RecentRoms {
+    // a missing or unreadable file simply results in an empty list
+    pub fn load() -> Self {
+        let file = recent_file_path();
+        let paths = file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str::<Vec<PathBuf>>(&contents).ok())
+            .unwrap_or_default();
+        RecentRoms { paths, file }
+    }
+
+    // an empty list that is never saved, e.g. for tests
+    pub fn in_memory() -> Self {
+        RecentRoms {
+            paths: Vec::new(),
+            file: None,
+        }
+    }
+
+    // without a home directory there is nowhere to save the list
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(&self.paths)?)
+    }
+
+    // the list is shared by every working directory, so the paths are saved absolute. a
+    // path that cannot be resolved is kept as given
+    pub fn push(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_ROMS);
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+fn recent_file_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config/chipotto/recent.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_keeps_most_recent_first() {
+        let mut recent = RecentRoms::in_memory();
+        for i in 0..7 {
+            recent.push(&PathBuf::from(format!("rom{}.ch8", i)));
+        }
+        recent.push(&PathBuf::from("rom4.ch8"));
+        let expected: Vec<PathBuf> = ["rom4.ch8", "rom6.ch8", "rom5.ch8", "rom3.ch8", "rom2.ch8"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(expected, recent.paths());
+
+        let mut recent = RecentRoms::in_memory();
+        recent.push(Path::new("Cargo.toml"));
+        recent.push(&fs::canonicalize("Cargo.toml").unwrap());
+        assert_eq!(1, recent.paths().len());
+        assert!(recent.paths()[0].is_absolute());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::core::framebuffer::FrameBuffer;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// the launcher shows one rom per row, each row being 6 pixels high
+const VISIBLE_ROWS: usize = 5;
+const ROW_HEIGHT: u8 = 6;
+// glyphs are 3 pixels wide plus 1 pixel of spacing, and the first column holds the cursor
+const GLYPH_WIDTH: u8 = 4;
+const MAX_NAME_LEN: usize = 15;
+
+// 3x5 glyphs for the characters that can appear in rom names, with the pixels
+// in the high bits of each byte so that they can be drawn as regular sprites
+const GLYPHS: [(char, [u8; 5]); 46] = [
+    ('A', [0x40, 0xA0, 0xE0, 0xA0, 0xA0]),
+    ('B', [0xC0, 0xA0, 0xC0, 0xA0, 0xC0]),
+    ('C', [0x60, 0x80, 0x80, 0x80, 0x60]),
+    ('D', [0xC0, 0xA0, 0xA0, 0xA0, 0xC0]),
+    ('E', [0xE0, 0x80, 0xC0, 0x80, 0xE0]),
+    ('F', [0xE0, 0x80, 0xC0, 0x80, 0x80]),
+    ('G', [0x60, 0x80, 0xA0, 0xA0, 0x60]),
+    ('H', [0xA0, 0xA0, 0xE0, 0xA0, 0xA0]),
+    ('I', [0xE0, 0x40, 0x40, 0x40, 0xE0]),
+    ('J', [0x20, 0x20, 0x20, 0xA0, 0x40]),
+    ('K', [0xA0, 0xA0, 0xC0, 0xA0, 0xA0]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xE0]),
+    ('M', [0xA0, 0xE0, 0xE0, 0xA0, 0xA0]),
+    ('N', [0xC0, 0xA0, 0xA0, 0xA0, 0xA0]),
+    ('O', [0x40, 0xA0, 0xA0, 0xA0, 0x40]),
+    ('P', [0xC0, 0xA0, 0xC0, 0x80, 0x80]),
+    ('Q', [0x40, 0xA0, 0xA0, 0xC0, 0x60]),
+    ('R', [0xC0, 0xA0, 0xC0, 0xA0, 0xA0]),
+    ('S', [0x60, 0x80, 0x40, 0x20, 0xC0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0xA0, 0xA0, 0xA0, 0xA0, 0xE0]),
+    ('V', [0xA0, 0xA0, 0xA0, 0xA0, 0x40]),
+    ('W', [0xA0, 0xA0, 0xE0, 0xE0, 0xA0]),
+    ('X', [0xA0, 0xA0, 0x40, 0xA0, 0xA0]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xE0, 0x20, 0x40, 0x80, 0xE0]),
+    ('0', [0xE0, 0xA0, 0xA0, 0xA0, 0xE0]),
+    ('1', [0x40, 0xC0, 0x40, 0x40, 0xE0]),
+    ('2', [0xC0, 0x20, 0x40, 0x80, 0xE0]),
+    ('3', [0xC0, 0x20, 0x40, 0x20, 0xC0]),
+    ('4', [0xA0, 0xA0, 0xE0, 0x20, 0x20]),
+    ('5', [0xE0, 0x80, 0xC0, 0x20, 0xC0]),
+    ('6', [0x60, 0x80, 0xC0, 0xA0, 0x40]),
+    ('7', [0xE0, 0x20, 0x40, 0x40, 0x40]),
+    ('8', [0x40, 0xA0, 0x40, 0xA0, 0x40]),
+    ('9', [0x40, 0xA0, 0x60, 0x20, 0xC0]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('-', [0x00, 0x00, 0xE0, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0xE0]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    ('(', [0x40, 0x80, 0x80, 0x80, 0x40]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+    ('[', [0xC0, 0x80, 0x80, 0x80, 0xC0]),
+    (']', [0x60, 0x20, 0x20, 0x20, 0x60]),
+    ('?', [0xC0, 0x20, 0x40, 0x00, 0x40]),
+    ('>', [0x80, 0x40, 0x20, 0x40, 0x80]),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+pub struct RomLibrary {
+    entries: Vec<RomEntry>,
+    selected: usize,
+}
+
+impl RomLibrary {
+    pub fn new(entries: Vec<RomEntry>) -> Self {
+        RomLibrary {
+            entries,
+            selected: 0,
+        }
+    }
+
+    // lists the .ch8 files in the directory sorted by name, an unreadable directory
+    // simply results in an empty list
+    pub fn scan(dir: &Path) -> Vec<RomEntry> {
+        let mut entries: Vec<RomEntry> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let is_rom = path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("ch8"));
+                let metadata = entry.metadata().ok()?;
+                if !is_rom || !metadata.is_file() {
+                    return None;
+                }
+                Some(RomEntry {
+                    name: path.file_stem()?.to_string_lossy().into_owned(),
+                    path,
+                    size_bytes: metadata.len(),
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+
+    pub fn entries(&self) -> &[RomEntry] {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> Option<&RomEntry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    // draws the names of the roms around the selected one, the selected rom being
+    // marked by a cursor
+    pub fn render(&self, frame_buffer: &mut FrameBuffer) {
+        frame_buffer.clear();
+        let first = self.selected.saturating_sub(VISIBLE_ROWS - 1);
+        for (row, entry) in self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(VISIBLE_ROWS)
+        {
+            let y = 1 + (row - first) as u8 * ROW_HEIGHT;
+            if row == self.selected {
+                frame_buffer.draw(1, y, &glyph('>'));
+            }
+            for (col, c) in entry.name.chars().take(MAX_NAME_LEN).enumerate() {
+                let x = 1 + (col as u8 + 1) * GLYPH_WIDTH;
+                frame_buffer.draw(x, y, &glyph(c));
+            }
+        }
+        frame_buffer.set_changed(true);
+    }
+}
+
+// characters without a glyph are drawn as a question mark
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|(g, _)| *g == c)
+        .or_else(|| GLYPHS.iter().find(|(g, _)| *g == '?'))
+        .map(|(_, sprite)| *sprite)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> RomEntry {
+        RomEntry {
+            path: PathBuf::from(format!("{}.ch8", name)),
+            name: name.to_string(),
+            size_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn test_scan_finds_roms_sorted_by_name() {
+        let dir = std::env::temp_dir().join(format!("chipotto_library_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pong.ch8"), [0x00, 0xE0]).unwrap();
+        fs::write(dir.join("Blitz.CH8"), [0x00, 0xE0, 0x12, 0x00]).unwrap();
+        fs::write(dir.join("notes.txt"), "not a rom").unwrap();
+        let names: Vec<(String, u64)> = RomLibrary::scan(&dir)
+            .into_iter()
+            .map(|entry| (entry.name, entry.size_bytes))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            vec![("Blitz".to_string(), 4), ("pong".to_string(), 2)],
+            names
+        );
+    }
+
+    #[test]
+    fn test_selection_stays_in_bounds() {
+        let mut library = RomLibrary::new(vec![entry("a"), entry("b")]);
+        library.select_previous();
+        assert_eq!("a", library.selected().unwrap().name);
+        library.select_next();
+        library.select_next();
+        assert_eq!("b", library.selected().unwrap().name);
+    }
+
+    #[test]
+    fn test_render_scrolls_to_selected_rom() {
+        let names = ["a", "b", "c", "d", "e", "f"];
+        let mut library = RomLibrary::new(names.iter().map(|name| entry(name)).collect());
+        let mut frame_buffer = FrameBuffer::default();
+        library.render(&mut frame_buffer);
+        // the cursor is drawn in the first row
+        assert_eq!(1, frame_buffer.get_buffer()[64 + 1]);
+        for _ in 0..5 {
+            library.select_next();
+        }
+        library.render(&mut frame_buffer);
+        // the selected rom is now the last visible one
+        assert_eq!(0, frame_buffer.get_buffer()[64 + 1]);
+        assert_eq!(1, frame_buffer.get_buffer()[(1 + 4 * 6) * 64 + 1]);
+    }
+}
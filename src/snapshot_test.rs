@@ -0,0 +1,122 @@
+use crate::core::chip8::Chip8;
+use crate::core::framebuffer::{BUFFER_HEIGHT, BUFFER_WIDTH};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+// roms are run with a fixed seed so that the same rom always renders the same frame
+const SNAPSHOT_SEED: u64 = 0;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotResult {
+    Match,
+    // coordinates (x, y) of the pixels that differ from the golden snapshot
+    Mismatch { diff_pixels: Vec<(u8, u8)> },
+    Created,
+}
+
+impl Display for SnapshotResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotResult::Match => write!(f, "frame buffer matches the snapshot"),
+            SnapshotResult::Created => write!(f, "snapshot created"),
+            SnapshotResult::Mismatch { diff_pixels } => {
+                // the differing pixels are marked with an X on an otherwise empty screen
+                writeln!(f, "{} pixels differ from the snapshot:", diff_pixels.len())?;
+                for y in 0..BUFFER_HEIGHT {
+                    let row: String = (0..BUFFER_WIDTH)
+                        .map(|x| {
+                            if diff_pixels.contains(&(x as u8, y as u8)) {
+                                'X'
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    writeln!(f, "{}", row)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// runs the rom for the given number of cycles and compares the frame buffer with the
+// golden snapshot, which is created instead when it does not exist yet. meant to be used
+// from tests, so it panics when the rom cannot be run or the snapshot cannot be accessed
+pub fn run(rom: &[u8], cycles: u64, golden_path: &Path) -> SnapshotResult {
+    let mut chip8 = Chip8::with_seed(SNAPSHOT_SEED);
+    chip8.load_rom(rom).expect("could not load rom");
+    for _ in 0..cycles {
+        chip8.cpu_cycle().expect("could not run rom");
+    }
+    let actual = to_snapshot(chip8.frame_buffer.get_buffer());
+
+    if !golden_path.exists() {
+        if let Some(dir) = golden_path.parent() {
+            fs::create_dir_all(dir).expect("could not create snapshot directory");
+        }
+        fs::write(golden_path, actual).expect("could not write snapshot");
+        return SnapshotResult::Created;
+    }
+
+    let golden = fs::read_to_string(golden_path).expect("could not read snapshot");
+    let golden_rows: Vec<&str> = golden.lines().collect();
+    let mut diff_pixels = Vec::new();
+    for (y, actual_row) in actual.lines().enumerate() {
+        let golden_row = golden_rows.get(y).copied().unwrap_or("").as_bytes();
+        for (x, pixel) in actual_row.bytes().enumerate() {
+            if golden_row.get(x) != Some(&pixel) {
+                diff_pixels.push((x as u8, y as u8));
+            }
+        }
+    }
+    if diff_pixels.is_empty() {
+        SnapshotResult::Match
+    } else {
+        SnapshotResult::Mismatch { diff_pixels }
+    }
+}
+
+// one line per row, with '.' for unlit pixels and the plane mask for lit ones
+fn to_snapshot(buffer: &[u8]) -> String {
+    let mut snapshot = String::with_capacity((BUFFER_WIDTH + 1) * BUFFER_HEIGHT);
+    for row in buffer.chunks(BUFFER_WIDTH) {
+        for pixel in row {
+            snapshot.push(match pixel {
+                0 => '.',
+                _ => (b'0' + pixel) as char,
+            });
+        }
+        snapshot.push('\n');
+    }
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_created_then_compared() {
+        let golden_path =
+            std::env::temp_dir().join(format!("chipotto_snapshot_{}.txt", std::process::id()));
+        let _ = fs::remove_file(&golden_path);
+        // LD V0, 0x00; LD F, V0; DRW V0, V0, 5
+        let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05];
+        assert_eq!(SnapshotResult::Created, run(&rom, 3, &golden_path));
+        assert_eq!(SnapshotResult::Match, run(&rom, 3, &golden_path));
+
+        let result = run(&rom, 2, &golden_path);
+        fs::remove_file(&golden_path).unwrap();
+        match result {
+            SnapshotResult::Mismatch { diff_pixels } => {
+                // the font sprite for 0 has 14 lit pixels, starting with the top row
+                assert_eq!(14, diff_pixels.len());
+                assert_eq!((0, 0), diff_pixels[0]);
+            }
+            _ => panic!("expected a mismatch, got {:?}", result),
+        }
+    }
+}
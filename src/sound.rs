@@ -0,0 +1,100 @@
+use crate::core::audio::{PatternBuffer, SoundEvent, SoundOutput};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+// the pattern is a full-scale square wave, which is loud
+const VOLUME: f32 = 0.2;
+
+// what the output callback plays, shared with the emulator
+struct Playback {
+    playing: bool,
+    pattern: PatternBuffer,
+    // the position in the pattern, carried from one buffer to the next
+    position: f32,
+}
+
+// plays the audio pattern through the default output device of the host while the sound
+// timer is running
+pub struct CpalOutput {
+    playback: Arc<Mutex<Playback>>,
+    // playing stops when the stream is dropped
+    _stream: Stream,
+}
+
+impl CpalOutput {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device")?;
+        let supported = device.default_output_config()?;
+        let config = supported.config();
+        let playback = Arc::new(Mutex::new(Playback {
+            playing: false,
+            pattern: PatternBuffer::SQUARE_WAVE,
+            position: 0.0,
+        }));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, playback.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, playback.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, playback.clone())?,
+            format => return Err(format!("unsupported sample format {}", format).into()),
+        };
+        stream.play()?;
+        Ok(CpalOutput {
+            playback,
+            _stream: stream,
+        })
+    }
+}
+
+impl SoundOutput for CpalOutput {
+    fn sound_event(&mut self, event: SoundEvent) {
+        if let Ok(mut playback) = self.playback.lock() {
+            playback.playing = matches!(event, SoundEvent::Started | SoundEvent::Continuing(_));
+        }
+    }
+
+    fn audio_pattern(&mut self, pattern: &PatternBuffer) {
+        if let Ok(mut playback) = self.playback.lock() {
+            playback.pattern = *pattern;
+        }
+    }
+}
+
+// the pattern is rendered once for all the channels
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &Device,
+    config: &StreamConfig,
+    playback: Arc<Mutex<Playback>>,
+) -> Result<Stream, Box<dyn Error>> {
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0;
+    let mut samples = Vec::new();
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            samples.clear();
+            samples.resize(data.len() / channels, 0.0);
+            if let Ok(mut playback) = playback.lock() {
+                let Playback {
+                    playing,
+                    pattern,
+                    position,
+                } = &mut *playback;
+                if *playing {
+                    pattern.fill(sample_rate, position, &mut samples);
+                }
+            }
+            for (frame, sample) in data.chunks_mut(channels).zip(&samples) {
+                for out in frame {
+                    *out = T::from_sample(sample * VOLUME);
+                }
+            }
+        },
+        |err| eprintln!("Audio output error: {}", err),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,1024 @@
+use crate::core::chip8::{Chip8, RomLoadError};
+use crate::core::compat::CompatibilityMode;
+use crate::core::cpu::CpuError;
+use crate::core::framebuffer::{crc32, FrameBuffer, BUFFER_WIDTH};
+use crate::core::instr::{INSTR_TYPE_COUNT, INSTR_TYPE_NAMES};
+use crate::rom_library::RomLibrary;
+use serde::Deserialize;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// roms are run with a fixed seed so that the checksum does not depend on the run
+const TEST_SEED: u64 = 0;
+
+// loads V0 = 5, V1 = 10, V2 = 0xFF and I = 0x300 before the instruction of a fuzz rom
+const FUZZ_SETUP: [u16; 4] = [0x6005, 0x610A, 0x62FF, 0xA300];
+
+// one opcode of each instruction type, in the order of their type index, working on the
+// registers set up by `FUZZ_SETUP`. HALT, the last type, is left out since only a cpu in
+// test mode decodes it. the CHIP-8E skips and the XO-CHIP audio instructions are only
+// decoded in their own mode
+const FUZZ_OPCODES: [u16; INSTR_TYPE_COUNT - 1] = [
+    0x00E0, 0x00EE, 0x0300, 0x1200, 0x2200, 0x3005, 0x4005, 0x5010, 0x9010, 0x6042, 0x7001, 0x8010,
+    0x8011, 0x8012, 0x8013, 0x8014, 0x8015, 0x8017, 0x8016, 0x801E, 0xA300, 0xB300, 0xC0FF, 0xD015,
+    0xE09E, 0xE0A1, 0xF007, 0xF00A, 0xF015, 0xF018, 0xF01E, 0xF029, 0xF033, 0xF055, 0xF065, 0x5011,
+    0x5012, 0xF002, 0xF03A,
+];
+
+// the registers compared by `differential_test`: V0 to VF, then the low byte of I
+const COMPARED_REGISTERS: usize = 17;
+
+// ANSI colors of `snapshot_diff`
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_WHITE: &str = "\x1b[37m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+// CRC-32 (IEEE) of the frame buffer, which identifies what the rom has drawn
+pub fn frame_checksum(buffer: &[u8]) -> u32 {
+    crc32(buffer)
+}
+
+#[derive(Debug)]
+pub enum TestOutcome {
+    Pass,
+    Fail { checksum: u32 },
+    // the checksum matches but not the frame buffer of the golden `.rle` file
+    FrameMismatch,
+    RomLoad(RomLoadError),
+    Cpu(CpuError),
+    // the golden files of the rom are missing or unreadable, see `GoldenGenerator`
+    InvalidGolden(GoldenError),
+    // the emulator panicked running the rom, with the message of the panic
+    Panic(String),
+}
+
+#[derive(Debug)]
+pub struct TestResult {
+    // the position of the test in the order it was added
+    pub index: usize,
+    pub outcome: TestOutcome,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, TestOutcome::Pass)
+    }
+}
+
+impl Display for TestResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "test {} ", self.index)?;
+        match &self.outcome {
+            TestOutcome::Pass => write!(f, "passed")?,
+            TestOutcome::Fail { checksum } => write!(f, "failed with checksum {:#010X}", checksum)?,
+            TestOutcome::FrameMismatch => write!(f, "failed with a different frame buffer")?,
+            TestOutcome::RomLoad(err) => write!(f, "could not load rom: {}", err)?,
+            TestOutcome::Cpu(err) => write!(f, "cpu error: {}", err)?,
+            TestOutcome::InvalidGolden(err) => write!(f, "invalid golden: {}", err)?,
+            TestOutcome::Panic(message) => write!(f, "panicked: {}", message)?,
+        }
+        write!(f, " in {:?}", self.duration)
+    }
+}
+
+struct RomTest {
+    index: usize,
+    rom: Vec<u8>,
+    cycles: u64,
+    expected_checksum: u32,
+    // the frame buffer as encoded by `FrameBuffer::encode_rle`, compared when known
+    expected_rle: Option<Vec<u8>>,
+}
+
+impl RomTest {
+    // a panic only fails this rom, the other tests of the worker go on
+    fn run(&self) -> TestResult {
+        let start = Instant::now();
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| self.outcome()))
+            .unwrap_or_else(|payload| TestOutcome::Panic(panic_message(payload.as_ref())));
+        TestResult {
+            index: self.index,
+            outcome,
+            duration: start.elapsed(),
+        }
+    }
+
+    fn outcome(&self) -> TestOutcome {
+        let mut chip8 = Chip8::with_seed(TEST_SEED);
+        if let Err(err) = chip8.load_rom(&self.rom) {
+            return TestOutcome::RomLoad(err);
+        }
+        for _ in 0..self.cycles {
+            if let Err(err) = chip8.cpu_cycle() {
+                return TestOutcome::Cpu(err);
+            }
+        }
+        let checksum = frame_checksum(chip8.frame_buffer.get_buffer());
+        if checksum != self.expected_checksum {
+            TestOutcome::Fail { checksum }
+        } else if self
+            .expected_rle
+            .as_ref()
+            .is_some_and(|rle| *rle != chip8.frame_buffer.encode_rle())
+        {
+            TestOutcome::FrameMismatch
+        } else {
+            TestOutcome::Pass
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "unknown panic".to_string()),
+    }
+}
+
+// runs roms for a number of cycles on a pool of threads, each one in its own `Chip8`, and
+// compares the checksum of the frame buffer they end with, see `frame_checksum`
+pub struct HostedTestRunner {
+    concurrency: usize,
+    tests: Vec<RomTest>,
+}
+
+impl HostedTestRunner {
+    pub fn new(concurrency: usize) -> Self {
+        HostedTestRunner {
+            concurrency: concurrency.max(1),
+            tests: Vec::new(),
+        }
+    }
+
+    pub fn add_test(&mut self, rom: &[u8], cycles: u64, expected_checksum: u32) {
+        self.tests.push(RomTest {
+            index: self.tests.len(),
+            rom: rom.to_vec(),
+            cycles,
+            expected_checksum,
+            expected_rle: None,
+        });
+    }
+
+    // also compares the whole frame buffer with its run-length encoding
+    pub fn add_test_with_rle(
+        &mut self,
+        rom: &[u8],
+        cycles: u64,
+        expected_checksum: u32,
+        expected_rle: &[u8],
+    ) {
+        self.add_test(rom, cycles, expected_checksum);
+        if let Some(test) = self.tests.last_mut() {
+            test.expected_rle = Some(expected_rle.to_vec());
+        }
+    }
+
+    // runs the queued tests and returns their results in the order they were added
+    pub fn run_all(&mut self) -> Vec<TestResult> {
+        let tests = Arc::new(Mutex::new(self.tests.drain(..).collect::<Vec<_>>()));
+        let (sender, receiver) = mpsc::channel();
+        let workers: Vec<_> = (0..self.concurrency)
+            .map(|_| {
+                let tests = Arc::clone(&tests);
+                let sender = sender.clone();
+                thread::spawn(move || loop {
+                    // the lock is released before running the test
+                    let test = tests.lock().unwrap().pop();
+                    match test {
+                        Some(test) => sender.send(test.run()).unwrap(),
+                        None => break,
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        let mut results: Vec<TestResult> = receiver.iter().collect();
+        for worker in workers {
+            worker.join().expect("test worker panicked");
+        }
+        results.sort_by_key(|result| result.index);
+        results
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(io::Error),
+    RomLoad(PathBuf, RomLoadError),
+    Cpu(PathBuf, CpuError),
+    // the golden file is missing, or the checksum file does not hold a checksum
+    InvalidGolden(PathBuf),
+}
+
+impl Display for GoldenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Io(err) => write!(f, "{}", err),
+            GoldenError::RomLoad(path, err) => write!(f, "{}: {}", path.display(), err),
+            GoldenError::Cpu(path, err) => write!(f, "{}: {}", path.display(), err),
+            GoldenError::InvalidGolden(path) => {
+                write!(f, "{}: missing or invalid golden file", path.display())
+            }
+        }
+    }
+}
+
+impl Error for GoldenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GoldenError::Io(err) => Some(err),
+            GoldenError::RomLoad(_, err) => Some(err),
+            GoldenError::Cpu(_, err) => Some(err),
+            GoldenError::InvalidGolden(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for GoldenError {
+    fn from(err: io::Error) -> Self {
+        GoldenError::Io(err)
+    }
+}
+
+// a rom and whether its golden files could be written
+pub type GeneratedGolden = (PathBuf, Result<(), GoldenError>);
+
+// writes the golden files of the .ch8 roms of a directory and checks the roms against
+// them later. for each rom `<name>.rle` holds the frame buffer after running it, as
+// encoded by `FrameBuffer::encode_rle`, and `<name>.crc` its `frame_checksum` in hex
+pub struct GoldenGenerator {
+    cycles: u64,
+}
+
+impl GoldenGenerator {
+    pub fn new(cycles: u64) -> Self {
+        GoldenGenerator { cycles }
+    }
+
+    // returns the result of each rom, sorted by name. a rom that fails to run gets no
+    // golden files and does not stop the others
+    pub fn generate(
+        &self,
+        rom_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Vec<GeneratedGolden>, GoldenError> {
+        fs::create_dir_all(output_dir)?;
+        Ok(RomLibrary::scan(rom_dir)
+            .into_iter()
+            .map(|entry| {
+                let result = self.generate_one(&entry.path, &entry.name, output_dir);
+                (entry.path, result)
+            })
+            .collect())
+    }
+
+    fn generate_one(&self, path: &Path, name: &str, output_dir: &Path) -> Result<(), GoldenError> {
+        let mut chip8 = Chip8::with_seed(TEST_SEED);
+        chip8
+            .load_rom(&fs::read(path)?)
+            .map_err(|err| GoldenError::RomLoad(path.to_path_buf(), err))?;
+        for _ in 0..self.cycles {
+            chip8
+                .cpu_cycle()
+                .map_err(|err| GoldenError::Cpu(path.to_path_buf(), err))?;
+        }
+        let golden = |extension| output_dir.join(format!("{}.{}", name, extension));
+        fs::write(golden("rle"), chip8.frame_buffer.encode_rle())?;
+        let checksum = frame_checksum(chip8.frame_buffer.get_buffer());
+        fs::write(golden("crc"), format!("{:08X}\n", checksum))?;
+        Ok(())
+    }
+
+    // runs the roms on all the cores and compares them with their golden files, the
+    // results being sorted by rom name. a rom without valid golden files fails
+    pub fn verify(&self, rom_dir: &Path, golden_dir: &Path) -> Vec<(PathBuf, TestResult)> {
+        let concurrency = thread::available_parallelism().map_or(1, |n| n.get());
+        let mut runner = HostedTestRunner::new(concurrency);
+        // the result of the roms that could not be queued, None for the others
+        let mut results = Vec::new();
+        for (index, entry) in RomLibrary::scan(rom_dir).into_iter().enumerate() {
+            let result = match read_golden(&entry.path, &entry.name, golden_dir) {
+                Ok((rom, checksum, rle)) => {
+                    runner.add_test_with_rle(&rom, self.cycles, checksum, &rle);
+                    None
+                }
+                Err(err) => Some(TestResult {
+                    index,
+                    outcome: TestOutcome::InvalidGolden(err),
+                    duration: Duration::default(),
+                }),
+            };
+            results.push((entry.path, result));
+        }
+        let mut run = runner.run_all().into_iter();
+        results
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, (path, result))| {
+                let result = result.or_else(|| run.next())?;
+                Some((path, TestResult { index, ..result }))
+            })
+            .collect()
+    }
+}
+
+// the rom with its expected checksum and run-length encoded frame buffer
+fn read_golden(
+    path: &Path,
+    name: &str,
+    golden_dir: &Path,
+) -> Result<(Vec<u8>, u32, Vec<u8>), GoldenError> {
+    let crc_path = golden_dir.join(format!("{}.crc", name));
+    let checksum = fs::read_to_string(&crc_path)
+        .ok()
+        .and_then(|crc| u32::from_str_radix(crc.trim(), 16).ok())
+        .ok_or(GoldenError::InvalidGolden(crc_path))?;
+    let rle_path = golden_dir.join(format!("{}.rle", name));
+    let rle = fs::read(&rle_path).map_err(|_| GoldenError::InvalidGolden(rle_path))?;
+    Ok((fs::read(path)?, checksum, rle))
+}
+
+// the golden and the actual frame buffer side by side, colored for the terminal: pixels
+// that match are white, and pixels that differ are green on the golden side and red on
+// the actual side. returns None when the two are identical
+pub fn snapshot_diff(golden: &FrameBuffer, actual: &FrameBuffer) -> Option<String> {
+    if golden.get_buffer() == actual.get_buffer() {
+        return None;
+    }
+    let pixel = |value: u8, other: u8, diff_color: &str| {
+        let color = if value == other {
+            ANSI_WHITE
+        } else {
+            diff_color
+        };
+        let block = if value == 0 { "··" } else { "██" };
+        format!("{}{}{}", color, block, ANSI_RESET)
+    };
+    let rows = golden
+        .get_buffer()
+        .chunks(BUFFER_WIDTH)
+        .zip(actual.get_buffer().chunks(BUFFER_WIDTH));
+    let mut diff = String::new();
+    for (golden_row, actual_row) in rows {
+        for (&g, &a) in golden_row.iter().zip(actual_row) {
+            diff.push_str(&pixel(g, a, ANSI_GREEN));
+        }
+        diff.push_str(" | ");
+        for (&a, &g) in actual_row.iter().zip(golden_row) {
+            diff.push_str(&pixel(a, g, ANSI_RED));
+        }
+        diff.push('\n');
+    }
+    Some(diff)
+}
+
+// `assert_register!(chip8, V3 == 0x42)` checks a V register of a `Chip8`, given by name
+#[macro_export]
+macro_rules! assert_register {
+    ($chip8:expr, $register:ident == $expected:expr) => {{
+        let name = stringify!($register);
+        let x = usize::from_str_radix(name.trim_start_matches('V'), 16)
+            .ok()
+            .filter(|x| name.starts_with('V') && *x < 16)
+            .unwrap_or_else(|| panic!("{} is not a register, V0 to VF", name));
+        let expected: u8 = $expected;
+        assert_eq!(
+            $chip8.cpu.register(x),
+            expected,
+            "{} should be {:#04X}",
+            name,
+            expected
+        );
+    }};
+}
+
+// `assert_memory!(chip8, 0x300 == [0x01, 0x02])` checks the bytes starting at an address,
+// and `assert_memory!(chip8, 0x300 == 0x400, 16)` that two areas hold the same bytes
+#[macro_export]
+macro_rules! assert_memory {
+    ($chip8:expr, $address:tt == [$($byte:expr),* $(,)?]) => {{
+        let expected: &[u8] = &[$($byte),*];
+        let address: u16 = $address;
+        assert_eq!(
+            $chip8.memory.read_data(address, expected.len() as u16),
+            expected,
+            "memory at {:#05X} should be {:02X?}",
+            address,
+            expected
+        );
+    }};
+    ($chip8:expr, $a:tt == $b:tt, $len:expr) => {{
+        let (a, b, len): (u16, u16, u16) = ($a, $b, $len);
+        assert_eq!(
+            $chip8.memory.compare(a, b, len).ok(),
+            Some(core::cmp::Ordering::Equal),
+            "the {} bytes at {:#05X} and {:#05X} should be equal",
+            len,
+            a,
+            b
+        );
+    }};
+}
+
+// `assert_pc!(chip8, 0x202)` checks the program counter
+#[macro_export]
+macro_rules! assert_pc {
+    ($chip8:expr, $expected:expr) => {{
+        let expected: u16 = $expected;
+        assert_eq!(
+            $chip8.cpu.pc(),
+            expected,
+            "the program counter should be {:#05X}",
+            expected
+        );
+    }};
+}
+
+// a register holding different values in the two runs of `differential_test`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StateDiff {
+    // the cycle the difference showed up in, starting from 0
+    pub cycle: u64,
+    // the address of the instruction run in that cycle by the first instance
+    pub pc: u16,
+    // Vx, or None for I, whose low byte is compared as the modes only move it by up to 16
+    pub register: Option<u8>,
+    // the value in the first mode, then the one in the second mode
+    pub old_value: u8,
+    pub new_value: u8,
+}
+
+// runs the rom in both modes side by side and compares the registers after every cycle.
+// a register is reported when it starts holding different values, and again each time
+// the values change while they still differ. the runs stop at the first cpu error of
+// either instance, and a rom that does not load gives no differences
+pub fn differential_test(
+    rom: &[u8],
+    cycles: u64,
+    mode_a: CompatibilityMode,
+    mode_b: CompatibilityMode,
+) -> Vec<StateDiff> {
+    let mut instances = [Chip8::with_seed(TEST_SEED), Chip8::with_seed(TEST_SEED)];
+    for (chip8, mode) in instances.iter_mut().zip(&[mode_a, mode_b]) {
+        if chip8.load_rom(rom).is_err() {
+            return Vec::new();
+        }
+        chip8.cpu.set_compatibility_mode_at_runtime(*mode);
+    }
+
+    let mut diffs = Vec::new();
+    let mut previous = [[0u8; COMPARED_REGISTERS]; 2];
+    for cycle in 0..cycles {
+        let pc = instances[0].cpu.pc();
+        if instances.iter_mut().any(|chip8| chip8.cpu_cycle().is_err()) {
+            break;
+        }
+        let current = [
+            compared_values(&instances[0]),
+            compared_values(&instances[1]),
+        ];
+        for index in 0..COMPARED_REGISTERS {
+            let (old_value, new_value) = (current[0][index], current[1][index]);
+            let changed = (old_value, new_value) != (previous[0][index], previous[1][index]);
+            if old_value != new_value && changed {
+                diffs.push(StateDiff {
+                    cycle,
+                    pc,
+                    register: if index < 16 { Some(index as u8) } else { None },
+                    old_value,
+                    new_value,
+                });
+            }
+        }
+        previous = current;
+    }
+    diffs
+}
+
+// seed corpus for fuzzing the cpu: one rom per instruction type, each setting a few
+// registers and I to known values before running the instruction, so that the fuzzer
+// starts from every instruction instead of having to find them
+pub struct FuzzCorpus;
+
+impl FuzzCorpus {
+    // in the order of the instruction type indices, each rom being 2 + 2 * 4 bytes long
+    pub fn generate() -> Vec<Vec<u8>> {
+        FUZZ_OPCODES
+            .iter()
+            .map(|opcode| {
+                FUZZ_SETUP
+                    .iter()
+                    .chain(std::iter::once(opcode))
+                    .flat_map(|word| word.to_be_bytes())
+                    .collect()
+            })
+            .collect()
+    }
+
+    // writes the roms as `<index>-<instruction type>.ch8`, e.g. for a cargo-fuzz corpus
+    pub fn write_to(dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        for (index, rom) in FuzzCorpus::generate().iter().enumerate() {
+            let name = format!("{:02}-{}.ch8", index, INSTR_TYPE_NAMES[index]);
+            fs::write(dir.join(name), rom)?;
+        }
+        Ok(())
+    }
+}
+
+fn compared_values(chip8: &Chip8) -> [u8; COMPARED_REGISTERS] {
+    let state = chip8.cpu.state();
+    let mut values = [0u8; COMPARED_REGISTERS];
+    values[..16].copy_from_slice(&state.v);
+    values[16] = state.i as u8;
+    values
+}
+
+#[derive(Debug)]
+pub enum FixtureError {
+    Io(io::Error),
+    // the file is not a fixture, or names a register or address that does not exist
+    Invalid(String),
+    Cpu(CpuError),
+    // a register did not hold the value the fixture expects after the cycle
+    Mismatch {
+        register: String,
+        expected: u16,
+        actual: u16,
+    },
+}
+
+impl Display for FixtureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureError::Io(err) => write!(f, "{}", err),
+            FixtureError::Invalid(reason) => write!(f, "invalid fixture: {}", reason),
+            FixtureError::Cpu(err) => write!(f, "{}", err),
+            FixtureError::Mismatch {
+                register,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} should be {:#X} after the cycle, got {:#X}",
+                register, expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for FixtureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FixtureError::Io(err) => Some(err),
+            FixtureError::Cpu(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FixtureError {
+    fn from(err: io::Error) -> Self {
+        FixtureError::Io(err)
+    }
+}
+
+// the registers a fixture can set and check, named V0 to VF, I, PC, DT and ST
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FixtureRegister {
+    V(usize),
+    I,
+    Pc,
+    Dt,
+    St,
+}
+
+impl FromStr for FixtureRegister {
+    type Err = FixtureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Ok(FixtureRegister::I),
+            "PC" => Ok(FixtureRegister::Pc),
+            "DT" => Ok(FixtureRegister::Dt),
+            "ST" => Ok(FixtureRegister::St),
+            name => name
+                .strip_prefix('V')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| usize::from_str_radix(x, 16).ok())
+                .map(FixtureRegister::V)
+                .ok_or_else(|| FixtureError::Invalid(format!("unknown register '{}'", s))),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureFile {
+    before: BTreeMap<String, u16>,
+    #[serde(default)]
+    rom_patch: BTreeMap<String, String>,
+    after_1_cycle: BTreeMap<String, u16>,
+}
+
+// a single instruction test written as data: the registers to start from, the bytes to
+// write to memory, and the registers expected after running one cycle, e.g. in toml
+// before = { V0 = 5, V1 = 3, I = 0x300, PC = 0x200 }
+// rom_patch = { "0x200" = "8014" }
+// after_1_cycle = { V0 = 8, VF = 0 }
+// registers missing from `before` keep their value after reset, and only the registers
+// listed in `after_1_cycle` are checked
+#[derive(Debug)]
+pub struct CycleFixture {
+    before: Vec<(FixtureRegister, u16)>,
+    rom_patch: Vec<(u16, Vec<u8>)>,
+    after: Vec<(String, FixtureRegister, u16)>,
+}
+
+impl CycleFixture {
+    // .json files are read as json, anything else as toml
+    pub fn from_file(path: &Path) -> Result<Self, FixtureError> {
+        let contents = fs::read_to_string(path)?;
+        let file = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents).map_err(|err| err.to_string())
+        } else {
+            toml::from_str(&contents).map_err(|err| err.to_string())
+        }
+        .map_err(|err| FixtureError::Invalid(format!("{}: {}", path.display(), err)))?;
+        CycleFixture::from_fixture_file(file)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, FixtureError> {
+        let file =
+            toml::from_str(contents).map_err(|err| FixtureError::Invalid(err.to_string()))?;
+        CycleFixture::from_fixture_file(file)
+    }
+
+    fn from_fixture_file(file: FixtureFile) -> Result<Self, FixtureError> {
+        let registers = |values: BTreeMap<String, u16>| {
+            values
+                .into_iter()
+                .map(|(name, value)| {
+                    let register = FixtureRegister::from_str(&name)?;
+                    let wide = register == FixtureRegister::I || register == FixtureRegister::Pc;
+                    if !wide && value > 0xFF {
+                        return Err(FixtureError::Invalid(format!(
+                            "{} does not fit in {}",
+                            value, name
+                        )));
+                    }
+                    Ok((name, register, value))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let before = registers(file.before)?
+            .into_iter()
+            .map(|(_, register, value)| (register, value))
+            .collect();
+        let mut rom_patch = Vec::new();
+        for (address, bytes) in &file.rom_patch {
+            let address = parse_number(address)
+                .ok_or_else(|| FixtureError::Invalid(format!("invalid address '{}'", address)))?;
+            let bytes = parse_hex_bytes(bytes)
+                .ok_or_else(|| FixtureError::Invalid(format!("invalid bytes '{}'", bytes)))?;
+            rom_patch.push((address, bytes));
+        }
+        Ok(CycleFixture {
+            before,
+            rom_patch,
+            after: registers(file.after_1_cycle)?,
+        })
+    }
+
+    // fails with the first register, in name order, that does not hold its expected value
+    pub fn run(&self) -> Result<(), FixtureError> {
+        let mut chip8 = Chip8::with_seed(TEST_SEED);
+        let mut state = chip8.cpu.state();
+        for &(register, value) in &self.before {
+            match register {
+                FixtureRegister::V(x) => state.v[x] = value as u8,
+                FixtureRegister::I => state.i = value,
+                FixtureRegister::Pc => state.pc = value,
+                FixtureRegister::Dt => state.dt = value as u8,
+                FixtureRegister::St => state.st = value as u8,
+            }
+        }
+        chip8
+            .cpu
+            .restore(&state)
+            .map_err(|err| FixtureError::Invalid(err.to_string()))?;
+        for (address, bytes) in &self.rom_patch {
+            chip8
+                .memory
+                .write_data(*address, bytes)
+                .map_err(|err| FixtureError::Invalid(err.to_string()))?;
+        }
+        chip8.cpu_cycle().map_err(FixtureError::Cpu)?;
+
+        let state = chip8.cpu.state();
+        for (name, register, expected) in &self.after {
+            let actual = match register {
+                FixtureRegister::V(x) => state.v[*x] as u16,
+                FixtureRegister::I => state.i,
+                FixtureRegister::Pc => state.pc,
+                FixtureRegister::Dt => state.dt as u16,
+                FixtureRegister::St => state.st as u16,
+            };
+            if actual != *expected {
+                return Err(FixtureError::Mismatch {
+                    register: name.clone(),
+                    expected: *expected,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// decimal, or hexadecimal with a 0x prefix
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::instr::Instr;
+
+    #[test]
+    fn test_run_all() {
+        // LD V0, 0x00; LD F, V0; DRW V0, V0, 5
+        let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05];
+        let blank_checksum = frame_checksum(&[0; 64 * 32]);
+        let mut chip8 = Chip8::with_seed(TEST_SEED);
+        chip8.load_rom(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.cpu_cycle().unwrap();
+        }
+        let drawn_checksum = frame_checksum(chip8.frame_buffer.get_buffer());
+        assert_ne!(blank_checksum, drawn_checksum);
+
+        let mut runner = HostedTestRunner::new(2);
+        runner.add_test(&rom, 3, drawn_checksum);
+        runner.add_test(&rom, 2, drawn_checksum);
+        runner.add_test(&rom, 2, blank_checksum);
+        runner.add_test(&[0x00, 0xEE], 1, blank_checksum);
+        // LD V0, 0x20; SKP V0 looks up a key past the end of the keypad, which panics
+        runner.add_test(&[0x60, 0x20, 0xE0, 0x9E], 2, blank_checksum);
+        runner.add_test(&rom, 3, drawn_checksum);
+        let results = runner.run_all();
+        assert_eq!(6, results.len());
+        assert!(results[0].passed());
+        assert!(matches!(
+            results[1].outcome,
+            TestOutcome::Fail { checksum } if checksum == blank_checksum
+        ));
+        assert!(results[2].passed());
+        assert!(matches!(
+            results[3].outcome,
+            TestOutcome::Cpu(CpuError::StackUnderflow)
+        ));
+        assert!(matches!(results[4].outcome, TestOutcome::Panic(_)));
+        assert!(results[5].passed());
+    }
+
+    #[test]
+    fn test_generate_and_verify_goldens() {
+        let dir = std::env::temp_dir().join(format!("chipotto_goldens_{}", std::process::id()));
+        let rom_dir = dir.join("roms");
+        let golden_dir = dir.join("goldens");
+        fs::create_dir_all(&rom_dir).unwrap();
+        // LD V0, 0x00; LD F, V0; DRW V0, V0, 5
+        fs::write(
+            rom_dir.join("zero.ch8"),
+            [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05],
+        )
+        .unwrap();
+        fs::write(rom_dir.join("blank.ch8"), [0x60, 0x00, 0x12, 0x02]).unwrap();
+        fs::write(rom_dir.join("notes.txt"), "not a rom").unwrap();
+
+        // RET with an empty stack
+        fs::write(rom_dir.join("broken.ch8"), [0x00, 0xEE]).unwrap();
+
+        let generator = GoldenGenerator::new(3);
+        let generated = generator.generate(&rom_dir, &golden_dir).unwrap();
+        let roms: Vec<_> = generated.iter().map(|(rom, _)| rom.clone()).collect();
+        assert_eq!(
+            vec![
+                rom_dir.join("blank.ch8"),
+                rom_dir.join("broken.ch8"),
+                rom_dir.join("zero.ch8")
+            ],
+            roms
+        );
+        // the broken rom does not stop the ones after it
+        assert!(matches!(generated[1].1, Err(GoldenError::Cpu(..))));
+        assert!(generated[2].1.is_ok());
+        fs::remove_file(rom_dir.join("broken.ch8")).unwrap();
+        let rle = fs::read(golden_dir.join("blank.rle")).unwrap();
+        assert_eq!(0, FrameBuffer::decode_rle(&rle).unwrap().count_lit_pixels());
+        let results = generator.verify(&rom_dir, &golden_dir);
+        assert!(results.iter().all(|(_, result)| result.passed()));
+
+        // the rom now ends with a blank screen
+        fs::write(rom_dir.join("zero.ch8"), [0x60, 0x00, 0x12, 0x02]).unwrap();
+        let results = generator.verify(&rom_dir, &golden_dir);
+        // a frame buffer differing only from the .rle is caught too
+        fs::write(golden_dir.join("blank.rle"), &rle[2..]).unwrap();
+        fs::write(rom_dir.join("new.ch8"), [0x12, 0x00]).unwrap();
+        let missing = generator.verify(&rom_dir, &golden_dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(results[0].1.passed());
+        assert_eq!(rom_dir.join("zero.ch8"), results[1].0);
+        assert!(!results[1].1.passed());
+        assert_eq!(3, missing.len());
+        assert!(matches!(missing[0].1.outcome, TestOutcome::FrameMismatch));
+        assert_eq!(rom_dir.join("new.ch8"), missing[1].0);
+        assert!(matches!(
+            missing[1].1.outcome,
+            TestOutcome::InvalidGolden(GoldenError::InvalidGolden(_))
+        ));
+        assert_eq!(1, missing[1].1.index);
+        assert!(!missing[2].1.passed());
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let golden = FrameBuffer::default();
+        let mut actual = FrameBuffer::default();
+        assert_eq!(None, snapshot_diff(&golden, &actual));
+
+        actual.draw(1, 0, &[0b1000_0000]);
+        let diff = snapshot_diff(&golden, &actual).unwrap();
+        let first_row = diff.lines().next().unwrap();
+        let (golden_row, actual_row) = first_row.split_once(" | ").unwrap();
+        let golden_pixel = format!("{}··{}", ANSI_GREEN, ANSI_RESET);
+        let actual_pixel = format!("{}██{}", ANSI_RED, ANSI_RESET);
+        assert_eq!(1, golden_row.matches(&golden_pixel).count());
+        assert_eq!(1, actual_row.matches(&actual_pixel).count());
+        assert!(golden_row.starts_with(&format!("{}··", ANSI_WHITE)));
+        assert_eq!(1, diff.matches(ANSI_RED).count());
+    }
+
+    #[test]
+    fn test_differential_test() {
+        let rom = [
+            0x60, 0x81, // 0200: LD V0, 0x81
+            0x61, 0x02, // 0202: LD V1, 2
+            0x80, 0x16, // 0204: SHR V0, V1
+            0xA3, 0x00, // 0206: LD I, 0x300
+            0xF1, 0x55, // 0208: LD [I], V1
+            0x12, 0x0A, // 020A: JP 0x20A
+        ];
+        let diffs = differential_test(
+            &rom,
+            8,
+            CompatibilityMode::Chip8,
+            CompatibilityMode::SuperChip,
+        );
+        let diff = |cycle, pc, register, old_value, new_value| StateDiff {
+            cycle,
+            pc,
+            register,
+            old_value,
+            new_value,
+        };
+        assert_eq!(
+            vec![
+                // CHIP-8 shifts V1 into V0, SUPER-CHIP shifts V0 in place
+                diff(2, 0x204, Some(0x0), 0x01, 0x40),
+                diff(2, 0x204, Some(0xF), 0, 1),
+                // and only CHIP-8 moves I past the stored registers
+                diff(4, 0x208, None, 0x02, 0x00),
+            ],
+            diffs
+        );
+        assert!(
+            differential_test(&rom, 8, CompatibilityMode::Chip8, CompatibilityMode::Chip8)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_assert_macros() {
+        let mut chip8 = Chip8::with_seed(TEST_SEED);
+        // LD V3, 0x42; LD I, 0x300; LD B, V3
+        chip8
+            .load_rom(&[0x63, 0x42, 0xA3, 0x00, 0xF3, 0x33])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.cpu_cycle().unwrap();
+        }
+        assert_register!(chip8, V3 == 0x42);
+        assert_register!(chip8, VF == 0);
+        assert_memory!(chip8, 0x300 == [0, 6, 6]);
+        chip8.memory.write_data(0x400, &[0, 6, 6]).unwrap();
+        assert_memory!(chip8, 0x300 == 0x400, 3);
+        assert_pc!(chip8, 0x206);
+    }
+
+    #[test]
+    #[should_panic(expected = "V3 should be 0x43")]
+    fn test_assert_register_message() {
+        let mut chip8 = Chip8::with_seed(TEST_SEED);
+        chip8.load_rom(&[0x63, 0x42]).unwrap();
+        chip8.cpu_cycle().unwrap();
+        assert_register!(chip8, V3 == 0x43);
+    }
+
+    #[test]
+    fn test_fuzz_corpus() {
+        let corpus = FuzzCorpus::generate();
+        assert_eq!(INSTR_TYPE_COUNT - 1, corpus.len());
+        for (index, rom) in corpus.iter().enumerate() {
+            assert_eq!(2 + 2 * FUZZ_SETUP.len(), rom.len());
+            let opcode = u16::from_be_bytes([rom[rom.len() - 2], rom[rom.len() - 1]]);
+            // decoded in the mode that has the instruction
+            assert!(
+                [
+                    CompatibilityMode::default(),
+                    CompatibilityMode::XoChip,
+                    CompatibilityMode::Chip8E
+                ]
+                .iter()
+                .any(|mode| Instr::decode(opcode, *mode)
+                    .map(|instr| instr.type_index())
+                    == Some(index)),
+                "{:04X}",
+                opcode
+            );
+            // the instruction may fail, e.g. RET with an empty stack, but never panics
+            let mut chip8 = Chip8::with_seed(TEST_SEED);
+            chip8.load_rom(rom).unwrap();
+            for _ in 0..=FUZZ_SETUP.len() {
+                if chip8.cpu_cycle().is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_checksum() {
+        assert_eq!(0xCBF4_3926, frame_checksum(b"123456789"));
+    }
+
+    #[test]
+    fn test_cycle_fixture() {
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for name in &["add_vx_vy.toml", "add_vx_vy_carry.json"] {
+            CycleFixture::from_file(&fixtures.join(name))
+                .unwrap()
+                .run()
+                .unwrap();
+        }
+
+        let fixture = CycleFixture::from_toml(
+            "before = { V0 = 5 }\nrom_patch = { \"0x200\" = \"7001\" }\nafter_1_cycle = { V0 = 7 }",
+        )
+        .unwrap();
+        assert!(matches!(
+            fixture.run(),
+            Err(FixtureError::Mismatch {
+                expected: 7,
+                actual: 6,
+                ..
+            })
+        ));
+        assert!(CycleFixture::from_toml("before = { VG = 1 }\nafter_1_cycle = {}").is_err());
+        assert!(CycleFixture::from_toml("before = { V0 = 256 }\nafter_1_cycle = {}").is_err());
+    }
+}
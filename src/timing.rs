@@ -0,0 +1,309 @@
+use std::cell::Cell;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// intervals more than this fraction longer than the target count as late
+const LATE_THRESHOLD: f64 = 0.1;
+
+// where the main loop of the app reads the time, so that tests can drive it by hand
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    // zero rather than a panic for an instant in the future
+    fn elapsed_since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// stands still until advanced. the clones share the same time, so that a test can keep
+// one to move the time of the app given the other
+#[derive(Clone, Debug)]
+pub struct FakeClock {
+    now: Rc<Cell<Instant>>,
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        FakeClock::new()
+    }
+}
+
+impl FakeClock {
+    // starts at the time it is created
+    pub fn new() -> Self {
+        FakeClock {
+            now: Rc::new(Cell::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+// statistics of the actual intervals between cycles compared to the target interval,
+// kept with Welford's online algorithm so that long sessions need no extra memory
+#[derive(Clone, Debug)]
+pub struct TimingStats {
+    target_micros: f64,
+    count: u64,
+    mean_micros: f64,
+    // sum of the squared differences from the mean
+    m2: f64,
+    max_jitter_micros: f64,
+    late_count: u64,
+}
+
+impl TimingStats {
+    pub fn new(target: Duration) -> Self {
+        TimingStats {
+            target_micros: target.as_secs_f64() * 1e6,
+            count: 0,
+            mean_micros: 0.0,
+            m2: 0.0,
+            max_jitter_micros: 0.0,
+            late_count: 0,
+        }
+    }
+
+    pub fn record(&mut self, actual: Duration) {
+        let micros = actual.as_secs_f64() * 1e6;
+        self.count += 1;
+        let delta = micros - self.mean_micros;
+        self.mean_micros += delta / self.count as f64;
+        self.m2 += delta * (micros - self.mean_micros);
+
+        let jitter = (micros - self.target_micros).abs();
+        if jitter > self.max_jitter_micros {
+            self.max_jitter_micros = jitter;
+        }
+        if micros > self.target_micros * (1.0 + LATE_THRESHOLD) {
+            self.late_count += 1;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_secs_f64(self.mean_micros / 1e6)
+    }
+
+    // population standard deviation of the recorded intervals
+    pub fn std_dev(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((self.m2 / self.count as f64).sqrt() / 1e6)
+    }
+
+    // the largest difference between a recorded interval and the target, either way
+    pub fn max_jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.max_jitter_micros / 1e6)
+    }
+
+    pub fn late_percentage(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.late_count as f64 * 100.0 / self.count as f64
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "intervals    {:>12}", self.count);
+        let _ = writeln!(report, "target       {:>10.1}us", self.target_micros);
+        let _ = writeln!(report, "mean         {:>10.1}us", self.mean_micros);
+        let _ = writeln!(
+            report,
+            "std dev      {:>10.1}us",
+            self.std_dev().as_secs_f64() * 1e6
+        );
+        let _ = writeln!(report, "max jitter   {:>10.1}us", self.max_jitter_micros);
+        let _ = write!(report, "late (>10%)  {:>11.2}%", self.late_percentage());
+        report
+    }
+}
+
+// width of the longest bar of the histogram report
+const HISTOGRAM_BAR_WIDTH: u64 = 40;
+
+// the actual frame times in 1ms buckets, for finding out where stutter comes from.
+// unlike `TimingStats` it keeps the whole distribution, so it is meant for short captures
+#[derive(Clone, Debug)]
+pub struct FrameHistogram {
+    target: Duration,
+    // the number of frames that took i milliseconds, rounded down, at index i
+    buckets: Vec<u64>,
+    count: u64,
+    missed_count: u64,
+}
+
+impl FrameHistogram {
+    pub fn new(target: Duration) -> Self {
+        FrameHistogram {
+            target,
+            buckets: Vec::new(),
+            count: 0,
+            missed_count: 0,
+        }
+    }
+
+    pub fn record(&mut self, actual: Duration) {
+        let bucket = actual.as_millis() as usize;
+        if bucket >= self.buckets.len() {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        if actual.as_secs_f64() > self.target.as_secs_f64() * (1.0 + LATE_THRESHOLD) {
+            self.missed_count += 1;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    // the upper bound of the bucket holding the given percentile, between 0 and 100
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let rank = (self.count as f64 * percentile / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (millis, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_millis(millis as u64 + 1);
+            }
+        }
+        Duration::ZERO
+    }
+
+    // share of the frames more than 10% longer than the target
+    pub fn missed_percentage(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.missed_count as f64 * 100.0 / self.count as f64
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let max = self.buckets.iter().copied().max().unwrap_or(0).max(1);
+        for (millis, &count) in self.buckets.iter().enumerate() {
+            // the empty buckets before the first frame time only push the bars down
+            if count == 0 && report.is_empty() {
+                continue;
+            }
+            let bar = "#".repeat(((count * HISTOGRAM_BAR_WIDTH + max - 1) / max) as usize);
+            let line = format!("{:>4}ms {:>8} {}", millis, count, bar);
+            let _ = writeln!(report, "{}", line.trim_end());
+        }
+        let _ = writeln!(report, "frames       {:>12}", self.count);
+        let _ = writeln!(
+            report,
+            "target       {:>10.1}ms",
+            self.target.as_secs_f64() * 1e3
+        );
+        for percentile in &[50, 95, 99] {
+            let _ = writeln!(
+                report,
+                "p{}          {:>10}ms",
+                percentile,
+                self.percentile(*percentile as f64).as_millis()
+            );
+        }
+        let _ = write!(report, "missed       {:>11.2}%", self.missed_percentage());
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_clock() {
+        let clock = FakeClock::new();
+        let app_clock: Box<dyn Clock> = Box::new(clock.clone());
+        let start = app_clock.now();
+        assert_eq!(start, app_clock.now());
+        clock.advance(Duration::from_millis(16));
+        assert_eq!(Duration::from_millis(16), app_clock.elapsed_since(start));
+        assert_eq!(
+            Duration::ZERO,
+            clock.elapsed_since(app_clock.now() + Duration::from_secs(1))
+        );
+    }
+
+    fn assert_micros(expected: f64, actual: Duration) {
+        let micros = actual.as_secs_f64() * 1e6;
+        assert!(
+            (expected - micros).abs() < 0.1,
+            "{} != {}",
+            expected,
+            micros
+        );
+    }
+
+    #[test]
+    fn test_mean_and_std_dev() {
+        let mut stats = TimingStats::new(Duration::from_micros(2000));
+        for micros in &[1000, 2000, 3000, 2000] {
+            stats.record(Duration::from_micros(*micros));
+        }
+        assert_eq!(4, stats.count());
+        assert_micros(2000.0, stats.mean());
+        // the squared differences from the mean add up to 2 * 1000^2
+        assert_micros(707.1, stats.std_dev());
+        assert_micros(1000.0, stats.max_jitter());
+    }
+
+    #[test]
+    fn test_late_percentage() {
+        let mut stats = TimingStats::new(Duration::from_micros(1000));
+        assert_eq!(0.0, stats.late_percentage());
+        // only the last interval is more than 10% late
+        for micros in &[900, 1000, 1100, 1101] {
+            stats.record(Duration::from_micros(*micros));
+        }
+        assert_eq!(25.0, stats.late_percentage());
+    }
+
+    #[test]
+    fn test_frame_histogram() {
+        let mut histogram = FrameHistogram::new(Duration::from_micros(16_667));
+        assert_eq!(Duration::ZERO, histogram.percentile(50.0));
+        for _ in 0..97 {
+            histogram.record(Duration::from_micros(16_700));
+        }
+        // two stutters and one frame that came early
+        histogram.record(Duration::from_millis(33));
+        histogram.record(Duration::from_millis(50));
+        histogram.record(Duration::from_millis(2));
+        assert_eq!(100, histogram.count());
+        assert_eq!(Duration::from_millis(17), histogram.percentile(50.0));
+        assert_eq!(Duration::from_millis(17), histogram.percentile(95.0));
+        assert_eq!(Duration::from_millis(34), histogram.percentile(99.0));
+        assert_eq!(Duration::from_millis(51), histogram.percentile(100.0));
+        assert_eq!(2.0, histogram.missed_percentage());
+        assert!(histogram
+            .report()
+            .starts_with("   2ms        1 #\n   3ms        0\n"));
+    }
+}
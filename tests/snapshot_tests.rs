@@ -0,0 +1,41 @@
+#![cfg(not(feature = "no-std"))]
+
+use chipotto::snapshot_test::{self, SnapshotResult};
+use std::path::PathBuf;
+
+// a missing snapshot is created on the first run and should then be committed
+fn check_snapshot(rom: &[u8], cycles: u64, name: &str) {
+    let golden_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.txt", name));
+    let result = snapshot_test::run(rom, cycles, &golden_path);
+    assert!(
+        !matches!(result, SnapshotResult::Mismatch { .. }),
+        "{}: {}",
+        name,
+        result
+    );
+}
+
+#[test]
+fn test_ibm_logo() {
+    check_snapshot(include_bytes!("../roms/IBM Logo.ch8"), 100, "ibm_logo");
+}
+
+#[test]
+fn test_opcode() {
+    check_snapshot(
+        include_bytes!("../roms/test_opcode.ch8"),
+        1000,
+        "test_opcode",
+    );
+}
+
+#[test]
+fn test_space_invaders_title() {
+    check_snapshot(
+        include_bytes!("../roms/Space Invaders [David Winter].ch8"),
+        2000,
+        "space_invaders_title",
+    );
+}